@@ -654,6 +654,8 @@ if USE_RUST and USE_RUST_EXACT and n <= HELD_KARP_MAX_TRACKS:
         _best_shifts_raw,
         global_overall_best_cost,
         (h_best_rust, t_best_rust, s_best_rust),
+        _rust_report,
+        _rust_shift_counts,
     ) = _rust_optimize_exact(
         bpms,
         base_key_ids,
@@ -715,6 +717,8 @@ elif USE_RUST:
         _per_track_min,
         _per_track_max,
         _per_track_avg,
+        _rust_report,
+        _per_position_costs,
     ) = _rust_optimize_mix(
         bpms,
         base_key_ids,
@@ -0,0 +1,98 @@
+//! Owner alternation for back-to-back sets: two (or more) DJs pool their tracks, and the
+//! order should hand over between them rather than play one crate at a time.
+//!
+//! Every track carries an owner tag.  A run is a stretch of consecutive tracks with one
+//! owner; runs up to `max_run` long are free (1 asks for strict alternation) and each
+//! track beyond that costs `weight`.  Unlike the key-family run limit this is a soft
+//! term: the annealer weighs it against the mix cost like a penalty-mode grouping.  It
+//! rides along in `Separation` with the other order-only terms; Held-Karp ignores it.
+
+pub struct Alternation {
+    owners: Vec<u8>,
+    /// Longest run charged nothing, at least 1.
+    pub max_run: usize,
+    /// Cost per track beyond `max_run`.
+    pub weight: f64,
+}
+
+impl Alternation {
+    /// Owner `owners[i]` for node `i`.
+    pub fn new(owners: Vec<u8>, max_run: usize, weight: f64) -> Self {
+        Alternation { owners, max_run, weight }
+    }
+
+    /// Positions `(first, last)` of the one-owner run through position `pos`.
+    fn run_at(&self, order: &[usize], pos: usize) -> (usize, usize) {
+        let owner = self.owners[order[pos]];
+        let mut first = pos;
+        while first > 0 && self.owners[order[first - 1]] == owner {
+            first -= 1;
+        }
+        let mut last = pos;
+        while last + 1 < order.len() && self.owners[order[last + 1]] == owner {
+            last += 1;
+        }
+        (first, last)
+    }
+
+    fn run_excess(&self, (first, last): (usize, usize)) -> usize {
+        (last - first + 1).saturating_sub(self.max_run)
+    }
+
+    /// Every run of the order, as `(first, last)` positions.
+    fn runs<'a>(&'a self, order: &'a [usize]) -> impl Iterator<Item = (usize, usize)> + 'a {
+        let mut pos = 0;
+        std::iter::from_fn(move || {
+            (pos < order.len()).then(|| {
+                let run = self.run_at(order, pos);
+                pos = run.1 + 1;
+                run
+            })
+        })
+    }
+
+    /// Tracks beyond `max_run`, summed over every run of the order.
+    pub fn excess(&self, order: &[usize]) -> usize {
+        self.runs(order).map(|run| self.run_excess(run)).sum()
+    }
+
+    /// `weight` times `excess`.
+    pub fn penalty(&self, order: &[usize]) -> f64 {
+        self.weight * self.excess(order) as f64
+    }
+
+    /// Length of the longest one-owner run.
+    pub fn longest_run(&self, order: &[usize]) -> usize {
+        self.runs(order).map(|(first, last)| last - first + 1).max().unwrap_or(0)
+    }
+
+    /// Owner at each position, the alternation pattern the order plays.
+    pub fn pattern(&self, order: &[usize]) -> Vec<u8> {
+        order.iter().map(|&i| self.owners[i]).collect()
+    }
+
+    /// Excess of the runs through positions `a`, `b` and their neighbours (each run once),
+    /// the only runs a swap of `a` and `b` can change.
+    fn local_excess(&self, order: &[usize], a: usize, b: usize) -> usize {
+        let mut runs = [(usize::MAX, usize::MAX); 6];
+        let mut count = 0;
+        for p in [a.wrapping_sub(1), a, a + 1, b.wrapping_sub(1), b, b + 1] {
+            if p >= order.len() || runs[..count].iter().any(|&(f, l)| f <= p && p <= l) {
+                continue;
+            }
+            runs[count] = self.run_at(order, p);
+            count += 1;
+        }
+        runs[..count].iter().map(|&run| self.run_excess(run)).sum()
+    }
+
+    /// Penalty delta for swapping positions `a` and `b`, which are swapped and restored in
+    /// place.
+    pub fn swap_delta(&self, order: &mut [usize], a: usize, b: usize) -> f64 {
+        let old = self.local_excess(order, a, b);
+        order.swap(a, b);
+        let new = self.local_excess(order, a, b);
+        order.swap(a, b);
+        self.weight * (new as f64 - old as f64)
+    }
+}
@@ -1,17 +1,35 @@
+use std::convert::Infallible;
+
+use rand::distr::weighted::WeightedIndex;
 use rand::prelude::*;
 use rand::rng;
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
 
 use crate::cost::{
-    affected_edges, edge_cost, optimize_shift_at, sum_edge_costs, total_edge_cost, CostParams,
+    affected_edges, edge_cost, edge_costs, optimize_shift_at, swap_local_cost, total_edge_cost, CostParams,
+    Subset, Tables,
 };
+use crate::separation::Separation;
 
 pub struct AnnealingParams {
     pub total_iterations: usize,
     pub initial_temp: f64,
     pub final_temp: f64,
     pub multi_swap_factor: usize,
+    /// Relative odds of starting each track at shift -1, 0 and +1 (uniform by default);
+    /// non-negative and not all zero.  Leaning on 0 suits configs that rarely shift.
+    pub shift_init_weights: [f64; 3],
+    /// Target acceptance rate in (0, 1) for a self-calibrated start temperature: each
+    /// attempt samples random swaps of its start, and `initial_temp` is replaced by the
+    /// temperature at which a move of the mean |delta| is accepted at this rate.  Makes the
+    /// schedule independent of the scale of the cost tables; `None` uses `initial_temp`.
+    pub auto_initial_temp: Option<f64>,
 }
 
+/// Random swaps sampled to calibrate `AnnealingParams::auto_initial_temp`.
+const AUTO_TEMP_SAMPLES: usize = 100;
+
 impl AnnealingParams {
     pub fn cooling_factor(&self) -> f64 {
         (self.final_temp / self.initial_temp).ln() / self.total_iterations as f64
@@ -19,8 +37,32 @@ impl AnnealingParams {
     pub fn cooling_factor_exp(&self) -> f64 {
         self.cooling_factor().exp()
     }
+
+    /// A schedule that actually cools: `initial_temp > final_temp > 0`, both finite, and
+    /// at least one iteration, so the cooling factor is a finite value in (0, 1).
+    pub fn validate(&self) -> Result<(), String> {
+        if self.total_iterations == 0 {
+            return Err("total_iterations must be >= 1".to_string());
+        }
+        if !(self.final_temp.is_finite() && self.final_temp > 0.0) {
+            return Err(format!("final_temp must be a finite value > 0, got {}", self.final_temp));
+        }
+        if !(self.initial_temp.is_finite() && self.initial_temp > self.final_temp) {
+            return Err(format!(
+                "initial_temp must be a finite value > final_temp ({}), got {}",
+                self.final_temp, self.initial_temp
+            ));
+        }
+        if let Some(rate) = self.auto_initial_temp {
+            if !(rate > 0.0 && rate < 1.0) {
+                return Err(format!("auto_initial_temp must be an acceptance rate in (0, 1), got {rate}"));
+            }
+        }
+        Ok(())
+    }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct SaResult {
     pub best_order: Vec<usize>,
     pub best_shifts: Vec<i8>,
@@ -28,159 +70,634 @@ pub struct SaResult {
     pub h_cost: f64,
     pub t_cost: f64,
     pub s_cost: f64,
+    /// Separation violations remaining in `best_order`, one count per grouping.
+    pub violations: Vec<usize>,
+    /// What became of the attempt's moves.
+    pub moves: MoveStats,
 }
 
-/// For each track index, compute its average adjacent-edge cost in the given ordering.
-/// Returns a Vec<f64> indexed by track index (not position).
-/// Mirrors the Python per-track cost analysis: average of incoming + outgoing edge costs.
-fn compute_per_track_costs(
+impl SaResult {
+    /// Track the best order opens with.
+    pub fn opener(&self) -> usize {
+        self.best_order[0]
+    }
+
+    /// Track the best order closes with.
+    pub fn closer(&self) -> usize {
+        self.best_order[self.best_order.len() - 1]
+    }
+}
+
+/// Outcome of every move an attempt tried, by reason, to tell an annealer that cannot
+/// climb out of a basin (many `rejected_worse`) from one that a hard constraint keeps from
+/// moving at all (many constraint rejections).  Counted by `run_attempt` and its variants;
+/// other solvers leave them at zero.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MoveStats {
+    /// Beat the best cost so far.
+    pub improved: usize,
+    /// Worse, but accepted by the Metropolis test, starting an escape.
+    pub accepted_worse: usize,
+    /// Taken unconditionally during an escape.
+    pub escape: usize,
+    /// Worse and declined by the Metropolis test.
+    pub rejected_worse: usize,
+    /// Vetoed by a hard separation grouping.
+    pub rejected_separation: usize,
+    /// Would have overrun `CostParams::max_shift_cost`.
+    pub rejected_shift_budget: usize,
+    /// Would have lengthened a key-family run beyond `CostParams::family_run`.
+    pub rejected_family_run: usize,
+}
+
+impl MoveStats {
+    /// Moves rejected by a hard constraint rather than by their cost.
+    pub fn constraint_rejected(&self) -> usize {
+        self.rejected_separation + self.rejected_shift_budget + self.rejected_family_run
+    }
+
+    /// Every move tried.
+    pub fn total(&self) -> usize {
+        self.improved + self.accepted_worse + self.escape + self.rejected_worse + self.constraint_rejected()
+    }
+}
+
+/// For each position in `order`, the average cost of its adjacent edges (incoming +
+/// outgoing; the first and last positions have one).  Indexed by position, for callers
+/// that lay the mix out as a timeline.
+pub fn compute_per_position_costs(
     order: &[usize],
     shifts: &[i8],
-    bpms: &[i32],
-    key_ids: &[u8],
-    shift_table: &[u8],
-    direct_costs: &[f64],
-    indirect_costs: &[f64],
+    tables: &Tables,
     params: &CostParams,
 ) -> Vec<f64> {
-    let n = order.len();
-    let mut costs = vec![0.0f64; n]; // indexed by track_idx
-    for pos in 0..n {
-        let idx = order[pos];
+    let edges: Vec<f64> = order
+        .windows(2)
+        .map(|w| edge_cost(w[0], w[1], shifts[w[0]], shifts[w[1]], tables, params))
+        .collect();
+    let mut costs = Vec::with_capacity(order.len());
+    for pos in 0..order.len() {
         let mut sum = 0.0f64;
         let mut count = 0usize;
         if pos > 0 {
-            let prev = order[pos - 1];
-            sum += edge_cost(prev, idx, shifts[prev], shifts[idx],
-                             bpms, key_ids, shift_table, direct_costs, indirect_costs, params);
+            sum += edges[pos - 1];
             count += 1;
         }
-        if pos < n - 1 {
-            let next = order[pos + 1];
-            sum += edge_cost(idx, next, shifts[idx], shifts[next],
-                             bpms, key_ids, shift_table, direct_costs, indirect_costs, params);
+        if let Some(&e) = edges.get(pos) {
+            sum += e;
             count += 1;
         }
-        if count > 0 {
-            costs[idx] = sum / count as f64;
-        }
+        costs.push(if count > 0 { sum / count as f64 } else { 0.0 });
     }
     costs
 }
 
+/// The costs of each track's two edges in an ordering, indexed by track index.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrackCosts {
+    /// Average of the incoming and outgoing edge costs (the one edge of an end track; 0
+    /// for a track not in the order).
+    pub avg: Vec<f64>,
+    /// Cost of the edge into each track, `None` for the opener and tracks not in the order.
+    pub incoming: Vec<Option<f64>>,
+    /// Cost of the edge out of each track, `None` for the closer and tracks not in the order.
+    pub outgoing: Vec<Option<f64>>,
+}
+
+/// `TrackCosts` of `order` over `n` tracks, e.g. to colour a hand-made order by how badly
+/// each track is mixed in and out; `order` may be a subset selection.
+pub fn compute_track_costs(
+    n: usize,
+    order: &[usize],
+    shifts: &[i8],
+    tables: &Tables,
+    params: &CostParams,
+) -> TrackCosts {
+    let mut incoming = vec![None; n];
+    let mut outgoing = vec![None; n];
+    for (j, cost) in edge_costs(order, shifts, tables, params).into_iter().enumerate() {
+        outgoing[order[j]] = Some(cost);
+        incoming[order[j + 1]] = Some(cost);
+    }
+    let avg = incoming
+        .iter()
+        .zip(&outgoing)
+        .map(|edges| match edges {
+            (Some(a), Some(b)) => (a + b) / 2.0,
+            (Some(a), None) | (None, Some(a)) => *a,
+            (None, None) => 0.0,
+        })
+        .collect();
+    TrackCosts { avg, incoming, outgoing }
+}
+
+/// For each track index, compute its average adjacent-edge cost in the given ordering.
+/// Returns a Vec<f64> of length `n` indexed by track index (not position); tracks not in
+/// `order` (a subset selection) get 0.
+/// Mirrors the Python per-track cost analysis: average of incoming + outgoing edge costs.
+pub fn compute_per_track_costs(
+    n: usize,
+    order: &[usize],
+    shifts: &[i8],
+    tables: &Tables,
+    params: &CostParams,
+) -> Vec<f64> {
+    compute_track_costs(n, order, shifts, tables, params).avg
+}
+
+/// `compute_per_track_costs` into `costs`, reusing its allocation.
+fn per_track_costs_into(
+    costs: &mut Vec<f64>,
+    n: usize,
+    order: &[usize],
+    shifts: &[i8],
+    tables: &Tables,
+    params: &CostParams,
+) {
+    costs.clear();
+    costs.resize(n, 0.0);
+    let mut incoming = None;
+    for (pos, &track) in order.iter().enumerate() {
+        let outgoing = order
+            .get(pos + 1)
+            .map(|&next| edge_cost(track, next, shifts[track], shifts[next], tables, params));
+        costs[track] = match (incoming, outgoing) {
+            (Some(a), Some(b)) => (a + b) / 2.0,
+            (Some(a), None) | (None, Some(a)) => a,
+            (None, None) => 0.0,
+        };
+        incoming = outgoing;
+    }
+}
+
+/// Buffers of annealing attempts kept between them: the working and best orders and
+/// shifts, the min-max blend's edge costs and the per-track costs of the stats.  Each
+/// attempt resizes and refills them, and a run hands every result it does not keep back,
+/// so attempts after the first allocate next to nothing.  One scratch reused across runs
+/// (`run_timed_in`) keeps the allocations of the largest instance it has seen.
+#[derive(Default)]
+pub struct Scratch {
+    order: Vec<usize>,
+    shifts: Vec<i8>,
+    best_order: Vec<usize>,
+    best_shifts: Vec<i8>,
+    edges: Vec<f64>,
+    best_edges: Vec<f64>,
+    // Copy of the start order that the temperature calibration swaps in place
+    probe: Vec<usize>,
+    track_costs: Vec<f64>,
+}
+
+impl Scratch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bytes currently reserved for the buffers.
+    pub fn capacity_bytes(&self) -> usize {
+        use std::mem::size_of;
+        (self.order.capacity() + self.best_order.capacity() + self.probe.capacity()) * size_of::<usize>()
+            + (self.shifts.capacity() + self.best_shifts.capacity()) * size_of::<i8>()
+            + (self.edges.capacity() + self.best_edges.capacity() + self.track_costs.capacity()) * size_of::<f64>()
+    }
+
+    /// Take back the order and shifts of a result that is no longer needed.
+    fn recycle(&mut self, result: SaResult) {
+        self.best_order = result.best_order;
+        self.best_shifts = result.best_shifts;
+    }
+}
+
 /// Run a single simulated annealing attempt. Returns the best solution found.
+///
+/// With `fixed_shifts` (node-indexed), shifts are held constant and only the order is
+/// annealed; their node costs are then a constant and drop out of the swap delta.
+/// Otherwise a shifted-track cap (`CostParams::max_shifted`) is kept throughout: the random
+/// start is trimmed to it, and a track the shift search would newly shift stays at 0 once
+/// the cap is reached.  A shift-cost budget (`CostParams::max_shift_cost`) trims the start
+/// the same way, but rejects any move whose shifts would overrun it.
+///
+/// With a stability penalty (`Tables::stability`) and no hard separation grouping, the
+/// attempt starts from the reference order rather than a random one, which every other
+/// order would start (n - 1) * weight behind.
 pub fn run_attempt(
     n: usize,
-    bpms: &[i32],
-    key_ids: &[u8],
-    shift_table: &[u8],
-    direct_costs: &[f64],
-    indirect_costs: &[f64],
+    tables: &Tables,
     cost_params: &CostParams,
     ann_params: &AnnealingParams,
+    separation: Option<&Separation>,
+    fixed_shifts: Option<&[i8]>,
     rng: &mut impl Rng,
 ) -> SaResult {
-    // Random initial order and shifts
-    let mut order: Vec<usize> = (0..n).collect();
-    order.shuffle(rng);
-    let mut shifts: Vec<i8> = (0..n)
-        .map(|_| [-1i8, 0, 1][rng.random_range(0usize..3)])
-        .collect();
+    run_attempt_in(&mut Scratch::new(), n, tables, cost_params, ann_params, separation, fixed_shifts, rng)
+}
 
-    // Full cost of initial state
-    let (h0, t0, s0) = total_edge_cost(
-        &order, &shifts, bpms, key_ids, shift_table, direct_costs, indirect_costs, cost_params,
+/// `run_attempt` on the buffers of `scratch`.  Draws the same moves and returns the same
+/// result whatever the scratch last held.
+pub fn run_attempt_in(
+    scratch: &mut Scratch,
+    n: usize,
+    tables: &Tables,
+    cost_params: &CostParams,
+    ann_params: &AnnealingParams,
+    separation: Option<&Separation>,
+    fixed_shifts: Option<&[i8]>,
+    rng: &mut impl Rng,
+) -> SaResult {
+    let separation = separation.filter(|sep| sep.is_active());
+    random_start(scratch, n, tables, ann_params, separation, fixed_shifts, rng);
+    let Ok(result) = anneal::<Infallible>(
+        n, tables, cost_params, ann_params, separation, fixed_shifts, None, None, scratch, rng,
     );
+    result
+}
+
+/// `run_attempt` that also records the best cost so far every `every` iterations (at
+/// least 1): `(iteration, best cost)` from iteration 0 up to a final entry at
+/// `total_iterations`, non-increasing.  Draws the same moves as `run_attempt`.
+pub fn run_attempt_recorded(
+    n: usize,
+    tables: &Tables,
+    cost_params: &CostParams,
+    ann_params: &AnnealingParams,
+    separation: Option<&Separation>,
+    fixed_shifts: Option<&[i8]>,
+    every: usize,
+    rng: &mut impl Rng,
+) -> (SaResult, Vec<(usize, f64)>) {
+    let separation = separation.filter(|sep| sep.is_active());
+    let mut scratch = Scratch::new();
+    random_start(&mut scratch, n, tables, ann_params, separation, fixed_shifts, rng);
+    let mut history = Vec::with_capacity(ann_params.total_iterations / every.max(1) + 2);
+    let Ok(result) = anneal::<Infallible>(
+        n, tables, cost_params, ann_params, separation, fixed_shifts, None, Some((every.max(1), &mut history)),
+        &mut scratch, rng,
+    );
+    (result, history)
+}
+
+/// Random initial order (respecting hard separation groupings) and shifts of `run_attempt`,
+/// into `scratch.order` and `scratch.shifts`.
+fn random_start(
+    scratch: &mut Scratch,
+    n: usize,
+    tables: &Tables,
+    ann_params: &AnnealingParams,
+    separation: Option<&Separation>,
+    fixed_shifts: Option<&[i8]>,
+    rng: &mut impl Rng,
+) {
+    let order = &mut scratch.order;
+    order.clear();
+    match (separation, tables.stability) {
+        (Some(sep), _) if sep.any_hard() => order.extend(sep.initial_order(n, rng)),
+        (_, Some(st)) => order.extend_from_slice(st.reference()),
+        _ => {
+            order.extend(0..n);
+            order.shuffle(rng);
+        }
+    }
+    let shifts = &mut scratch.shifts;
+    shifts.clear();
+    match fixed_shifts {
+        Some(fixed) => shifts.extend_from_slice(fixed),
+        None => {
+            let init = WeightedIndex::new(ann_params.shift_init_weights)
+                .expect("shift_init_weights must be non-negative and not all zero");
+            shifts.extend((0..n).map(|_| init.sample(rng) as i8 - 1));
+        }
+    }
+}
+
+/// `run_attempt` warm-started from `order` and `shifts` (node-indexed) instead of a random
+/// start, e.g. a heuristic solution to polish.  The result is never worse than the start.
+pub fn run_attempt_from(
+    n: usize,
+    tables: &Tables,
+    cost_params: &CostParams,
+    ann_params: &AnnealingParams,
+    order: &[usize],
+    shifts: &[i8],
+    rng: &mut impl Rng,
+) -> SaResult {
+    let mut scratch = Scratch { order: order.to_vec(), shifts: shifts.to_vec(), ..Scratch::default() };
+    let Ok(result) = anneal::<Infallible>(
+        n, tables, cost_params, ann_params, None, None, None, None, &mut scratch, rng,
+    );
+    result
+}
+
+/// A custom move for `run_attempt_with_moves`: given the current order and node-indexed
+/// shifts, propose a new order (a permutation of 0..n), or `Ok(None)` to make a native swap
+/// this iteration.  An error ends the attempt.
+pub type ProposeMove<'a, E> = dyn FnMut(&[usize], &[i8]) -> Result<Option<Vec<usize>>, E> + 'a;
+
+/// `run_attempt` (without separation groupings) that asks `propose` for the move on every
+/// `interval`-th iteration, for experimenting with domain-specific moves.  A proposed order
+/// keeps the current shifts and is scored in full, then accepted or rejected like a native
+/// swap; a proposal that would lengthen an over-long family run is skipped.  The native
+/// swaps run in between, so an occasional proposal costs little.
+///
+/// # Panics
+/// If a proposed order is not a permutation of 0..n.
+pub fn run_attempt_with_moves<E>(
+    n: usize,
+    tables: &Tables,
+    cost_params: &CostParams,
+    ann_params: &AnnealingParams,
+    interval: usize,
+    propose: &mut ProposeMove<'_, E>,
+    rng: &mut impl Rng,
+) -> Result<SaResult, E> {
+    let mut scratch = Scratch::new();
+    random_start(&mut scratch, n, tables, ann_params, None, None, rng);
+    let custom = Some((interval.max(1), propose));
+    anneal(n, tables, cost_params, ann_params, None, None, custom, None, &mut scratch, rng)
+}
+
+/// The annealing loop of `run_attempt` from the start in `scratch.order` and
+/// `scratch.shifts` (`separation` already filtered to an active one), with optional custom
+/// moves every so many iterations and an optional record of the best cost every so many
+/// iterations.  The result takes `scratch`'s best order and shifts buffers.
+fn anneal<E>(
+    n: usize,
+    tables: &Tables,
+    cost_params: &CostParams,
+    ann_params: &AnnealingParams,
+    separation: Option<&Separation>,
+    fixed_shifts: Option<&[i8]>,
+    mut custom: Option<(usize, &mut ProposeMove<'_, E>)>,
+    mut history: Option<(usize, &mut Vec<(usize, f64)>)>,
+    scratch: &mut Scratch,
+    rng: &mut impl Rng,
+) -> Result<SaResult, E> {
+    let mut order = std::mem::take(&mut scratch.order);
+    let mut shifts = std::mem::take(&mut scratch.shifts);
+
+    // Weighted shift cost spent so far, against the budget
+    let max_shift_cost = cost_params.max_shift_cost.filter(|_| fixed_shifts.is_none());
+    let shift_cost_of = |i: usize, s: i8| cost_params.shift_weight * tables.node_components(i, s, cost_params).2;
+    let mut shift_spent = (0..n).map(|i| shift_cost_of(i, shifts[i])).sum::<f64>();
+    if let Some(cap) = max_shift_cost {
+        for (i, s) in shifts.iter_mut().enumerate() {
+            if *s != 0 && shift_spent > cap {
+                shift_spent += shift_cost_of(i, 0) - shift_cost_of(i, *s);
+                *s = 0;
+            }
+        }
+    }
+
+    let max_shifted = cost_params.max_shifted.filter(|_| fixed_shifts.is_none());
+    let mut shifted = 0usize;
+    if let Some(k) = max_shifted {
+        for s in shifts.iter_mut().filter(|s| **s != 0) {
+            if shifted < k {
+                shifted += 1;
+            } else {
+                *s = 0;
+            }
+        }
+    }
+
+    // Full cost of initial state
+    let (h0, t0, s0) = total_edge_cost(&order, &shifts, tables, cost_params);
     let full_cost = |h: f64, t: f64, s: f64| -> f64 {
         h + cost_params.tempo_cost_weight * t + cost_params.shift_weight * s
     };
-    let mut best_cost = full_cost(h0, t0, s0);
-    let mut best_order = order.clone();
-    let mut best_shifts = shifts.clone();
+    let family_run = cost_params.family_run;
+    let penalties = |order: &[usize], shifts: &[i8]| -> f64 {
+        tables.boundary_cost(order, shifts, cost_params)
+            + separation.map_or(0.0, |sep| sep.penalty(order))
+            + family_run.map_or(0.0, |fr| fr.penalty(order, shifts, tables))
+    };
+    // Min-max blend: the objective is (1 - λ) × the sum above + λ × the costliest edge.  The
+    // edge costs are kept per position, so a swap only rescans them when it lowers the max.
+    let blend = cost_params.minmax_blend;
+    let blended = |sum: f64, max: f64| if blend > 0.0 { (1.0 - blend) * sum + blend * max } else { sum };
+    let max_of = |edges: &[f64]| edges.iter().copied().fold(0.0, f64::max);
+    let mut edges = std::mem::take(&mut scratch.edges);
+    edges.clear();
+    if blend > 0.0 {
+        edges.extend(order.windows(2).map(|w| edge_cost(w[0], w[1], shifts[w[0]], shifts[w[1]], tables, cost_params)));
+    }
+    let mut max_edge = max_of(&edges);
+    let mut best_edges = std::mem::take(&mut scratch.best_edges);
+    best_edges.clone_from(&edges);
+    let mut best_max_edge = max_edge;
+
+    let mut best_sum = full_cost(h0, t0, s0) + penalties(&order, &shifts);
+    let mut best_cost = blended(best_sum, max_edge);
+    let mut best_order = std::mem::take(&mut scratch.best_order);
+    best_order.clone_from(&order);
+    let mut best_shifts = std::mem::take(&mut scratch.best_shifts);
+    best_shifts.clone_from(&shifts);
     let mut h_best = h0;
     let mut t_best = t0;
     let mut s_best = s0;
+    let mut best_shifted = shifted;
+    let mut best_shift_spent = shift_spent;
 
-    let mut current_cost = best_cost;
-    let cooling = ann_params.cooling_factor_exp();
-    let mut temp = ann_params.initial_temp;
+    let mut current_sum = best_sum;
+    let mut temp = match ann_params.auto_initial_temp {
+        Some(rate) => {
+            let objective = |order: &[usize]| {
+                let (h, t, s) = total_edge_cost(order, &shifts, tables, cost_params);
+                let sum = full_cost(h, t, s) + penalties(order, &shifts);
+                let max = if blend > 0.0 { max_of(&edge_costs(order, &shifts, tables, cost_params)) } else { 0.0 };
+                blended(sum, max)
+            };
+            scratch.probe.clone_from(&order);
+            calibrated_temp(&mut scratch.probe, separation, objective, best_cost, rate, rng)
+                .map_or(ann_params.initial_temp, |t| t.max(ann_params.final_temp))
+        }
+        None => ann_params.initial_temp,
+    };
+    let cooling = ((ann_params.final_temp / temp).ln() / ann_params.total_iterations as f64).exp();
     let num_candidates = ann_params.multi_swap_factor * n;
 
     let mut in_escape_mode = false;
     let mut escape_counter: usize = 0;
+    let mut moves = MoveStats::default();
 
     let mut edge_buf = [0usize; 4];
 
     for master_iter in 0..ann_params.total_iterations {
+        if let Some((every, record)) = history.as_mut() {
+            if master_iter.is_multiple_of(*every) {
+                record.push((master_iter, best_cost));
+            }
+        }
         if !in_escape_mode {
             // Reset to best known state
             order.copy_from_slice(&best_order);
             shifts.copy_from_slice(&best_shifts);
-            current_cost = best_cost;
+            shifted = best_shifted;
+            shift_spent = best_shift_spent;
+            current_sum = best_sum;
+            if blend > 0.0 {
+                edges.copy_from_slice(&best_edges);
+                max_edge = best_max_edge;
+            }
         }
 
-        // Pick two distinct random positions
-        let a = rng.random_range(0..n);
-        let mut b = rng.random_range(0..n - 1);
-        if b >= a { b += 1; }
+        // A custom move, when one is due and proposed, takes the place of the native swap
+        let proposal = match custom.as_mut() {
+            Some((interval, propose)) if (master_iter + 1) % *interval == 0 => propose(&order, &shifts)?,
+            _ => None,
+        };
+        let candidate_sum = if let Some(proposal) = proposal {
+            let mut seen = vec![false; n];
+            assert!(
+                proposal.len() == n
+                    && proposal.iter().all(|&t| t < n && !std::mem::replace(&mut seen[t], true)),
+                "custom move proposed {proposal:?}, which is not a permutation of 0..{n}"
+            );
+            if let Some(fr) = family_run {
+                if fr.excess(&proposal, &shifts, tables) > fr.excess(&order, &shifts, tables) {
+                    moves.rejected_family_run += 1;
+                    temp *= cooling;
+                    continue;
+                }
+            }
+            order.copy_from_slice(&proposal);
+            if blend > 0.0 {
+                edges = edge_costs(&order, &shifts, tables, cost_params);
+                max_edge = max_of(&edges);
+            }
+            let (h, t, s) = total_edge_cost(&order, &shifts, tables, cost_params);
+            full_cost(h, t, s) + penalties(&order, &shifts)
+        } else {
+            // Pick two distinct random positions
+            let a = rng.random_range(0..n);
+            let mut b = rng.random_range(0..n - 1);
+            if b >= a { b += 1; }
 
-        // Affected edges before swap
-        let num_affected = affected_edges(a, b, n, &mut edge_buf);
-        let affected = &edge_buf[..num_affected];
+            // Affected edges before swap
+            let num_affected = affected_edges(a, b, n, &mut edge_buf);
+            let affected = &edge_buf[..num_affected];
 
-        let old_edge_cost = sum_edge_costs(
-            affected, &order, &shifts, bpms, key_ids, shift_table, direct_costs, indirect_costs, cost_params,
-        );
+            // Separation penalty delta; hard groupings veto the swap before any shift work
+            let violation_delta = match separation {
+                Some(sep) => match sep.swap_delta(&mut order, a, b) {
+                    Some(d) => d,
+                    None => {
+                        moves.rejected_separation += 1;
+                        temp *= cooling;
+                        continue;
+                    }
+                },
+                None => 0.0,
+            };
 
-        // Track old shift contributions for the two tracks at positions a and b
-        let old_shift_a = shifts[order[a]];
-        let old_shift_b = shifts[order[b]];
-        let old_shift_count =
-            (if old_shift_a != 0 { 1usize } else { 0 }) + (if old_shift_b != 0 { 1 } else { 0 });
+            let (old_edge_cost, old_node_cost) = swap_local_cost(&order, &shifts, affected, a, b, tables, cost_params);
 
-        // Perform the swap
-        order.swap(a, b);
+            // Family-run excess around both positions, and their shifts to restore on a veto
+            let old_family_excess = family_run.map(|fr| fr.local_excess(&order, &shifts, tables, a, b));
+            let old_shifts = (shifts[order[a]], shifts[order[b]]);
+            let old_shifted = shifted;
+            let old_pair_spent = shift_cost_of(order[a], old_shifts.0) + shift_cost_of(order[b], old_shifts.1);
+
+            // Old shift contributions for the two tracks at positions a and b (constant, and
+            // skipped, when shifts are fixed)
+            let old_shift_cost = if fixed_shifts.is_none() { old_node_cost } else { 0.0 };
+
+            // Perform the swap
+            order.swap(a, b);
+
+            // Optimize shifts at both swapped positions (unless they are fixed)
+            if fixed_shifts.is_none() {
+                for pos in [a, b] {
+                    let was_shifted = shifts[order[pos]] != 0;
+                    optimize_shift_at(&order, &mut shifts, pos, tables, cost_params);
+                    if let Some(k) = max_shifted {
+                        match (was_shifted, shifts[order[pos]] != 0) {
+                            (false, true) if shifted < k => shifted += 1,
+                            (false, true) => shifts[order[pos]] = 0,
+                            (true, false) => shifted -= 1,
+                            _ => {}
+                        }
+                    }
+                }
+            }
+
+            // A move whose shifts overrun the budget is undone and skipped
+            let new_spent = shift_spent - old_pair_spent
+                + shift_cost_of(order[a], shifts[order[a]])
+                + shift_cost_of(order[b], shifts[order[b]]);
+            if max_shift_cost.is_some_and(|cap| new_spent > cap + 1e-9) {
+                order.swap(a, b);
+                (shifts[order[a]], shifts[order[b]]) = old_shifts;
+                shifted = old_shifted;
+                moves.rejected_shift_budget += 1;
+                temp *= cooling;
+                continue;
+            }
+
+            // A move that lengthens an over-long family run is undone and skipped
+            let family_delta = match (family_run, old_family_excess) {
+                (Some(fr), Some(old)) => {
+                    let new = fr.local_excess(&order, &shifts, tables, a, b);
+                    if new > old {
+                        order.swap(a, b);
+                        (shifts[order[a]], shifts[order[b]]) = old_shifts;
+                        shifted = old_shifted;
+                        moves.rejected_family_run += 1;
+                        temp *= cooling;
+                        continue;
+                    }
+                    fr.penalty * (new as f64 - old as f64)
+                }
+                _ => 0.0,
+            };
+            shift_spent = new_spent;
+
+            // Affected edges after swap
+            let (new_edge_cost, new_node_cost) = swap_local_cost(&order, &shifts, affected, a, b, tables, cost_params);
 
-        // Optimize shifts at both swapped positions
-        optimize_shift_at(
-            &order, &mut shifts, a,
-            bpms, key_ids, shift_table, direct_costs, indirect_costs, cost_params,
-        );
-        optimize_shift_at(
-            &order, &mut shifts, b,
-            bpms, key_ids, shift_table, direct_costs, indirect_costs, cost_params,
-        );
-
-        // Affected edges after swap
-        let new_edge_cost = sum_edge_costs(
-            affected, &order, &shifts, bpms, key_ids, shift_table, direct_costs, indirect_costs, cost_params,
-        );
-
-        // Shift penalty delta
-        let new_shift_a = shifts[order[a]];
-        let new_shift_b = shifts[order[b]];
-        let new_shift_count =
-            (if new_shift_a != 0 { 1usize } else { 0 }) + (if new_shift_b != 0 { 1 } else { 0 });
-        let shift_delta = cost_params.shift_penalty * cost_params.shift_weight
-            * (new_shift_count as f64 - old_shift_count as f64);
-
-        let candidate_cost = current_cost + (new_edge_cost - old_edge_cost) + shift_delta;
+            // Shift penalty delta: the shift search weighed the penalty to pick a shift, but
+            // only this term charges it to the candidate
+            let new_shift_cost = if fixed_shifts.is_none() { new_node_cost } else { 0.0 };
+            let shift_delta = new_shift_cost - old_shift_cost;
+
+            if blend > 0.0 {
+                let mut lowered_max = false;
+                for &j in affected {
+                    let new = edge_cost(order[j], order[j + 1], shifts[order[j]], shifts[order[j + 1]], tables, cost_params);
+                    lowered_max |= edges[j] >= max_edge && new < edges[j];
+                    edges[j] = new;
+                    max_edge = max_edge.max(new);
+                }
+                if lowered_max {
+                    max_edge = max_of(&edges);
+                }
+            }
+
+            current_sum + (new_edge_cost - old_edge_cost) + shift_delta + violation_delta + family_delta
+        };
+        let candidate_cost = blended(candidate_sum, max_edge);
 
         if candidate_cost < best_cost {
             best_order.copy_from_slice(&order);
             best_shifts.copy_from_slice(&shifts);
+            best_shifted = shifted;
+            best_shift_spent = shift_spent;
+            best_sum = candidate_sum;
             best_cost = candidate_cost;
-            current_cost = candidate_cost;
+            current_sum = candidate_sum;
+            if blend > 0.0 {
+                best_edges.copy_from_slice(&edges);
+                best_max_edge = max_edge;
+            }
             in_escape_mode = false;
+            moves.improved += 1;
             // Recompute split costs (rare — only on improvement)
-            let (h, t, s) = total_edge_cost(
-                &best_order, &best_shifts, bpms, key_ids, shift_table, direct_costs, indirect_costs, cost_params,
-            );
+            let (h, t, s) = total_edge_cost(&best_order, &best_shifts, tables, cost_params);
             h_best = h;
             t_best = t;
             s_best = s;
         } else if in_escape_mode {
-            current_cost = candidate_cost;
+            current_sum = candidate_sum;
+            moves.escape += 1;
             escape_counter += 1;
             if escape_counter > num_candidates {
                 in_escape_mode = false;
@@ -191,22 +708,66 @@ pub fn run_attempt(
             if (delta / temp).exp() > rng.random::<f64>() {
                 in_escape_mode = true;
                 escape_counter = 0;
-                current_cost = candidate_cost;
+                current_sum = candidate_sum;
+                moves.accepted_worse += 1;
+            } else {
+                moves.rejected_worse += 1;
             }
         }
 
         temp *= cooling;
-        let _ = master_iter; // suppress lint
+    }
+    if let Some((_, record)) = history {
+        record.push((ann_params.total_iterations, best_cost));
     }
 
-    SaResult {
+    let violations = separation.map_or_else(Vec::new, |sep| sep.violations(&best_order));
+    scratch.order = order;
+    scratch.shifts = shifts;
+    scratch.edges = edges;
+    scratch.best_edges = best_edges;
+
+    Ok(SaResult {
         best_order,
         best_shifts,
         best_cost,
         h_cost: h_best,
         t_cost: t_best,
         s_cost: s_best,
+        violations,
+        moves,
+    })
+}
+
+/// Start temperature at which a move of the mean |delta| over `AUTO_TEMP_SAMPLES` random
+/// swaps of `order` (shifts unchanged, swaps a hard grouping forbids skipped) is accepted
+/// with probability `rate`: exp(-mean / T) = rate.  `None` when no swap changed the cost.
+fn calibrated_temp(
+    order: &mut [usize],
+    separation: Option<&Separation>,
+    objective: impl Fn(&[usize]) -> f64,
+    cost: f64,
+    rate: f64,
+    rng: &mut impl Rng,
+) -> Option<f64> {
+    let n = order.len();
+    let (mut total, mut moved) = (0.0, 0usize);
+    for _ in 0..AUTO_TEMP_SAMPLES {
+        let a = rng.random_range(0..n);
+        let mut b = rng.random_range(0..n - 1);
+        if b >= a { b += 1; }
+        if separation.is_some_and(|sep| sep.swap_delta(order, a, b).is_none()) {
+            continue;
+        }
+        order.swap(a, b);
+        let delta = (objective(order) - cost).abs();
+        order.swap(a, b);
+        if delta > 0.0 {
+            total += delta;
+            moved += 1;
+        }
     }
+    (moved > 0).then(|| -(total / moved as f64) / rate.ln())
 }
 
 /// Per-track stats aggregated across all attempts: (min, max, avg) indexed by track index.
@@ -216,67 +777,624 @@ pub struct PerTrackStats {
     pub avg: Vec<f64>,
 }
 
+/// How each attempt's per-track costs contribute to `PerTrackStats`.
+///
+/// The weightings other than `Uniform` judge an attempt by its gap to the best attempt of
+/// the run, `(cost - best) / max(|best|, 1)`, which is defined for any costs, including
+/// the zero and negative ones that `prefer_adjacent` bonuses make reachable.
+///
+/// - `Uniform`: every attempt counts equally (min/max over all attempts, plain mean).
+/// - `InverseCost`: the mean weights each attempt by `1 / (1 + gap)`, so good solutions
+///   dominate; min/max still cover all attempts.  For a best cost of 1 or more this is
+///   `best / cost`, proportional to weighting by `1 / cost`.
+/// - `WithinPercent(x)`: only attempts whose gap is at most `x`% count, for min, max and
+///   mean alike.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum StatsWeighting {
+    Uniform,
+    InverseCost,
+    WithinPercent(f64),
+}
+
+impl StatsWeighting {
+    /// Weight in the stats of an attempt costing `cost` in a run whose best attempt cost
+    /// `best`; `None` when the attempt is left out altogether.
+    pub fn weight(&self, cost: f64, best: f64) -> Option<f64> {
+        let gap = (cost - best) / best.abs().max(1.0);
+        match *self {
+            StatsWeighting::Uniform => Some(1.0),
+            StatsWeighting::InverseCost => Some(1.0 / (1.0 + gap)),
+            StatsWeighting::WithinPercent(pct) => (gap * 100.0 <= pct).then_some(1.0),
+        }
+    }
+}
+
+/// An attempt that panicked, with what it takes to replay it: the attempt ran on
+/// `StdRng::seed_from_u64(seed)`, so `run_attempt` with that RNG and the same inputs
+/// panics again.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AttemptPanic {
+    /// Index of the attempt within its run (0-based).
+    pub attempt: usize,
+    pub seed: u64,
+    /// The panic message, when it was a string.
+    pub message: String,
+}
+
+impl std::fmt::Display for AttemptPanic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "annealing attempt {} (seed {}) panicked: {}", self.attempt, self.seed, self.message)
+    }
+}
+
+impl std::error::Error for AttemptPanic {}
+
 /// Run multiple SA attempts until the time budget (seconds) is exhausted.
 /// Always runs at least one attempt.
-/// Returns the global best result, per-attempt cost breakdown, and per-track stats.
+/// Returns the global best result, per-attempt cost breakdown, per-track stats, and each
+/// attempt's wall time in seconds (parallel to the cost breakdown).
+///
+/// Each attempt runs on its own RNG, seeded from the run's, and a panicking attempt stops
+/// the run with an `AttemptPanic` rather than unwinding into the caller.
 pub fn run_timed(
     n: usize,
-    bpms: &[i32],
-    key_ids: &[u8],
-    shift_table: &[u8],
-    direct_costs: &[f64],
-    indirect_costs: &[f64],
+    tables: &Tables,
     cost_params: &CostParams,
     ann_params: &AnnealingParams,
+    separation: Option<&Separation>,
+    fixed_shifts: Option<&[i8]>,
+    weighting: StatsWeighting,
     time_limit_secs: f64,
-) -> (SaResult, Vec<(f64, f64, f64, f64)>, PerTrackStats) {
-    let mut rng = rng();
+) -> Result<TimedRun, AttemptPanic> {
+    run_timed_in(
+        &mut Scratch::new(), n, tables, cost_params, ann_params, separation, fixed_shifts, weighting, time_limit_secs,
+    )
+}
+
+/// `run_timed` on the buffers of `scratch`, e.g. one kept by a caller that optimizes again
+/// and again, so only the first run allocates them.
+pub fn run_timed_in(
+    scratch: &mut Scratch,
+    n: usize,
+    tables: &Tables,
+    cost_params: &CostParams,
+    ann_params: &AnnealingParams,
+    separation: Option<&Separation>,
+    fixed_shifts: Option<&[i8]>,
+    weighting: StatsWeighting,
+    time_limit_secs: f64,
+) -> Result<TimedRun, AttemptPanic> {
+    let mut state = RunState::new(n, weighting);
+    resume_in(
+        scratch, n, tables, cost_params, ann_params, separation, fixed_shifts, &mut state, time_limit_secs,
+        &mut |_| true,
+    )
+}
+
+/// `run_timed` continuing from `state`: a fresh `RunState::new`, or one restored from a
+/// checkpoint (`checkpoint::load`) of a run on the same inputs, whose attempts then count
+/// towards the result.  The time budget starts now and buys at least one new attempt.
+/// `on_attempt` sees the state after every attempt, e.g. to save a checkpoint, and can
+/// end the run early by returning false; `state` is left holding the whole run.
+///
+/// # Panics
+/// If `state` is for another number of nodes.
+pub fn run_resumed(
+    n: usize,
+    tables: &Tables,
+    cost_params: &CostParams,
+    ann_params: &AnnealingParams,
+    separation: Option<&Separation>,
+    fixed_shifts: Option<&[i8]>,
+    state: &mut RunState,
+    time_limit_secs: f64,
+    on_attempt: &mut dyn FnMut(&RunState) -> bool,
+) -> Result<TimedRun, AttemptPanic> {
+    resume_in(
+        &mut Scratch::new(), n, tables, cost_params, ann_params, separation, fixed_shifts, state, time_limit_secs,
+        on_attempt,
+    )
+}
+
+/// `run_resumed` on the buffers of `scratch`.
+fn resume_in(
+    scratch: &mut Scratch,
+    n: usize,
+    tables: &Tables,
+    cost_params: &CostParams,
+    ann_params: &AnnealingParams,
+    separation: Option<&Separation>,
+    fixed_shifts: Option<&[i8]>,
+    state: &mut RunState,
+    time_limit_secs: f64,
+    on_attempt: &mut dyn FnMut(&RunState) -> bool,
+) -> Result<TimedRun, AttemptPanic> {
+    assert_eq!(state.n, n, "run state is for {} nodes, not {n}", state.n);
     let start = std::time::Instant::now();
-    let mut global_best: Option<SaResult> = None;
-    let mut attempt_costs: Vec<(f64, f64, f64, f64)> = Vec::new();
+    let resumed = state.attempts();
+    let stopped = std::cell::Cell::new(false);
+    let attempt = |scratch: &mut Scratch, rng: &mut StdRng| {
+        run_attempt_in(scratch, n, tables, cost_params, ann_params, separation, fixed_shifts, rng)
+    };
+    run_attempts(
+        tables, cost_params, scratch, attempt, &mut rng(), state,
+        |attempts| !stopped.get() && (attempts == resumed || start.elapsed().as_secs_f64() < time_limit_secs),
+        |state| stopped.set(!on_attempt(state)),
+    )?;
+    Ok(state.result())
+}
 
-    // Per-track accumulators (indexed by track index)
-    let mut track_min = vec![f64::INFINITY; n];
-    let mut track_max = vec![f64::NEG_INFINITY; n];
-    let mut track_sum = vec![0.0f64; n];
+/// `run_timed` with every attempt warm-started by `run_attempt_from` from `order` and
+/// `shifts` (node-indexed), so the best result is never worse than the start.  Separation
+/// groupings and fixed shifts are not available.
+pub fn run_timed_from(
+    n: usize,
+    tables: &Tables,
+    cost_params: &CostParams,
+    ann_params: &AnnealingParams,
+    order: &[usize],
+    shifts: &[i8],
+    time_limit_secs: f64,
+) -> Result<TimedRun, AttemptPanic> {
+    let start = std::time::Instant::now();
+    let mut state = RunState::new(n, StatsWeighting::Uniform);
+    let attempt = |_: &mut Scratch, rng: &mut StdRng| {
+        run_attempt_from(n, tables, cost_params, ann_params, order, shifts, rng)
+    };
+    run_attempts(
+        tables, cost_params, &mut Scratch::new(), attempt, &mut rng(), &mut state,
+        |attempts| attempts == 0 || start.elapsed().as_secs_f64() < time_limit_secs,
+        |_| {},
+    )?;
+    Ok(state.result())
+}
 
-    loop {
-        let elapsed = start.elapsed().as_secs_f64();
-        if !attempt_costs.is_empty() && elapsed >= time_limit_secs {
-            break;
+/// `run_timed` with exactly `attempts` attempts (at least one) drawn from an RNG seeded
+/// with `seed`, so the result does not depend on machine speed.  Only the attempt wall
+/// times vary between runs.
+pub fn run_seeded(
+    n: usize,
+    tables: &Tables,
+    cost_params: &CostParams,
+    ann_params: &AnnealingParams,
+    separation: Option<&Separation>,
+    fixed_shifts: Option<&[i8]>,
+    weighting: StatsWeighting,
+    attempts: usize,
+    seed: u64,
+) -> Result<TimedRun, AttemptPanic> {
+    let mut state = RunState::new(n, weighting);
+    let attempt = |scratch: &mut Scratch, rng: &mut StdRng| {
+        run_attempt_in(scratch, n, tables, cost_params, ann_params, separation, fixed_shifts, rng)
+    };
+    run_attempts(
+        tables, cost_params, &mut Scratch::new(), attempt, &mut StdRng::seed_from_u64(seed), &mut state,
+        |done| done < attempts.max(1),
+        |_| {},
+    )?;
+    Ok(state.result())
+}
+
+/// Costs within this of each other count as one in `distinct_costs`: far above the
+/// rounding noise of summing edge costs, far below any real difference between mixes.
+pub const DISTINCT_COST_EPSILON: f64 = 1e-6;
+
+/// Number of distinct values among `costs` (e.g. the attempts' best costs), as a
+/// convergence signal: one means every attempt agreed, many suggest the attempts are too
+/// short.  Sorted costs are bucketed greedily, a new bucket starting at the first cost more
+/// than `DISTINCT_COST_EPSILON` above the current bucket's lowest.
+pub fn distinct_costs(costs: &[f64]) -> usize {
+    let mut sorted = costs.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let mut buckets = 0;
+    let mut bucket_start = f64::NEG_INFINITY;
+    for c in sorted {
+        if buckets == 0 || c > bucket_start + DISTINCT_COST_EPSILON {
+            buckets += 1;
+            bucket_start = c;
+        }
+    }
+    buckets
+}
+
+/// Everything a run of attempts has gathered so far: the best result, each attempt's cost
+/// and wall time, and the per-track stats accumulators.  `run_resumed` continues from it
+/// and `checkpoint` turns it into bytes.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RunState {
+    n: usize,
+    weighting: StatsWeighting,
+    best: Option<SaResult>,
+    attempt_costs: Vec<(f64, f64, f64, f64)>,
+    attempt_secs: Vec<f64>,
+    // Per-track accumulators (indexed by track index), empty until an attempt is counted
+    track_min: Vec<f64>,
+    track_max: Vec<f64>,
+    track_sum: Vec<f64>,
+    weight_sum: f64,
+    // Per-attempt per-track costs, kept only when the final best is needed to weight them
+    history: Vec<(f64, Vec<f64>)>,
+}
+
+impl RunState {
+    pub fn new(n: usize, weighting: StatsWeighting) -> Self {
+        RunState {
+            n,
+            weighting,
+            best: None,
+            attempt_costs: Vec::new(),
+            attempt_secs: Vec::new(),
+            track_min: Vec::new(),
+            track_max: Vec::new(),
+            track_sum: Vec::new(),
+            weight_sum: 0.0,
+            history: Vec::new(),
         }
+    }
 
-        let result = run_attempt(
-            n, bpms, key_ids, shift_table, direct_costs, indirect_costs,
-            cost_params, ann_params, &mut rng,
-        );
+    pub fn n(&self) -> usize {
+        self.n
+    }
+
+    pub fn weighting(&self) -> StatsWeighting {
+        self.weighting
+    }
+
+    /// Attempts run so far.
+    pub fn attempts(&self) -> usize {
+        self.attempt_costs.len()
+    }
+
+    /// Best result so far, `None` before the first attempt.
+    pub fn best(&self) -> Option<&SaResult> {
+        self.best.as_ref()
+    }
+
+    fn accumulate(&mut self, tc: &[f64], w: f64) {
+        if self.track_min.is_empty() {
+            self.track_min = vec![f64::INFINITY; self.n];
+            self.track_max = vec![f64::NEG_INFINITY; self.n];
+            self.track_sum = vec![0.0; self.n];
+        }
+        for (i, &c) in tc.iter().enumerate() {
+            if c < self.track_min[i] { self.track_min[i] = c; }
+            if c > self.track_max[i] { self.track_max[i] = c; }
+            self.track_sum[i] += w * c;
+        }
+        self.weight_sum += w;
+    }
+
+    /// Count one attempt, whose per-track costs are `tc`.  Returns the result it no longer
+    /// needs: the previous best when this one beats it, else this one (`None` for the first).
+    fn record(&mut self, result: SaResult, tc: &[f64], secs: f64) -> Option<SaResult> {
+        match self.weighting {
+            StatsWeighting::Uniform => self.accumulate(tc, 1.0),
+            _ => self.history.push((result.best_cost, tc.to_vec())),
+        }
+        self.attempt_secs.push(secs);
+        self.attempt_costs.push((result.best_cost, result.h_cost, result.t_cost, result.s_cost));
+        if self.best.as_ref().is_none_or(|prev| result.best_cost < prev.best_cost) {
+            self.best.replace(result)
+        } else {
+            Some(result)
+        }
+    }
+
+    /// The run's result so far, as `run_timed` returns it.
+    ///
+    /// # Panics
+    /// Before the first attempt.
+    pub fn result(&self) -> TimedRun {
+        let mut run = self.clone();
+        let global_best = run.best.take().expect("a run has at least one attempt");
+
+        // The best attempt always has weight 1, so at least one attempt is counted
+        for (cost, tc) in std::mem::take(&mut run.history) {
+            if let Some(w) = self.weighting.weight(cost, global_best.best_cost) {
+                run.accumulate(&tc, w);
+            }
+        }
+
+        let weight_sum = run.weight_sum;
+        let stats = PerTrackStats {
+            min: run.track_min,
+            max: run.track_max,
+            avg: run.track_sum.into_iter().map(|s| s / weight_sum).collect(),
+        };
+
+        (global_best, run.attempt_costs, stats, run.attempt_secs)
+    }
+}
+
+/// Run attempts (`attempt` on `scratch` and each attempt's RNG) into `state` while
+/// `keep_going(attempts so far)` holds, calling `after_attempt` after each.
+fn run_attempts(
+    tables: &Tables,
+    cost_params: &CostParams,
+    scratch: &mut Scratch,
+    attempt: impl Fn(&mut Scratch, &mut StdRng) -> SaResult,
+    rng: &mut impl Rng,
+    state: &mut RunState,
+    keep_going: impl Fn(usize) -> bool,
+    mut after_attempt: impl FnMut(&RunState),
+) -> Result<(), AttemptPanic> {
+    let n = state.n;
+    while keep_going(state.attempts()) {
+        let attempt_start = std::time::Instant::now();
+        let seed = rng.random::<u64>();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            attempt(scratch, &mut StdRng::seed_from_u64(seed))
+        }))
+        .map_err(|payload| AttemptPanic {
+            attempt: state.attempts(),
+            seed,
+            message: payload
+                .downcast_ref::<&str>()
+                .map(|m| m.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default(),
+        })?;
+        let secs = attempt_start.elapsed().as_secs_f64();
 
         // Per-track cost for this attempt
-        let tc = compute_per_track_costs(
-            &result.best_order, &result.best_shifts,
-            bpms, key_ids, shift_table, direct_costs, indirect_costs, cost_params,
-        );
-        for i in 0..n {
-            if tc[i] < track_min[i] { track_min[i] = tc[i]; }
-            if tc[i] > track_max[i] { track_max[i] = tc[i]; }
-            track_sum[i] += tc[i];
+        per_track_costs_into(&mut scratch.track_costs, n, &result.best_order, &result.best_shifts, tables, cost_params);
+        if let Some(spare) = state.record(result, &scratch.track_costs, secs) {
+            scratch.recycle(spare);
         }
+        after_attempt(state);
+    }
+    Ok(())
+}
 
-        attempt_costs.push((result.best_cost, result.h_cost, result.t_cost, result.s_cost));
+/// Upper limit on one component of the cost breakdown (unweighted, as in `SaResult`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CostCap {
+    /// Keep `t_cost` at or below the limit and minimise the rest.
+    Tempo(f64),
+    /// Keep `h_cost` at or below the limit and minimise the rest.
+    Harmonic(f64),
+}
 
-        match &global_best {
-            None => { global_best = Some(result); }
-            Some(prev) if result.best_cost < prev.best_cost => { global_best = Some(result); }
-            _ => {}
+impl CostCap {
+    /// How far `result` exceeds the cap (0 when it is met).
+    pub fn excess(&self, result: &SaResult) -> f64 {
+        match *self {
+            CostCap::Tempo(limit) => (result.t_cost - limit).max(0.0),
+            CostCap::Harmonic(limit) => (result.h_cost - limit).max(0.0),
         }
     }
+}
+
+/// Outcome of `run_capped`.
+pub struct CapOutcome {
+    /// Whether the returned order meets the cap; if not, it is the least-violating one found.
+    pub satisfied: bool,
+    /// Factor the capped component's weight was escalated by in the round that produced
+    /// the result (1 = the plain weighted objective already met the cap).
+    pub weight_factor: f64,
+}
+
+/// Everything `run_timed` returns.
+pub type TimedRun = (SaResult, Vec<(f64, f64, f64, f64)>, PerTrackStats, Vec<f64>);
+
+/// Rounds of weight escalation in `run_capped`; the capped component's weight grows by
+/// `CAP_ESCALATION` per round.
+const CAP_ROUNDS: usize = 6;
+const CAP_ESCALATION: f64 = 4.0;
+
+/// `run_timed` under a cap on one cost component: solve, check the cap, escalate the
+/// capped component's weight and repeat, for up to `CAP_ROUNDS` equal slices of the
+/// budget.  Once a round meets the cap, the rest of the budget refines at that weight.
+///
+/// Escalating the harmonic side divides the tempo and shift weights instead, since `h` has
+/// no weight of its own.  The returned `SaResult` is scored under the original
+/// `cost_params`; the attempt costs and per-track stats are those of the round that
+/// produced it, scored with that round's weights.
+pub fn run_capped(
+    n: usize,
+    tables: &Tables,
+    cost_params: &CostParams,
+    ann_params: &AnnealingParams,
+    separation: Option<&Separation>,
+    fixed_shifts: Option<&[i8]>,
+    weighting: StatsWeighting,
+    time_limit_secs: f64,
+    cap: CostCap,
+) -> Result<(TimedRun, CapOutcome), AttemptPanic> {
+    let start = std::time::Instant::now();
+    let original_cost = |r: &SaResult| -> f64 {
+        r.h_cost
+            + cost_params.tempo_cost_weight * r.t_cost
+            + cost_params.shift_weight * r.s_cost
+            + tables.boundary_cost(&r.best_order, &r.best_shifts, cost_params)
+            + separation.filter(|sep| sep.is_active()).map_or(0.0, |sep| sep.penalty(&r.best_order))
+            + cost_params.family_run.map_or(0.0, |fr| fr.penalty(&r.best_order, &r.best_shifts, tables))
+    };
+    // Rank by cap excess first, then by the original objective
+    let key = |r: &SaResult| (cap.excess(r), original_cost(r));
+    let solve = |factor: f64, budget: f64| {
+        let params = match cap {
+            CostCap::Tempo(_) => CostParams {
+                tempo_cost_weight: cost_params.tempo_cost_weight * factor,
+                ..*cost_params
+            },
+            CostCap::Harmonic(_) => CostParams {
+                tempo_cost_weight: cost_params.tempo_cost_weight / factor,
+                shift_weight: cost_params.shift_weight / factor,
+                ..*cost_params
+            },
+        };
+        run_timed(n, tables, &params, ann_params, separation, fixed_shifts, weighting, budget)
+    };
+    let remaining = || (time_limit_secs - start.elapsed().as_secs_f64()).max(0.0);
 
-    let n_att = attempt_costs.len() as f64;
-    let stats = PerTrackStats {
-        min: track_min,
-        max: track_max,
-        avg: track_sum.into_iter().map(|s| s / n_att).collect(),
+    let mut best: Option<(TimedRun, f64)> = None;
+    let mut keep = |run: TimedRun, factor: f64| {
+        if best.as_ref().is_none_or(|(b, _)| key(&run.0) < key(&b.0)) {
+            best = Some((run, factor));
+        }
+        best.as_ref().is_some_and(|(b, _)| cap.excess(&b.0) == 0.0)
     };
+    let mut factor = 1.0;
+    for round in 0..CAP_ROUNDS {
+        let met = keep(solve(factor, remaining() / (CAP_ROUNDS - round) as f64)?, factor);
+        if met {
+            // Spend what is left refining at the weight that met the cap
+            if remaining() > 0.0 {
+                keep(solve(factor, remaining())?, factor);
+            }
+            break;
+        }
+        factor *= CAP_ESCALATION;
+    }
+
+    let ((mut result, attempt_costs, stats, attempt_secs), weight_factor) = best.unwrap();
+    result.best_cost = original_cost(&result);
+    let satisfied = cap.excess(&result) == 0.0;
+    Ok(((result, attempt_costs, stats, attempt_secs), CapOutcome { satisfied, weight_factor }))
+}
+
+/// `run_timed` under each shifted-track cap k = 0..=max_k (any `cost_params.max_shifted` is
+/// replaced), with the time budget split evenly between the caps.  Every order allowed
+/// under k is allowed under k + 1, so when a run does worse than the previous cap's result
+/// that result is kept instead: costs are non-increasing in k.
+pub fn run_shift_sweep(
+    n: usize,
+    tables: &Tables,
+    cost_params: &CostParams,
+    ann_params: &AnnealingParams,
+    max_k: usize,
+    time_limit_secs: f64,
+) -> Result<Vec<SaResult>, AttemptPanic> {
+    let budget = time_limit_secs / (max_k + 1) as f64;
+    let mut results: Vec<SaResult> = Vec::with_capacity(max_k + 1);
+    for k in 0..=max_k {
+        let params = CostParams { max_shifted: Some(k), ..*cost_params };
+        let (best, _, _, _) =
+            run_timed(n, tables, &params, ann_params, None, None, StatsWeighting::Uniform, budget)?;
+        let result = match results.last() {
+            Some(prev) if prev.best_cost <= best.best_cost => SaResult {
+                best_order: prev.best_order.clone(),
+                best_shifts: prev.best_shifts.clone(),
+                violations: prev.violations.clone(),
+                ..*prev
+            },
+            _ => best,
+        };
+        results.push(result);
+    }
+    Ok(results)
+}
+
+/// One distinct solution of `run_pareto`.
+pub struct ParetoPoint {
+    /// Lowest `tempo_cost_weight` that produced this order and shifts.
+    pub tempo_cost_weight: f64,
+    /// Scored with that weight; `h_cost`/`t_cost`/`s_cost` give the trade-off itself.
+    pub result: SaResult,
+}
+
+/// Solve once per `tempo_cost_weight` in `weights` (any order, repeats ignored) to trace
+/// the harmonic vs tempo trade-off, sharing `tables` and splitting the time budget evenly.
+/// With `seeded` = `(seed, attempts)`, each weight instead runs `run_seeded` with that many
+/// attempts (seeded `seed + i` for the i-th smallest weight), so the sweep is reproducible.
+///
+/// Returned by increasing weight; a weight whose order and shifts match an earlier one's
+/// is dropped.
+pub fn run_pareto(
+    n: usize,
+    tables: &Tables,
+    cost_params: &CostParams,
+    ann_params: &AnnealingParams,
+    weights: &[f64],
+    time_limit_secs: f64,
+    seeded: Option<(u64, usize)>,
+) -> Result<Vec<ParetoPoint>, AttemptPanic> {
+    let mut weights = weights.to_vec();
+    weights.sort_by(f64::total_cmp);
+    weights.dedup();
+    let budget = time_limit_secs / weights.len().max(1) as f64;
+
+    let mut points: Vec<ParetoPoint> = Vec::with_capacity(weights.len());
+    for (i, &w) in weights.iter().enumerate() {
+        let params = CostParams { tempo_cost_weight: w, ..*cost_params };
+        let (best, _, _, _) = match seeded {
+            Some((seed, attempts)) => run_seeded(
+                n, tables, &params, ann_params, None, None, StatsWeighting::Uniform, attempts,
+                seed.wrapping_add(i as u64),
+            ),
+            None => run_timed(n, tables, &params, ann_params, None, None, StatsWeighting::Uniform, budget),
+        }?;
+        let seen = points
+            .iter()
+            .any(|p| p.result.best_order == best.best_order && p.result.best_shifts == best.best_shifts);
+        if !seen {
+            points.push(ParetoPoint { tempo_cost_weight: w, result: best });
+        }
+    }
+    Ok(points)
+}
+
+/// Result of optimising one segment of a multi-segment set.
+pub struct SegmentResult {
+    /// Original track indices in play order.
+    pub order: Vec<usize>,
+    /// Shift of each track in `order` (aligned with `order`).
+    pub shifts: Vec<i8>,
+    pub cost: f64,
+    pub h_cost: f64,
+    pub t_cost: f64,
+    pub s_cost: f64,
+    pub n_attempts: usize,
+}
+
+/// Optimise each segment (a list of original track indices) independently, as separate
+/// sub-playlists: edges across segment boundaries cost nothing.
+///
+/// The time budget is shared out in proportion to `len²` per segment, a rough proxy for
+/// how many swaps the annealer has to explore.  Every segment with at least two tracks gets
+/// at least one attempt; single-track segments are returned as-is with no shift.
+pub fn run_segments(
+    segments: &[Vec<usize>],
+    tables: &Tables,
+    cost_params: &CostParams,
+    ann_params: &AnnealingParams,
+    time_limit_secs: f64,
+) -> Result<Vec<SegmentResult>, AttemptPanic> {
+    let weight_total: f64 = segments.iter().map(|s| (s.len() * s.len()) as f64).sum();
+
+    segments
+        .iter()
+        .map(|seg| {
+            let m = seg.len();
+            if m < 2 {
+                return Ok(SegmentResult {
+                    order: seg.clone(),
+                    shifts: vec![0; m],
+                    cost: 0.0,
+                    h_cost: 0.0,
+                    t_cost: 0.0,
+                    s_cost: 0.0,
+                    n_attempts: 0,
+                });
+            }
+
+            let subset = Subset::new(tables, seg);
+            let sub = subset.tables(tables);
+            let budget = time_limit_secs * (m * m) as f64 / weight_total;
+            let (best, attempt_costs, _, _) = run_timed(
+                m, &sub, cost_params, ann_params, None, None, StatsWeighting::Uniform, budget,
+            )?;
 
-    (global_best.unwrap(), attempt_costs, stats)
+            Ok(SegmentResult {
+                order: best.best_order.iter().map(|&j| seg[j]).collect(),
+                shifts: best.best_order.iter().map(|&j| best.best_shifts[j]).collect(),
+                cost: best.best_cost,
+                h_cost: best.h_cost,
+                t_cost: best.t_cost,
+                s_cost: best.s_cost,
+                n_attempts: attempt_costs.len(),
+            })
+        })
+        .collect()
 }
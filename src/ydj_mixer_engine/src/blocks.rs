@@ -0,0 +1,237 @@
+//! Contiguous track blocks: pre-mixed sequences that must be played together, in order.
+//!
+//! Each block is contracted into a single node before optimisation.  The node is entered
+//! through the block's first member and left through its last, so its boundary BPMs and
+//! keys come from those two tracks.  The block's first and last members share the node's
+//! shift; interior shifts are optimised once here (per boundary shift) and their internal
+//! edges and shift penalties become the node's fixed `node_breakdown` cost.  The solvers
+//! then order the contracted nodes exactly as they would order tracks, and `expand` maps
+//! the result back to the original track indices.
+
+use crate::cost::{
+    edge_cost, total_edge_cost, AdjacencyBonus, CompatCosts, CostParams, StabilityPenalty, Tables,
+};
+
+const SHIFTS: [i8; 3] = [-1, 0, 1];
+
+pub struct Contraction {
+    /// Original track indices per node, in play order (singletons for ungrouped tracks).
+    pub members: Vec<Vec<usize>>,
+    bpms: Vec<i32>,
+    key_ids: Vec<u8>,
+    exit_bpms: Vec<i32>,
+    exit_key_ids: Vec<u8>,
+    node_breakdown: Vec<(f64, f64, f64)>,
+    /// Boundary members' key confidences, when the base tables carry them.
+    key_confidence: Option<(Vec<f64>, Vec<f64>)>,
+    /// Last member's blend scale, when the base tables carry them.
+    blend_scale: Option<Vec<f64>>,
+    /// Preferred pairs that meet across node boundaries (last member → first member);
+    /// pairs inside a block are already in its `node_breakdown`.
+    adjacency: Option<AdjacencyBonus>,
+    /// Compatibility costs from each node's last member to each node's first member.
+    compat: Option<CompatCosts>,
+    /// Reference successors across node boundaries; pairs inside a block are already in
+    /// its `node_breakdown`.
+    stability: Option<StabilityPenalty>,
+    /// Interior member shifts per node and boundary shift index (`shift + 1`).
+    interior_shifts: Vec<[Vec<i8>; 3]>,
+    /// Node containing each original track.
+    node_of: Vec<usize>,
+}
+
+impl Contraction {
+    /// Contract `groups` (ordered lists of track indices) over an `n`-track instance.
+    ///
+    /// Every track not listed in a group becomes a singleton node.  Empty or overlapping
+    /// groups and out-of-range indices are rejected.
+    pub fn new(
+        n: usize,
+        groups: &[Vec<usize>],
+        tables: &Tables,
+        params: &CostParams,
+    ) -> Result<Self, String> {
+        let mut node_of = vec![usize::MAX; n];
+        let mut members: Vec<Vec<usize>> = Vec::new();
+        for (g, group) in groups.iter().enumerate() {
+            if group.is_empty() {
+                return Err(format!("groups[{g}] is empty"));
+            }
+            for &t in group {
+                if t >= n {
+                    return Err(format!("groups[{g}] contains track {t}, but there are only {n} tracks"));
+                }
+                if node_of[t] != usize::MAX {
+                    return Err(format!("track {t} appears more than once in groups"));
+                }
+                node_of[t] = members.len();
+            }
+            members.push(group.clone());
+        }
+        for (t, node) in node_of.iter_mut().enumerate() {
+            if *node == usize::MAX {
+                *node = members.len();
+                members.push(vec![t]);
+            }
+        }
+
+        let mut scratch = vec![0i8; n];
+        let mut node_breakdown = Vec::with_capacity(members.len() * 3);
+        let mut interior_shifts = Vec::with_capacity(members.len());
+        for m in &members {
+            let mut per_shift: [Vec<i8>; 3] = Default::default();
+            for (s_idx, &s) in SHIFTS.iter().enumerate() {
+                let interior = best_interior_shifts(m, s, tables, params);
+                scratch[m[0]] = s;
+                scratch[m[m.len() - 1]] = s;
+                for (k, &si) in interior.iter().enumerate() {
+                    scratch[m[k + 1]] = si;
+                }
+                node_breakdown.push(total_edge_cost(m, &scratch, tables, params));
+                per_shift[s_idx] = interior;
+            }
+            interior_shifts.push(per_shift);
+        }
+
+        Ok(Contraction {
+            bpms: members.iter().map(|m| tables.bpms[m[0]]).collect(),
+            key_ids: members.iter().map(|m| tables.key_ids[m[0]]).collect(),
+            exit_bpms: members.iter().map(|m| tables.exit_bpms[m[m.len() - 1]]).collect(),
+            exit_key_ids: members.iter().map(|m| tables.exit_key_ids[m[m.len() - 1]]).collect(),
+            key_confidence: tables.key_confidence.zip(tables.exit_key_confidence).map(|(entry, exit)| {
+                (
+                    members.iter().map(|m| entry[m[0]]).collect(),
+                    members.iter().map(|m| exit[m[m.len() - 1]]).collect(),
+                )
+            }),
+            blend_scale: tables.blend_scale.map(|b| members.iter().map(|m| b[m[m.len() - 1]]).collect()),
+            adjacency: tables.adjacency.map(|adj| {
+                let boundary = adj.edges().filter_map(|(a, b, bonus)| {
+                    let (u, v) = (node_of[a], node_of[b]);
+                    let (mu, mv) = (&members[u], &members[v]);
+                    (u != v && mu[mu.len() - 1] == a && mv[0] == b).then_some((u, v, bonus))
+                });
+                AdjacencyBonus::from_edges(members.len(), boundary)
+            }),
+            compat: tables.compat.map(|c| {
+                let last: Vec<usize> = members.iter().map(|m| m[m.len() - 1]).collect();
+                let first: Vec<usize> = members.iter().map(|m| m[0]).collect();
+                c.select(&last, &first)
+            }),
+            stability: tables.stability.map(|st| {
+                let last: Vec<usize> = members.iter().map(|m| m[m.len() - 1]).collect();
+                let first: Vec<usize> = members.iter().map(|m| m[0]).collect();
+                st.select(&last, &first)
+            }),
+            members,
+            node_breakdown,
+            interior_shifts,
+            node_of,
+        })
+    }
+
+    /// Number of contracted nodes.
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Cost view over the contracted nodes, sharing the lookup tables of `base`.
+    pub fn tables<'a>(&'a self, base: &Tables<'a>) -> Tables<'a> {
+        Tables {
+            bpms: &self.bpms,
+            key_ids: &self.key_ids,
+            exit_bpms: &self.exit_bpms,
+            exit_key_ids: &self.exit_key_ids,
+            shift_table: base.shift_table,
+            direct_costs: base.direct_costs,
+            indirect_costs: base.indirect_costs,
+            harmonic_mask: base.harmonic_mask,
+            node_breakdown: Some(&self.node_breakdown),
+            anchors: base.anchors,
+            key_confidence: self.key_confidence.as_ref().map(|(entry, _)| entry.as_slice()),
+            exit_key_confidence: self.key_confidence.as_ref().map(|(_, exit)| exit.as_slice()),
+            blend_scale: self.blend_scale.as_deref(),
+            adjacency: self.adjacency.as_ref(),
+            stability: self.stability.as_ref(),
+            compat: self.compat.as_ref(),
+            sparse_costs: base.sparse_costs,
+        }
+    }
+
+    /// Map a node order and node shifts back to a track order and track-indexed shifts.
+    pub fn expand(&self, order: &[usize], shifts: &[i8]) -> (Vec<usize>, Vec<i8>) {
+        let n = self.node_of.len();
+        let mut track_order = Vec::with_capacity(n);
+        let mut track_shifts = vec![0i8; n];
+        for &u in order {
+            let m = &self.members[u];
+            let s = shifts[u];
+            track_shifts[m[0]] = s;
+            track_shifts[m[m.len() - 1]] = s;
+            for (k, &si) in self.interior_shifts[u][(s + 1) as usize].iter().enumerate() {
+                track_shifts[m[k + 1]] = si;
+            }
+            track_order.extend_from_slice(m);
+        }
+        (track_order, track_shifts)
+    }
+
+    /// Node whose block opens with `track`, if any.
+    pub fn node_starting_with(&self, track: usize) -> Option<usize> {
+        let u = self.node_of[track];
+        (self.members[u][0] == track).then_some(u)
+    }
+
+    /// Spread a node-indexed vector onto tracks (each member gets its block's value).
+    pub fn spread(&self, per_node: &[f64]) -> Vec<f64> {
+        self.node_of.iter().map(|&u| per_node[u]).collect()
+    }
+}
+
+/// Viterbi over the interior members of block `m` with both boundary members at shift `s`.
+/// Minimises internal edge costs plus weighted shift penalties; returns the interior shifts.
+fn best_interior_shifts(m: &[usize], s: i8, tables: &Tables, params: &CostParams) -> Vec<i8> {
+    let len = m.len();
+    if len <= 2 {
+        return Vec::new();
+    }
+    // cost[k][j]: best cost of the chain up to interior member k (position k + 1) at SHIFTS[j]
+    let mut cost = vec![[0.0f64; 3]; len - 2];
+    let mut back = vec![[0usize; 3]; len - 2];
+    for (j, &sj) in SHIFTS.iter().enumerate() {
+        cost[0][j] = edge_cost(m[0], m[1], s, sj, tables, params) + tables.node_cost(m[1], sj, params);
+    }
+    for k in 1..len - 2 {
+        for (j, &sj) in SHIFTS.iter().enumerate() {
+            let mut best = f64::INFINITY;
+            for (p, &sp) in SHIFTS.iter().enumerate() {
+                let c = cost[k - 1][p] + edge_cost(m[k], m[k + 1], sp, sj, tables, params);
+                if c < best {
+                    best = c;
+                    back[k][j] = p;
+                }
+            }
+            cost[k][j] = best + tables.node_cost(m[k + 1], sj, params);
+        }
+    }
+    let last = len - 3;
+    let mut j = 0;
+    let mut best = f64::INFINITY;
+    for (p, &sp) in SHIFTS.iter().enumerate() {
+        let c = cost[last][p] + edge_cost(m[len - 2], m[len - 1], sp, s, tables, params);
+        if c < best {
+            best = c;
+            j = p;
+        }
+    }
+    let mut out = vec![0i8; len - 2];
+    for k in (0..len - 2).rev() {
+        out[k] = SHIFTS[j];
+        j = back[k][j];
+    }
+    out
+}
@@ -0,0 +1,160 @@
+//! Estimating how long to anneal.  A few short attempts are run with their best cost
+//! recorded as they go; the mean best-so-far curve, as a gap to the best cost any of them
+//! reached, is read off where it gets within the target, or, when it never does, its tail
+//! is fitted with a power law gap ≈ a·i^(-b) and extrapolated.
+//!
+//! This is a rough model, not a guarantee.  A longer schedule cools more slowly, so it
+//! does not retrace the short curve; the best seen is only an estimate of the optimum; and
+//! a power law far beyond the calibration budget is a guess.  `BudgetEstimate::confidence`
+//! grades how far the estimate leans on the fit.
+
+use std::time::Instant;
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::annealing::{self, AnnealingParams};
+use crate::cost::{CostParams, Tables};
+use crate::separation::Separation;
+
+/// Points recorded per calibration attempt.
+const HISTORY_POINTS: usize = 100;
+
+/// The power law is fitted to the recorded points from this fraction of the calibration
+/// budget on, past the early plunge from the random start.
+const FIT_FROM: f64 = 0.25;
+
+/// Fits explaining less of the tail's variance than this (R²) get low confidence.
+const MIN_FIT_R2: f64 = 0.8;
+
+/// Extrapolating further than this multiple of the calibration budget gets low confidence.
+const MAX_EXTRAPOLATION: f64 = 10.0;
+
+/// What `estimate_budget` found.
+#[derive(Clone, Debug)]
+pub struct BudgetEstimate {
+    /// Iterations per attempt to bring the mean attempt within the target gap; `None`
+    /// when the curve shows no decay to extrapolate.
+    pub iterations: Option<usize>,
+    /// Wall time of one attempt of `iterations` on this machine, from the calibration's
+    /// time per iteration.
+    pub secs: Option<f64>,
+    /// Lowest cost any calibration attempt reached: the reference of the gap.
+    pub best_seen: f64,
+    /// Gap of the mean calibration attempt at the end of its schedule.
+    pub final_gap: f64,
+    /// Fitted decay exponent `b`, when the tail could be fitted.
+    pub decay_exponent: Option<f64>,
+    /// R² of the fit in log-log space.
+    pub fit_r2: Option<f64>,
+    /// Whether `iterations` comes from the fit rather than the recorded curve.
+    pub extrapolated: bool,
+    /// `iterations` as a multiple of the calibration budget.
+    pub extrapolation: f64,
+    /// Calibration attempts run and iterations in each.
+    pub attempts: usize,
+    pub calibration_iterations: usize,
+}
+
+impl BudgetEstimate {
+    /// "high" when read off the recorded curve, "medium" for a good fit extrapolated at
+    /// most `MAX_EXTRAPOLATION`-fold, "low" otherwise.
+    pub fn confidence(&self) -> &'static str {
+        match (self.iterations, self.fit_r2) {
+            (None, _) => "low",
+            (Some(_), _) if !self.extrapolated => "high",
+            (Some(_), Some(r2)) if r2 >= MIN_FIT_R2 && self.extrapolation <= MAX_EXTRAPOLATION => "medium",
+            _ => "low",
+        }
+    }
+}
+
+/// Run `attempts` (at least 1) attempts of `ann_params` from an RNG seeded with `seed`
+/// and estimate the iterations per attempt needed for the mean attempt to come within
+/// `target_gap` (relative, e.g. 0.01 = 1%) of the best cost seen.
+pub fn estimate_budget(
+    n: usize,
+    tables: &Tables,
+    cost_params: &CostParams,
+    ann_params: &AnnealingParams,
+    separation: Option<&Separation>,
+    attempts: usize,
+    target_gap: f64,
+    seed: u64,
+) -> Result<BudgetEstimate, String> {
+    if !(target_gap.is_finite() && target_gap > 0.0) {
+        return Err(format!("target_gap must be a finite value > 0, got {target_gap}"));
+    }
+    ann_params.validate()?;
+    let attempts = attempts.max(1);
+    let total = ann_params.total_iterations;
+    let every = (total / HISTORY_POINTS).max(1);
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let start = Instant::now();
+    let histories: Vec<Vec<(usize, f64)>> = (0..attempts)
+        .map(|_| annealing::run_attempt_recorded(n, tables, cost_params, ann_params, separation, None, every, &mut rng).1)
+        .collect();
+    let secs_per_iteration = start.elapsed().as_secs_f64() / (attempts * total) as f64;
+
+    let best_seen = histories.iter().filter_map(|h| h.last()).map(|&(_, c)| c).fold(f64::INFINITY, f64::min);
+    let scale = best_seen.abs().max(1e-9);
+    // Every attempt records the same iterations, so the curves average point by point
+    let curve: Vec<(usize, f64)> = (0..histories[0].len())
+        .map(|k| {
+            let mean = histories.iter().map(|h| h[k].1).sum::<f64>() / attempts as f64;
+            (histories[0][k].0, (mean - best_seen) / scale)
+        })
+        .collect();
+    let final_gap = curve.last().map_or(0.0, |&(_, g)| g);
+
+    let fit = fit_power_law(&curve, total);
+    let recorded = curve.iter().find(|&&(_, g)| g <= target_gap).map(|&(i, _)| i.max(1));
+    let iterations = recorded.or_else(|| {
+        let (a, b, _) = fit?;
+        let needed = (a / target_gap).powf(1.0 / b).max(total as f64);
+        Some(needed.min(usize::MAX as f64) as usize)
+    });
+
+    Ok(BudgetEstimate {
+        iterations,
+        secs: iterations.map(|i| i as f64 * secs_per_iteration),
+        best_seen,
+        final_gap,
+        decay_exponent: fit.map(|(_, b, _)| b),
+        fit_r2: fit.map(|(_, _, r2)| r2),
+        extrapolated: recorded.is_none(),
+        extrapolation: iterations.map_or(f64::INFINITY, |i| i as f64 / total as f64),
+        attempts,
+        calibration_iterations: total,
+    })
+}
+
+/// Least-squares fit of gap = a·i^(-b) to the points of `curve` from `FIT_FROM` of `total`
+/// on with a positive gap, in log-log space: `(a, b, R²)`.  `None` with fewer than three
+/// such points or when the gap does not decay (b <= 0).
+fn fit_power_law(curve: &[(usize, f64)], total: usize) -> Option<(f64, f64, f64)> {
+    let from = (total as f64 * FIT_FROM) as usize;
+    let points: Vec<(f64, f64)> = curve
+        .iter()
+        .filter(|&&(i, g)| i >= from.max(1) && g > 0.0)
+        .map(|&(i, g)| ((i as f64).ln(), g.ln()))
+        .collect();
+    if points.len() < 3 {
+        return None;
+    }
+    let m = points.len() as f64;
+    let (mx, my) = points.iter().fold((0.0, 0.0), |(sx, sy), &(x, y)| (sx + x / m, sy + y / m));
+    let sxx: f64 = points.iter().map(|&(x, _)| (x - mx).powi(2)).sum();
+    let sxy: f64 = points.iter().map(|&(x, y)| (x - mx) * (y - my)).sum();
+    let syy: f64 = points.iter().map(|&(_, y)| (y - my).powi(2)).sum();
+    if sxx <= 0.0 {
+        return None;
+    }
+    let slope = sxy / sxx;
+    if slope >= 0.0 {
+        return None;
+    }
+    let r2 = if syy > 0.0 { sxy * sxy / (sxx * syy) } else { 1.0 };
+    Some(((my - slope * mx).exp(), -slope, r2))
+}
@@ -0,0 +1,69 @@
+//! Checkpoints of long annealing runs, so a crash or restart does not lose the attempts
+//! already made.
+//!
+//! A checkpoint is a `RunState` (best order and shifts, every attempt's cost, the per-track
+//! stats accumulators) serialised as JSON behind a format tag and a version number.  The
+//! version changes whenever the layout does, and `load` rejects any other version rather
+//! than misreading it.  A checkpoint records the number of nodes and the stats weighting
+//! but not the instance itself: resuming is only meaningful with the inputs of the run that
+//! saved it.
+
+use serde::{Deserialize, Serialize};
+
+use crate::annealing::{RunState, StatsWeighting};
+
+const FORMAT: &str = "ydj-mixer-checkpoint";
+
+/// Version of the checkpoint layout written by `save`.
+pub const VERSION: u32 = 3;
+
+#[derive(Serialize)]
+struct Saved<'a> {
+    format: &'a str,
+    version: u32,
+    state: &'a RunState,
+}
+
+/// Format tag and version, read before trusting the rest.
+#[derive(Deserialize)]
+struct Header {
+    format: String,
+    version: u32,
+}
+
+#[derive(Deserialize)]
+struct Loaded {
+    state: RunState,
+}
+
+/// Serialise `state`.
+pub fn save(state: &RunState) -> Vec<u8> {
+    serde_json::to_vec(&Saved { format: FORMAT, version: VERSION, state })
+        .expect("run states hold only finite numbers and plain containers")
+}
+
+/// Restore a `RunState` saved by `save`, checking that it is a checkpoint of this version
+/// for `n` nodes under `weighting`.
+pub fn load(bytes: &[u8], n: usize, weighting: StatsWeighting) -> Result<RunState, String> {
+    let header: Header = serde_json::from_slice(bytes).map_err(|e| format!("not a checkpoint: {e}"))?;
+    if header.format != FORMAT {
+        return Err(format!("not a checkpoint: format {:?}", header.format));
+    }
+    if header.version != VERSION {
+        return Err(format!(
+            "checkpoint version {} cannot be loaded by this version ({VERSION})",
+            header.version
+        ));
+    }
+    let Loaded { state } = serde_json::from_slice(bytes).map_err(|e| format!("corrupt checkpoint: {e}"))?;
+    if state.n() != n {
+        return Err(format!("checkpoint is for {} tracks, not {n}", state.n()));
+    }
+    if state.weighting() != weighting {
+        return Err(format!(
+            "checkpoint was saved with stats weighting {:?}, not {weighting:?}",
+            state.weighting()
+        ));
+    }
+    Ok(state)
+}
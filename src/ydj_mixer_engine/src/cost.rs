@@ -1,112 +1,1532 @@
+use crate::family::FamilyRunLimit;
+
 /// Edge cost between two tracks using precomputed flat integer tables.
 ///
 /// Mirrors Python's `_fast_edge_cost`:
-///   - If |bpm1 - bpm2| > tempo_break_threshold: return tempo_cost_weight * tempo_penalty * tempo_break_factor
+///   - If |bpm1 - bpm2| > tempo_break_threshold (a log-ratio with `perceptual_tempo`, see
+///     `CostParams::tempo_diff`): return tempo_cost_weight * tempo_penalty * tempo_break_factor
+///     (plus the harmonic cost when `harmonic_across_breaks` is set)
+///   - Over tempo_threshold but not a break, the tempo charge is tempo_penalty plus
+///     tempo_penalty_slope per BPM of overshoot (a plain step with the default slope of 0).
+///   - With `harmonic_only`, skip the BPMs altogether and return the harmonic cost alone.
 ///   - Otherwise: look up effective keys via shift_table, then harmonic cost via direct_costs / indirect_costs.
 pub struct CostParams {
     pub tempo_threshold: f64,
     pub tempo_penalty: f64,
     pub tempo_break_factor: f64,
+    /// Extra tempo cost per BPM (unit of `tempo_diff`) over `tempo_threshold`, up to the
+    /// break point; 0 keeps the flat `tempo_penalty` step, while a slope gives the annealer
+    /// a gradient to follow.
+    pub tempo_penalty_slope: f64,
     pub tempo_cost_weight: f64,
     pub non_harmonic_cost: f64,
     pub shift_penalty: f64,
+    /// Penalties `(down, up)` of a -1 and a +1 shift when they differ (e.g. a semitone down
+    /// sounds more natural than one up); `None` charges `shift_penalty` for either.
+    pub shift_penalty_by_direction: Option<(f64, f64)>,
     pub shift_weight: f64,
-    pub num_keys: usize, // 24
+    pub num_keys: usize, // 24 for Camelot keys
+    /// Add the harmonic cost on top of the break penalty for tempo-break edges, instead of
+    /// skipping the key lookup (the clash is still audible when crossfading across a break).
+    pub harmonic_across_breaks: bool,
+    /// Ignore tempo entirely: no BPM comparison, no break, every edge charges its harmonic
+    /// cost and `t` is always 0 (e.g. acapella sets mixed purely by key).
+    pub harmonic_only: bool,
+    /// Compare tempos by ratio rather than difference (see `tempo_diff`): a 4 BPM jump
+    /// counts for more at 90 BPM than at 170, as it is heard.
+    pub perceptual_tempo: bool,
+    /// Combine the two endpoints' key confidences by product instead of minimum (see
+    /// `Tables::key_confidence`).
+    pub confidence_product: bool,
+    /// Fixed cost of a hard cut instead of a blend; `None` disables cuts.  Each edge takes
+    /// whichever of the two is cheaper (see `cut_or_blend`).
+    pub cut_penalty: Option<f64>,
+    /// Fraction of the harmonic cost waived by a cut, in [0, 1].
+    pub cut_harmonic_discount: f64,
+    /// How the components combine into the objective (see `resolve_objective`).
+    pub objective_mode: ObjectiveMode,
+    /// Weighted edge cost below which an edge counts as perfect (see
+    /// `ObjectiveMode::PerfectTransitions`).
+    pub perfect_threshold: f64,
+    /// Limit on consecutive tracks of one key family (see `family`); `None` disables it.
+    pub family_run: Option<FamilyRunLimit>,
+    /// Most tracks played at a nonzero shift; `None` leaves it unlimited.  A hard limit for
+    /// Held-Karp and the annealer alike, except on shifts the caller fixes.
+    pub max_shifted: Option<usize>,
+    /// Budget for the weighted shift cost (`shift_weight` × the `s` component); `None`
+    /// leaves it unlimited.  Annealer only: a move whose shifts would overrun it is
+    /// rejected.  Held-Karp ignores it, as do shifts the caller fixes.
+    pub max_shift_cost: Option<f64>,
+    /// Weight λ in [0, 1] of the costliest edge: the annealer minimises (1 - λ) × the usual
+    /// objective + λ × the largest weighted edge cost, trading overall smoothness for
+    /// avoiding a single bad transition.  0 (the plain sum) by default; the other solvers
+    /// ignore it.
+    pub minmax_blend: f64,
+}
+
+/// How the harmonic, tempo and shift components combine into the objective.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ObjectiveMode {
+    /// `h + tempo_cost_weight * t + shift_weight * s`.
+    #[default]
+    Weighted,
+    /// Minimise `h` first; among equal `h`, minimise the weighted tempo and shift cost.
+    LexicographicHThenT,
+    /// Minimise the number of imperfect edges: each edge contributes 0 to `h` if its
+    /// weighted cost is below `perfect_threshold` and 1 otherwise, with no tempo part.
+    /// Among equal counts, fewer shifts win.
+    PerfectTransitions,
 }
 
+/// Tempo at which a `perceptual_tempo` difference equals the BPM difference (to first
+/// order): 4 BPM at 120 counts as 4, at 90 as about 5.2 and at 170 as about 2.8.
+pub const PERCEPTUAL_REFERENCE_BPM: f64 = 120.0;
+
+/// Cost substituted for `+inf` table entries when infinity-as-forbidden semantics are enabled.
+/// Large but finite, so the SA delta arithmetic (`new - old`) never produces NaN.
+pub const FORBIDDEN_COST: f64 = 1e9;
+
 impl CostParams {
     pub fn tempo_break_threshold(&self) -> f64 {
         self.tempo_break_factor * self.tempo_threshold
     }
+
+    /// Tempo difference of a transition, the value compared with `tempo_threshold` and the
+    /// break threshold: `|from - to|` BPM, or with `perceptual_tempo` the log-ratio
+    /// `PERCEPTUAL_REFERENCE_BPM * |ln from - ln to|`, which is the BPM difference of the
+    /// same ratio at the reference tempo, so the thresholds keep their BPM meaning there.
+    #[inline(always)]
+    pub fn tempo_diff(&self, from_bpm: i32, to_bpm: i32) -> f64 {
+        if self.perceptual_tempo {
+            let ln = |bpm: i32| f64::from(bpm.max(1)).ln();
+            PERCEPTUAL_REFERENCE_BPM * (ln(from_bpm) - ln(to_bpm)).abs()
+        } else {
+            // Widened so no i32 input can overflow the subtraction
+            (from_bpm as i64 - to_bpm as i64).unsigned_abs() as f64
+        }
+    }
+
+    /// Reject settings that make the cost model incoherent: a negative or non-finite
+    /// threshold, penalty or weight, or a `tempo_break_factor` below 1, whose break
+    /// threshold would sit under `tempo_threshold` so a gap could be a break without being
+    /// over the threshold.  The error names the offending key.
+    pub fn validate(&self) -> Result<(), String> {
+        for (key, value) in [
+            ("tempo_threshold", self.tempo_threshold),
+            ("tempo_penalty", self.tempo_penalty),
+            ("tempo_cost_weight", self.tempo_cost_weight),
+            ("non_harmonic_cost", self.non_harmonic_cost),
+            ("shift_penalty", self.shift_penalty),
+            ("shift_weight", self.shift_weight),
+        ] {
+            if !(value.is_finite() && value >= 0.0) {
+                return Err(format!("{key} must be a finite value >= 0, got {value}"));
+            }
+        }
+        if let Some((down, up)) = self.shift_penalty_by_direction {
+            if !(down.is_finite() && down >= 0.0 && up.is_finite() && up >= 0.0) {
+                return Err(format!("shift_penalty must be a finite value >= 0 in each direction, got {down} (-1) and {up} (+1)"));
+            }
+        }
+        if !(0.0..=1.0).contains(&self.minmax_blend) {
+            return Err(format!("minmax_blend must be between 0 and 1, got {}", self.minmax_blend));
+        }
+        if !(self.tempo_break_factor.is_finite() && self.tempo_break_factor >= 1.0) {
+            return Err(format!(
+                "tempo_break_factor must be a finite value >= 1 (a break is a gap over tempo_threshold \
+                 times this factor), got {}",
+                self.tempo_break_factor
+            ));
+        }
+        Ok(())
+    }
+
+    /// Unweighted penalty of a plain track at shift `s`: 0 unshifted, otherwise
+    /// `shift_penalty` or the penalty of its direction.
+    #[inline(always)]
+    pub fn shift_penalty_of(&self, s: i8) -> f64 {
+        match (s, self.shift_penalty_by_direction) {
+            (0, _) => 0.0,
+            (_, None) => self.shift_penalty,
+            (s, Some((down, up))) => if s < 0 { down } else { up },
+        }
+    }
+
+    /// Shift component of plain tracks at `shifts`, unweighted and times `shift_weight`:
+    /// the penalty of each track's shift, as the optimizers count it.  Any shift outside
+    /// -1..=1 is an error naming its index.
+    pub fn shift_penalty_cost(&self, shifts: &[i8]) -> Result<(f64, f64), String> {
+        if let Some(i) = shifts.iter().position(|s| !(-1..=1).contains(s)) {
+            return Err(format!("shifts[{i}] is {}, shifts must be -1, 0 or +1", shifts[i]));
+        }
+        let s: f64 = shifts.iter().map(|&s| self.shift_penalty_of(s)).sum();
+        Ok((s, self.shift_weight * s))
+    }
+
+    /// Unweighted tempo charge of a BPM difference that is over the threshold but not a
+    /// break.
+    #[inline(always)]
+    pub fn over_threshold_penalty(&self, diff: f64) -> f64 {
+        self.tempo_penalty + self.tempo_penalty_slope * (diff - self.tempo_threshold)
+    }
+
+    /// Rewrite a lexicographic objective over `n` nodes of `tables` as the equivalent
+    /// weighted one, so every solver runs unchanged; returns the scale `K` (1 if already
+    /// weighted).
+    ///
+    /// `K` is the smallest power of two above any possible tempo + shift total, and the
+    /// tempo and shift weights are divided by it: the objective becomes
+    /// `h + (weighted t + s) / K`, ordered exactly like `K * h + (weighted t + s)`.  Dividing
+    /// by a power of two is exact and cannot overflow, where multiplying `h` could.  Orders
+    /// whose harmonic totals differ by at least 1 are never traded for tempo.
+    ///
+    /// `PerfectTransitions` keeps its mode (the edges are mapped by `objective_edge`) and
+    /// only divides the shift weight by the `K` above the shift total, so shifts break ties
+    /// between equal counts without ever costing a perfect edge.
+    pub fn resolve_objective(&mut self, n: usize, tables: &Tables) -> Result<f64, String> {
+        if self.objective_mode == ObjectiveMode::Weighted {
+            return Ok(1.0);
+        }
+        let perfect = self.objective_mode == ObjectiveMode::PerfectTransitions;
+        // Every edge (plus the two anchor edges) at its worst tempo charge, and every node at
+        // its worst shift (contracted blocks include their internal edges here)
+        let over = self.tempo_penalty.abs()
+            + self.tempo_penalty_slope.abs() * (self.tempo_break_threshold() - self.tempo_threshold).max(0.0);
+        let edge_t = (self.tempo_penalty.abs() * self.tempo_break_factor.abs().max(1.0)).max(over);
+        let mut bound = if perfect || self.harmonic_only { 0.0 } else { (n + 1) as f64 * self.tempo_cost_weight.abs() * edge_t };
+        for i in 0..n {
+            bound += [-1, 0, 1]
+                .iter()
+                .map(|&s| {
+                    let (_, t, sp) = tables.node_components(i, s, self);
+                    let t = if perfect { 0.0 } else { t };
+                    self.tempo_cost_weight.abs() * t.abs() + self.shift_weight.abs() * sp.abs()
+                })
+                .fold(0.0, f64::max);
+        }
+        let mode = if perfect { "perfect-transitions" } else { "lexicographic" };
+        let too_large = || format!("tempo and shift costs up to {bound} are too large for a {mode} objective");
+        if !bound.is_finite() {
+            return Err(too_large());
+        }
+        let mut scale = 1.0f64;
+        while scale <= bound {
+            scale *= 2.0;
+            if !scale.is_finite() {
+                return Err(too_large());
+            }
+        }
+        if !perfect {
+            self.tempo_cost_weight /= scale;
+            self.objective_mode = ObjectiveMode::Weighted;
+        }
+        self.shift_weight /= scale;
+        self.max_shift_cost = self.max_shift_cost.map(|c| c / scale);
+        Ok(scale)
+    }
+
+    /// Whether an edge of weighted cost `cost` counts as perfect.
+    #[inline(always)]
+    pub fn is_perfect(&self, cost: f64) -> bool {
+        cost < self.perfect_threshold
+    }
+
+    /// Edge components `(h, t)` as the objective sees them: unchanged, or under
+    /// `PerfectTransitions` `(0, 0)` for a perfect edge and `(1, 0)` otherwise.
+    #[inline(always)]
+    pub fn objective_edge(&self, h: f64, t: f64) -> (f64, f64) {
+        if self.objective_mode == ObjectiveMode::PerfectTransitions {
+            (if self.is_perfect(h + self.tempo_cost_weight * t) { 0.0 } else { 1.0 }, 0.0)
+        } else {
+            (h, t)
+        }
+    }
+
+    /// Weight on the harmonic cost of an edge whose endpoints have key confidences `a`, `b`.
+    #[inline(always)]
+    pub fn confidence_weight(&self, a: f64, b: f64) -> f64 {
+        if self.confidence_product { a * b } else { a.min(b) }
+    }
+
+    /// Harmonic cost `h` of an edge played as the cheaper of a blend (`h`) and a cut
+    /// (`h * (1 - cut_harmonic_discount) + cut_penalty`), and whether it is a cut.
+    #[inline(always)]
+    pub fn cut_or_blend(&self, h: f64) -> (f64, bool) {
+        match self.cut_penalty {
+            Some(penalty) => {
+                let cut = h * (1.0 - self.cut_harmonic_discount) + penalty;
+                if cut < h { (cut, true) } else { (h, false) }
+            }
+            None => (h, false),
+        }
+    }
+}
+
+/// Camelot relationship from one effective key to the next, under the names DJs use.  The
+/// built-in tables (`camelot_key_costs`) rank them: a perfect match costs 0, ±1 and the
+/// relative key 1, ±2 and the energy boost 2, and a clash `non_harmonic_cost`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyRelation {
+    PerfectMatch,
+    /// One step clockwise round the wheel (8A → 9A).
+    EnergyUp,
+    /// One step anticlockwise (8A → 7A).
+    MoodDown,
+    /// Same number, other letter (8A → 8B).
+    Relative,
+    EnergyUpTwo,
+    MoodDownTwo,
+    /// One semitone up, seven steps clockwise (8A → 3A).
+    EnergyBoost,
+    Clash,
+}
+
+impl KeyRelation {
+    /// Relationship between two Camelot key IDs (0..24, id = (number - 1) × 2 + letter).
+    pub fn classify(from: usize, to: usize) -> Self {
+        let up = (to / 2 + 12 - from / 2) % 12;
+        match (from % 2 == to % 2, up) {
+            (true, 0) => KeyRelation::PerfectMatch,
+            (true, 1) => KeyRelation::EnergyUp,
+            (true, 11) => KeyRelation::MoodDown,
+            (false, 0) => KeyRelation::Relative,
+            (true, 2) => KeyRelation::EnergyUpTwo,
+            (true, 10) => KeyRelation::MoodDownTwo,
+            (true, 7) => KeyRelation::EnergyBoost,
+            _ => KeyRelation::Clash,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            KeyRelation::PerfectMatch => "perfect match",
+            KeyRelation::EnergyUp => "+1 energy",
+            KeyRelation::MoodDown => "-1 mood",
+            KeyRelation::Relative => "relative major/minor",
+            KeyRelation::EnergyUpTwo => "+2 energy",
+            KeyRelation::MoodDownTwo => "-2 mood",
+            KeyRelation::EnergyBoost => "energy boost",
+            KeyRelation::Clash => "clash",
+        }
+    }
+}
+
+/// `KeyRelation::classify` for effective keys of a `num_keys` system; only the 24-key
+/// Camelot system has named relationships.
+pub fn classify_transition(ek1: usize, ek2: usize, num_keys: usize) -> Result<KeyRelation, String> {
+    if num_keys != 24 {
+        return Err(format!("key relationships are defined for the 24 Camelot keys, got num_keys = {num_keys}"));
+    }
+    if let Some(k) = [ek1, ek2].into_iter().find(|&k| k >= num_keys) {
+        return Err(format!("effective key {k} is out of range (0-23)"));
+    }
+    Ok(KeyRelation::classify(ek1, ek2))
+}
+
+/// Split a wheel key ("8A", "12d") into its number (1-12) and the index of its letter in
+/// `letters`, ignoring case, surrounding spaces and leading zeros.
+fn parse_wheel_key(key: &str, letters: [char; 2]) -> Option<(u8, u8)> {
+    let key = key.trim();
+    let (split, letter) = key.char_indices().last()?;
+    let letter = letters.iter().position(|&l| l.eq_ignore_ascii_case(&letter))? as u8;
+    let number = &key[..split];
+    if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    match number.parse::<u8>() {
+        Ok(number @ 1..=12) => Some((number, letter)),
+        _ => None,
+    }
+}
+
+/// Parse a Camelot key string ("8A", "12b", "05A") into its key ID, (number - 1) × 2 +
+/// letter with A (minor) = 0 and B (major) = 1, so 1A = 0, 1B = 1, ..., 12B = 23.  This is
+/// the one mapping the engine uses; `format_camelot_key` is its inverse, and `parse_key`
+/// reads the other notations into the same IDs.
+pub fn parse_camelot_key(key: &str) -> Result<u8, String> {
+    parse_wheel_key(key, ['A', 'B'])
+        .map(|(number, letter)| (number - 1) * 2 + letter)
+        .ok_or_else(|| format!("invalid Camelot key {:?} (expected 1A-12B)", key.trim()))
+}
+
+/// Canonical Camelot string ("8A") of a key ID in 0..24, the inverse of `parse_camelot_key`.
+pub fn format_camelot_key(id: u8) -> Result<String, String> {
+    if id >= 24 {
+        return Err(format!("key ID {id} is out of range for Camelot keys (0-23)"));
+    }
+    Ok(format!("{}{}", id / 2 + 1, ['A', 'B'][usize::from(id % 2)]))
+}
+
+/// How key strings are written.  Open Key (Traktor) numbers the same wheel from C major
+/// = 1d, seven places on from Camelot (8B), with m for minor and d for major; musical
+/// names give the root and mode ("Am", "F#maj").
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyNotation {
+    Camelot,
+    OpenKey,
+    Musical,
+}
+
+impl KeyNotation {
+    /// "camelot", "open_key" or "musical".
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "camelot" => Ok(KeyNotation::Camelot),
+            "open_key" => Ok(KeyNotation::OpenKey),
+            "musical" => Ok(KeyNotation::Musical),
+            _ => Err(format!("unknown key notation {name:?} (expected camelot, open_key or musical)")),
+        }
+    }
+}
+
+/// Musical names by Camelot number, minor (A) and major (B), as DJ software spells them.
+const MINOR_NAMES: [&str; 12] = ["Abm", "Ebm", "Bbm", "Fm", "Cm", "Gm", "Dm", "Am", "Em", "Bm", "F#m", "Dbm"];
+const MAJOR_NAMES: [&str; 12] = ["B", "F#", "Db", "Ab", "Eb", "Bb", "F", "C", "G", "D", "A", "E"];
+
+/// Key ID of a musical key name: a root A-G, an optional # or b (♯/♭), and a mode of m,
+/// min or minor, or none, maj or major (case-insensitive, a space allowed before it).
+fn parse_musical_key(key: &str) -> Option<u8> {
+    let mut chars = key.trim().chars();
+    let root = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let (pitch, rest) = match rest.chars().next() {
+        Some(c @ ('#' | '♯')) => (root + 1, &rest[c.len_utf8()..]),
+        Some(c @ ('b' | '♭')) => (root + 11, &rest[c.len_utf8()..]),
+        _ => (root, rest),
+    };
+    let minor = match rest.trim_start().to_ascii_lowercase().as_str() {
+        "" | "maj" | "major" => false,
+        "m" | "min" | "minor" => true,
+        _ => return None,
+    };
+    // A fifth up (7 semitones) is one step clockwise; Am and C are 8A and 8B
+    let tonic = if minor { 9 } else { 0 };
+    let steps = (pitch % 12 + 12 - tonic) * 7 % 12;
+    let number = (7 + steps) % 12 + 1;
+    Some((number as u8 - 1) * 2 + u8::from(!minor))
+}
+
+/// Key ID of `key` written in `notation`.  Musical names are read in any notation, since
+/// they start with a letter where the wheels start with a digit.
+pub fn parse_key(key: &str, notation: KeyNotation) -> Result<u8, String> {
+    let musical = key.trim_start().starts_with(|c: char| c.is_ascii_alphabetic());
+    if musical || notation == KeyNotation::Musical {
+        return parse_musical_key(key)
+            .ok_or_else(|| format!("invalid musical key {:?} (expected e.g. Am, F#maj, Bb minor)", key.trim()));
+    }
+    match notation {
+        KeyNotation::Camelot => parse_camelot_key(key),
+        _ => parse_wheel_key(key, ['m', 'd'])
+            .map(|(number, letter)| (number + 6) % 12 * 2 + letter)
+            .ok_or_else(|| format!("invalid Open Key {:?} (expected 1d-12m)", key.trim())),
+    }
+}
+
+/// Key ID `id` (0..24) written in `notation`: "8A", "1m" or "Am".
+pub fn format_key(id: u8, notation: KeyNotation) -> Result<String, String> {
+    let camelot = format_camelot_key(id)?;
+    let number = usize::from(id / 2);
+    let major = id % 2 == 1;
+    Ok(match notation {
+        KeyNotation::Camelot => camelot,
+        KeyNotation::OpenKey => format!("{}{}", (number + 5) % 12 + 1, if major { 'd' } else { 'm' }),
+        KeyNotation::Musical => (if major { MAJOR_NAMES } else { MINOR_NAMES })[number].to_string(),
+    })
+}
+
+/// Camelot `shift_table` (24 × 3 entries): one semitone moves 7 positions round the wheel
+/// and keeps the letter.
+pub fn camelot_shift_table() -> Vec<u8> {
+    let mut table = Vec::with_capacity(24 * 3);
+    for k in 0..24i32 {
+        for s in -1..=1 {
+            table.push(((k / 2 + 7 * s).rem_euclid(12) * 2 + k % 2) as u8);
+        }
+    }
+    table
+}
+
+/// Key id that base key `key` plays in at shift `s` (-1, 0 or +1), from `shift_table`
+/// (`shift_table[key * 3 + s + 1]`).
+#[inline(always)]
+pub fn effective_key(shift_table: &[u8], key: u8, s: i8) -> usize {
+    shift_table[key as usize * 3 + (s + 1) as usize] as usize
+}
+
+/// The mix as DJ software plays it: `(track, effective key id, shift in semitones)` for
+/// each entry of `order`, in play order.  A shift of ±1 is one semitone.  `order` may be a
+/// subset of the tracks or repeat them; `shifts` is indexed by track, like `key_ids`.
+pub fn export_schedule(
+    order: &[usize],
+    shifts: &[i8],
+    key_ids: &[u8],
+    shift_table: &[u8],
+) -> Result<Vec<(usize, usize, i8)>, String> {
+    let n = key_ids.len();
+    if shifts.len() != n {
+        return Err(format!("shifts has {} entries, expected {n}", shifts.len()));
+    }
+    if shift_table.is_empty() || !shift_table.len().is_multiple_of(3) {
+        return Err(format!("shift_table has {} entries, expected 3 per key", shift_table.len()));
+    }
+    let num_keys = shift_table.len() / 3;
+    order
+        .iter()
+        .map(|&t| {
+            let Some(&key) = key_ids.get(t) else {
+                return Err(format!("order: track {t} is out of range (0-{})", n.saturating_sub(1)));
+            };
+            if key as usize >= num_keys {
+                return Err(format!("base_key_ids[{t}] is {key}, but shift_table covers {num_keys} keys"));
+            }
+            if !(-1..=1).contains(&shifts[t]) {
+                return Err(format!("shifts[{t}] is {}, expected -1, 0 or +1", shifts[t]));
+            }
+            Ok((t, effective_key(shift_table, key, shifts[t]), shifts[t]))
+        })
+        .collect()
 }
 
-/// Compute the combined edge cost (harmonic + weighted tempo) between positions i1 and i2.
+/// Cost of each kind of Camelot transition (see `KeyRelation`) for `camelot_tables`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyCostRules {
+    pub same_key: f64,
+    /// ±1 round the wheel (8A → 7A, 9A).
+    pub adjacent: f64,
+    /// Relative major/minor (8A → 8B).
+    pub relative: f64,
+    /// ±2 round the wheel (8A → 6A, 10A).
+    pub two_steps: f64,
+    /// One semitone up (8A → 3A).
+    pub energy_boost: f64,
+    /// Every other pair, a clash.  Set it to `non_harmonic_cost` so clashes also pay the
+    /// non-harmonic surcharge.
+    pub other: f64,
+}
+
+impl KeyCostRules {
+    /// The built-in model: 0, 1, 1, 2, 2 and `non_harmonic_cost`.
+    pub fn builtin(non_harmonic_cost: f64) -> Self {
+        KeyCostRules {
+            same_key: 0.0,
+            adjacent: 1.0,
+            relative: 1.0,
+            two_steps: 2.0,
+            energy_boost: 2.0,
+            other: non_harmonic_cost,
+        }
+    }
+
+    pub fn cost(&self, relation: KeyRelation) -> f64 {
+        match relation {
+            KeyRelation::PerfectMatch => self.same_key,
+            KeyRelation::EnergyUp | KeyRelation::MoodDown => self.adjacent,
+            KeyRelation::Relative => self.relative,
+            KeyRelation::EnergyUpTwo | KeyRelation::MoodDownTwo => self.two_steps,
+            KeyRelation::EnergyBoost => self.energy_boost,
+            KeyRelation::Clash => self.other,
+        }
+    }
+}
+
+/// `(direct_costs, indirect_costs)` over the 24 Camelot keys (id = (number - 1) × 2 +
+/// letter) charging each transition its `rules` cost.  The indirect table repeats the
+/// direct one, so with `other` = `non_harmonic_cost` exactly the clashes are unrelated and
+/// pay the 2 × `non_harmonic_cost` surcharge.  Every cost must be finite and >= 0.
+pub fn camelot_tables(rules: &KeyCostRules) -> Result<(Vec<f64>, Vec<f64>), String> {
+    let named = [
+        ("same_key", rules.same_key),
+        ("adjacent", rules.adjacent),
+        ("relative", rules.relative),
+        ("two_steps", rules.two_steps),
+        ("energy_boost", rules.energy_boost),
+        ("other", rules.other),
+    ];
+    if let Some((name, c)) = named.iter().find(|(_, c)| !(c.is_finite() && *c >= 0.0)) {
+        return Err(format!("{name} must be a finite cost >= 0, got {c}"));
+    }
+    const KEYS: usize = 24;
+    let mut direct = Vec::with_capacity(KEYS * KEYS);
+    for a in 0..KEYS {
+        direct.extend((0..KEYS).map(|b| rules.cost(KeyRelation::classify(a, b))));
+    }
+    let indirect = direct.clone();
+    Ok((direct, indirect))
+}
+
+/// Built-in `(direct_costs, indirect_costs)` over the 24 Camelot keys, for callers without
+/// tables of their own: `camelot_tables` with `KeyCostRules::builtin`.  Direct costs from
+/// a key: 0 to itself, 1 one step round the wheel or to its relative major/minor (8A → 7A,
+/// 9A, 8B), 2 two steps round the wheel or one semitone up (the energy boost, 8A → 3A),
+/// otherwise `non_harmonic_cost`.
+///
+/// # Panics
+/// If `non_harmonic_cost` is negative or not finite.
+pub fn camelot_key_costs(non_harmonic_cost: f64) -> (Vec<f64>, Vec<f64>) {
+    camelot_tables(&KeyCostRules::builtin(non_harmonic_cost)).expect("non_harmonic_cost must be finite and >= 0")
+}
+
+/// Scan a cost table for NaN/inf entries, returning the index of the first offending entry.
+///
+/// NaN and `-inf` are always rejected.  With `inf_forbidden`, `+inf` marks a forbidden
+/// transition and is replaced in place by `FORBIDDEN_COST`; otherwise it is rejected too.
+pub fn sanitize_cost_table(table: &mut [f64], inf_forbidden: bool) -> Result<(), usize> {
+    for (i, c) in table.iter_mut().enumerate() {
+        if c.is_finite() {
+            continue;
+        }
+        if inf_forbidden && *c == f64::INFINITY {
+            *c = FORBIDDEN_COST;
+        } else {
+            return Err(i);
+        }
+    }
+    Ok(())
+}
+
+/// Put `value` back into a cost table read from float32, where it could only be stored
+/// rounded: every entry equal to `value` rounded to f32 becomes `value` again.  The
+/// non-harmonic surcharge compares table entries with `non_harmonic_cost` exactly, so
+/// without this a float32 table loses it unless the cost is exactly representable.
+pub fn restore_f32_value(table: &mut [f64], value: f64) {
+    let rounded = value as f32 as f64;
+    for c in table.iter_mut().filter(|c| **c == rounded) {
+        *c = value;
+    }
+}
+
+/// Virtual boundary tracks outside the playlist, as `(bpm, key_id)`: `entry` is played
+/// just before the first real track (e.g. the previous DJ's closing track) and `exit` just
+/// after the last one.  Virtual tracks are never shifted.
+#[derive(Clone, Copy, Default)]
+pub struct Anchors {
+    pub entry: Option<(i32, u8)>,
+    pub exit: Option<(i32, u8)>,
+}
+
+/// Bonuses credited when two specific nodes are played back to back.  Stored as directed
+/// edges (`partners[i1]` holds `(i2, bonus)` for each preferred edge i1 → i2), so contracted
+/// blocks can keep only the pairs that meet at their boundaries; a node has few partners, so
+/// a linear scan beats hashing.
+pub struct AdjacencyBonus {
+    partners: Vec<Vec<(usize, f64)>>,
+}
+
+impl AdjacencyBonus {
+    /// Undirected `(a, b, bonus)` track pairs over `n` tracks: each pair earns its bonus in
+    /// either play order.  Out-of-range, self or repeated pairs and negative or non-finite
+    /// bonuses are rejected.
+    pub fn from_pairs(n: usize, pairs: &[(usize, usize, f64)]) -> Result<Self, String> {
+        let mut partners = vec![Vec::new(); n];
+        for (p, &(a, b, bonus)) in pairs.iter().enumerate() {
+            if a >= n || b >= n {
+                return Err(format!("prefer_adjacent[{p}] refers to a track >= {n}"));
+            }
+            if a == b {
+                return Err(format!("prefer_adjacent[{p}] pairs track {a} with itself"));
+            }
+            if !(bonus.is_finite() && bonus >= 0.0) {
+                return Err(format!("prefer_adjacent[{p}] has bonus {bonus}, expected a finite value >= 0"));
+            }
+            if partners[a].iter().any(|&(j, _)| j == b) {
+                return Err(format!("pair ({a}, {b}) appears more than once in prefer_adjacent"));
+            }
+            partners[a].push((b, bonus));
+            partners[b].push((a, bonus));
+        }
+        Ok(AdjacencyBonus { partners })
+    }
+
+    /// Directed edges over `n` nodes, taken as given.
+    pub fn from_edges(n: usize, edges: impl IntoIterator<Item = (usize, usize, f64)>) -> Self {
+        let mut partners = vec![Vec::new(); n];
+        for (i1, i2, bonus) in edges {
+            partners[i1].push((i2, bonus));
+        }
+        AdjacencyBonus { partners }
+    }
+
+    /// Bonus for playing node i2 straight after node i1 (0 if the pair is not preferred).
+    #[inline(always)]
+    pub fn get(&self, i1: usize, i2: usize) -> f64 {
+        self.partners[i1].iter().find(|&&(j, _)| j == i2).map_or(0.0, |&(_, b)| b)
+    }
+
+    /// Every directed preferred edge as `(i1, i2, bonus)`.
+    pub fn edges(&self) -> impl Iterator<Item = (usize, usize, f64)> + '_ {
+        self.partners
+            .iter()
+            .enumerate()
+            .flat_map(|(i1, ps)| ps.iter().map(move |&(i2, b)| (i1, i2, b)))
+    }
+
+    /// Total bonus earned by the given order.
+    pub fn total(&self, order: &[usize]) -> f64 {
+        order.windows(2).map(|w| self.get(w[0], w[1])).sum()
+    }
+}
+
+/// Order-stability penalty against a reference order: `weight` on every edge i1 → i2 where
+/// i2 is not i1's successor in the reference.  Over a whole order this is `weight * d`, with
+/// d the number of the reference's back-to-back pairs (in their direction) that the order
+/// breaks, so the reference itself costs nothing.  Being a per-edge charge, every solver
+/// handles it incrementally like any other edge cost.
+pub struct StabilityPenalty {
+    reference: Vec<usize>,
+    /// Successor of each node in the reference (`usize::MAX` for its last node).
+    successor: Vec<usize>,
+    pub weight: f64,
+}
+
+impl StabilityPenalty {
+    /// Penalty against `reference`, a permutation of 0..n; `weight` must be finite and >= 0.
+    pub fn new(reference: &[usize], weight: f64) -> Result<Self, String> {
+        if !(weight.is_finite() && weight >= 0.0) {
+            return Err(format!("stability_weight must be a finite value >= 0, got {weight}"));
+        }
+        let n = reference.len();
+        let mut successor = vec![usize::MAX; n];
+        let mut seen = vec![false; n];
+        for (p, &t) in reference.iter().enumerate() {
+            if t >= n || std::mem::replace(&mut seen[t], true) {
+                return Err(format!("reference_order is not a permutation of 0..{}", n.saturating_sub(1)));
+            }
+            successor[t] = reference.get(p + 1).copied().unwrap_or(usize::MAX);
+        }
+        Ok(StabilityPenalty { reference: reference.to_vec(), successor, weight })
+    }
+
+    /// Restriction to node boundaries: node u is followed by node v when the reference
+    /// successor of `last[u]` is `first[v]`.  The node reference orders nodes by where
+    /// their first member falls in the track reference.
+    pub fn select(&self, last: &[usize], first: &[usize]) -> Self {
+        let mut node_of_first = vec![usize::MAX; self.successor.len()];
+        for (v, &f) in first.iter().enumerate() {
+            node_of_first[f] = v;
+        }
+        let successor = last
+            .iter()
+            .map(|&l| self.successor[l])
+            .map(|s| if s == usize::MAX { s } else { node_of_first[s] })
+            .collect();
+        let reference = self.reference.iter().map(|&t| node_of_first[t]).filter(|&v| v != usize::MAX).collect();
+        StabilityPenalty { reference, successor, weight: self.weight }
+    }
+
+    /// The reference order.
+    pub fn reference(&self) -> &[usize] {
+        &self.reference
+    }
+
+    /// Penalty on the edge i1 → i2.
+    #[inline(always)]
+    pub fn get(&self, i1: usize, i2: usize) -> f64 {
+        if self.successor[i1] == i2 { 0.0 } else { self.weight }
+    }
+
+    /// Number of reference pairs the given order breaks.
+    pub fn broken(&self, order: &[usize]) -> usize {
+        order.windows(2).filter(|w| self.successor[w[0]] != w[1]).count()
+    }
+}
+
+/// Sparse alternative to the dense `direct_costs` / `indirect_costs` tables for large key
+/// systems, where most effective-key pairs share one default cost: a default pair plus the
+/// pairs that differ from it, sorted by flat index `ek1 * num_keys + ek2` and found by binary
+/// search.  The dense tables stay the fast path for the usual 24 keys.
+pub struct SparseKeyCosts {
+    num_keys: usize,
+    default: (f64, f64),
+    /// `(flat index, direct, indirect)`, sorted by index.
+    overrides: Vec<(u32, f64, f64)>,
+}
+
+impl SparseKeyCosts {
+    /// `default` is the `(direct, indirect)` cost of every pair not listed in `overrides`,
+    /// given as `(ek1, ek2, direct, indirect)`.  Out-of-range keys, repeated pairs and
+    /// non-finite costs are rejected.
+    pub fn new(
+        num_keys: usize,
+        default: (f64, f64),
+        overrides: &[(usize, usize, f64, f64)],
+    ) -> Result<Self, String> {
+        if !(default.0.is_finite() && default.1.is_finite()) {
+            return Err(format!("sparse default costs {default:?} must be finite"));
+        }
+        let mut entries = Vec::with_capacity(overrides.len());
+        for (o, &(ek1, ek2, direct, indirect)) in overrides.iter().enumerate() {
+            if ek1 >= num_keys || ek2 >= num_keys {
+                return Err(format!("sparse override {o} refers to a key >= {num_keys}"));
+            }
+            if !(direct.is_finite() && indirect.is_finite()) {
+                return Err(format!("sparse override {o} has costs ({direct}, {indirect}), expected finite values"));
+            }
+            entries.push(((ek1 * num_keys + ek2) as u32, direct, indirect));
+        }
+        entries.sort_unstable_by_key(|&(idx, _, _)| idx);
+        if let Some(w) = entries.windows(2).find(|w| w[0].0 == w[1].0) {
+            let idx = w[0].0 as usize;
+            return Err(format!(
+                "key pair ({}, {}) appears more than once in the sparse overrides",
+                idx / num_keys, idx % num_keys
+            ));
+        }
+        Ok(SparseKeyCosts { num_keys, default, overrides: entries })
+    }
+
+    /// `(direct, indirect)` cost of the effective-key pair at flat index `idx`.
+    #[inline(always)]
+    pub fn get(&self, idx: usize) -> (f64, f64) {
+        match self.overrides.binary_search_by_key(&(idx as u32), |&(i, _, _)| i) {
+            Ok(k) => (self.overrides[k].1, self.overrides[k].2),
+            Err(_) => self.default,
+        }
+    }
+
+    /// Expand into dense `(direct_costs, indirect_costs)` tables of length num_keys².
+    pub fn to_dense(&self) -> (Vec<f64>, Vec<f64>) {
+        let len = self.num_keys * self.num_keys;
+        let mut direct = vec![self.default.0; len];
+        let mut indirect = vec![self.default.1; len];
+        for &(idx, d, i) in &self.overrides {
+            direct[idx as usize] = d;
+            indirect[idx as usize] = i;
+        }
+        (direct, indirect)
+    }
+}
+
+/// Externally measured transition costs between specific nodes (e.g. beatgrid alignment
+/// and spectral clash scored from the audio), as a flat row-major n × n matrix indexed by
+/// node, not key.  Stored already multiplied by its weight.
+pub struct CompatCosts {
+    costs: Vec<f64>,
+    n: usize,
+    /// One entry per shift pair of each edge (see `with_shifts`) rather than per edge.
+    per_shift: bool,
+    /// Use these costs instead of the key model's harmonic cost rather than on top of it.
+    pub replaces_harmonic: bool,
+}
+
+impl CompatCosts {
+    /// Row-major `costs[i1 * n + i2]` for the edge i1 → i2, scaled by `weight`.  A matrix of
+    /// the wrong size, non-finite entries and a negative or non-finite weight are rejected.
+    pub fn new(n: usize, costs: &[f64], weight: f64, replaces_harmonic: bool) -> Result<Self, String> {
+        if costs.len() != n * n {
+            return Err(format!("compat_costs has {} entries, expected {n}² = {}", costs.len(), n * n));
+        }
+        if let Some(i) = costs.iter().position(|c| !c.is_finite()) {
+            return Err(format!("compat_costs[{i}] is {}, expected a finite value", costs[i]));
+        }
+        if !(weight.is_finite() && weight >= 0.0) {
+            return Err(format!("compat_weight is {weight}, expected a finite value >= 0"));
+        }
+        Ok(CompatCosts { costs: costs.iter().map(|c| c * weight).collect(), n, per_shift: false, replaces_harmonic })
+    }
+
+    /// Costs that also depend on the shifts at both ends, e.g. sampled from a caller's own
+    /// edge-cost function: `costs[(i1 * n + i2) * 9 + (s1 + 1) * 3 + (s2 + 1)]` for the edge
+    /// i1 → i2 at shifts s1, s2.  Validated like `new`.
+    pub fn with_shifts(n: usize, costs: &[f64], weight: f64, replaces_harmonic: bool) -> Result<Self, String> {
+        if costs.len() != n * n * 9 {
+            return Err(format!("expected {n}² × 9 = {} edge costs, got {}", n * n * 9, costs.len()));
+        }
+        let flat = CompatCosts::new(3 * n, costs, weight, replaces_harmonic)?;
+        Ok(CompatCosts { n, per_shift: true, ..flat })
+    }
+
+    /// Weighted cost of playing node i2 at shift s2 straight after node i1 at shift s1.
+    #[inline(always)]
+    pub fn get(&self, i1: usize, i2: usize, s1: i8, s2: i8) -> f64 {
+        let edge = i1 * self.n + i2;
+        if self.per_shift {
+            self.costs[edge * 9 + (s1 + 1) as usize * 3 + (s2 + 1) as usize]
+        } else {
+            self.costs[edge]
+        }
+    }
+
+    /// Matrix over new nodes, where leaving node u means leaving `from[u]` and entering
+    /// node v means entering `to[v]` (both slices index the current nodes).
+    /// A node keeps the shift of the track it is entered or left through.
+    pub fn select(&self, from: &[usize], to: &[usize]) -> Self {
+        let width = if self.per_shift { 9 } else { 1 };
+        CompatCosts {
+            costs: from
+                .iter()
+                .flat_map(|&a| to.iter().flat_map(move |&b| {
+                    let edge = (a * self.n + b) * width;
+                    &self.costs[edge..edge + width]
+                }))
+                .copied()
+                .collect(),
+            n: to.len(),
+            per_shift: self.per_shift,
+            replaces_harmonic: self.replaces_harmonic,
+        }
+    }
+
+    /// Total weighted cost of the edges of the given order at the given (node-indexed)
+    /// shifts.
+    pub fn total(&self, order: &[usize], shifts: &[i8]) -> f64 {
+        order.windows(2).map(|w| self.get(w[0], w[1], shifts[w[0]], shifts[w[1]])).sum()
+    }
+}
+
+/// Read-only view of one optimisation instance: per-node BPMs and keys plus the shared
+/// shift and harmonic lookup tables.
+///
+/// Nodes are normally tracks, entered at their intro tempo (`bpms`) and left at their outro
+/// tempo (`exit_bpms`; the same slice unless the caller supplies separate outro BPMs).
+/// A contracted instance (see `blocks.rs`) turns each contiguous block into a single node
+/// that is entered through its first member (`bpms`, `key_ids`) and left through its last
+/// (`exit_bpms`, `exit_key_ids`), and carries the cost of its internal edges in
+/// `node_breakdown`.
 ///
 /// - `shift_table`: flat array of length num_keys * 3, indexed by `key_id * 3 + (shift + 1)`
-/// - `direct_costs` / `indirect_costs`: flat arrays of length num_keys^2
+/// - `direct_costs` / `indirect_costs`: flat arrays of length num_keys^2, unused (and may be
+///   empty) when `sparse_costs` is set
+/// - `harmonic_mask`: optional flat array of length num_keys^2, 1 = harmonically related
+#[derive(Clone, Copy)]
+pub struct Tables<'a> {
+    pub bpms: &'a [i32],
+    pub key_ids: &'a [u8],
+    pub exit_bpms: &'a [i32],
+    pub exit_key_ids: &'a [u8],
+    pub shift_table: &'a [u8],
+    pub direct_costs: &'a [f64],
+    pub indirect_costs: &'a [f64],
+    /// Explicit "harmonically related" flags deciding the non-harmonic surcharge.  `None`
+    /// keeps the legacy rule: surcharge when `direct == non_harmonic_cost` and
+    /// `indirect >= non_harmonic_cost`.
+    pub harmonic_mask: Option<&'a [u8]>,
+    /// Per-node `(h, t, s)` cost of choosing each shift, indexed `node * 3 + (shift + 1)`,
+    /// not counting the node's edges to its neighbours.  `None` means plain tracks:
+    /// `(0, 0, params.shift_penalty_of(s))`.
+    pub node_breakdown: Option<&'a [(f64, f64, f64)]>,
+    /// Boundary edges to virtual tracks, charged on top of the in-order edges.
+    pub anchors: Anchors,
+    /// Per-node key-detection confidence in [0, 1] on the entry (`key_confidence`) and exit
+    /// (`exit_key_confidence`) side.  Each edge's harmonic cost is scaled by
+    /// `params.confidence_weight` of its two endpoints, so transitions between uncertain keys
+    /// fall back to tempo.  Virtual anchor tracks count as fully confident.  `None` = 1.
+    pub key_confidence: Option<&'a [f64]>,
+    pub exit_key_confidence: Option<&'a [f64]>,
+    /// Per-node factor on the harmonic cost of the node's outgoing edge, from how long its
+    /// outro is blended into the next track: a quick cut forgives a key clash, a long blend
+    /// exposes it.  `None` = 1.
+    pub blend_scale: Option<&'a [f64]>,
+    /// Preferred back-to-back pairs; the bonus is credited (subtracted) on the edge.
+    pub adjacency: Option<&'a AdjacencyBonus>,
+    /// Penalty for departing from a reference order, charged to `h` on the edge.
+    pub stability: Option<&'a StabilityPenalty>,
+    /// Sparse key costs used instead of `direct_costs` / `indirect_costs` when set.
+    pub sparse_costs: Option<&'a SparseKeyCosts>,
+    /// Per-edge compatibility costs between nodes, charged to `h` on top of (or instead of)
+    /// the key model.  Edges to virtual anchor tracks have no entry and keep the key model.
+    pub compat: Option<&'a CompatCosts>,
+}
+
+impl<'a> Tables<'a> {
+    /// View over plain tracks (every node is one track).
+    pub fn new(
+        bpms: &'a [i32],
+        key_ids: &'a [u8],
+        shift_table: &'a [u8],
+        direct_costs: &'a [f64],
+        indirect_costs: &'a [f64],
+    ) -> Self {
+        Tables {
+            bpms,
+            key_ids,
+            exit_bpms: bpms,
+            exit_key_ids: key_ids,
+            shift_table,
+            direct_costs,
+            indirect_costs,
+            harmonic_mask: None,
+            node_breakdown: None,
+            anchors: Anchors::default(),
+            key_confidence: None,
+            exit_key_confidence: None,
+            blend_scale: None,
+            adjacency: None,
+            stability: None,
+            compat: None,
+            sparse_costs: None,
+        }
+    }
+
+    /// Unweighted `(h, t, s)` contribution of node `i` at shift `s`, excluding its edges.
+    #[inline(always)]
+    pub fn node_components(&self, i: usize, s: i8, params: &CostParams) -> (f64, f64, f64) {
+        match self.node_breakdown {
+            Some(nb) => nb[i * 3 + (s + 1) as usize],
+            None => (0.0, 0.0, params.shift_penalty_of(s)),
+        }
+    }
+
+    /// Weighted objective contribution of node `i` at shift `s`, excluding its edges.
+    #[inline(always)]
+    pub fn node_cost(&self, i: usize, s: i8, params: &CostParams) -> f64 {
+        let (h, t, sp) = self.node_components(i, s, params);
+        h + params.tempo_cost_weight * t + params.shift_weight * sp
+    }
+
+    /// The `(direct, indirect)` table entries of the effective-key pair at flat index `idx`.
+    #[inline(always)]
+    pub fn key_cells(&self, idx: usize) -> (f64, f64) {
+        match self.sparse_costs {
+            Some(sparse) => sparse.get(idx),
+            None => (self.direct_costs[idx], self.indirect_costs[idx]),
+        }
+    }
+
+    /// Harmonic cost of the effective-key pair at flat index `idx`, and whether the
+    /// 2 × non_harmonic_cost surcharge for unrelated keys was applied.
+    #[inline(always)]
+    pub fn harmonic_cost(&self, idx: usize, params: &CostParams) -> (f64, bool) {
+        let (direct, indirect) = self.key_cells(idx);
+        let surcharge = match self.harmonic_mask {
+            Some(mask) => mask[idx] == 0,
+            None => direct == params.non_harmonic_cost && indirect >= params.non_harmonic_cost,
+        };
+        if surcharge {
+            (direct + 2.0 * params.non_harmonic_cost, true)
+        } else {
+            (direct, false)
+        }
+    }
+
+    /// Weighted cost of the edge from the virtual entry track into node `i` at shift `s`.
+    pub fn entry_cost(&self, i: usize, s: i8, params: &CostParams) -> f64 {
+        match self.anchors.entry {
+            Some((bpm, key)) => {
+                let (h, t) =
+                    transition_components(bpm, key, 0, self.bpms[i], self.key_ids[i], s, self, params);
+                let w = self.key_confidence.map_or(1.0, |c| params.confidence_weight(1.0, c[i]));
+                let (h, t) = params.objective_edge(params.cut_or_blend(h * w).0, t);
+                h + params.tempo_cost_weight * t
+            }
+            None => 0.0,
+        }
+    }
+
+    /// Weighted cost of the edge from node `i` at shift `s` into the virtual exit track.
+    pub fn exit_cost(&self, i: usize, s: i8, params: &CostParams) -> f64 {
+        match self.anchors.exit {
+            Some((bpm, key)) => {
+                let (h, t) =
+                    transition_components(self.exit_bpms[i], self.exit_key_ids[i], s, bpm, key, 0, self, params);
+                let w = self.exit_key_confidence.map_or(1.0, |c| params.confidence_weight(c[i], 1.0));
+                let b = self.blend_scale.map_or(1.0, |b| b[i]);
+                let (h, t) = params.objective_edge(params.cut_or_blend(h * w * b).0, t);
+                h + params.tempo_cost_weight * t
+            }
+            None => 0.0,
+        }
+    }
+
+    /// Weighted cost of both boundary edges for the given order (0 without anchors).
+    #[inline(always)]
+    pub fn boundary_cost(&self, order: &[usize], shifts: &[i8], params: &CostParams) -> f64 {
+        let (first, last) = (order[0], order[order.len() - 1]);
+        self.entry_cost(first, shifts[first], params) + self.exit_cost(last, shifts[last], params)
+    }
+
+    /// Harmonic weight of the edge from node i1 into node i2 (1 without key confidences).
+    #[inline(always)]
+    pub fn edge_confidence(&self, i1: usize, i2: usize, params: &CostParams) -> f64 {
+        match (self.exit_key_confidence, self.key_confidence) {
+            (Some(exit), Some(entry)) => params.confidence_weight(exit[i1], entry[i2]),
+            _ => 1.0,
+        }
+    }
+}
+
+/// Per-track inputs of a plain-track `Tables` restricted to some of its tracks, re-indexed
+/// 0..len in the given order, for solving part of an instance on its own.  Shared key
+/// tables, the harmonic mask and sparse costs carry over; anchors, preferred pairs,
+/// compatibility and stability do not.
+pub struct Subset {
+    bpms: Vec<i32>,
+    exit_bpms: Vec<i32>,
+    key_ids: Vec<u8>,
+    key_confidence: Option<Vec<f64>>,
+    blend_scale: Option<Vec<f64>>,
+}
+
+impl Subset {
+    pub fn new(tables: &Tables, tracks: &[usize]) -> Self {
+        let pick = |v: &[f64]| tracks.iter().map(|&i| v[i]).collect();
+        Subset {
+            bpms: tracks.iter().map(|&i| tables.bpms[i]).collect(),
+            exit_bpms: tracks.iter().map(|&i| tables.exit_bpms[i]).collect(),
+            key_ids: tracks.iter().map(|&i| tables.key_ids[i]).collect(),
+            key_confidence: tables.key_confidence.map(pick),
+            blend_scale: tables.blend_scale.map(pick),
+        }
+    }
+
+    /// Tables over the subset, sharing `base`'s key tables.
+    pub fn tables<'a>(&'a self, base: &Tables<'a>) -> Tables<'a> {
+        let mut sub = Tables::new(&self.bpms, &self.key_ids, base.shift_table, base.direct_costs, base.indirect_costs);
+        sub.exit_bpms = &self.exit_bpms;
+        sub.harmonic_mask = base.harmonic_mask;
+        sub.sparse_costs = base.sparse_costs;
+        sub.key_confidence = self.key_confidence.as_deref();
+        sub.exit_key_confidence = self.key_confidence.as_deref();
+        sub.blend_scale = self.blend_scale.as_deref();
+        sub
+    }
+}
+
+/// Unweighted `(h, t)` of the edge from node i1 into node i2: the single source of truth for
+/// edge costs.  `edge_cost` weights it and `total_edge_cost` sums it per component.
+///
+/// A preferred-adjacent pair's bonus is credited to `h`: like the harmonic cost it is a
+/// musical preference about the pair, and keeping it inside the edge lets every solver (and
+/// the annealer's swap delta) see it without extra bookkeeping.  Compatibility costs are
+/// charged to `h` for the same reason.  Under `ObjectiveMode::PerfectTransitions` the
+/// result is mapped to the 0/1 edge count last (see `CostParams::objective_edge`).
+#[inline(always)]
+pub fn edge_components(
+    i1: usize,
+    i2: usize,
+    s1: i8,
+    s2: i8,
+    tables: &Tables,
+    params: &CostParams,
+) -> (f64, f64) {
+    let (h, t) = transition_components(
+        tables.exit_bpms[i1], tables.exit_key_ids[i1], s1,
+        tables.bpms[i2], tables.key_ids[i2], s2,
+        tables, params,
+    );
+    let h = if tables.key_confidence.is_some() { h * tables.edge_confidence(i1, i2, params) } else { h };
+    let h = match tables.blend_scale {
+        Some(b) => h * b[i1],
+        None => h,
+    };
+    let h = params.cut_or_blend(h).0;
+    let h = match tables.compat {
+        Some(c) if c.replaces_harmonic => c.get(i1, i2, s1, s2),
+        Some(c) => h + c.get(i1, i2, s1, s2),
+        None => h,
+    };
+    let h = match tables.adjacency {
+        Some(adj) => h - adj.get(i1, i2),
+        None => h,
+    };
+    let h = match tables.stability {
+        Some(st) => h + st.get(i1, i2),
+        None => h,
+    };
+    params.objective_edge(h, t)
+}
+
+/// Compute the combined edge cost (harmonic + weighted tempo) from node i1 into node i2.
 #[inline(always)]
 pub fn edge_cost(
     i1: usize,
     i2: usize,
     s1: i8,
     s2: i8,
-    bpms: &[i32],
-    key_ids: &[u8],
-    shift_table: &[u8],
-    direct_costs: &[f64],
-    indirect_costs: &[f64],
+    tables: &Tables,
     params: &CostParams,
 ) -> f64 {
-    let diff = (bpms[i1] - bpms[i2]).unsigned_abs() as f64;
-    let break_thresh = params.tempo_break_threshold();
+    let (h, t) = edge_components(i1, i2, s1, s2, tables, params);
+    h + params.tempo_cost_weight * t
+}
 
-    if diff > break_thresh {
-        return params.tempo_cost_weight * params.tempo_penalty * params.tempo_break_factor;
+/// Unweighted `(h, t)` of a transition given raw BPMs and base keys rather than node
+/// indices; `edge_components` delegates here, and virtual anchors call it directly.
+///
+/// A tempo-break edge charges `tempo_penalty * tempo_break_factor` to `t` and, unless
+/// `harmonic_across_breaks` is set, nothing to `h` (the keys are not even looked up).
+/// With `harmonic_only` the BPMs are never read and `t` is 0.
+#[inline(always)]
+pub fn transition_components(
+    from_bpm: i32,
+    from_key: u8,
+    s1: i8,
+    to_bpm: i32,
+    to_key: u8,
+    s2: i8,
+    tables: &Tables,
+    params: &CostParams,
+) -> (f64, f64) {
+    if params.harmonic_only {
+        return (harmonic_between(from_key, to_key, s1, s2, tables, params), 0.0);
+    }
+    let diff = params.tempo_diff(from_bpm, to_bpm);
+    if diff > params.tempo_break_threshold() {
+        let h = if params.harmonic_across_breaks {
+            harmonic_between(from_key, to_key, s1, s2, tables, params)
+        } else {
+            0.0
+        };
+        return (h, params.tempo_penalty * params.tempo_break_factor);
     }
+    let h = harmonic_between(from_key, to_key, s1, s2, tables, params);
+    let t = if diff > params.tempo_threshold { params.over_threshold_penalty(diff) } else { 0.0 };
+    (h, t)
+}
+
+/// Harmonic cost between two base keys at the given shifts (effective keys via shift_table).
+#[inline(always)]
+fn harmonic_between(from_key: u8, to_key: u8, s1: i8, s2: i8, tables: &Tables, params: &CostParams) -> f64 {
+    let ek1 = effective_key(tables.shift_table, from_key, s1);
+    let ek2 = effective_key(tables.shift_table, to_key, s2);
+    tables.harmonic_cost(ek1 * params.num_keys + ek2, params).0
+}
 
-    // Effective key IDs via shift table
-    let ek1 = shift_table[key_ids[i1] as usize * 3 + (s1 + 1) as usize] as usize;
-    let ek2 = shift_table[key_ids[i2] as usize * 3 + (s2 + 1) as usize] as usize;
-    let idx = ek1 * params.num_keys + ek2;
+/// Step-by-step account of one `edge_cost` evaluation, for inspection outside the hot path.
+pub struct EdgeExplanation {
+    pub from_effective_key: usize,
+    pub to_effective_key: usize,
+    /// Camelot relationship of the two effective keys (`None` unless num_keys is 24).
+    pub key_relation: Option<KeyRelation>,
+    pub bpm_diff: f64,
+    /// The difference compared with the thresholds: `bpm_diff`, or its log-ratio form
+    /// with `perceptual_tempo` (see `CostParams::tempo_diff`).
+    pub tempo_diff: f64,
+    pub over_threshold: bool,
+    pub tempo_break: bool,
+    /// False on tempo-break edges, where `edge_cost` skips the key lookup entirely
+    /// (unless `harmonic_across_breaks` is set).
+    pub harmonic_assessed: bool,
+    /// The direct and indirect table entries of the effective-key pair (looked up even when
+    /// the harmonic cost is not assessed, for inspection).
+    pub direct_cell: f64,
+    pub indirect_cell: f64,
+    /// Whether `harmonic_mask` decided the surcharge; otherwise the legacy rule did (direct
+    /// == non_harmonic_cost and indirect >= non_harmonic_cost).
+    pub surcharge_from_mask: bool,
+    /// Weight applied to the harmonic cost from the endpoints' key confidences (1 = trusted).
+    pub key_confidence: f64,
+    /// Factor applied to the harmonic cost for the outgoing track's blend length (1 = neutral).
+    pub blend_scale: f64,
+    /// Whether the edge is played as a cut (cheaper than blending; only with `cut_penalty`).
+    pub cut: bool,
+    /// Harmonic cost after the key-confidence and blend weights, and after the cut discount
+    /// plus `cut_penalty` when `cut` is set.  0 when compatibility costs replace it.
+    pub harmonic_cost: f64,
+    /// Weighted compatibility cost of the pair (0 without a compatibility matrix).
+    pub compat_cost: f64,
+    /// Bonus credited because the pair is preferred adjacent (subtracted from the edge cost).
+    pub adjacency_bonus: f64,
+    /// Stability penalty because the pair is not back to back in the reference order.
+    pub stability_cost: f64,
+    /// The 2 × non_harmonic_cost surcharge for pairs with no direct or indirect relation.
+    pub non_harmonic_surcharge: bool,
+    /// Weighted tempo contribution (tempo_cost_weight already applied).
+    pub tempo_cost: f64,
+    pub edge_cost: f64,
+    /// Weighted shift penalty charged to each endpoint (not part of the edge cost).
+    pub from_shift_cost: f64,
+    pub to_shift_cost: f64,
+}
 
-    let direct = direct_costs[idx];
-    let h_cost = if direct == params.non_harmonic_cost && indirect_costs[idx] >= params.non_harmonic_cost {
-        direct + 2.0 * params.non_harmonic_cost
+/// Explain the cost of the edge from node i1 (shift s1) into node i2 (shift s2).
+///
+/// Follows `edge_cost` branch by branch; debug builds assert both agree.  Under
+/// `ObjectiveMode::PerfectTransitions` it explains the weighted cost that decides whether
+/// the edge is perfect.
+pub fn explain_edge(
+    i1: usize,
+    i2: usize,
+    s1: i8,
+    s2: i8,
+    tables: &Tables,
+    params: &CostParams,
+) -> EdgeExplanation {
+    let (from_bpm, to_bpm) = (tables.exit_bpms[i1], tables.bpms[i2]);
+    let bpm_diff = (from_bpm as i64 - to_bpm as i64).unsigned_abs() as f64;
+    let diff = params.tempo_diff(from_bpm, to_bpm);
+    let ek1 = effective_key(tables.shift_table, tables.exit_key_ids[i1], s1);
+    let ek2 = effective_key(tables.shift_table, tables.key_ids[i2], s2);
+    let tempo_break = !params.harmonic_only && diff > params.tempo_break_threshold();
+    let over_threshold = !params.harmonic_only && diff > params.tempo_threshold;
+
+    let harmonic_assessed = !tempo_break || params.harmonic_across_breaks;
+    let (direct_cell, indirect_cell) = tables.key_cells(ek1 * params.num_keys + ek2);
+    let key_confidence = tables.edge_confidence(i1, i2, params);
+    let blend_scale = tables.blend_scale.map_or(1.0, |b| b[i1]);
+    let (harmonic_cost, non_harmonic_surcharge) = if harmonic_assessed {
+        let (h, surcharge) = tables.harmonic_cost(ek1 * params.num_keys + ek2, params);
+        (h * key_confidence * blend_scale, surcharge)
     } else {
-        direct
+        (0.0, false)
+    };
+    let (harmonic_cost, cut) = params.cut_or_blend(harmonic_cost);
+    let (harmonic_cost, cut, compat_cost) = match tables.compat {
+        Some(c) if c.replaces_harmonic => (0.0, false, c.get(i1, i2, s1, s2)),
+        Some(c) => (harmonic_cost, cut, c.get(i1, i2, s1, s2)),
+        None => (harmonic_cost, cut, 0.0),
     };
+    let adjacency_bonus = tables.adjacency.map_or(0.0, |adj| adj.get(i1, i2));
+    let stability_cost = tables.stability.map_or(0.0, |st| st.get(i1, i2));
+    // The tempo charge and the total come from the scorer itself, so they match it to the bit
+    let weighted = CostParams { objective_mode: ObjectiveMode::Weighted, ..*params };
+    let (h, t) = edge_components(i1, i2, s1, s2, tables, &weighted);
+    let tempo_cost = weighted.tempo_cost_weight * t;
 
-    let t_cost = if diff > params.tempo_threshold { params.tempo_penalty } else { 0.0 };
+    let explanation = EdgeExplanation {
+        from_effective_key: ek1,
+        to_effective_key: ek2,
+        key_relation: classify_transition(ek1, ek2, params.num_keys).ok(),
+        bpm_diff,
+        tempo_diff: diff,
+        over_threshold,
+        tempo_break,
+        harmonic_assessed,
+        direct_cell,
+        indirect_cell,
+        surcharge_from_mask: tables.harmonic_mask.is_some(),
+        key_confidence,
+        blend_scale,
+        cut,
+        harmonic_cost,
+        compat_cost,
+        adjacency_bonus,
+        stability_cost,
+        non_harmonic_surcharge,
+        tempo_cost,
+        edge_cost: h + tempo_cost,
+        from_shift_cost: tables.node_cost(i1, s1, params),
+        to_shift_cost: tables.node_cost(i2, s2, params),
+    };
+    let parts = harmonic_cost + compat_cost - adjacency_bonus + stability_cost + tempo_cost;
+    debug_assert!((parts - explanation.edge_cost).abs() <= 1e-9 * explanation.edge_cost.abs().max(1.0));
+    explanation
+}
 
-    h_cost + params.tempo_cost_weight * t_cost
+/// One transition of `transition_report`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransitionRecord {
+    pub from_track: usize,
+    pub to_track: usize,
+    /// Effective keys after the two tracks' shifts.
+    pub from_key: usize,
+    pub to_key: usize,
+    pub bpm_diff: f64,
+    pub over_threshold: bool,
+    pub tempo_break: bool,
+    /// Weight of the harmonic cost from the two tracks' key confidences (1 without).
+    pub key_confidence: f64,
+    /// Scale of the harmonic cost from the outgoing track's blend length (1 without).
+    pub blend_scale: f64,
+    /// Unweighted `(h, t)` of the edge, as `total_edge_cost` sums them.
+    pub harmonic: f64,
+    pub tempo: f64,
+    /// Weighted edge cost, as the solvers sum it.
+    pub cost: f64,
+    /// Weighted node cost (the shift penalty) of `to_track`, and of `from_track` too on
+    /// the first transition, so every track is counted once.
+    pub shift_cost: f64,
+    /// Anchor edges: into `from_track` on the first transition, out of `to_track` on the
+    /// last.
+    pub anchor_cost: f64,
 }
 
-/// Sum edge costs for all adjacent pairs in the order (full cost scan).
+/// Every transition of `order` with its (node-indexed) `shifts`, in play order, for
+/// performing the mix.  The `harmonic` and `tempo` columns sum to the `h` and `t` of
+/// `total_edge_cost` exactly when nodes carry no cost of their own (no contracted blocks),
+/// and `cost + shift_cost + anchor_cost` sums to the objective.  An order of one track has
+/// no transitions, so its node cost and anchor edges appear nowhere.
+pub fn transition_report(order: &[usize], shifts: &[i8], tables: &Tables, params: &CostParams) -> Vec<TransitionRecord> {
+    let last = order.len().saturating_sub(2);
+    order
+        .windows(2)
+        .enumerate()
+        .map(|(j, w)| {
+            let (i1, i2) = (w[0], w[1]);
+            let e = explain_edge(i1, i2, shifts[i1], shifts[i2], tables, params);
+            let (harmonic, tempo) = edge_components(i1, i2, shifts[i1], shifts[i2], tables, params);
+            let mut shift_cost = tables.node_cost(i2, shifts[i2], params);
+            let mut anchor_cost = 0.0;
+            if j == 0 {
+                shift_cost += tables.node_cost(i1, shifts[i1], params);
+                anchor_cost += tables.entry_cost(i1, shifts[i1], params);
+            }
+            if j == last {
+                anchor_cost += tables.exit_cost(i2, shifts[i2], params);
+            }
+            TransitionRecord {
+                from_track: i1,
+                to_track: i2,
+                from_key: e.from_effective_key,
+                to_key: e.to_effective_key,
+                bpm_diff: e.bpm_diff,
+                over_threshold: e.over_threshold,
+                tempo_break: e.tempo_break,
+                key_confidence: e.key_confidence,
+                blend_scale: e.blend_scale,
+                harmonic,
+                tempo,
+                cost: edge_cost(i1, i2, shifts[i1], shifts[i2], tables, params),
+                shift_cost,
+                anchor_cost,
+            }
+        })
+        .collect()
+}
+
+/// Sum edge costs for all adjacent pairs in the order (full cost scan), plus each node's
+/// own `(h, t, s)` contribution (shift penalty, and internal edges for contracted blocks).
 pub fn total_edge_cost(
     order: &[usize],
     shifts: &[i8],
-    bpms: &[i32],
-    key_ids: &[u8],
-    shift_table: &[u8],
-    direct_costs: &[f64],
-    indirect_costs: &[f64],
+    tables: &Tables,
     params: &CostParams,
 ) -> (f64, f64, f64) {
     let n = order.len();
     let mut h_total = 0.0f64;
     let mut t_total = 0.0f64;
+    let mut s_total = 0.0f64;
 
     for j in 0..n - 1 {
-        let i1 = order[j];
-        let i2 = order[j + 1];
-        let diff = (bpms[i1] - bpms[i2]).unsigned_abs() as f64;
-        let break_thresh = params.tempo_break_threshold();
-
-        if diff > break_thresh {
-            t_total += params.tempo_penalty * params.tempo_break_factor;
-        } else {
-            let ek1 = shift_table[key_ids[i1] as usize * 3 + (shifts[i1] + 1) as usize] as usize;
-            let ek2 = shift_table[key_ids[i2] as usize * 3 + (shifts[i2] + 1) as usize] as usize;
-            let idx = ek1 * params.num_keys + ek2;
-            let direct = direct_costs[idx];
-            let h = if direct == params.non_harmonic_cost && indirect_costs[idx] >= params.non_harmonic_cost {
-                direct + 2.0 * params.non_harmonic_cost
-            } else {
-                direct
-            };
-            h_total += h;
-            if diff > params.tempo_threshold {
-                t_total += params.tempo_penalty;
-            }
-        }
+        let (i1, i2) = (order[j], order[j + 1]);
+        let (h, t) = edge_components(i1, i2, shifts[i1], shifts[i2], tables, params);
+        h_total += h;
+        t_total += t;
     }
 
-    let s_total = params.shift_penalty
-        * order.iter().filter(|&&i| shifts[i] != 0).count() as f64;
+    for &i in order {
+        let (h, t, s) = tables.node_components(i, shifts[i], params);
+        h_total += h;
+        t_total += t;
+        s_total += s;
+    }
 
     (h_total, t_total, s_total)
 }
 
+/// Objective of each order with its (node-indexed) shifts, as the solvers report it:
+/// `h + tempo_cost_weight * t + shift_weight * s` plus the anchor edges.  The orders are
+/// split into contiguous chunks over up to `threads` scoped threads.
+pub fn score_orders(
+    orders: &[Vec<usize>],
+    shifts: &[Vec<i8>],
+    tables: &Tables,
+    params: &CostParams,
+    threads: usize,
+) -> Vec<f64> {
+    let score = |(order, shifts): (&Vec<usize>, &Vec<i8>)| -> f64 { order_cost(order, shifts, tables, params).0 };
+    let chunk = orders.len().div_ceil(threads.max(1)).max(1);
+    if chunk >= orders.len() {
+        return orders.iter().zip(shifts).map(score).collect();
+    }
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = orders
+            .chunks(chunk)
+            .zip(shifts.chunks(chunk))
+            .map(|(o, s)| scope.spawn(move || o.iter().zip(s).map(score).collect::<Vec<f64>>()))
+            .collect();
+        handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+    })
+}
+
+/// Objective of one order as `score_orders` computes it, and its `(h, t, s)` breakdown.
+fn order_cost(order: &[usize], shifts: &[i8], tables: &Tables, params: &CostParams) -> (f64, (f64, f64, f64)) {
+    let (h, t, s) = total_edge_cost(order, shifts, tables, params);
+    let cost = h + params.tempo_cost_weight * t + params.shift_weight * s + tables.boundary_cost(order, shifts, params);
+    (cost, (h, t, s))
+}
+
+/// Check that `order` is a permutation of 0..n-1 and `shifts` holds n shifts of -1, 0 or +1.
+pub fn validate_order_and_shifts(order: &[usize], shifts: &[i8], n: usize) -> Result<(), String> {
+    if order.len() != n || shifts.len() != n {
+        return Err(format!("order and shifts must have {n} entries, got {} and {}", order.len(), shifts.len()));
+    }
+    let mut seen = vec![false; n];
+    if order.iter().any(|&t| t >= n || std::mem::replace(&mut seen[t], true)) {
+        return Err(format!("order is not a permutation of 0..{}", n.saturating_sub(1)));
+    }
+    if let Some(i) = shifts.iter().position(|s| !(-1..=1).contains(s)) {
+        return Err(format!("shifts[{i}] is {}, shifts must be -1, 0 or +1", shifts[i]));
+    }
+    Ok(())
+}
+
+/// The reference score of a complete order with its (node-indexed) shifts: the objective
+/// the solvers report, from a full `total_edge_cost` scan plus the weighted shift
+/// penalty and the anchor edges, and the unweighted `(h, t, s)` breakdown.  Every
+/// incremental delta in this module must agree with differences of it.
+pub fn evaluate_order(
+    order: &[usize],
+    shifts: &[i8],
+    tables: &Tables,
+    params: &CostParams,
+) -> Result<(f64, (f64, f64, f64)), String> {
+    let n = tables.bpms.len();
+    if n == 0 {
+        return Err("Need at least 1 track".to_string());
+    }
+    validate_order_and_shifts(order, shifts, n)?;
+    Ok(order_cost(order, shifts, tables, params))
+}
+
+/// What `compare_orders` found: each order's `evaluate_order` score and `edge_costs`, and
+/// the transitions (i, j) — i directly before j — that one plays and the other does not,
+/// in play order.  Direction matters, as in `similarity`: i → j and j → i differ.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrderComparison {
+    pub cost_a: f64,
+    pub breakdown_a: (f64, f64, f64),
+    pub cost_b: f64,
+    pub breakdown_b: (f64, f64, f64),
+    pub edge_costs_a: Vec<f64>,
+    pub edge_costs_b: Vec<f64>,
+    pub only_in_a: Vec<(usize, usize)>,
+    pub only_in_b: Vec<(usize, usize)>,
+}
+
+/// Score two complete orders of the same tracks (e.g. the user's arrangement and an
+/// optimizer's) edge by edge, to show which transitions a change replaced.
+pub fn compare_orders(
+    order_a: &[usize],
+    shifts_a: &[i8],
+    order_b: &[usize],
+    shifts_b: &[i8],
+    tables: &Tables,
+    params: &CostParams,
+) -> Result<OrderComparison, String> {
+    let (cost_a, breakdown_a) = evaluate_order(order_a, shifts_a, tables, params).map_err(|e| format!("order_a: {e}"))?;
+    let (cost_b, breakdown_b) = evaluate_order(order_b, shifts_b, tables, params).map_err(|e| format!("order_b: {e}"))?;
+    // The transitions of `order` missing from the order whose successors are `next`
+    let missing = |order: &[usize], next: &[Option<usize>]| -> Vec<(usize, usize)> {
+        order.windows(2).filter(|w| next[w[0]] != Some(w[1])).map(|w| (w[0], w[1])).collect()
+    };
+    let successors = |order: &[usize]| {
+        let mut next = vec![None; order.len()];
+        for w in order.windows(2) {
+            next[w[0]] = Some(w[1]);
+        }
+        next
+    };
+    Ok(OrderComparison {
+        cost_a,
+        breakdown_a,
+        cost_b,
+        breakdown_b,
+        edge_costs_a: edge_costs(order_a, shifts_a, tables, params),
+        edge_costs_b: edge_costs(order_b, shifts_b, tables, params),
+        only_in_a: missing(order_a, &successors(order_b)),
+        only_in_b: missing(order_b, &successors(order_a)),
+    })
+}
+
 /// Returns the set of edge start-positions (j meaning edge j→j+1) affected by swapping positions a and b.
 /// Returned as a small fixed-size array; count indicates how many are valid.
 pub fn affected_edges(a: usize, b: usize, n: usize, out: &mut [usize; 4]) -> usize {
@@ -134,61 +1554,433 @@ pub fn affected_edges(a: usize, b: usize, n: usize, out: &mut [usize; 4]) -> usi
     count
 }
 
+/// Weighted cost of every edge in the order: entry `j` is the edge from position j to j+1.
+pub fn edge_costs(order: &[usize], shifts: &[i8], tables: &Tables, params: &CostParams) -> Vec<f64> {
+    order
+        .windows(2)
+        .map(|w| edge_cost(w[0], w[1], shifts[w[0]], shifts[w[1]], tables, params))
+        .collect()
+}
+
+/// Cheapest `edge_cost` from node i into node j over the nine shift pairs, for every ordered
+/// pair of the n nodes: a flat row-major n×n matrix (entry i * n + j) for a compatibility
+/// heatmap.  Independent of any order; shift penalties are left out and the diagonal is 0.
+pub fn pairwise_best_costs(n: usize, tables: &Tables, params: &CostParams) -> Vec<f64> {
+    let mut costs = vec![0.0; n * n];
+    for i in 0..n {
+        for j in (0..n).filter(|&j| j != i) {
+            costs[i * n + j] = [-1, 0, 1]
+                .iter()
+                .flat_map(|&s1| [-1, 0, 1].map(|s2| edge_cost(i, j, s1, s2, tables, params)))
+                .fold(f64::INFINITY, f64::min);
+        }
+    }
+    costs
+}
+
+/// Objective of `order` (a permutation of all n nodes) with each track left out in turn,
+/// indexed by track: the order is re-stitched around the gap, its neighbours' shifts are
+/// re-chosen as `optimize_shift_at` would and everything else stays put.  This is a cheap
+/// upper bound on the cost a full re-optimization without the track would find.  O(n²).
+pub fn leave_one_out_costs(order: &[usize], shifts: &[i8], tables: &Tables, params: &CostParams) -> Vec<f64> {
+    let objective = |order: &[usize], shifts: &[i8]| -> f64 {
+        let (h, t, s) = total_edge_cost(order, shifts, tables, params);
+        h + params.tempo_cost_weight * t + params.shift_weight * s + tables.boundary_cost(order, shifts, params)
+    };
+    let mut costs = vec![0.0; order.len()];
+    let mut rest = Vec::with_capacity(order.len());
+    let mut rest_shifts = shifts.to_vec();
+    for (pos, &track) in order.iter().enumerate() {
+        rest.clear();
+        rest.extend(order.iter().enumerate().filter(|&(p, _)| p != pos).map(|(_, &t)| t));
+        rest_shifts.copy_from_slice(shifts);
+        // The former neighbours now sit at pos - 1 and pos
+        for q in [pos.wrapping_sub(1), pos].into_iter().filter(|&q| q < rest.len()) {
+            optimize_shift_at(&rest, &mut rest_shifts, q, tables, params);
+        }
+        costs[track] = if rest.is_empty() { 0.0 } else { objective(&rest, &rest_shifts) };
+    }
+    costs
+}
+
+/// `leave_one_out_costs` less the objective of `order`, indexed by track, evaluated
+/// locally: removing a track only changes its own node cost, its incident edges, the edges
+/// its neighbours' re-chosen shifts touch and (at an end of the set) an anchor edge, so
+/// each delta rescores the five positions around it.  Negative = the set is cheaper
+/// without the track.  O(n).
+pub fn removal_deltas(order: &[usize], shifts: &[i8], tables: &Tables, params: &CostParams) -> Vec<f64> {
+    let n = order.len();
+    // Edges, node costs and any anchor edge of `region`, a window of an order of `len`
+    // positions starting at `lo`
+    let local = |region: &[usize], shifts: &[i8], lo: usize, len: usize| -> f64 {
+        if region.is_empty() {
+            return 0.0;
+        }
+        let (first, last) = (region[0], region[region.len() - 1]);
+        let mut c: f64 = edge_costs(region, shifts, tables, params).iter().sum::<f64>()
+            + region.iter().map(|&t| tables.node_cost(t, shifts[t], params)).sum::<f64>();
+        if lo == 0 {
+            c += tables.entry_cost(first, shifts[first], params);
+        }
+        if lo + region.len() == len {
+            c += tables.exit_cost(last, shifts[last], params);
+        }
+        c
+    };
+    let mut deltas = vec![0.0; n];
+    let mut local_shifts = shifts.to_vec();
+    for (pos, &track) in order.iter().enumerate() {
+        let (lo, hi) = (pos.saturating_sub(2), (pos + 2).min(n - 1));
+        let before = local(&order[lo..=hi], shifts, lo, n);
+        let rest: Vec<usize> = order[lo..=hi].iter().copied().filter(|&t| t != track).collect();
+        // The former neighbours, now at pos - 1 and pos of the shorter order, sit inside
+        // the window, so re-choosing their shifts there sees their true edges
+        let at = pos - lo;
+        for q in [at.wrapping_sub(1), at].into_iter().filter(|&q| q < rest.len()) {
+            optimize_shift_at(&rest, &mut local_shifts, q, tables, params);
+        }
+        deltas[track] = local(&rest, &local_shifts, lo, n - 1) - before;
+        for &t in &rest {
+            local_shifts[t] = shifts[t];
+        }
+    }
+    deltas
+}
+
+/// Objective change from reversing positions `i..=j` of `order` (i <= j < n), without
+/// touching the order, plus the weighted cost of every edge the reversal rewrites as it
+/// would be afterwards: the edges from position `i.saturating_sub(1)` up to `min(j, n - 2)`.
+/// The internal edges are recomputed as well as the two boundary ones, since intro/outro
+/// BPMs, directed tables and adjacency bonuses make an edge's cost depend on its direction.
+/// Shifts are node-indexed and move with their tracks; anchors are charged when the segment
+/// reaches an end of the set.  O(j - i).
+pub fn reverse_segment_delta(
+    order: &[usize],
+    shifts: &[i8],
+    i: usize,
+    j: usize,
+    tables: &Tables,
+    params: &CostParams,
+) -> (f64, Vec<f64>) {
+    let n = order.len();
+    assert!(i <= j && j < n, "segment {i}..={j} is not within an order of {n} tracks");
+    let (first, last) = (i.saturating_sub(1), (j + 1).min(n - 1));
+    let mut region = order[first..=last].to_vec();
+    let before: f64 = edge_costs(&region, shifts, tables, params).iter().sum();
+    region[i - first..=j - first].reverse();
+    let after = edge_costs(&region, shifts, tables, params);
+    let mut delta = after.iter().sum::<f64>() - before;
+    if i == 0 {
+        let (old, new) = (order[0], order[j]);
+        delta += tables.entry_cost(new, shifts[new], params) - tables.entry_cost(old, shifts[old], params);
+    }
+    if j == n - 1 {
+        let (old, new) = (order[n - 1], order[i]);
+        delta += tables.exit_cost(new, shifts[new], params) - tables.exit_cost(old, shifts[old], params);
+    }
+    (delta, after)
+}
+
+/// The parts of the objective a swap of positions `a` and `b` can change: the weighted
+/// cost of the `affected` edges (from `affected_edges`) plus the anchor edges, and the node
+/// costs of the tracks at `a` and `b`.  The annealer scores its swaps with this, before and
+/// after, as do `swap_delta` and `move_delta`.
+#[inline(always)]
+pub fn swap_local_cost(
+    order: &[usize],
+    shifts: &[i8],
+    affected: &[usize],
+    a: usize,
+    b: usize,
+    tables: &Tables,
+    params: &CostParams,
+) -> (f64, f64) {
+    (
+        sum_edge_costs(affected, order, shifts, tables, params) + tables.boundary_cost(order, shifts, params),
+        tables.node_cost(order[a], shifts[order[a]], params) + tables.node_cost(order[b], shifts[order[b]], params),
+    )
+}
+
+/// What a manual edit would do to an order, from `swap_delta` or `move_delta`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MovePreview {
+    /// Objective after the edit less before (negative = better).
+    pub delta: f64,
+    /// `(j, cost)` for each edge the edit rewrites: edge j runs from position j to j + 1 of
+    /// the edited order, at its weighted cost there.
+    pub edges: Vec<(usize, f64)>,
+    /// `(track, shift)` for each moved track, its shift re-chosen for its new neighbours.
+    pub shifts: Vec<(usize, i8)>,
+}
+
+/// Objective change from swapping positions `a` and `b` (distinct, < n) exactly as the
+/// annealer scores the move: the shifts of both tracks are re-chosen by `optimize_shift_at`
+/// at their new positions, first `a` then `b`, and the affected edges, anchor edges and
+/// node costs are compared.  Nothing is modified.  Penalties and hard limits outside the
+/// edge costs (separation, family runs, shift caps) are not applied.
+pub fn swap_delta(order: &[usize], shifts: &[i8], a: usize, b: usize, tables: &Tables, params: &CostParams) -> MovePreview {
+    let n = order.len();
+    assert!(a != b && a < n && b < n, "cannot swap positions {a} and {b} of an order of {n} tracks");
+    let mut buf = [0usize; 4];
+    let count = affected_edges(a, b, n, &mut buf);
+    let affected = &buf[..count];
+    let mut order = order.to_vec();
+    let mut shifts = shifts.to_vec();
+    let (old_edges, old_nodes) = swap_local_cost(&order, &shifts, affected, a, b, tables, params);
+    order.swap(a, b);
+    for pos in [a, b] {
+        optimize_shift_at(&order, &mut shifts, pos, tables, params);
+    }
+    let (new_edges, new_nodes) = swap_local_cost(&order, &shifts, affected, a, b, tables, params);
+    let mut edges: Vec<(usize, f64)> = affected
+        .iter()
+        .map(|&j| (j, edge_cost(order[j], order[j + 1], shifts[order[j]], shifts[order[j + 1]], tables, params)))
+        .collect();
+    edges.sort_by_key(|&(j, _)| j);
+    MovePreview {
+        delta: (new_edges - old_edges) + (new_nodes - old_nodes),
+        edges,
+        shifts: [a, b].iter().map(|&p| (order[p], shifts[order[p]])).collect(),
+    }
+}
+
+/// Objective change from taking the track at position `from` out of `order` and putting
+/// it back so that it plays at position `to` (both < n), its shift re-chosen there by
+/// `optimize_shift_at`.  The tracks in between each move up or down one place; only the
+/// edges around the gap and the new slot are rewritten.  As `swap_delta`, nothing is
+/// modified and penalties outside the edge costs are not applied.  O(|to - from|).
+pub fn move_delta(
+    order: &[usize],
+    shifts: &[i8],
+    from: usize,
+    to: usize,
+    tables: &Tables,
+    params: &CostParams,
+) -> MovePreview {
+    let n = order.len();
+    assert!(from < n && to < n, "cannot move position {from} to {to} of an order of {n} tracks");
+    let track = order[from];
+    // The edges from the one before the first moved position to the one after the last
+    let (lo, hi) = (from.min(to), from.max(to));
+    let region: Vec<usize> = (lo.saturating_sub(1)..(hi + 1).min(n - 1)).collect();
+    let local = |order: &[usize], shifts: &[i8]| -> f64 {
+        sum_edge_costs(&region, order, shifts, tables, params)
+            + tables.boundary_cost(order, shifts, params)
+            + tables.node_cost(track, shifts[track], params)
+    };
+    let before = local(order, shifts);
+    let mut moved = order.to_vec();
+    let mut new_shifts = shifts.to_vec();
+    moved.remove(from);
+    moved.insert(to, track);
+    optimize_shift_at(&moved, &mut new_shifts, to, tables, params);
+    let after = local(&moved, &new_shifts);
+
+    // Where the track now sits, and the edge that closes the gap it left
+    let gap = match from.cmp(&to) {
+        std::cmp::Ordering::Less => from.checked_sub(1),
+        std::cmp::Ordering::Greater => Some(from),
+        std::cmp::Ordering::Equal => None,
+    };
+    let mut rewritten: Vec<usize> = [to.checked_sub(1), Some(to), gap].into_iter().flatten().filter(|&j| j + 1 < n).collect();
+    rewritten.sort_unstable();
+    rewritten.dedup();
+    let edges = rewritten
+        .into_iter()
+        .map(|j| (j, edge_cost(moved[j], moved[j + 1], new_shifts[moved[j]], new_shifts[moved[j + 1]], tables, params)))
+        .collect();
+    MovePreview { delta: after - before, edges, shifts: vec![(track, new_shifts[track])] }
+}
+
+/// Result of `change_delta`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChangeDelta {
+    /// Objective after the changes less before (negative = better).
+    pub delta: f64,
+    /// The part of `delta` from node costs: the shift penalties of re-shifted tracks.
+    pub shift_delta: f64,
+    /// `(j, cost)` for each touched edge (see `touched_edges`), at its weighted cost after
+    /// the changes.
+    pub edges: Vec<(usize, f64)>,
+}
+
+/// Objective change from an arbitrary local move, for local searches outside the
+/// annealer: `changes` puts track `t` at position `p` for each `(p, t)`, and
+/// `shift_changes` gives track `t` shift `s` for each `(t, s)`.  The tracks placed must be
+/// a rearrangement of the ones they replace, so the order stays a permutation.  Only the
+/// edges touching a changed position or a re-shifted track, the anchor edges and the
+/// re-shifted tracks' node costs are rescored; nothing is modified.  O(k log k) for k
+/// changes and re-shifts (they are sorted for lookup), plus O(n) to find each re-shifted
+/// track that does not move.
+pub fn change_delta(
+    order: &[usize],
+    shifts: &[i8],
+    changes: &[(usize, usize)],
+    shift_changes: &[(usize, i8)],
+    tables: &Tables,
+    params: &CostParams,
+) -> Result<ChangeDelta, String> {
+    let n = order.len();
+    let mut old_tracks = Vec::with_capacity(changes.len());
+    let mut new_tracks = Vec::with_capacity(changes.len());
+    for &(p, t) in changes {
+        if p >= n || t >= n {
+            return Err(format!("change ({p}, {t}) is out of range for an order of {n} tracks"));
+        }
+        old_tracks.push(order[p]);
+        new_tracks.push(t);
+    }
+    let mut positions: Vec<usize> = changes.iter().map(|&(p, _)| p).collect();
+    positions.sort_unstable();
+    if let Some(w) = positions.windows(2).find(|w| w[0] == w[1]) {
+        return Err(format!("position {} is changed more than once", w[0]));
+    }
+    old_tracks.sort_unstable();
+    new_tracks.sort_unstable();
+    if old_tracks != new_tracks {
+        return Err("the changes must rearrange the tracks at the changed positions".to_string());
+    }
+    if let Some(&(t, s)) = shift_changes.iter().find(|&&(t, s)| t >= n || !(-1..=1).contains(&s)) {
+        return Err(format!("shift change ({t}, {s}) needs a track below {n} and a shift of -1, 0 or +1"));
+    }
+    let mut reshifts = shift_changes.to_vec();
+    reshifts.sort_unstable_by_key(|&(t, _)| t);
+    if let Some(w) = reshifts.windows(2).find(|w| w[0].0 == w[1].0) {
+        return Err(format!("track {} is re-shifted more than once", w[0].0));
+    }
+
+    // Sorted copies of the changes, so each lookup is a binary search
+    let mut placed = changes.to_vec();
+    placed.sort_unstable_by_key(|&(p, _)| p);
+    let mut destinations: Vec<(usize, usize)> = changes.iter().map(|&(p, t)| (t, p)).collect();
+    destinations.sort_unstable_by_key(|&(t, _)| t);
+    let track_at = |p: usize| placed.binary_search_by_key(&p, |&(q, _)| q).map_or(order[p], |k| placed[k].1);
+    let shift_of = |t: usize| reshifts.binary_search_by_key(&t, |&(u, _)| u).map_or(shifts[t], |k| reshifts[k].1);
+    // A re-shifted track's edges change wherever it ends up
+    for &(t, _) in shift_changes {
+        let p = destinations.binary_search_by_key(&t, |&(u, _)| u).map(|k| destinations[k].1);
+        positions.push(p.unwrap_or_else(|_| order.iter().position(|&u| u == t).expect("order is a permutation")));
+    }
+    let mut edges = Vec::new();
+    touched_edges(&positions, n, &mut edges);
+
+    let mut delta = 0.0;
+    let edges = edges
+        .into_iter()
+        .map(|j| {
+            let (a, b) = (track_at(j), track_at(j + 1));
+            let new = edge_cost(a, b, shift_of(a), shift_of(b), tables, params);
+            delta += new - edge_cost(order[j], order[j + 1], shifts[order[j]], shifts[order[j + 1]], tables, params);
+            (j, new)
+        })
+        .collect();
+    if n > 0 {
+        let (first, last) = (track_at(0), track_at(n - 1));
+        delta += tables.entry_cost(first, shift_of(first), params) + tables.exit_cost(last, shift_of(last), params)
+            - tables.boundary_cost(order, shifts, params);
+    }
+    let shift_delta: f64 = shift_changes
+        .iter()
+        .map(|&(t, s)| tables.node_cost(t, s, params) - tables.node_cost(t, shifts[t], params))
+        .sum();
+    Ok(ChangeDelta { delta: delta + shift_delta, shift_delta, edges })
+}
+
+/// Reverse `order` in place when that puts the lower-indexed end track first and leaves
+/// the cost unchanged, so that a symmetric configuration, where the optimizers may return
+/// either orientation, always returns the same one.  `extra` scores any order-dependent
+/// terms beyond the edges and anchors (e.g. separation penalties).  Shifts are node-indexed
+/// and need no change.  Returns whether the order was reversed.
+pub fn canonicalize_orientation(
+    order: &mut [usize],
+    shifts: &[i8],
+    tables: &Tables,
+    params: &CostParams,
+    extra: impl Fn(&[usize]) -> f64,
+) -> bool {
+    if order.len() < 2 || order[0] < order[order.len() - 1] {
+        return false;
+    }
+    let cost = |order: &[usize]| -> f64 {
+        let (h, t, s) = total_edge_cost(order, shifts, tables, params);
+        h + params.tempo_cost_weight * t + params.shift_weight * s + tables.boundary_cost(order, shifts, params)
+            + extra(order)
+    };
+    let forward = cost(order);
+    let reversed: Vec<usize> = order.iter().rev().copied().collect();
+    if (cost(&reversed) - forward).abs() > 1e-9 * forward.abs().max(1.0) {
+        return false;
+    }
+    order.copy_from_slice(&reversed);
+    true
+}
+
+/// `affected_edges` for any set of changed `positions` of an order of `n`: the edges
+/// (j meaning j→j+1) into or out of them, sorted and without repeats, written to `out`.
+pub fn touched_edges(positions: &[usize], n: usize, out: &mut Vec<usize>) {
+    out.clear();
+    for &p in positions {
+        if p > 0 {
+            out.push(p - 1);
+        }
+        if p + 1 < n {
+            out.push(p);
+        }
+    }
+    out.sort_unstable();
+    out.dedup();
+}
+
 /// Sum costs for the given set of edge positions.
 pub fn sum_edge_costs(
     edge_positions: &[usize],
     order: &[usize],
     shifts: &[i8],
-    bpms: &[i32],
-    key_ids: &[u8],
-    shift_table: &[u8],
-    direct_costs: &[f64],
-    indirect_costs: &[f64],
+    tables: &Tables,
     params: &CostParams,
 ) -> f64 {
     edge_positions.iter().map(|&j| {
         edge_cost(
             order[j], order[j + 1],
             shifts[order[j]], shifts[order[j + 1]],
-            bpms, key_ids, shift_table, direct_costs, indirect_costs, params,
+            tables, params,
         )
     }).sum()
 }
 
 /// Optimize shift for position `pos` in-place using fast integer lookups.
-/// Tries shifts -1, 0, +1 and picks the one minimizing local edge cost.
+/// Tries shifts 0, -1, +1 and picks the one minimizing the local objective: the adjacent
+/// edges plus the node's own cost (its shift penalty, and the internal cost of a
+/// contracted block node).  Ties go to the smaller |shift|, so a track never keeps a
+/// shift that buys nothing.
 pub fn optimize_shift_at(
     order: &[usize],
     shifts: &mut [i8],
     pos: usize,
-    bpms: &[i32],
-    key_ids: &[u8],
-    shift_table: &[u8],
-    direct_costs: &[f64],
-    indirect_costs: &[f64],
+    tables: &Tables,
     params: &CostParams,
 ) {
     let i = order[pos];
     let n = order.len();
 
     let local_cost = |s: i8| -> f64 {
-        let mut c = 0.0;
+        let mut c = tables.node_cost(i, s, params);
         if pos > 0 {
-            c += edge_cost(order[pos - 1], i, shifts[order[pos - 1]], s,
-                           bpms, key_ids, shift_table, direct_costs, indirect_costs, params);
+            c += edge_cost(order[pos - 1], i, shifts[order[pos - 1]], s, tables, params);
+        } else {
+            c += tables.entry_cost(i, s, params);
         }
         if pos < n - 1 {
-            c += edge_cost(i, order[pos + 1], s, shifts[order[pos + 1]],
-                           bpms, key_ids, shift_table, direct_costs, indirect_costs, params);
+            c += edge_cost(i, order[pos + 1], s, shifts[order[pos + 1]], tables, params);
+        } else {
+            c += tables.exit_cost(i, s, params);
         }
         c
     };
 
-    let current_cost = local_cost(shifts[i]);
-    let mut best_s = shifts[i];
-    let mut best_cost = current_cost;
-
-    for s in [-1i8, 0, 1] {
+    let mut best_s = 0;
+    let mut best_cost = local_cost(0);
+    for s in [-1i8, 1] {
         let c = local_cost(s);
         if c < best_cost {
             best_cost = c;
@@ -0,0 +1,117 @@
+//! Tempo coverage rules: make a "journey" set span the library's tempo range instead of
+//! settling wherever the cheapest transitions are.
+//!
+//! A rule asks for at least `min_count` tracks with an intro BPM in `bpms` (inclusive)
+//! within a window of positions given as fractions of the set: `(0.0, 0.25)` is the first
+//! quarter, positions ⌊0.0·n⌋ .. ⌈0.25·n⌉ (exclusive).  Its shortfall is the number of such
+//! tracks missing.  As with separation groupings, a rule is hard when it can be met on its
+//! own (enough matching tracks, and a window at least `min_count` long): the annealer then
+//! rejects any swap that increases its shortfall.  Every rule also charges `penalty` per
+//! missing track, which steers a start that falls short and is all an infeasible rule gets.
+
+pub struct CoverageRule {
+    /// Window as fractions of the set, 0 <= start < end <= 1.
+    pub positions: (f64, f64),
+    /// Inclusive intro-BPM range.
+    pub bpms: (i32, i32),
+    pub min_count: usize,
+    pub penalty: f64,
+}
+
+/// A rule resolved against an instance.
+struct Window {
+    lo: usize,
+    hi: usize,
+    /// Whether each node's BPM is in range.
+    matches: Vec<bool>,
+    min_count: usize,
+    penalty: f64,
+    hard: bool,
+}
+
+impl Window {
+    fn count(&self, order: &[usize]) -> usize {
+        order[self.lo..self.hi].iter().filter(|&&i| self.matches[i]).count()
+    }
+
+    fn shortfall(&self, order: &[usize]) -> usize {
+        self.min_count.saturating_sub(self.count(order))
+    }
+}
+
+/// All coverage rules of one optimisation call.
+pub struct Coverage {
+    windows: Vec<Window>,
+}
+
+impl Coverage {
+    /// Resolve `rules` over nodes with intro BPMs `bpms`.
+    pub fn new(rules: &[CoverageRule], bpms: &[i32]) -> Result<Self, String> {
+        let n = bpms.len();
+        let mut windows = Vec::with_capacity(rules.len());
+        for (r, rule) in rules.iter().enumerate() {
+            let (start, end) = rule.positions;
+            if !(0.0 <= start && start < end && end <= 1.0) {
+                return Err(format!(
+                    "rule {r}: positions must satisfy 0 <= start < end <= 1, got ({start}, {end})"
+                ));
+            }
+            if rule.bpms.0 > rule.bpms.1 {
+                return Err(format!("rule {r}: BPM range ({}, {}) is empty", rule.bpms.0, rule.bpms.1));
+            }
+            if !(rule.penalty >= 0.0 && rule.penalty.is_finite()) {
+                return Err(format!(
+                    "rule {r}: penalty must be a finite value >= 0, got {}", rule.penalty
+                ));
+            }
+            let lo = (start * n as f64).floor() as usize;
+            let hi = ((end * n as f64).ceil() as usize).min(n);
+            let matches: Vec<bool> = bpms.iter().map(|b| (rule.bpms.0..=rule.bpms.1).contains(b)).collect();
+            let available = matches.iter().filter(|&&m| m).count();
+            let hard = rule.min_count <= available.min(hi - lo);
+            windows.push(Window { lo, hi, matches, min_count: rule.min_count, penalty: rule.penalty, hard });
+        }
+        Ok(Coverage { windows })
+    }
+
+    /// "hard" or "penalty" per rule.
+    pub fn modes(&self) -> Vec<&'static str> {
+        self.windows.iter().map(|w| if w.hard { "hard" } else { "penalty" }).collect()
+    }
+
+    /// Missing tracks per rule in the given ordering (0 = satisfied).
+    pub fn shortfalls(&self, order: &[usize]) -> Vec<usize> {
+        self.windows.iter().map(|w| w.shortfall(order)).collect()
+    }
+
+    /// Weighted penalty of all shortfalls in the given ordering.
+    pub fn penalty(&self, order: &[usize]) -> f64 {
+        self.windows.iter().map(|w| w.penalty * w.shortfall(order) as f64).sum()
+    }
+
+    /// Penalty delta for swapping positions `a` and `b`, or `None` when the swap would
+    /// increase a hard rule's shortfall.  Only rules whose window holds exactly one of the
+    /// two positions, and whose count the swap changes, are recounted.
+    pub fn swap_delta(&self, order: &[usize], a: usize, b: usize) -> Option<f64> {
+        let mut delta = 0.0;
+        for w in &self.windows {
+            let (in_a, in_b) = ((w.lo..w.hi).contains(&a), (w.lo..w.hi).contains(&b));
+            if in_a == in_b {
+                continue;
+            }
+            let (inside, outside) = if in_a { (a, b) } else { (b, a) };
+            let change = w.matches[order[outside]] as isize - w.matches[order[inside]] as isize;
+            if change == 0 {
+                continue;
+            }
+            let count = w.count(order);
+            let old = w.min_count.saturating_sub(count);
+            let new = w.min_count.saturating_sub(count.saturating_add_signed(change));
+            if w.hard && new > old {
+                return None;
+            }
+            delta += w.penalty * (new as f64 - old as f64);
+        }
+        Some(delta)
+    }
+}
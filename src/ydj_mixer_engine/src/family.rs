@@ -0,0 +1,95 @@
+//! Key-family run limit: at most `max_run` consecutive tracks whose effective keys share a
+//! Camelot number (8A and 8B are one family), however smooth each transition is.
+//!
+//! Key IDs follow the 24-key Camelot layout (id = (number - 1) * 2 + letter), so the family
+//! of an effective key is `id / 2`.  A shift moves a track to another family, so families
+//! are taken at the track's current shift.
+//!
+//! The annealer rejects any move that adds to the excess (tracks beyond the limit, summed
+//! over all runs) and charges `penalty` per excess track, which steers an unclean random
+//! start back to zero as with hard separation groupings.  Held-Karp keeps the current run
+//! length in its state and prunes longer runs, so its result always satisfies the limit.
+
+use crate::cost::{effective_key, Tables};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FamilyRunLimit {
+    /// Longest allowed run, at least 1.
+    pub max_run: usize,
+    /// Cost per track beyond `max_run` while the annealer works back to a clean order.
+    pub penalty: f64,
+}
+
+/// Family of node `i` at shift `s`: the Camelot number of its effective key.
+#[inline(always)]
+pub fn family(tables: &Tables, i: usize, s: i8) -> usize {
+    effective_key(tables.shift_table, tables.key_ids[i], s) / 2
+}
+
+/// Positions `(first, last)` of the same-family run through position `pos`.
+fn run_at(order: &[usize], shifts: &[i8], tables: &Tables, pos: usize) -> (usize, usize) {
+    let fam = |p: usize| family(tables, order[p], shifts[order[p]]);
+    let f = fam(pos);
+    let mut first = pos;
+    while first > 0 && fam(first - 1) == f {
+        first -= 1;
+    }
+    let mut last = pos;
+    while last + 1 < order.len() && fam(last + 1) == f {
+        last += 1;
+    }
+    (first, last)
+}
+
+/// Length of the longest same-family run in the order.
+pub fn longest_run(order: &[usize], shifts: &[i8], tables: &Tables) -> usize {
+    let mut longest = 0;
+    let mut pos = 0;
+    while pos < order.len() {
+        let (first, last) = run_at(order, shifts, tables, pos);
+        longest = longest.max(last - first + 1);
+        pos = last + 1;
+    }
+    longest
+}
+
+impl FamilyRunLimit {
+    fn run_excess(&self, (first, last): (usize, usize)) -> usize {
+        (last - first + 1).saturating_sub(self.max_run)
+    }
+
+    /// Tracks beyond the limit, summed over every run of the order.
+    pub fn excess(&self, order: &[usize], shifts: &[i8], tables: &Tables) -> usize {
+        let mut total = 0;
+        let mut pos = 0;
+        while pos < order.len() {
+            let run = run_at(order, shifts, tables, pos);
+            total += self.run_excess(run);
+            pos = run.1 + 1;
+        }
+        total
+    }
+
+    /// `penalty` times `excess`.
+    pub fn penalty(&self, order: &[usize], shifts: &[i8], tables: &Tables) -> f64 {
+        self.penalty * self.excess(order, shifts, tables) as f64
+    }
+
+    /// Excess of the runs through positions `a`, `b` and their neighbours (each run once).
+    ///
+    /// Swapping `a` and `b` and re-choosing their shifts can only change runs that touch
+    /// one of these positions, so comparing this before and after the move gives the exact
+    /// change in `excess`.
+    pub fn local_excess(&self, order: &[usize], shifts: &[i8], tables: &Tables, a: usize, b: usize) -> usize {
+        let mut runs = [(usize::MAX, usize::MAX); 6];
+        let mut count = 0;
+        for p in [a.wrapping_sub(1), a, a + 1, b.wrapping_sub(1), b, b + 1] {
+            if p >= order.len() || runs[..count].iter().any(|&(f, l)| f <= p && p <= l) {
+                continue;
+            }
+            runs[count] = run_at(order, shifts, tables, p);
+            count += 1;
+        }
+        runs[..count].iter().map(|&run| self.run_excess(run)).sum()
+    }
+}
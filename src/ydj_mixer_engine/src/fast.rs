@@ -0,0 +1,136 @@
+//! Fast approximate solver: space-filling-curve ordering plus a short local cleanup.
+//!
+//! Each node is placed on a 1024 x 1024 grid by (entry BPM, key ID) and the nodes are
+//! sorted by their position along a Hilbert curve, so neighbours in the order tend to be
+//! close in both tempo and key.  Key IDs follow the Camelot wheel (`1A, 1B, 2A, ...`), so
+//! adjacent IDs are harmonically related; the wrap from 12 back to 1 is not modelled.
+//!
+//! The cleanup then makes a few greedy passes of windowed swaps and per-node shift choices,
+//! keeping only strict improvements.  The whole run is O(n log n + passes * n * window),
+//! which stays in the millisecond range for thousands of tracks.  The result is approximate:
+//! use it as a quick preview or as a seed for the annealing solver, not as a final answer.
+
+use crate::cost::{affected_edges, edge_cost, sum_edge_costs, total_edge_cost, CostParams, Tables};
+
+/// Hilbert curve order: the grid is `2^CURVE_BITS` cells per side.
+const CURVE_BITS: u32 = 10;
+
+/// Swap partners considered on each side of a position during cleanup.
+const SWAP_WINDOW: usize = 8;
+
+/// Maximum number of cleanup passes (each pass stops the loop early if it finds nothing).
+const MAX_PASSES: usize = 6;
+
+/// Distance of cell `(x, y)` along the Hilbert curve covering the `2^CURVE_BITS` grid.
+fn hilbert_index(mut x: u32, mut y: u32) -> u64 {
+    let side = 1u32 << CURVE_BITS;
+    let mut d = 0u64;
+    let mut s = side / 2;
+    while s > 0 {
+        let rx = u32::from(x & s > 0);
+        let ry = u32::from(y & s > 0);
+        d += u64::from(s) * u64::from(s) * u64::from((3 * rx) ^ ry);
+        // Rotate the quadrant so the sub-curve is in standard orientation
+        if ry == 0 {
+            if rx == 1 {
+                x = side - 1 - x;
+                y = side - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
+
+/// Initial order: nodes sorted by Hilbert index over (entry BPM, key ID).
+fn curve_order(n: usize, tables: &Tables, params: &CostParams) -> Vec<usize> {
+    let max_cell = (1u32 << CURVE_BITS) - 1;
+    let lo = tables.bpms[..n].iter().copied().min().unwrap_or(0);
+    let hi = tables.bpms[..n].iter().copied().max().unwrap_or(0);
+    let bpm_span = (hi as i64 - lo as i64).max(1) as f64;
+    let key_span = (params.num_keys.max(2) - 1) as f64;
+
+    let mut keyed: Vec<(u64, usize)> = (0..n)
+        .map(|i| {
+            let x = ((tables.bpms[i] as i64 - lo as i64) as f64 / bpm_span * max_cell as f64).round() as u32;
+            let y = (tables.key_ids[i] as f64 / key_span * max_cell as f64).round() as u32;
+            (hilbert_index(x.min(max_cell), y.min(max_cell)), i)
+        })
+        .collect();
+    keyed.sort_unstable();
+    keyed.into_iter().map(|(_, i)| i).collect()
+}
+
+/// Set the shift at `pos` to the one minimising its two edges plus its own node cost.
+/// Returns true if the shift changed.
+fn improve_shift_at(
+    order: &[usize],
+    shifts: &mut [i8],
+    pos: usize,
+    tables: &Tables,
+    params: &CostParams,
+) -> bool {
+    let i = order[pos];
+    let n = order.len();
+    let local_cost = |s: i8| -> f64 {
+        let mut c = tables.node_cost(i, s, params);
+        if pos > 0 {
+            c += edge_cost(order[pos - 1], i, shifts[order[pos - 1]], s, tables, params);
+        }
+        if pos < n - 1 {
+            c += edge_cost(i, order[pos + 1], s, shifts[order[pos + 1]], tables, params);
+        }
+        c
+    };
+
+    let current = shifts[i];
+    let mut best_s = current;
+    let mut best_cost = local_cost(current);
+    for s in [-1i8, 0, 1] {
+        let c = local_cost(s);
+        if c < best_cost {
+            best_cost = c;
+            best_s = s;
+        }
+    }
+    shifts[i] = best_s;
+    best_s != current
+}
+
+/// Run the fast heuristic over `n` nodes.  Returns (order, shifts, cost, (h, t, s)).
+pub fn run(n: usize, tables: &Tables, params: &CostParams) -> (Vec<usize>, Vec<i8>, f64, (f64, f64, f64)) {
+    let mut order = curve_order(n, tables, params);
+    let mut shifts = vec![0i8; n];
+    let mut edges = [0usize; 4];
+
+    for _ in 0..MAX_PASSES {
+        let mut improved = false;
+
+        for pos in 0..n {
+            improved |= improve_shift_at(&order, &mut shifts, pos, tables, params);
+        }
+
+        for a in 0..n {
+            for b in a + 1..(a + 1 + SWAP_WINDOW).min(n) {
+                let count = affected_edges(a, b, n, &mut edges);
+                let before = sum_edge_costs(&edges[..count], &order, &shifts, tables, params);
+                order.swap(a, b);
+                let after = sum_edge_costs(&edges[..count], &order, &shifts, tables, params);
+                if after < before - 1e-12 {
+                    improved = true;
+                } else {
+                    order.swap(a, b);
+                }
+            }
+        }
+
+        if !improved {
+            break;
+        }
+    }
+
+    let (h, t, s) = total_edge_cost(&order, &shifts, tables, params);
+    let cost = h + params.tempo_cost_weight * t + params.shift_weight * s;
+    (order, shifts, cost, (h, t, s))
+}
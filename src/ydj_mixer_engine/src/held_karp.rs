@@ -1,59 +1,284 @@
-/// Held-Karp exact dynamic-programming solver for the Hamiltonian Path problem.
-///
-/// Finds the optimal track ordering and per-track shifts minimising:
-///
-///   Σ edge_cost(π[i], π[i+1], s[π[i]], s[π[i+1]])   for i in 0..n-2
-///   + shift_weight * shift_penalty * |{ i : s[π[i]] ≠ 0 }|
-///
-/// DP state:
-///   dp[mask * n * 3 + last * 3 + s_idx]  =  minimum cost to:
-///       • visit exactly the tracks whose bits are set in `mask`
-///       • end at track `last`
-///       • with shift `s_idx - 1 ∈ {-1, 0, +1}` for that last track
-///
-/// Time complexity:  O(n² · 2ⁿ · 9)   ≈ O(n² · 2ⁿ)
-/// Space complexity: O(n · 2ⁿ · 3)
-///
-/// Practical limits (rough estimates on Apple Silicon):
-///   n ≤ 17 : < 1 s,  ~53 MB
-///   n ≤ 20 : ~5 s,  ~503 MB
-///   n > 20 : infeasible → use SA instead
-
-use crate::cost::{edge_cost, total_edge_cost, CostParams};
+//! Held-Karp exact dynamic-programming solver for the Hamiltonian Path problem.
+//!
+//! Finds the optimal track ordering and per-track shifts minimising:
+//!
+//!   Σ edge_cost(π[i], π[i+1], s[π[i]], s[π[i+1]])   for i in 0..n-2
+//!   + shift_weight * Σ shift_penalty_of(s[π[i]])        (per direction, if set)
+//!   + entry_cost(π[0]) + exit_cost(π[n-1])      (virtual anchors, if any)
+//!
+//! Optionally the path is pinned to start at a given track, or closed into a cycle: the
+//! classic TSP form, with the wrap edge π[n-1] → π[0] added when a full-mask state is
+//! closed.  A cycle is solved once per shift of its start track, since the DP state does
+//! not remember it.
+//!
+//! DP state:
+//!   dp[mask * n * 3 + last * 3 + s_idx]  =  minimum cost to:
+//!       • visit exactly the tracks whose bits are set in `mask`
+//!       • end at track `last`
+//!       • with shift `s_idx - 1 ∈ {-1, 0, +1}` for that last track
+//!
+//! With a key-family run limit (`CostParams::family_run`) the state also carries the length
+//! of the current same-family run, `r + 1` for `r < max_run`; extending a run past the limit
+//! is pruned, so the result always satisfies it (with `cyclic`, runs do not wrap).  This
+//! multiplies time and space by `max_run`.
+//!
+//! With a shifted-track cap (`CostParams::max_shifted`, k) it also carries the number of
+//! shifted tracks so far, 0..=k, and prunes paths that shift more.  The final states then
+//! hold the optimum for every smaller cap as well, which `shift_sweep` reads off one table.
+//! This multiplies time and space by `min(k, n) + 1`.
+//!
+//! Time complexity:  O(n² · 2ⁿ · 9)   ≈ O(n² · 2ⁿ)
+//! Space complexity: O(n · 2ⁿ · 3)
+//!
+//! Practical limits (rough estimates on Apple Silicon):
+//!   n ≤ 17 : < 1 s,  ~53 MB
+//!   n ≤ 20 : ~5 s,  ~503 MB
+//!   n = 21 : ~1 GB, and every further node doubles it
+//! Callers check `memory_bytes` against their budget and use SA beyond it.
 
+use crate::cost::{edge_components, edge_cost, total_edge_cost, CostParams, Tables};
+use crate::family;
+use crate::separation::Separation;
+
+/// Run-length states per (mask, last, shift): `max_run` with a family-run limit, else 1.
+fn run_states(params: &CostParams) -> usize {
+    params.family_run.map_or(1, |fr| fr.max_run)
+}
+
+/// Shifted-count states per (mask, last, shift, run): `min(k, n) + 1` under a cap of k, else 1.
+fn count_states(n: usize, params: &CostParams) -> usize {
+    params.max_shifted.map_or(1, |k| k.min(n) + 1)
+}
+
+/// One solver result: (order, shifts, cost, (h, t, s), violations).
+type HkResult = (Vec<usize>, Vec<i8>, f64, (f64, f64, f64), Vec<usize>);
+
+/// Bytes taken by the DP table over `n` nodes (`None` if that overflows `usize`).
+pub fn memory_bytes(n: usize, params: &CostParams) -> Option<usize> {
+    let num_masks = 1usize.checked_shl(u32::try_from(n).ok()?)?;
+    num_masks
+        .checked_mul(n * 3)?
+        .checked_mul(run_states(params))?
+        .checked_mul(count_states(n, params))?
+        .checked_mul(std::mem::size_of::<f64>())
+}
+
+/// DP table kept between solver runs.  Each run resizes and refills it, so one workspace
+/// reused across calls keeps the allocation of the largest table it has held instead of
+/// taking fresh pages every time.
+#[derive(Default)]
+pub struct Workspace {
+    dp: Vec<f64>,
+}
+
+impl Workspace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bytes currently reserved for the DP table.
+    pub fn capacity_bytes(&self) -> usize {
+        self.dp.capacity() * std::mem::size_of::<f64>()
+    }
+}
+
+/// Solve over `n` nodes.  `start` pins the first node of the order; with `cyclic` the order
+/// is a closed loop starting at `start` (node 0 if unset) and the cost and breakdown include
+/// the wrap edge back to it.  If the family-run limit admits no order, the cost is
+/// infinite.
 pub fn run(
     n: usize,
-    bpms: &[i32],
-    key_ids: &[u8],
-    shift_table: &[u8],
-    direct_costs: &[f64],
-    indirect_costs: &[f64],
+    tables: &Tables,
+    params: &CostParams,
+    separation: Option<&Separation>,
+    start: Option<usize>,
+    cyclic: bool,
+) -> HkResult {
+    run_in(&mut Workspace::new(), n, tables, params, separation, start, cyclic)
+}
+
+/// `run` with its DP table in `workspace`.
+pub fn run_in(
+    workspace: &mut Workspace,
+    n: usize,
+    tables: &Tables,
     params: &CostParams,
-) -> (Vec<usize>, Vec<i8>, f64, (f64, f64, f64)) {
+    separation: Option<&Separation>,
+    start: Option<usize>,
+    cyclic: bool,
+) -> HkResult {
+    run_caps(&mut workspace.dp, n, tables, params, separation, start, cyclic, &[usize::MAX]).pop().unwrap()
+}
+
+/// Optimum under each shifted-track cap k = 0..=max_k, from a single DP whose state counts
+/// shifted tracks up to `max_k` (any `params.max_shifted` is replaced).  Costs are
+/// non-increasing in k, since every order allowed under k is allowed under k + 1.
+pub fn shift_sweep(
+    n: usize,
+    tables: &Tables,
+    params: &CostParams,
+    separation: Option<&Separation>,
+    max_k: usize,
+) -> Vec<HkResult> {
+    let params = CostParams { max_shifted: Some(max_k), ..*params };
+    let caps: Vec<usize> = (0..=max_k).collect();
+    run_caps(&mut Vec::new(), n, tables, &params, separation, None, false, &caps)
+}
+
+/// `run`, backtracking one optimal path per entry of `caps`: the best final state with at
+/// most that many shifted tracks.
+fn run_caps(
+    dp: &mut Vec<f64>,
+    n: usize,
+    tables: &Tables,
+    params: &CostParams,
+    separation: Option<&Separation>,
+    start: Option<usize>,
+    cyclic: bool,
+    caps: &[usize],
+) -> Vec<HkResult> {
     assert!(n >= 1);
+    assert!(start.is_none_or(|s| s < n));
 
-    let num_masks = 1usize << n;
+    // Separation is penalty-only here: the DP state only knows the last track, so only
+    // back-to-back repeats can be seen and each costs its grouping's `penalty`.
+    let separation = separation.filter(|sep| sep.is_active());
+    let sep_cost = |a: usize, b: usize| -> f64 {
+        separation.map_or(0.0, |sep| sep.adjacent_penalty(a, b))
+    };
 
-    // dp[mask * n * 3 + last * 3 + s_idx] = minimum cost
+    // dp[((mask * n * 3 + last * 3 + s_idx) * runs + r) * counts + c] = minimum cost
     // s_idx encodes shift: s_idx = shift + 1, so shift ∈ {-1, 0, +1}
-    let mut dp = vec![f64::INFINITY; num_masks * n * 3];
+    // `solve` refills the table, so a reused one only needs the right length
+    dp.resize((1usize << n) * n * 3 * run_states(params) * count_states(n, params), f64::INFINITY);
+
+    let solved = if cyclic {
+        let first = start.unwrap_or(0);
+        let mut best: Vec<(f64, Vec<usize>, Vec<i8>)> = Vec::new();
+        for first_s_idx in 0usize..3 {
+            let first_shift = first_s_idx as i8 - 1;
+            let closed = solve(
+                n, tables, params, &sep_cost, dp,
+                |i, s_idx| i == first && s_idx == first_s_idx,
+                |last, s_last| {
+                    edge_cost(last, first, s_last, first_shift, tables, params) + sep_cost(last, first)
+                },
+                caps,
+            );
+            if best.is_empty() {
+                best = closed;
+            } else {
+                for (b, c) in best.iter_mut().zip(closed) {
+                    if c.0 < b.0 {
+                        *b = c;
+                    }
+                }
+            }
+        }
+        best
+    } else {
+        solve(
+            n, tables, params, &sep_cost, dp,
+            |i, _| start.is_none_or(|s| s == i),
+            |last, s_last| tables.exit_cost(last, s_last, params),
+            caps,
+        )
+    };
+
+    solved
+        .into_iter()
+        .map(|(best_cost, order, shifts_out)| {
+            // Compute true cost breakdown (harmonic / tempo / shift components).
+            let (mut h, mut t, s) = total_edge_cost(&order, &shifts_out, tables, params);
+            if cyclic && n > 1 {
+                let (first, last) = (order[0], order[n - 1]);
+                let (wh, wt) = edge_components(last, first, shifts_out[last], shifts_out[first], tables, params);
+                h += wh;
+                t += wt;
+            }
+
+            // Violations over the full gap window (the DP only penalised adjacent repeats).
+            let violations = separation.map_or_else(Vec::new, |sep| sep.violations(&order));
+
+            (order, shifts_out, best_cost, (h, t, s), violations)
+        })
+        .collect()
+}
+
+/// Fill `dp` and backtrack one optimal path per entry of `caps`, the most shifted tracks
+/// its final state may have.  Only `(track, s_idx)` pairs accepted by `allow_first` may
+/// open the path; `close(last, shift)` is charged on the final state.
+/// Returns (cost, order, shifts) per cap.
+fn solve(
+    n: usize,
+    tables: &Tables,
+    params: &CostParams,
+    sep_cost: &impl Fn(usize, usize) -> f64,
+    dp: &mut [f64],
+    allow_first: impl Fn(usize, usize) -> bool,
+    close: impl Fn(usize, i8) -> f64,
+    caps: &[usize],
+) -> Vec<(f64, Vec<usize>, Vec<i8>)> {
+    let num_masks = 1usize << n;
+    dp.fill(f64::INFINITY);
 
     // Inline index helper (avoids repeated multiply-add in hot path)
-    let idx = |mask: usize, last: usize, s_idx: usize| -> usize {
-        mask * n * 3 + last * 3 + s_idx
+    let runs = run_states(params);
+    let counts = count_states(n, params);
+    let idx = |mask: usize, last: usize, s_idx: usize, r: usize, c: usize| -> usize {
+        ((mask * n * 3 + last * 3 + s_idx) * runs + r) * counts + c
+    };
+    // Every (s_idx, r, c) state of one (mask, last)
+    let states = || {
+        (0usize..3).flat_map(move |s| (0..runs).flat_map(move |r| (0..counts).map(move |c| (s, r, c))))
     };
 
-    // Effective shift penalty per shifted track:  shift_weight * shift_penalty
-    let eff_sp = params.shift_weight * params.shift_penalty;
+    // Key family per (node, s_idx) under a family-run limit.  `next_run(r, a, b)` is the run
+    // index after stepping from (node, s_idx) `a` to `b`: 0 when the family changes, `None`
+    // when the run would pass the limit.
+    let limited = params.family_run.is_some();
+    let families: Vec<usize> = if limited {
+        (0..n * 3).map(|k| family::family(tables, k / 3, (k % 3) as i8 - 1)).collect()
+    } else {
+        Vec::new()
+    };
+    let next_run = |r: usize, a: usize, b: usize| -> Option<usize> {
+        if limited && families[a] == families[b] {
+            (r + 1 < runs).then_some(r + 1)
+        } else {
+            Some(0)
+        }
+    };
+
+    // Shifted tracks after adding a node at `s_idx` to a path with `c` of them: `None` past
+    // the cap.  Without a cap the count is not tracked and stays 0.
+    let capped = params.max_shifted.is_some();
+    let next_count = |c: usize, s_idx: usize| -> Option<usize> {
+        if capped && s_idx != 1 {
+            (c + 1 < counts).then_some(c + 1)
+        } else {
+            Some(c)
+        }
+    };
+
+    // Weighted per-node shift cost (shift_weight * shift_penalty_of(s) for a plain track;
+    // contracted blocks also carry their internal edges here)
+    let node_cost = |i: usize, s: i8| -> f64 { tables.node_cost(i, s, params) };
 
     // -----------------------------------------------------------------------
-    // Base cases: single-track sub-paths
+    // Base cases: single-track sub-paths (entered from the virtual entry anchor, if any)
     // -----------------------------------------------------------------------
     for i in 0..n {
         let mask = 1usize << i;
         for s_idx in 0usize..3 {
+            if !allow_first(i, s_idx) {
+                continue;
+            }
+            let Some(c) = next_count(0, s_idx) else {
+                continue; // no shift allowed at all
+            };
             let shift = s_idx as i8 - 1;
-            dp[idx(mask, i, s_idx)] = if shift != 0 { eff_sp } else { 0.0 };
+            dp[idx(mask, i, s_idx, 0, c)] = node_cost(i, shift) + tables.entry_cost(i, shift, params);
         }
     }
 
@@ -70,8 +295,8 @@ pub fn run(
             if mask & (1 << last) == 0 {
                 continue; // track `last` not in this subset
             }
-            for s_idx in 0usize..3 {
-                let current = dp[idx(mask, last, s_idx)];
+            for (s_idx, r, c) in states() {
+                let current = dp[idx(mask, last, s_idx, r, c)];
                 if current == f64::INFINITY {
                     continue; // unreachable state
                 }
@@ -84,14 +309,16 @@ pub fn run(
                     let new_mask = mask | (1 << j);
 
                     for sj_idx in 0usize..3 {
+                        let Some(r_j) = next_run(r, last * 3 + s_idx, j * 3 + sj_idx) else {
+                            continue; // run too long: pruned
+                        };
+                        let Some(c_j) = next_count(c, sj_idx) else {
+                            continue; // too many shifted tracks: pruned
+                        };
                         let s_j = sj_idx as i8 - 1;
-                        let ec = edge_cost(
-                            last, j, s_last, s_j,
-                            bpms, key_ids, shift_table,
-                            direct_costs, indirect_costs, params,
-                        );
-                        let new_cost = current + ec + if s_j != 0 { eff_sp } else { 0.0 };
-                        let t = idx(new_mask, j, sj_idx);
+                        let ec = edge_cost(last, j, s_last, s_j, tables, params);
+                        let new_cost = current + ec + sep_cost(last, j) + node_cost(j, s_j);
+                        let t = idx(new_mask, j, sj_idx, r_j, c_j);
                         if new_cost < dp[t] {
                             dp[t] = new_cost;
                         }
@@ -101,98 +328,109 @@ pub fn run(
         }
     }
 
-    // -----------------------------------------------------------------------
-    // Find the optimal final state
-    // -----------------------------------------------------------------------
-    let full_mask = num_masks - 1;
-    let mut best_cost = f64::INFINITY;
-    let mut best_last = 0usize;
-    let mut best_s_idx = 1usize; // default: no shift
+    caps.iter().map(|&cap| {
+        // -------------------------------------------------------------------
+        // Find the optimal final state with at most `cap` shifted tracks
+        // -------------------------------------------------------------------
+        let full_mask = num_masks - 1;
+        let mut best_cost = f64::INFINITY;
+        let mut best_last = 0usize;
+        let mut best_s_idx = 1usize; // default: no shift
+        let mut best_r = 0usize;
+        let mut best_c = 0usize;
 
-    for last in 0..n {
-        for s_idx in 0usize..3 {
-            let c = dp[idx(full_mask, last, s_idx)];
-            if c < best_cost {
-                best_cost = c;
-                best_last = last;
-                best_s_idx = s_idx;
+        for last in 0..n {
+            for (s_idx, r, c) in states().filter(|&(_, _, c)| c <= cap) {
+                let c_cost = dp[idx(full_mask, last, s_idx, r, c)] + close(last, s_idx as i8 - 1);
+                if c_cost < best_cost {
+                    best_cost = c_cost;
+                    best_last = last;
+                    best_s_idx = s_idx;
+                    best_r = r;
+                    best_c = c;
+                }
             }
         }
-    }
+        if best_cost == f64::INFINITY {
+            // Only a family-run limit, or a shift cap against a pinned first shift, can leave
+            // every final state unreachable
+            return (best_cost, (0..n).collect(), vec![0; n]);
+        }
 
-    // -----------------------------------------------------------------------
-    // Backtrack — no parent table stored; reconstruct by searching the DP.
-    //
-    // At each step we know (current_mask, current_last, current_s_idx).
-    // The previous state has prev_mask = current_mask ^ (1 << current_last).
-    // We search all (prev_last, prev_s_idx) in prev_mask for the one that
-    // satisfies the DP recurrence (up to floating-point epsilon).
-    //
-    // All edge costs and shift penalties are exact multiples of 0.5, so f64
-    // arithmetic is exact and a tiny epsilon (1e-9) is sufficient.
-    // -----------------------------------------------------------------------
-    let mut order = Vec::with_capacity(n);
-    let mut shifts_out = vec![0i8; n];
+        // -------------------------------------------------------------------
+        // Backtrack — no parent table stored; reconstruct by searching the DP.
+        //
+        // At each step we know (current_mask, current_last, current_s_idx,
+        // current_r, current_c).  The previous state has prev_mask = current_mask
+        // ^ (1 << current_last).  We search all (prev_last, prev_s_idx, prev_r,
+        // prev_c) in prev_mask whose step leads to (current_r, current_c) for the
+        // one that satisfies the DP recurrence (up to floating-point epsilon).
+        //
+        // All edge costs and shift penalties are exact multiples of 0.5, so f64
+        // arithmetic is exact and a tiny epsilon (1e-9) is sufficient.
+        // -------------------------------------------------------------------
+        let mut order = Vec::with_capacity(n);
+        let mut shifts_out = vec![0i8; n];
 
-    let mut cur_mask = full_mask;
-    let mut cur_last = best_last;
-    let mut cur_s_idx = best_s_idx;
+        let mut cur_mask = full_mask;
+        let mut cur_last = best_last;
+        let mut cur_s_idx = best_s_idx;
+        let mut cur_r = best_r;
+        let mut cur_c = best_c;
 
-    loop {
-        order.push(cur_last);
-        shifts_out[cur_last] = cur_s_idx as i8 - 1;
+        loop {
+            order.push(cur_last);
+            shifts_out[cur_last] = cur_s_idx as i8 - 1;
 
-        if cur_mask.count_ones() == 1 {
-            break; // this was the first track
-        }
+            if cur_mask.count_ones() == 1 {
+                break; // this was the first track
+            }
 
-        let cur_cost = dp[idx(cur_mask, cur_last, cur_s_idx)];
-        let s_cur = cur_s_idx as i8 - 1;
-        let shift_cost_cur = if s_cur != 0 { eff_sp } else { 0.0 };
-        let prev_mask = cur_mask ^ (1 << cur_last);
+            let cur_cost = dp[idx(cur_mask, cur_last, cur_s_idx, cur_r, cur_c)];
+            let s_cur = cur_s_idx as i8 - 1;
+            let shift_cost_cur = node_cost(cur_last, s_cur);
+            let prev_mask = cur_mask ^ (1 << cur_last);
 
-        let mut found = false;
-        'search: for prev_last in 0..n {
-            if prev_mask & (1 << prev_last) == 0 {
-                continue;
-            }
-            for prev_s_idx in 0usize..3 {
-                let prev_cost = dp[idx(prev_mask, prev_last, prev_s_idx)];
-                if prev_cost == f64::INFINITY {
+            let mut found = false;
+            'search: for prev_last in 0..n {
+                if prev_mask & (1 << prev_last) == 0 {
                     continue;
                 }
-                let prev_s = prev_s_idx as i8 - 1;
-                let ec = edge_cost(
-                    prev_last, cur_last, prev_s, s_cur,
-                    bpms, key_ids, shift_table,
-                    direct_costs, indirect_costs, params,
-                );
-                let expected = prev_cost + ec + shift_cost_cur;
-                if (expected - cur_cost).abs() < 1e-9 {
-                    cur_mask = prev_mask;
-                    cur_last = prev_last;
-                    cur_s_idx = prev_s_idx;
-                    found = true;
-                    break 'search;
+                for (prev_s_idx, prev_r, prev_c) in states() {
+                    if next_run(prev_r, prev_last * 3 + prev_s_idx, cur_last * 3 + cur_s_idx) != Some(cur_r)
+                        || next_count(prev_c, cur_s_idx) != Some(cur_c)
+                    {
+                        continue;
+                    }
+                    let prev_cost = dp[idx(prev_mask, prev_last, prev_s_idx, prev_r, prev_c)];
+                    if prev_cost == f64::INFINITY {
+                        continue;
+                    }
+                    let prev_s = prev_s_idx as i8 - 1;
+                    let ec = edge_cost(prev_last, cur_last, prev_s, s_cur, tables, params);
+                    let expected = prev_cost + ec + sep_cost(prev_last, cur_last) + shift_cost_cur;
+                    if (expected - cur_cost).abs() < 1e-9 {
+                        cur_mask = prev_mask;
+                        cur_last = prev_last;
+                        cur_s_idx = prev_s_idx;
+                        cur_r = prev_r;
+                        cur_c = prev_c;
+                        found = true;
+                        break 'search;
+                    }
                 }
             }
-        }
 
-        if !found {
-            // Should never happen with a valid DP table.
-            // Break defensively to avoid an infinite loop.
-            break;
+            if !found {
+                // Should never happen with a valid DP table.
+                // Break defensively to avoid an infinite loop.
+                break;
+            }
         }
-    }
-
-    // Built from end → start; reverse to get correct order.
-    order.reverse();
 
-    // Compute true cost breakdown (harmonic / tempo / shift components).
-    let (h, t, s) = total_edge_cost(
-        &order, &shifts_out,
-        bpms, key_ids, shift_table, direct_costs, indirect_costs, params,
-    );
+        // Built from end → start; reverse to get correct order.
+        order.reverse();
 
-    (order, shifts_out, best_cost, (h, t, s))
+        (best_cost, order, shifts_out)
+    }).collect()
 }
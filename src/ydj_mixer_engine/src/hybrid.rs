@@ -0,0 +1,129 @@
+//! Hybrid exact + annealing solver for instances just past the Held-Karp limit (n ≈ 21–40).
+//!
+//!   1. cluster: sort the tracks by intro BPM (then key) and cut them into ⌈n / cluster_size⌉
+//!      clusters of near-equal size, so each cluster holds tempo neighbours;
+//!   2. solve each cluster exactly with Held-Karp, as an open path of its own;
+//!   3. stitch the cluster paths in order of tempo, rising or falling, whichever costs less
+//!      over the whole order;
+//!   4. polish: simulated-annealing attempts warm-started from the stitched order.
+//!
+//! This is a heuristic.  The stitched order is only optimal within each cluster, and the
+//! polish repairs the seams and moves tracks between clusters with no guarantee of reaching
+//! the global optimum, but it starts far closer to it than a random order.  Family-run
+//! limits and shifted-track caps are left to the polish.
+
+use rand::prelude::*;
+use rand::rng;
+use rand::rngs::StdRng;
+
+use crate::annealing::{run_attempt_from, AnnealingParams, SaResult};
+use crate::cost::{score_orders, CostParams, Subset, Tables};
+use crate::held_karp;
+
+/// Result of `run`.
+pub struct HybridResult {
+    /// Best polished solution; never worse than the stitched order unless a family-run limit
+    /// or shifted-track cap had to be applied to it first.
+    pub result: SaResult,
+    /// Track indices per cluster, in stitched play order.
+    pub clusters: Vec<Vec<usize>>,
+    /// Cost of the stitched order before polishing.
+    pub stitched_cost: f64,
+    pub n_attempts: usize,
+}
+
+/// Tracks 0..n sorted by (intro BPM, key) and cut into ⌈n / cluster_size⌉ near-equal runs.
+pub fn clusters(n: usize, tables: &Tables, cluster_size: usize) -> Vec<Vec<usize>> {
+    let mut tracks: Vec<usize> = (0..n).collect();
+    tracks.sort_by_key(|&i| (tables.bpms[i], tables.key_ids[i]));
+    let count = n.div_ceil(cluster_size.max(1)).max(1);
+    (0..count).map(|c| tracks[c * n / count..(c + 1) * n / count].to_vec()).collect()
+}
+
+/// Steps 1–3: the stitched order, its node-indexed shifts, its cost and the clusters in
+/// play order.
+fn stitch(
+    n: usize,
+    tables: &Tables,
+    cost_params: &CostParams,
+    cluster_size: usize,
+) -> (Vec<usize>, Vec<i8>, f64, Vec<Vec<usize>>) {
+    let params = CostParams { family_run: None, max_shifted: None, ..*cost_params };
+    let mut clusters = clusters(n, tables, cluster_size);
+    let mut shifts = vec![0i8; n];
+    let mut workspace = held_karp::Workspace::new();
+    for cluster in &mut clusters {
+        let subset = Subset::new(tables, cluster);
+        let (order, sub_shifts, _, _, _) = held_karp::run_in(
+            &mut workspace, cluster.len(), &subset.tables(tables), &params, None, None, false,
+        );
+        for (&j, &s) in cluster.iter().zip(&sub_shifts) {
+            shifts[j] = s;
+        }
+        *cluster = order.iter().map(|&j| cluster[j]).collect();
+    }
+
+    // Rising and falling tempo
+    let [rising, falling] = [clusters.concat(), clusters.iter().rev().flatten().copied().collect()];
+    let costs = score_orders(&[rising, falling], &[shifts.clone(), shifts.clone()], tables, cost_params, 1);
+    let falls = costs[1] < costs[0];
+    if falls {
+        clusters.reverse();
+    }
+    (clusters.concat(), shifts, costs[falls as usize], clusters)
+}
+
+/// Hybrid solve over tracks 0..n with clusters of at most `cluster_size` (2..=20; the
+/// Held-Karp table of a cluster of 20 takes ~500 MB), polishing until the time budget
+/// (seconds) is exhausted, at least one attempt.
+pub fn run(
+    n: usize,
+    tables: &Tables,
+    cost_params: &CostParams,
+    ann_params: &AnnealingParams,
+    cluster_size: usize,
+    time_limit_secs: f64,
+) -> HybridResult {
+    let start = std::time::Instant::now();
+    polish(n, tables, cost_params, ann_params, cluster_size, &mut rng(), |attempts| {
+        attempts == 0 || start.elapsed().as_secs_f64() < time_limit_secs
+    })
+}
+
+/// `run` with exactly `attempts` polishing attempts (at least one) drawn from an RNG seeded
+/// with `seed`, so the result does not depend on machine speed.
+pub fn run_seeded(
+    n: usize,
+    tables: &Tables,
+    cost_params: &CostParams,
+    ann_params: &AnnealingParams,
+    cluster_size: usize,
+    attempts: usize,
+    seed: u64,
+) -> HybridResult {
+    let mut rng = StdRng::seed_from_u64(seed);
+    polish(n, tables, cost_params, ann_params, cluster_size, &mut rng, |done| done < attempts.max(1))
+}
+
+/// Stitch, then run polishing attempts while `keep_going(attempts so far)` holds.
+fn polish(
+    n: usize,
+    tables: &Tables,
+    cost_params: &CostParams,
+    ann_params: &AnnealingParams,
+    cluster_size: usize,
+    rng: &mut impl Rng,
+    keep_going: impl Fn(usize) -> bool,
+) -> HybridResult {
+    let (order, shifts, stitched_cost, clusters) = stitch(n, tables, cost_params, cluster_size);
+    let mut best: Option<SaResult> = None;
+    let mut n_attempts = 0;
+    while keep_going(n_attempts) {
+        let result = run_attempt_from(n, tables, cost_params, ann_params, &order, &shifts, rng);
+        n_attempts += 1;
+        if best.as_ref().is_none_or(|b| result.best_cost < b.best_cost) {
+            best = Some(result);
+        }
+    }
+    HybridResult { result: best.unwrap(), clusters, stitched_cost, n_attempts }
+}
@@ -0,0 +1,96 @@
+//! Re-optimizing after a small playlist edit.  Rather than a full run from random starts,
+//! the previous order is spliced (the caller drops the departed tracks, and each new track
+//! goes where it adds the least cost) and the splice is then polished by short
+//! low-temperature annealing attempts warm-started from it, so the result is never worse
+//! than the splice and stays close to the order the user already knows.
+
+use crate::annealing::{self, AnnealingParams, AttemptPanic, TimedRun};
+use crate::cost::{edge_cost, CostParams, Tables};
+
+/// Start temperature of the polish as a multiple of `final_temp` (never above
+/// `initial_temp`): warm enough to reorder a neighbourhood, too cold to wander off.
+pub const POLISH_TEMP_FACTOR: f64 = 10.0;
+
+/// The cost of inserting node `track` before each position of `order` (`order.len()` =
+/// at the end), with the shift that adds the least there: its node cost and new edges,
+/// less the edge it splits, plus any change in the anchor edges.  `shifts` is
+/// node-indexed; ties go to shift 0, then -1.
+pub fn insertion_costs(order: &[usize], shifts: &[i8], track: usize, tables: &Tables, params: &CostParams) -> Vec<(i8, f64)> {
+    (0..=order.len())
+        .map(|pos| {
+            let prev = pos.checked_sub(1).map(|p| order[p]);
+            let next = order.get(pos).copied();
+            let mut best = (0, f64::INFINITY);
+            for s in [0, -1, 1] {
+                let mut added = tables.node_cost(track, s, params);
+                if let Some(p) = prev {
+                    added += edge_cost(p, track, shifts[p], s, tables, params);
+                } else {
+                    added += tables.entry_cost(track, s, params) - next.map_or(0.0, |n| tables.entry_cost(n, shifts[n], params));
+                }
+                if let Some(n) = next {
+                    added += edge_cost(track, n, s, shifts[n], tables, params);
+                } else {
+                    added += tables.exit_cost(track, s, params) - prev.map_or(0.0, |p| tables.exit_cost(p, shifts[p], params));
+                }
+                if let (Some(p), Some(n)) = (prev, next) {
+                    added -= edge_cost(p, n, shifts[p], shifts[n], tables, params);
+                }
+                if added < best.1 {
+                    best = (s, added);
+                }
+            }
+            best
+        })
+        .collect()
+}
+
+/// Insert node `track` into `order` where `insertion_costs` is least (the earliest such
+/// position on ties), writing its shift to the node-indexed `shifts`.
+pub fn insert_cheapest(order: &mut Vec<usize>, shifts: &mut [i8], track: usize, tables: &Tables, params: &CostParams) {
+    let costs = insertion_costs(order, shifts, track, tables, params);
+    let mut pos = 0;
+    for (p, c) in costs.iter().enumerate() {
+        if c.1 < costs[pos].1 {
+            pos = p;
+        }
+    }
+    order.insert(pos, track);
+    shifts[track] = costs[pos].0;
+}
+
+/// `kept` (the previous order without the departed tracks) with each node of `added`
+/// inserted in turn by `insert_cheapest`.
+pub fn splice(kept: &[usize], shifts: &mut [i8], added: &[usize], tables: &Tables, params: &CostParams) -> Vec<usize> {
+    let mut order = Vec::with_capacity(kept.len() + added.len());
+    order.extend_from_slice(kept);
+    for &track in added {
+        insert_cheapest(&mut order, shifts, track, tables, params);
+    }
+    order
+}
+
+/// Splice `added` into `kept` and polish the result for `time_limit_secs` with
+/// `run_timed_from`, on a schedule whose start temperature is lowered to
+/// `POLISH_TEMP_FACTOR * final_temp`.  `kept` and `added` together are 0..n; `shifts`
+/// (node-indexed, n entries) holds the kept tracks' previous shifts.
+pub fn reoptimize(
+    n: usize,
+    tables: &Tables,
+    cost_params: &CostParams,
+    ann_params: &AnnealingParams,
+    kept: &[usize],
+    shifts: &[i8],
+    added: &[usize],
+    time_limit_secs: f64,
+) -> Result<TimedRun, AttemptPanic> {
+    let mut start_shifts = shifts.to_vec();
+    let start = splice(kept, &mut start_shifts, added, tables, cost_params);
+    assert_eq!(start.len(), n, "kept and added tracks must together be the {n} nodes");
+    let polish = AnnealingParams {
+        initial_temp: ann_params.initial_temp.min(ann_params.final_temp * POLISH_TEMP_FACTOR),
+        auto_initial_temp: None,
+        ..*ann_params
+    };
+    annealing::run_timed_from(n, tables, cost_params, &polish, &start, &start_shifts, time_limit_secs)
+}
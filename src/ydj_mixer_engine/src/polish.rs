@@ -0,0 +1,87 @@
+//! Deterministic 2-opt polish: a final cleanup for any order, from a solver or by hand.
+//!
+//! Each step scores every segment reversal of the order and applies the one that lowers the
+//! objective most, until none does or the move budget runs out.  Edge costs can depend on
+//! direction (intro/outro BPMs, directed tables, adjacency bonuses), so a reversal rewrites
+//! the segment's internal edges as well as its two boundary ones; with prefix sums of each
+//! edge's cost forwards and backwards every reversal is still scored in O(1), so a step is
+//! O(n²).  Shifts are node-indexed and move with their tracks.  Separation and the other
+//! positional rules are not applied.
+
+use crate::cost::{edge_cost, score_orders, CostParams, Tables};
+
+/// Improvements smaller than this are ignored, so rounding in the prefix sums cannot make
+/// the polish cycle between equivalent orders.
+const MIN_GAIN: f64 = 1e-9;
+
+/// Result of `two_opt`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Polished {
+    pub order: Vec<usize>,
+    /// Objective of `order`, as `score_orders` computes it.
+    pub cost: f64,
+    /// Reversals applied.
+    pub moves: usize,
+    /// Whether the order is 2-opt optimal, i.e. the budget did not stop the polish first.
+    pub converged: bool,
+}
+
+/// Apply best-improving segment reversals to `order` (with node-indexed `shifts`), at most
+/// `max_moves` of them.
+pub fn two_opt(order: &[usize], shifts: &[i8], tables: &Tables, params: &CostParams, max_moves: usize) -> Polished {
+    let mut order = order.to_vec();
+    let mut moves = 0;
+    let converged = loop {
+        let Some((i, j)) = best_reversal(&order, shifts, tables, params) else { break true };
+        if moves == max_moves {
+            break false;
+        }
+        order[i..=j].reverse();
+        moves += 1;
+    };
+    let cost = score_orders(std::slice::from_ref(&order), &[shifts.to_vec()], tables, params, 1)[0];
+    Polished { order, cost, moves, converged }
+}
+
+/// The reversal of positions `i..=j` that lowers the objective most, `None` when none
+/// lowers it by `MIN_GAIN`.  Ties go to the smallest `i`, then the smallest `j`.
+fn best_reversal(order: &[usize], shifts: &[i8], tables: &Tables, params: &CostParams) -> Option<(usize, usize)> {
+    let n = order.len();
+    if n < 2 {
+        return None;
+    }
+    let edge = |a: usize, b: usize| edge_cost(a, b, shifts[a], shifts[b], tables, params);
+    // forward[k] and backward[k]: sums of the first k edges played forwards and reversed
+    let mut forward = vec![0.0; n];
+    let mut backward = vec![0.0; n];
+    for k in 0..n - 1 {
+        forward[k + 1] = forward[k] + edge(order[k], order[k + 1]);
+        backward[k + 1] = backward[k] + edge(order[k + 1], order[k]);
+    }
+    let entry = |t: usize| tables.entry_cost(t, shifts[t], params);
+    let exit = |t: usize| tables.exit_cost(t, shifts[t], params);
+
+    let mut best = None;
+    let mut best_delta = -MIN_GAIN;
+    for i in 0..n - 1 {
+        for j in i + 1..n {
+            // Internal edges i..j-1 are played backwards; the edges into i and out of j are replaced
+            let mut delta = (backward[j] - backward[i]) - (forward[j] - forward[i]);
+            if i > 0 {
+                delta += edge(order[i - 1], order[j]) - edge(order[i - 1], order[i]);
+            } else {
+                delta += entry(order[j]) - entry(order[0]);
+            }
+            if j + 1 < n {
+                delta += edge(order[i], order[j + 1]) - edge(order[j], order[j + 1]);
+            } else {
+                delta += exit(order[i]) - exit(order[n - 1]);
+            }
+            if delta < best_delta {
+                best_delta = delta;
+                best = Some((i, j));
+            }
+        }
+    }
+    best
+}
@@ -0,0 +1,5817 @@
+//! pyo3 bindings: converts Python arguments into the core types and results back into
+//! tuples and dicts.  Built only with the `python` feature.
+
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyTuple};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::alternation::Alternation;
+use crate::annealing::{self, AnnealingParams, CostCap, PerTrackStats, StatsWeighting};
+use crate::blocks::Contraction;
+use crate::calibrate;
+use crate::checkpoint;
+use crate::cost::{
+    self, AdjacencyBonus, Anchors, CompatCosts, CostParams, ObjectiveMode, SparseKeyCosts,
+    StabilityPenalty, Tables,
+};
+use crate::coverage::{Coverage, CoverageRule};
+use crate::family::{self, FamilyRunLimit};
+use crate::fast;
+use crate::held_karp;
+use crate::hybrid;
+use crate::incremental;
+use crate::polish;
+use crate::repeat;
+use crate::select;
+use crate::separation::{Grouping, Separation};
+use crate::similarity;
+use crate::testing;
+
+/// A panicking annealing attempt surfaces as a RuntimeError carrying its seed and index.
+impl From<annealing::AttemptPanic> for PyErr {
+    fn from(e: annealing::AttemptPanic) -> PyErr {
+        pyo3::exceptions::PyRuntimeError::new_err(e.to_string())
+    }
+}
+
+/// Named result of `optimize_mix_v2` / `optimize_mix_exact_v2`.  The annealing-only fields
+/// (`attempt_costs`, `n_attempts`, `per_track_*`, `per_position_costs`) are None for the
+/// exact solver; `proven_optimal` is True only for a Held-Karp run that did not fall back to
+/// annealing.
+#[pyclass(get_all, frozen, module = "ydj_mixer_engine")]
+struct MixResult {
+    order: Vec<usize>,
+    shifts: Vec<i8>,
+    cost: f64,
+    h_cost: f64,
+    t_cost: f64,
+    s_cost: f64,
+    attempt_costs: Option<Vec<(f64, f64, f64, f64)>>,
+    n_attempts: Option<usize>,
+    per_track_min: Option<Vec<f64>>,
+    per_track_max: Option<Vec<f64>>,
+    per_track_avg: Option<Vec<f64>>,
+    per_position_costs: Option<Vec<f64>>,
+    shift_counts: (usize, usize, usize),
+    proven_optimal: bool,
+    report: Py<PyDict>,
+}
+
+#[pymethods]
+impl MixResult {
+    fn __repr__(&self) -> String {
+        format!(
+            "MixResult(cost={:.4}, h_cost={:.4}, t_cost={:.4}, s_cost={:.4}, n_tracks={}, proven_optimal={})",
+            self.cost, self.h_cost, self.t_cost, self.s_cost, self.order.len(),
+            if self.proven_optimal { "True" } else { "False" },
+        )
+    }
+
+    /// Every attribute in a plain dict (the report is copied).
+    fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let d = PyDict::new(py);
+        d.set_item("order", &self.order)?;
+        d.set_item("shifts", &self.shifts)?;
+        d.set_item("cost", self.cost)?;
+        d.set_item("h_cost", self.h_cost)?;
+        d.set_item("t_cost", self.t_cost)?;
+        d.set_item("s_cost", self.s_cost)?;
+        d.set_item("attempt_costs", &self.attempt_costs)?;
+        d.set_item("n_attempts", self.n_attempts)?;
+        d.set_item("per_track_min", &self.per_track_min)?;
+        d.set_item("per_track_max", &self.per_track_max)?;
+        d.set_item("per_track_avg", &self.per_track_avg)?;
+        d.set_item("per_position_costs", &self.per_position_costs)?;
+        d.set_item("shift_counts", self.shift_counts)?;
+        d.set_item("proven_optimal", self.proven_optimal)?;
+        d.set_item("report", self.report.bind(py).copy()?)?;
+        Ok(d)
+    }
+}
+
+/// A key model prepared once for many solves: `MixOptimizer(shift_table, direct_costs,
+/// indirect_costs, bpms=None, base_key_ids=None, inf_forbidden=False)` checks and
+/// sanitizes the shift and cost tables (empty cost tables select the built-in Camelot
+/// model, as in `optimize_mix`) and, when given, a library of tracks, so each call only
+/// builds its cost parameters and the slices of the tracks it plays.  Every method takes
+/// the tracks either as `indices` into the library (None for the whole library) or, for
+/// an optimizer built without one, as `bpms=` and `base_key_ids=` keywords; orders and
+/// shifts are over positions in that selection, so `indices[order[k]]` is the library
+/// track.  `cost_params` must keep the `num_keys` the tables were built for.
+///
+///   optimize(indices, cost_params, annealing_params, time_limit_secs)  -> MixResult
+///   optimize_exact(indices, cost_params, max_memory_mb=512.0)          -> MixResult
+///   evaluate(order, shifts, cost_params, indices=None)  -> (cost, (h, t, s))
+///
+/// These cover the plain model; the optional features of `optimize_mix` (separation,
+/// anchors, groups, ...) still go through the functions, which also accept the optimizer
+/// as `optimize_mix_exact(..., optimizer=)` to reuse its DP table.  The tables are
+/// immutable, the DP table and the annealing buffers are locked, and the solvers run
+/// without the GIL, so one instance may serve several Python threads at once (exact solves
+/// take turns; an `optimize` that finds the buffers in use anneals on fresh ones).
+#[pyclass(frozen, module = "ydj_mixer_engine")]
+struct MixOptimizer {
+    num_keys: usize,
+    shift_table: Vec<u8>,
+    /// Both empty for the built-in Camelot model, which depends on non_harmonic_cost.
+    direct_costs: Vec<f64>,
+    indirect_costs: Vec<f64>,
+    /// The cost tables were read from float32 buffers (see `Array::from_f32`).
+    from_f32: bool,
+    library: Option<(Vec<i32>, Vec<u8>)>,
+    workspace: std::sync::Mutex<held_karp::Workspace>,
+    scratch: std::sync::Mutex<annealing::Scratch>,
+}
+
+impl MixOptimizer {
+    /// Cost parameters of one call, checked against the tables.
+    fn cost_params(&self, arg: &CostParamsArg) -> PyResult<CostParams> {
+        let cp = build_cost_params(arg)?;
+        if cp.num_keys != self.num_keys {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "cost_params has num_keys = {}, but the optimizer's tables are for {} keys",
+                cp.num_keys, self.num_keys
+            )));
+        }
+        Ok(cp)
+    }
+
+    /// BPMs and base keys of the tracks a call plays.
+    fn tracks(
+        &self,
+        indices: Option<Vec<usize>>,
+        bpms: Option<Array<i32>>,
+        base_key_ids: Option<Array<u8>>,
+    ) -> PyResult<(Vec<i32>, Vec<u8>)> {
+        match (&self.library, bpms, base_key_ids) {
+            (Some((lib_bpms, lib_keys)), None, None) => {
+                let Some(indices) = indices else { return Ok((lib_bpms.clone(), lib_keys.clone())) };
+                let mut seen = vec![false; lib_bpms.len()];
+                let repeated = |&&i: &&usize| i >= seen.len() || std::mem::replace(&mut seen[i], true);
+                if let Some(&i) = indices.iter().find(repeated) {
+                    return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                        "indices: track {i} is repeated or outside the library of {}", lib_bpms.len()
+                    )));
+                }
+                Ok((indices.iter().map(|&i| lib_bpms[i]).collect(), indices.iter().map(|&i| lib_keys[i]).collect()))
+            }
+            (None, Some(bpms), Some(keys)) if indices.is_none() => {
+                validate_track_bpms(&bpms, None, None, bpms.len())?;
+                if keys.len() != bpms.len() {
+                    return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                        "base_key_ids has {} entries, expected {}", keys.len(), bpms.len()
+                    )));
+                }
+                if let Some(i) = keys.iter().position(|&k| k as usize >= self.num_keys) {
+                    return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                        "base_key_ids[{i}] is {}, but num_keys is {}", keys[i], self.num_keys
+                    )));
+                }
+                Ok((bpms.into_vec(), keys.into_vec()))
+            }
+            (Some(_), _, _) => Err(pyo3::exceptions::PyValueError::new_err(
+                "this optimizer has a library: pick tracks with indices, not bpms and base_key_ids",
+            )),
+            (None, _, _) => Err(pyo3::exceptions::PyValueError::new_err(
+                "this optimizer has no library: pass bpms and base_key_ids (and no indices)",
+            )),
+        }
+    }
+
+    /// The key cost tables under `cp`: as stored, unless the built-in model or float32
+    /// rounding makes them depend on non_harmonic_cost.
+    fn key_costs(&self, cp: &CostParams) -> (std::borrow::Cow<'_, [f64]>, std::borrow::Cow<'_, [f64]>) {
+        if self.direct_costs.is_empty() {
+            let (direct, indirect) = cost::camelot_key_costs(cp.non_harmonic_cost);
+            return (direct.into(), indirect.into());
+        }
+        if self.from_f32 {
+            let (mut direct, mut indirect) = (self.direct_costs.clone(), self.indirect_costs.clone());
+            cost::restore_f32_value(&mut direct, cp.non_harmonic_cost);
+            cost::restore_f32_value(&mut indirect, cp.non_harmonic_cost);
+            return (direct.into(), indirect.into());
+        }
+        (self.direct_costs.as_slice().into(), self.indirect_costs.as_slice().into())
+    }
+}
+
+#[pymethods]
+impl MixOptimizer {
+    #[new]
+    #[pyo3(signature = (shift_table, direct_costs, indirect_costs, bpms=None, base_key_ids=None, inf_forbidden=false))]
+    fn new(
+        shift_table: Array<u8>,
+        mut direct_costs: Array<f64>,
+        mut indirect_costs: Array<f64>,
+        bpms: Option<Array<i32>>,
+        base_key_ids: Option<Array<u8>>,
+        inf_forbidden: bool,
+    ) -> PyResult<Self> {
+        check_arrays(&[
+            ("shift_table", &shift_table), ("direct_costs", &direct_costs),
+            ("indirect_costs", &indirect_costs), ("bpms", &bpms), ("base_key_ids", &base_key_ids),
+        ])?;
+        if shift_table.is_empty() || !shift_table.len().is_multiple_of(3) {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "shift_table has {} entries, expected 3 per key", shift_table.len()
+            )));
+        }
+        let num_keys = shift_table.len() / 3;
+        if let Some(i) = shift_table.iter().position(|&k| k as usize >= num_keys) {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "shift_table[{i}] is {}, but the table covers {num_keys} keys", shift_table[i]
+            )));
+        }
+        validate_cost_table("direct_costs", &mut direct_costs, inf_forbidden)?;
+        validate_cost_table("indirect_costs", &mut indirect_costs, inf_forbidden)?;
+        if direct_costs.is_empty() && indirect_costs.is_empty() {
+            if num_keys != 24 {
+                return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "empty direct_costs and indirect_costs select the built-in Camelot model, which \
+                     needs num_keys = 24, got {num_keys}"
+                )));
+            }
+        } else {
+            for (name, table) in [("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs)] {
+                if table.len() != num_keys * num_keys {
+                    return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                        "{name} has {} entries, expected {}", table.len(), num_keys * num_keys
+                    )));
+                }
+            }
+        }
+        let from_f32 = direct_costs.from_f32 || indirect_costs.from_f32;
+        let mut optimizer = MixOptimizer {
+            num_keys,
+            shift_table: shift_table.into_vec(),
+            direct_costs: direct_costs.into_vec(),
+            indirect_costs: indirect_costs.into_vec(),
+            from_f32,
+            library: None,
+            workspace: std::sync::Mutex::new(held_karp::Workspace::new()),
+            scratch: std::sync::Mutex::new(annealing::Scratch::new()),
+        };
+        let library = match (bpms, base_key_ids) {
+            (None, None) => None,
+            (bpms, keys) => Some(optimizer.tracks(None, bpms, keys)?),
+        };
+        optimizer.library = library;
+        Ok(optimizer)
+    }
+
+    /// Simulated annealing over the selected tracks, as `optimize_mix_v2` without its
+    /// optional arguments.  The report holds attempt_secs, distinct_attempt_costs,
+    /// move_stats, constraint_rejection_rate, opener/closer and the transition summaries.
+    #[pyo3(signature = (indices, cost_params, annealing_params, time_limit_secs, bpms=None, base_key_ids=None))]
+    fn optimize(
+        &self,
+        py: Python<'_>,
+        indices: Option<Vec<usize>>,
+        cost_params: CostParamsArg,
+        annealing_params: AnnealingParamsArg,
+        time_limit_secs: f64,
+        bpms: Option<Array<i32>>,
+        base_key_ids: Option<Array<u8>>,
+    ) -> PyResult<MixResult> {
+        check_arrays(&[("bpms", &bpms), ("base_key_ids", &base_key_ids)])?;
+        let (bpms, key_ids) = self.tracks(indices, bpms, base_key_ids)?;
+        let n = bpms.len();
+        if n < 2 {
+            return Err(pyo3::exceptions::PyValueError::new_err("Need at least 2 tracks"));
+        }
+        let mut cp = self.cost_params(&cost_params)?;
+        let ap = build_annealing_params(&annealing_params)?;
+        let (direct, indirect) = self.key_costs(&cp);
+        let tables = Tables::new(&bpms, &key_ids, &self.shift_table, &direct, &indirect);
+        let normal = CostParams { objective_mode: ObjectiveMode::Weighted, ..cp };
+        let lexicographic_scale = resolve_objective(&mut cp, n, &tables)?;
+
+        let (best, attempt_costs, stats, attempt_secs) = py.allow_threads(|| {
+            let mut pooled = match self.scratch.try_lock() {
+                Ok(scratch) => Some(scratch),
+                Err(std::sync::TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+                Err(std::sync::TryLockError::WouldBlock) => None,
+            };
+            let mut fresh = annealing::Scratch::new();
+            let scratch = pooled.as_deref_mut().unwrap_or(&mut fresh);
+            annealing::run_timed_in(scratch, n, &tables, &cp, &ap, None, None, StatsWeighting::Uniform, time_limit_secs)
+        })?;
+        let perfect = cp.objective_mode == ObjectiveMode::PerfectTransitions;
+        if perfect {
+            cp = normal;
+        }
+        let report = PyDict::new(py);
+        report.set_item("attempt_secs", attempt_secs)?;
+        let overall: Vec<f64> = attempt_costs.iter().map(|c| c.0).collect();
+        report.set_item("distinct_attempt_costs", annealing::distinct_costs(&overall))?;
+        report_move_stats(&report, &best.moves)?;
+        let mut breakdown = (best.h_cost, best.t_cost, best.s_cost);
+        report_perfect(&report, perfect, &best.best_order, &best.best_shifts, false, &mut breakdown, &tables, &cp)?;
+        report_endpoints(&report, &best.best_order)?;
+        report_transition_types(&report, &best.best_order, &best.best_shifts, &tables, &cp)?;
+        report_key_relations(&report, &best.best_order, &best.best_shifts, &tables, &cp)?;
+        report_objective(&report, lexicographic_scale)?;
+        let per_position = annealing::compute_per_position_costs(&best.best_order, &best.best_shifts, &tables, &cp);
+
+        Ok(MixResult {
+            shift_counts: shift_counts(&best.best_shifts),
+            n_attempts: Some(attempt_costs.len()),
+            order: best.best_order,
+            shifts: best.best_shifts,
+            cost: best.best_cost,
+            h_cost: breakdown.0,
+            t_cost: breakdown.1,
+            s_cost: breakdown.2,
+            attempt_costs: Some(attempt_costs),
+            per_track_min: Some(stats.min),
+            per_track_max: Some(stats.max),
+            per_track_avg: Some(stats.avg),
+            per_position_costs: Some(per_position),
+            proven_optimal: false,
+            report: report.unbind(),
+        })
+    }
+
+    /// Held-Karp over the selected tracks, reusing this optimizer's DP table, as
+    /// `optimize_mix_exact_v2` without its optional arguments.  Raises ValueError when the
+    /// table would exceed `max_memory_mb`.
+    #[pyo3(signature = (indices, cost_params, max_memory_mb=512.0, bpms=None, base_key_ids=None))]
+    fn optimize_exact(
+        &self,
+        py: Python<'_>,
+        indices: Option<Vec<usize>>,
+        cost_params: CostParamsArg,
+        max_memory_mb: f64,
+        bpms: Option<Array<i32>>,
+        base_key_ids: Option<Array<u8>>,
+    ) -> PyResult<MixResult> {
+        check_arrays(&[("bpms", &bpms), ("base_key_ids", &base_key_ids)])?;
+        let (bpms, key_ids) = self.tracks(indices, bpms, base_key_ids)?;
+        let n = bpms.len();
+        if n < 2 {
+            return Err(pyo3::exceptions::PyValueError::new_err("Need at least 2 tracks"));
+        }
+        if !(max_memory_mb.is_finite() && max_memory_mb > 0.0) {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "max_memory_mb must be a finite value > 0, got {max_memory_mb}"
+            )));
+        }
+        let mut cp = self.cost_params(&cost_params)?;
+        let (direct, indirect) = self.key_costs(&cp);
+        let tables = Tables::new(&bpms, &key_ids, &self.shift_table, &direct, &indirect);
+        let normal = CostParams { objective_mode: ObjectiveMode::Weighted, ..cp };
+        let lexicographic_scale = resolve_objective(&mut cp, n, &tables)?;
+        if !held_karp::memory_bytes(n, &cp).is_some_and(|b| b as f64 <= max_memory_mb * 1024.0 * 1024.0) {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "Held-Karp with n={n} needs more than the budget of {max_memory_mb} MB (max_memory_mb)"
+            )));
+        }
+
+        let (order, shifts, cost, mut breakdown, _) = py.allow_threads(|| {
+            let mut workspace = self.workspace.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+            held_karp::run_in(&mut workspace, n, &tables, &cp, None, None, false)
+        });
+        let perfect = cp.objective_mode == ObjectiveMode::PerfectTransitions;
+        if perfect {
+            cp = normal;
+        }
+        let report = PyDict::new(py);
+        report.set_item("fallback_to_sa", false)?;
+        report_perfect(&report, perfect, &order, &shifts, false, &mut breakdown, &tables, &cp)?;
+        report_endpoints(&report, &order)?;
+        report_transition_types(&report, &order, &shifts, &tables, &cp)?;
+        report_key_relations(&report, &order, &shifts, &tables, &cp)?;
+        report_objective(&report, lexicographic_scale)?;
+
+        Ok(MixResult {
+            shift_counts: shift_counts(&shifts),
+            order,
+            shifts,
+            cost,
+            h_cost: breakdown.0,
+            t_cost: breakdown.1,
+            s_cost: breakdown.2,
+            attempt_costs: None,
+            n_attempts: None,
+            per_track_min: None,
+            per_track_max: None,
+            per_track_avg: None,
+            per_position_costs: None,
+            proven_optimal: true,
+            report: report.unbind(),
+        })
+    }
+
+    /// Cost of a given order of the selected tracks with its (track-indexed) shifts, as
+    /// `score_orders` computes it, and its `(h, t, s)` breakdown.
+    #[pyo3(signature = (order, shifts, cost_params, indices=None, bpms=None, base_key_ids=None))]
+    fn evaluate(
+        &self,
+        order: Vec<usize>,
+        shifts: Vec<i8>,
+        cost_params: CostParamsArg,
+        indices: Option<Vec<usize>>,
+        bpms: Option<Array<i32>>,
+        base_key_ids: Option<Array<u8>>,
+    ) -> PyResult<(f64, (f64, f64, f64))> {
+        check_arrays(&[("bpms", &bpms), ("base_key_ids", &base_key_ids)])?;
+        let (bpms, key_ids) = self.tracks(indices, bpms, base_key_ids)?;
+        let n = bpms.len();
+        if n == 0 {
+            return Err(pyo3::exceptions::PyValueError::new_err("Need at least 1 track"));
+        }
+        validate_order_and_shifts(&order, &shifts, n)?;
+        let mut cp = self.cost_params(&cost_params)?;
+        let (direct, indirect) = self.key_costs(&cp);
+        let tables = Tables::new(&bpms, &key_ids, &self.shift_table, &direct, &indirect);
+        resolve_objective(&mut cp, n, &tables)?;
+        cost::evaluate_order(&order, &shifts, &tables, &cp).map_err(pyo3::exceptions::PyValueError::new_err)
+    }
+
+    /// Number of tracks in the library (0 without one).
+    #[getter]
+    fn library_size(&self) -> usize {
+        self.library.as_ref().map_or(0, |(bpms, _)| bpms.len())
+    }
+
+    /// Bytes currently held for the DP table and the annealing buffers.
+    #[getter]
+    fn reserved_bytes(&self) -> usize {
+        self.workspace.lock().unwrap_or_else(std::sync::PoisonError::into_inner).capacity_bytes()
+            + self.scratch.lock().unwrap_or_else(std::sync::PoisonError::into_inner).capacity_bytes()
+    }
+}
+
+fn shift_counts(shifts: &[i8]) -> (usize, usize, usize) {
+    let count = |v: i8| shifts.iter().filter(|&&s| s == v).count();
+    (count(-1), count(0), count(1))
+}
+
+/// A 1-D numeric input (`bpms`, key IDs, the shift, cost and compat tables): a sequence of
+/// numbers, or an object exposing the buffer protocol — a NumPy array — holding exactly
+/// the element type (int32, uint8 or float64) in one C-contiguous dimension.  Such a
+/// buffer is borrowed for the length of the call, and the copy-on-write `to_mut` copies it
+/// only when an entry actually has to be rewritten (a `+inf` under `inf_forbidden`) or the
+/// call lets other Python code run while reading it (see `TableData::detach`); a
+/// float32 cost table is widened in one bulk copy.  A buffer of any other dtype, shape or
+/// strides is rejected, not converted element by element or copied: `check_arrays` raises
+/// it as a ValueError naming the argument, and every function taking arrays calls it
+/// before reading them.
+struct Array<T: pyo3::buffer::Element> {
+    data: ArrayData<T>,
+    /// Read from a float32 buffer, so values may be rounded (see `restore_f32_value`).
+    from_f32: bool,
+}
+
+enum ArrayData<T: pyo3::buffer::Element> {
+    /// A C-contiguous 1-D buffer of `T`, held for the length of the call.
+    Borrowed(pyo3::buffer::PyBuffer<T>),
+    Owned(Vec<T>),
+    /// A buffer of the wrong layout or element type, and what was expected of it.
+    Rejected(String),
+}
+
+/// Element types of `Array`; f64 tables also take float32 buffers.
+trait ArrayElement: pyo3::buffer::Element + Copy {
+    /// The NumPy dtype a buffer must have.
+    const DTYPE: &'static str;
+
+    fn from_f32_buffer(_ob: &Bound<'_, PyAny>) -> Option<PyResult<ArrayData<Self>>> {
+        None
+    }
+}
+
+impl ArrayElement for i32 {
+    const DTYPE: &'static str = "int32";
+}
+
+impl ArrayElement for u8 {
+    const DTYPE: &'static str = "uint8";
+}
+
+impl ArrayElement for f64 {
+    const DTYPE: &'static str = "float64 (or float32)";
+
+    fn from_f32_buffer(ob: &Bound<'_, PyAny>) -> Option<PyResult<ArrayData<f64>>> {
+        Some(match read_buffer::<f32>(ob)? {
+            Ok(buf) => buf.to_vec(ob.py()).map(|v| ArrayData::Owned(v.into_iter().map(f64::from).collect())),
+            Err(problem) => Ok(ArrayData::Rejected(expected::<f64>(&problem))),
+        })
+    }
+}
+
+/// What a buffer for an `Array<T>` must be, and `problem` with the one given.
+fn expected<T: ArrayElement>(problem: &str) -> String {
+    format!("expected a C-contiguous 1-D {} array, got {problem}", T::DTYPE)
+}
+
+/// `ob` when it is a buffer of `T` (`None` for any other object), or what is wrong with it
+/// when it is not C-contiguous and 1-D.
+fn read_buffer<T: pyo3::buffer::Element>(ob: &Bound<'_, PyAny>) -> Option<Result<pyo3::buffer::PyBuffer<T>, String>> {
+    let buf = pyo3::buffer::PyBuffer::<T>::get(ob).ok()?;
+    if buf.dimensions() != 1 {
+        return Some(Err(format!("shape {}", numpy_tuple(buf.shape()))));
+    }
+    if !buf.is_c_contiguous() {
+        return Some(Err(format!("strides {}", numpy_tuple(buf.strides()))));
+    }
+    Some(Ok(buf))
+}
+
+/// The element type of a buffer as NumPy names it ("dtype int64" for format `q` or `l`),
+/// `None` when `ob` is not a buffer.
+fn buffer_dtype(ob: &Bound<'_, PyAny>) -> Option<String> {
+    let view = pyo3::types::PyMemoryView::from(ob).ok()?;
+    let format: String = view.getattr("format").ok()?.extract().ok()?;
+    let itemsize: usize = view.getattr("itemsize").ok()?.extract().ok()?;
+    let kind = match format.trim_start_matches(['@', '=', '<', '>', '!']) {
+        "b" | "h" | "i" | "l" | "q" | "n" => "int",
+        "B" | "H" | "I" | "L" | "Q" | "N" => "uint",
+        "e" | "f" | "d" => "float",
+        "?" => return Some("dtype bool".into()),
+        _ => return Some(format!("format {format:?}")),
+    };
+    Some(format!("dtype {kind}{}", 8 * itemsize))
+}
+
+/// A shape or strides as NumPy prints them: `(24, 24)`, `(16,)`.
+fn numpy_tuple<D: std::fmt::Display>(values: &[D]) -> String {
+    match values {
+        [v] => format!("({v},)"),
+        _ => format!("({})", values.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")),
+    }
+}
+
+impl<'py, T: ArrayElement + FromPyObject<'py>> FromPyObject<'py> for Array<T> {
+    fn extract_bound(ob: &Bound<'py, PyAny>) -> PyResult<Self> {
+        if let Some(buf) = read_buffer::<T>(ob) {
+            let data = buf.map_or_else(|problem| ArrayData::Rejected(expected::<T>(&problem)), ArrayData::Borrowed);
+            return Ok(Array { data, from_f32: false });
+        }
+        if let Some(data) = T::from_f32_buffer(ob) {
+            return Ok(Array { data: data?, from_f32: true });
+        }
+        if let Some(dtype) = buffer_dtype(ob) {
+            return Ok(Array { data: ArrayData::Rejected(expected::<T>(&dtype)), from_f32: false });
+        }
+        Ok(Array::from(ob.extract::<Vec<T>>()?))
+    }
+}
+
+/// An `Array` argument, optional or not, as `check_arrays` takes it.
+trait ArrayArg {
+    /// Why its buffer was rejected.
+    fn rejected(&self) -> Option<&str>;
+}
+
+impl<T: pyo3::buffer::Element> ArrayArg for Array<T> {
+    fn rejected(&self) -> Option<&str> {
+        match &self.data {
+            ArrayData::Rejected(problem) => Some(problem),
+            _ => None,
+        }
+    }
+}
+
+impl<A: ArrayArg> ArrayArg for Option<A> {
+    fn rejected(&self) -> Option<&str> {
+        self.as_ref()?.rejected()
+    }
+}
+
+/// Raise a rejected buffer among a function's `(name, array)` arguments as a ValueError
+/// naming it.  Called before any of them is read.
+fn check_arrays(arrays: &[(&str, &dyn ArrayArg)]) -> PyResult<()> {
+    for (name, array) in arrays {
+        if let Some(problem) = array.rejected() {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!("{name}: {problem}")));
+        }
+    }
+    Ok(())
+}
+
+impl<T: pyo3::buffer::Element> From<Vec<T>> for Array<T> {
+    fn from(values: Vec<T>) -> Self {
+        Array { data: ArrayData::Owned(values), from_f32: false }
+    }
+}
+
+impl<T: pyo3::buffer::Element> std::ops::Deref for Array<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match &self.data {
+            ArrayData::Borrowed(buf) if buf.item_count() == 0 => &[],
+            // SAFETY: `read_buffer` only borrows C-contiguous 1-D buffers, which `PyBuffer::get`
+            // has checked hold `T`s at an aligned address; the buffer stays exported (its
+            // memory alive and unresized) until `buf` is dropped with `self`.  No other Python
+            // code can write to it while the slice is read: the bindings hold the GIL and run
+            // no Python code meanwhile, and the calls that release it or call back into
+            // Python (`polish_2opt`, `checkpoint_fn`, `propose`) `detach` their tables first.
+            ArrayData::Borrowed(buf) => unsafe {
+                std::slice::from_raw_parts(buf.buf_ptr() as *const T, buf.item_count())
+            },
+            ArrayData::Owned(values) => values,
+            ArrayData::Rejected(_) => unreachable!("check_arrays raises a rejected array before it is read"),
+        }
+    }
+}
+
+impl<T: pyo3::buffer::Element + Copy> Array<T> {
+    /// The values, copied out of a borrowed buffer first.
+    fn to_mut(&mut self) -> &mut Vec<T> {
+        if !matches!(self.data, ArrayData::Owned(_)) {
+            self.data = ArrayData::Owned(self.to_vec());
+        }
+        match &mut self.data {
+            ArrayData::Owned(values) => values,
+            _ => unreachable!("just copied"),
+        }
+    }
+
+    /// The values as an owned `Vec`, e.g. to keep beyond the call.
+    fn into_vec(self) -> Vec<T> {
+        match self.data {
+            ArrayData::Owned(values) => values,
+            _ => self.to_vec(),
+        }
+    }
+}
+
+impl Array<f64> {
+    /// Undo the float32 rounding of `value` (see `cost::restore_f32_value`).
+    fn restore_f32_value(&mut self, value: f64) {
+        if self.from_f32 {
+            cost::restore_f32_value(self.to_mut(), value);
+        }
+    }
+}
+
+/// A `cost_params` value: numbers, plus strings for the few named options and a map from
+/// shift to penalty for `shift_penalty`.
+#[derive(FromPyObject)]
+enum CostParamValue {
+    Number(f64),
+    Text(String),
+    PerShift(std::collections::HashMap<i64, f64>),
+}
+
+/// The `shift_penalty` argument of `CostParams`: one penalty or one per shift.
+#[derive(FromPyObject)]
+enum ShiftPenaltyArg {
+    Uniform(f64),
+    PerShift(std::collections::HashMap<i64, f64>),
+}
+
+/// Penalties `(down, up)` from a `shift_penalty` map, which must give one for each of -1
+/// and +1 and nothing outside -1..=1; a 0 entry, if given, must be 0.
+fn shift_penalties(map: &std::collections::HashMap<i64, f64>) -> PyResult<(f64, f64)> {
+    if let Some(s) = map.keys().find(|s| !(-1..=1).contains(*s)) {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "shift_penalty has a penalty for shift {s}, shifts are -1, 0 or +1"
+        )));
+    }
+    if let Some(&p) = map.get(&0).filter(|&&p| p != 0.0) {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "shift_penalty of shift 0 must be 0, got {p}"
+        )));
+    }
+    match (map.get(&-1), map.get(&1)) {
+        (Some(&down), Some(&up)) => Ok((down, up)),
+        _ => Err(pyo3::exceptions::PyValueError::new_err(
+            "shift_penalty must give a penalty for each of shifts -1 and +1",
+        )),
+    }
+}
+
+/// Cost parameters as a Python class: keyword arguments with the mixer's defaults, checked
+/// on construction, so a misspelt name fails at once instead of at the optimizer call.
+/// Every function taking `cost_params` accepts an instance or the legacy dict.
+#[pyclass(name = "CostParams", get_all, frozen, module = "ydj_mixer_engine")]
+struct PyCostParams {
+    tempo_threshold: f64,
+    tempo_penalty: f64,
+    tempo_break_factor: f64,
+    tempo_penalty_slope: f64,
+    tempo_cost_weight: f64,
+    non_harmonic_cost: f64,
+    /// The larger of the two when `shift_penalty_by_direction` is set.
+    shift_penalty: f64,
+    /// `(down, up)` penalties of a -1 and a +1 shift, when given as a map.
+    shift_penalty_by_direction: Option<(f64, f64)>,
+    shift_weight: f64,
+    num_keys: usize,
+    harmonic_across_breaks: bool,
+    harmonic_only: bool,
+    perceptual_tempo: bool,
+    confidence_product: bool,
+    cut_penalty: Option<f64>,
+    cut_harmonic_discount: f64,
+    objective_mode: String,
+    perfect_threshold: f64,
+}
+
+impl PyCostParams {
+    /// `shift_penalty_by_direction` as a `shift_penalty` map.
+    fn shift_penalty_map(&self) -> Option<std::collections::HashMap<i64, f64>> {
+        self.shift_penalty_by_direction.map(|(down, up)| [(-1, down), (1, up)].into())
+    }
+
+    /// The legacy dict form; `cut_penalty` is left out when off.
+    fn entries(&self) -> std::collections::HashMap<String, CostParamValue> {
+        let flag = |b: bool| if b { 1.0 } else { 0.0 };
+        let mut d: std::collections::HashMap<String, CostParamValue> = [
+            ("tempo_threshold", self.tempo_threshold),
+            ("tempo_penalty", self.tempo_penalty),
+            ("tempo_break_factor", self.tempo_break_factor),
+            ("tempo_penalty_slope", self.tempo_penalty_slope),
+            ("tempo_cost_weight", self.tempo_cost_weight),
+            ("non_harmonic_cost", self.non_harmonic_cost),
+            ("shift_penalty", self.shift_penalty),
+            ("shift_weight", self.shift_weight),
+            ("num_keys", self.num_keys as f64),
+            ("harmonic_across_breaks", flag(self.harmonic_across_breaks)),
+            ("harmonic_only", flag(self.harmonic_only)),
+            ("perceptual_tempo", flag(self.perceptual_tempo)),
+            ("confidence_product", flag(self.confidence_product)),
+            ("cut_harmonic_discount", self.cut_harmonic_discount),
+            ("perfect_threshold", self.perfect_threshold),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), CostParamValue::Number(v)))
+        .collect();
+        if let Some(m) = self.shift_penalty_map() {
+            d.insert("shift_penalty".into(), CostParamValue::PerShift(m));
+        }
+        if let Some(p) = self.cut_penalty {
+            d.insert("cut_penalty".into(), CostParamValue::Number(p));
+        }
+        d.insert("objective_mode".into(), CostParamValue::Text(self.objective_mode.clone()));
+        d
+    }
+}
+
+#[pymethods]
+impl PyCostParams {
+    /// Raises PyValueError for out-of-range values (as the optimizers would), and also for a
+    /// tempo_threshold that is not > 0.  `shift_penalty` is a number or a dict giving the
+    /// penalties of shifts -1 and +1, e.g. `{-1: 0.5, 1: 1.5}`.
+    #[new]
+    #[pyo3(signature = (
+        *, tempo_threshold=4.5, tempo_penalty=5.0, tempo_break_factor=2.0,
+        tempo_penalty_slope=0.0, tempo_cost_weight=3.0, non_harmonic_cost=5.0,
+        shift_penalty=ShiftPenaltyArg::Uniform(1.0),
+        shift_weight=1.0, num_keys=24, harmonic_across_breaks=false, harmonic_only=false,
+        confidence_product=false, cut_penalty=None, cut_harmonic_discount=0.0,
+        objective_mode="weighted".to_string(), perfect_threshold=1e-9, perceptual_tempo=false,
+    ))]
+    fn new(
+        tempo_threshold: f64,
+        tempo_penalty: f64,
+        tempo_break_factor: f64,
+        tempo_penalty_slope: f64,
+        tempo_cost_weight: f64,
+        non_harmonic_cost: f64,
+        shift_penalty: ShiftPenaltyArg,
+        shift_weight: f64,
+        num_keys: usize,
+        harmonic_across_breaks: bool,
+        harmonic_only: bool,
+        confidence_product: bool,
+        cut_penalty: Option<f64>,
+        cut_harmonic_discount: f64,
+        objective_mode: String,
+        perfect_threshold: f64,
+        perceptual_tempo: bool,
+    ) -> PyResult<Self> {
+        if tempo_threshold.is_nan() || tempo_threshold <= 0.0 {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "tempo_threshold must be > 0, got {tempo_threshold}"
+            )));
+        }
+        let (shift_penalty, shift_penalty_by_direction) = match shift_penalty {
+            ShiftPenaltyArg::Uniform(p) => (p, None),
+            ShiftPenaltyArg::PerShift(m) => {
+                let (down, up) = shift_penalties(&m)?;
+                (down.max(up), Some((down, up)))
+            }
+        };
+        let params = PyCostParams {
+            tempo_threshold,
+            tempo_penalty,
+            tempo_break_factor,
+            tempo_penalty_slope,
+            tempo_cost_weight,
+            non_harmonic_cost,
+            shift_penalty,
+            shift_penalty_by_direction,
+            shift_weight,
+            num_keys,
+            harmonic_across_breaks,
+            harmonic_only,
+            perceptual_tempo,
+            confidence_product,
+            cut_penalty,
+            cut_harmonic_discount,
+            objective_mode,
+            perfect_threshold,
+        };
+        build_cost_params(&params.entries())?;
+        Ok(params)
+    }
+
+    /// The legacy `cost_params` dict (flags as bools; `cut_penalty` only when set;
+    /// `shift_penalty` a dict when it differs by direction).
+    fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let d = PyDict::new(py);
+        d.set_item("tempo_threshold", self.tempo_threshold)?;
+        d.set_item("tempo_penalty", self.tempo_penalty)?;
+        d.set_item("tempo_break_factor", self.tempo_break_factor)?;
+        d.set_item("tempo_penalty_slope", self.tempo_penalty_slope)?;
+        d.set_item("tempo_cost_weight", self.tempo_cost_weight)?;
+        d.set_item("non_harmonic_cost", self.non_harmonic_cost)?;
+        match self.shift_penalty_map() {
+            Some(m) => d.set_item("shift_penalty", m)?,
+            None => d.set_item("shift_penalty", self.shift_penalty)?,
+        }
+        d.set_item("shift_weight", self.shift_weight)?;
+        d.set_item("num_keys", self.num_keys)?;
+        d.set_item("harmonic_across_breaks", self.harmonic_across_breaks)?;
+        d.set_item("harmonic_only", self.harmonic_only)?;
+        d.set_item("perceptual_tempo", self.perceptual_tempo)?;
+        d.set_item("confidence_product", self.confidence_product)?;
+        if let Some(p) = self.cut_penalty {
+            d.set_item("cut_penalty", p)?;
+        }
+        d.set_item("cut_harmonic_discount", self.cut_harmonic_discount)?;
+        d.set_item("objective_mode", &self.objective_mode)?;
+        d.set_item("perfect_threshold", self.perfect_threshold)?;
+        Ok(d)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "CostParams(tempo_threshold={:?}, tempo_penalty={:?}, tempo_cost_weight={:?}, \
+             non_harmonic_cost={:?}, shift_penalty={}, objective_mode={:?})",
+            self.tempo_threshold, self.tempo_penalty, self.tempo_cost_weight,
+            self.non_harmonic_cost,
+            match self.shift_penalty_by_direction {
+                Some((down, up)) => format!("{{-1: {down:?}, 1: {up:?}}}"),
+                None => format!("{:?}", self.shift_penalty),
+            },
+            self.objective_mode,
+        )
+    }
+}
+
+/// A `cost_params` argument: a `CostParams` instance or the legacy dict.
+struct CostParamsArg(std::collections::HashMap<String, CostParamValue>);
+
+impl<'py> FromPyObject<'py> for CostParamsArg {
+    fn extract_bound(ob: &Bound<'py, PyAny>) -> PyResult<Self> {
+        match ob.downcast::<PyCostParams>() {
+            Ok(p) => Ok(CostParamsArg(p.get().entries())),
+            Err(_) => Ok(CostParamsArg(ob.extract()?)),
+        }
+    }
+}
+
+impl std::ops::Deref for CostParamsArg {
+    type Target = std::collections::HashMap<String, CostParamValue>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Build `CostParams` from the Python `cost_params` dict.
+fn build_cost_params(
+    raw: &std::collections::HashMap<String, CostParamValue>,
+) -> PyResult<CostParams> {
+    // Optional: how the components combine (default "weighted").  Every other value is
+    // numeric, except that shift_penalty may map each shift to its own penalty.
+    let mut objective_mode = ObjectiveMode::Weighted;
+    let mut shift_penalty_by_direction = None;
+    let mut d = std::collections::HashMap::new();
+    for (k, v) in raw {
+        match v {
+            CostParamValue::Text(t) if k == "objective_mode" && t == "weighted" => {}
+            CostParamValue::Text(t) if k == "objective_mode" && t == "lexicographic_h_then_t" => {
+                objective_mode = ObjectiveMode::LexicographicHThenT;
+            }
+            CostParamValue::Text(t) if k == "objective_mode" && t == "perfect_transitions" => {
+                objective_mode = ObjectiveMode::PerfectTransitions;
+            }
+            _ if k == "objective_mode" => {
+                return Err(pyo3::exceptions::PyValueError::new_err(
+                    "objective_mode must be \"weighted\", \"lexicographic_h_then_t\" or \"perfect_transitions\"",
+                ));
+            }
+            CostParamValue::PerShift(m) if k == "shift_penalty" => {
+                let (down, up) = shift_penalties(m)?;
+                shift_penalty_by_direction = Some((down, up));
+                d.insert("shift_penalty", down.max(up));
+            }
+            CostParamValue::Number(x) => {
+                d.insert(k.as_str(), *x);
+            }
+            CostParamValue::Text(t) => {
+                return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "cost_params[{k:?}] must be a number, got {t:?}"
+                )));
+            }
+            CostParamValue::PerShift(_) => {
+                return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "cost_params[{k:?}] must be a number, got a dict"
+                )));
+            }
+        }
+    }
+    let get = |k: &str| -> PyResult<f64> {
+        d.get(k).copied().ok_or_else(|| {
+            pyo3::exceptions::PyKeyError::new_err(format!("Missing param: {k}"))
+        })
+    };
+
+    // Optional: size of the key system (default 24 Camelot keys).  Key IDs are u8.
+    let num_keys = match d.get("num_keys") {
+        None => 24,
+        Some(&k) if k.fract() == 0.0 && (1.0..=256.0).contains(&k) => k as usize,
+        Some(&k) => {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "num_keys must be an integer between 1 and 256, got {k}"
+            )));
+        }
+    };
+
+    // Optional: per-edge choice between a blend and a hard cut (off when cut_penalty is absent).
+    let cut_penalty = match d.get("cut_penalty") {
+        Some(&p) if !(p.is_finite() && p >= 0.0) => {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "cut_penalty must be a finite value >= 0, got {p}"
+            )));
+        }
+        p => p.copied(),
+    };
+    let cut_harmonic_discount = d.get("cut_harmonic_discount").copied().unwrap_or(0.0);
+    if !(0.0..=1.0).contains(&cut_harmonic_discount) {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "cut_harmonic_discount must be between 0 and 1, got {cut_harmonic_discount}"
+        )));
+    }
+
+    // Optional: tempo cost per BPM over the threshold (default 0, a flat step)
+    let tempo_penalty_slope = d.get("tempo_penalty_slope").copied().unwrap_or(0.0);
+    if !(tempo_penalty_slope.is_finite() && tempo_penalty_slope >= 0.0) {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "tempo_penalty_slope must be a finite value >= 0, got {tempo_penalty_slope}"
+        )));
+    }
+
+    // Optional: edges cheaper than this are perfect (default: only edges costing 0 or less)
+    let perfect_threshold = d.get("perfect_threshold").copied().unwrap_or(1e-9);
+    if !perfect_threshold.is_finite() {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "perfect_threshold must be finite, got {perfect_threshold}"
+        )));
+    }
+
+    let cp = CostParams {
+        tempo_threshold:    get("tempo_threshold")?,
+        tempo_penalty:      get("tempo_penalty")?,
+        tempo_break_factor: get("tempo_break_factor")?,
+        tempo_penalty_slope,
+        tempo_cost_weight:  get("tempo_cost_weight")?,
+        non_harmonic_cost:  get("non_harmonic_cost")?,
+        shift_penalty:      get("shift_penalty")?,
+        shift_penalty_by_direction,
+        shift_weight:       get("shift_weight")?,
+        num_keys,
+        // Optional flag: any nonzero value enables it
+        harmonic_across_breaks: d.get("harmonic_across_breaks").is_some_and(|&v| v != 0.0),
+        harmonic_only: d.get("harmonic_only").is_some_and(|&v| v != 0.0),
+        perceptual_tempo: d.get("perceptual_tempo").is_some_and(|&v| v != 0.0),
+        confidence_product: d.get("confidence_product").is_some_and(|&v| v != 0.0),
+        cut_penalty,
+        cut_harmonic_discount,
+        objective_mode,
+        perfect_threshold,
+        family_run: None,
+        max_shifted: None,
+        max_shift_cost: None,
+        minmax_blend: 0.0,
+    };
+    cp.validate().map_err(pyo3::exceptions::PyValueError::new_err)?;
+    Ok(cp)
+}
+
+/// Annealing schedule as a Python class, the `AnnealingParams` counterpart of `CostParams`:
+/// keyword arguments with the mixer's defaults, and a schedule that does not cool
+/// (initial_temp <= final_temp, final_temp <= 0, no iterations) is rejected on
+/// construction.  Accepted wherever an `annealing_params` dict is.
+#[pyclass(name = "AnnealingParams", get_all, frozen, module = "ydj_mixer_engine")]
+struct PyAnnealingParams {
+    total_iterations: usize,
+    initial_temp: f64,
+    final_temp: f64,
+    multi_swap_factor: usize,
+    auto_initial_temp: Option<f64>,
+}
+
+impl PyAnnealingParams {
+    /// The legacy dict entries (auto_initial_temp only when set).
+    fn entries(&self) -> Vec<(&'static str, f64)> {
+        let mut entries = vec![
+            ("total_iterations", self.total_iterations as f64),
+            ("initial_temp", self.initial_temp),
+            ("final_temp", self.final_temp),
+            ("multi_swap_factor", self.multi_swap_factor as f64),
+        ];
+        entries.extend(self.auto_initial_temp.map(|rate| ("auto_initial_temp", rate)));
+        entries
+    }
+}
+
+#[pymethods]
+impl PyAnnealingParams {
+    #[new]
+    #[pyo3(signature = (
+        *, total_iterations=410_000, initial_temp=500.0, final_temp=0.1, multi_swap_factor=2,
+        auto_initial_temp=None,
+    ))]
+    fn new(
+        total_iterations: usize,
+        initial_temp: f64,
+        final_temp: f64,
+        multi_swap_factor: usize,
+        auto_initial_temp: Option<f64>,
+    ) -> PyResult<Self> {
+        AnnealingParams {
+            total_iterations,
+            initial_temp,
+            final_temp,
+            multi_swap_factor,
+            shift_init_weights: [1.0; 3],
+            auto_initial_temp,
+        }
+        .validate()
+        .map_err(pyo3::exceptions::PyValueError::new_err)?;
+        Ok(PyAnnealingParams { total_iterations, initial_temp, final_temp, multi_swap_factor, auto_initial_temp })
+    }
+
+    /// Build from a legacy `annealing_params` dict; missing keys take the defaults and an
+    /// unknown key raises TypeError naming it.
+    #[classmethod]
+    fn from_dict<'py>(cls: &Bound<'py, pyo3::types::PyType>, d: &Bound<'py, PyDict>) -> PyResult<Bound<'py, PyAny>> {
+        // Counts arrive as floats in the legacy dicts
+        let kwargs = PyDict::new(cls.py());
+        for (k, v) in d.iter() {
+            let key: String = k.extract()?;
+            match key.as_str() {
+                "total_iterations" | "multi_swap_factor" => {
+                    let x: f64 = v.extract()?;
+                    if x.fract() != 0.0 || x < 0.0 {
+                        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                            "{key} must be a non-negative integer, got {x}"
+                        )));
+                    }
+                    kwargs.set_item(k, x as usize)?;
+                }
+                _ => kwargs.set_item(k, v)?,
+            }
+        }
+        cls.call((), Some(&kwargs))
+    }
+
+    /// The legacy `annealing_params` dict (all values as floats).
+    fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let d = PyDict::new(py);
+        for (k, v) in self.entries() {
+            d.set_item(k, v)?;
+        }
+        Ok(d)
+    }
+
+    fn __repr__(&self) -> String {
+        let auto = self.auto_initial_temp.map_or_else(|| "None".to_string(), |rate| format!("{rate:?}"));
+        format!(
+            "AnnealingParams(total_iterations={}, initial_temp={:?}, final_temp={:?}, multi_swap_factor={}, \
+             auto_initial_temp={auto})",
+            self.total_iterations, self.initial_temp, self.final_temp, self.multi_swap_factor,
+        )
+    }
+}
+
+/// An `annealing_params` argument: an `AnnealingParams` instance or the legacy dict.
+struct AnnealingParamsArg(std::collections::HashMap<String, f64>);
+
+impl<'py> FromPyObject<'py> for AnnealingParamsArg {
+    fn extract_bound(ob: &Bound<'py, PyAny>) -> PyResult<Self> {
+        match ob.downcast::<PyAnnealingParams>() {
+            Ok(p) => Ok(AnnealingParamsArg(p.get().entries().into_iter().map(|(k, v)| (k.to_string(), v)).collect())),
+            Err(_) => Ok(AnnealingParamsArg(ob.extract()?)),
+        }
+    }
+}
+
+impl std::ops::Deref for AnnealingParamsArg {
+    type Target = std::collections::HashMap<String, f64>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+fn build_annealing_params(d: &std::collections::HashMap<String, f64>) -> PyResult<AnnealingParams> {
+    let get = |k: &str| -> PyResult<f64> {
+        d.get(k).copied().ok_or_else(|| {
+            pyo3::exceptions::PyKeyError::new_err(format!("Missing param: {k}"))
+        })
+    };
+
+    let auto_initial_temp = d.get("auto_initial_temp").copied();
+    if let Some(rate) = auto_initial_temp.filter(|rate| !(*rate > 0.0 && *rate < 1.0)) {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "auto_initial_temp must be an acceptance rate in (0, 1), got {rate}"
+        )));
+    }
+
+    Ok(AnnealingParams {
+        total_iterations: get("total_iterations")? as usize,
+        initial_temp:     get("initial_temp")?,
+        final_temp:       get("final_temp")?,
+        multi_swap_factor: get("multi_swap_factor")? as usize,
+        shift_init_weights: [1.0; 3],
+        auto_initial_temp,
+    })
+}
+
+/// Parse `shift_init_weights` (odds of starting a track at shift -1 / 0 / +1).
+fn build_shift_init_weights(weights: Option<(f64, f64, f64)>) -> PyResult<[f64; 3]> {
+    let Some((down, none, up)) = weights else {
+        return Ok([1.0; 3]);
+    };
+    let w = [down, none, up];
+    if !w.iter().all(|x| x.is_finite() && *x >= 0.0) || w.iter().sum::<f64>() <= 0.0 {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "shift_init_weights must be finite, >= 0 and not all zero, got {w:?}"
+        )));
+    }
+    Ok(w)
+}
+
+/// Parse the `stats_weighting` keyword (see `annealing::StatsWeighting`).
+fn build_stats_weighting(mode: &str, within_pct: f64) -> PyResult<StatsWeighting> {
+    match mode {
+        "uniform" => Ok(StatsWeighting::Uniform),
+        "inverse_cost" => Ok(StatsWeighting::InverseCost),
+        "within_pct" => {
+            if within_pct.is_nan() || within_pct < 0.0 {
+                return Err(pyo3::exceptions::PyValueError::new_err(
+                    "stats_within_pct must be non-negative",
+                ));
+            }
+            Ok(StatsWeighting::WithinPercent(within_pct))
+        }
+        _ => Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "Unknown stats_weighting: {mode:?} (expected \"uniform\", \"inverse_cost\" or \"within_pct\")"
+        ))),
+    }
+}
+
+/// Reject NaN/inf entries in a cost table (see `cost::sanitize_cost_table`).
+/// A clean table is left borrowed; it is copied only when a `+inf` has to be rewritten.
+fn validate_cost_table(name: &str, table: &mut Array<f64>, inf_forbidden: bool) -> PyResult<()> {
+    if table.iter().all(|c| c.is_finite()) {
+        return Ok(());
+    }
+    let table = table.to_mut();
+    cost::sanitize_cost_table(table, inf_forbidden).map_err(|i| {
+        pyo3::exceptions::PyValueError::new_err(format!(
+            "{name}[{i}] is {} — cost tables must be finite{}",
+            table[i],
+            if inf_forbidden { " (or +inf for forbidden transitions)" } else { "" },
+        ))
+    })
+}
+
+/// Turn a lexicographic `objective_mode` into the equivalent weighted objective over the
+/// `n` tracks of `plain` (see `CostParams::resolve_objective`); returns the scale in that
+/// mode.
+fn resolve_objective(cp: &mut CostParams, n: usize, plain: &Tables) -> PyResult<Option<f64>> {
+    let lexicographic = cp.objective_mode == ObjectiveMode::LexicographicHThenT;
+    let scale = cp.resolve_objective(n, plain).map_err(pyo3::exceptions::PyValueError::new_err)?;
+    Ok(lexicographic.then_some(scale))
+}
+
+/// Record the lexicographic scale under `lexicographic_scale`, when that mode is active.
+fn report_objective(report: &Bound<'_, PyDict>, scale: Option<f64>) -> PyResult<()> {
+    if let Some(scale) = scale {
+        report.set_item("lexicographic_scale", scale)?;
+    }
+    Ok(())
+}
+
+/// Under the perfect-transitions objective the solvers see 0/1 edges; with `active`,
+/// restate the result under the normal model `cp`: the `(h, t, s)` breakdown (with the
+/// wrap edge when `cyclic`) and, under `perfect_transitions`, the number of perfect edges.
+fn report_perfect(
+    report: &Bound<'_, PyDict>,
+    active: bool,
+    order: &[usize],
+    shifts: &[i8],
+    cyclic: bool,
+    breakdown: &mut (f64, f64, f64),
+    plain: &Tables,
+    cp: &CostParams,
+) -> PyResult<()> {
+    if !active {
+        return Ok(());
+    }
+    let mut edges = cost::edge_costs(order, shifts, plain, cp);
+    *breakdown = cost::total_edge_cost(order, shifts, plain, cp);
+    if cyclic {
+        let (last, first) = (order[order.len() - 1], order[0]);
+        let (h, t) = cost::edge_components(last, first, shifts[last], shifts[first], plain, cp);
+        edges.push(h + cp.tempo_cost_weight * t);
+        breakdown.0 += h;
+        breakdown.1 += t;
+    }
+    report.set_item("perfect_transitions", edges.iter().filter(|&&c| cp.is_perfect(c)).count())?;
+    Ok(())
+}
+
+/// Check the key lookup tables against `num_keys` so no cost lookup can index out of bounds:
+/// one base key per track, `num_keys * 3` shift-table entries, `num_keys²` cost entries (none
+/// with `sparse`), and every key ID (base or shifted) below `num_keys`.  Two empty cost
+/// tables without `sparse` are filled in with `cost::camelot_key_costs`.
+fn validate_key_tables(
+    n: usize,
+    base_key_ids: &[u8],
+    shift_table: &[u8],
+    direct_costs: &mut Array<f64>,
+    indirect_costs: &mut Array<f64>,
+    cp: &CostParams,
+    sparse: bool,
+) -> PyResult<()> {
+    let num_keys = cp.num_keys;
+    let expect_len = |name: &str, len: usize, expected: usize| -> PyResult<()> {
+        if len != expected {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "{name} has {len} entries, expected {expected}"
+            )));
+        }
+        Ok(())
+    };
+    expect_len("base_key_ids", base_key_ids.len(), n)?;
+    expect_len("shift_table", shift_table.len(), num_keys * 3)?;
+    if sparse {
+        // The dense tables are not read; insist they are empty so nothing is silently ignored
+        if !(direct_costs.is_empty() && indirect_costs.is_empty()) {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "pass direct_costs and indirect_costs as empty lists with sparse_costs",
+            ));
+        }
+    } else if direct_costs.is_empty() && indirect_costs.is_empty() {
+        // No tables: the built-in Camelot wheel model
+        if num_keys != 24 {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "empty direct_costs and indirect_costs select the built-in Camelot model, which \
+                 needs num_keys = 24, got {num_keys}"
+            )));
+        }
+        let (direct, indirect) = cost::camelot_key_costs(cp.non_harmonic_cost);
+        (*direct_costs, *indirect_costs) = (direct.into(), indirect.into());
+    } else {
+        expect_len("direct_costs", direct_costs.len(), num_keys * num_keys)?;
+        expect_len("indirect_costs", indirect_costs.len(), num_keys * num_keys)?;
+        // The surcharge spots non-harmonic pairs by exact equality with non_harmonic_cost
+        direct_costs.restore_f32_value(cp.non_harmonic_cost);
+        indirect_costs.restore_f32_value(cp.non_harmonic_cost);
+    }
+    for (name, ids) in [("base_key_ids", base_key_ids), ("shift_table", shift_table)] {
+        if let Some(i) = ids.iter().position(|&k| k as usize >= num_keys) {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "{name}[{i}] is {}, but num_keys is {num_keys}", ids[i]
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Build the sparse key costs from the `sparse_costs` keyword argument,
+/// `(default_direct, default_indirect, [(ek1, ek2, direct, indirect), ...])`.  `+inf` costs
+/// follow `inf_forbidden` as in the dense tables.
+fn build_sparse_costs(
+    sparse: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+    num_keys: usize,
+    inf_forbidden: bool,
+) -> PyResult<Option<SparseKeyCosts>> {
+    let Some((default_direct, default_indirect, mut overrides)) = sparse else { return Ok(None) };
+    let forbid = |c: f64| if inf_forbidden && c == f64::INFINITY { cost::FORBIDDEN_COST } else { c };
+    for o in &mut overrides {
+        (o.2, o.3) = (forbid(o.2), forbid(o.3));
+    }
+    SparseKeyCosts::new(num_keys, (forbid(default_direct), forbid(default_indirect)), &overrides)
+        .map(Some)
+        .map_err(pyo3::exceptions::PyValueError::new_err)
+}
+
+/// Check that an optional `harmonic_mask` covers every effective-key pair.
+fn validate_harmonic_mask(mask: Option<&[u8]>, num_keys: usize) -> PyResult<()> {
+    if let Some(m) = mask {
+        if m.len() != num_keys * num_keys {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "harmonic_mask has {} entries, expected {}", m.len(), num_keys * num_keys
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Accepted BPM range.  Anything outside it is garbage from upstream metadata parsing
+/// (e.g. a sentinel `i32::MIN`), not a tempo.
+const BPM_RANGE: std::ops::RangeInclusive<i32> = 1..=400;
+
+/// Check that a BPM lies in `BPM_RANGE`.
+fn validate_bpm(name: &str, bpm: i32) -> PyResult<()> {
+    if !BPM_RANGE.contains(&bpm) {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "{name} is {bpm}, expected a BPM between {} and {}", BPM_RANGE.start(), BPM_RANGE.end()
+        )));
+    }
+    Ok(())
+}
+
+/// Check `bpms` and the optional `intro_bpms`/`outro_bpms`: one entry per track, each in
+/// `BPM_RANGE`.
+fn validate_track_bpms(
+    bpms: &[i32],
+    intro: Option<&[i32]>,
+    outro: Option<&[i32]>,
+    n: usize,
+) -> PyResult<()> {
+    for (name, bpms) in [("bpms", Some(bpms)), ("intro_bpms", intro), ("outro_bpms", outro)] {
+        if let Some(b) = bpms {
+            if b.len() != n {
+                return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "{name} has {} entries, expected {n}", b.len()
+                )));
+            }
+            for (i, &bpm) in b.iter().enumerate() {
+                validate_bpm(&format!("{name}[{i}]"), bpm)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check that an optional `key_confidence` has one value in [0, 1] per track.
+fn validate_key_confidence(conf: Option<&[f64]>, n: usize) -> PyResult<()> {
+    if let Some(c) = conf {
+        if c.len() != n {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "key_confidence has {} entries, expected {n}", c.len()
+            )));
+        }
+        if let Some(i) = c.iter().position(|v| !(0.0..=1.0).contains(v)) {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "key_confidence[{i}] is {}, expected a value in [0, 1]", c[i]
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn validate_inclusion_value(value: Option<&[f64]>, n: usize) -> PyResult<()> {
+    if let Some(v) = value {
+        if v.len() != n {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "inclusion_value has {} entries, expected {n}", v.len()
+            )));
+        }
+        if let Some(i) = v.iter().position(|x| !x.is_finite()) {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "inclusion_value[{i}] is {}, expected a finite value", v[i]
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Check the arguments of a set-duration target.
+fn validate_duration_target(
+    durations: Option<&[f64]>,
+    target: Option<f64>,
+    tolerance: f64,
+    penalty: f64,
+    n: usize,
+) -> PyResult<()> {
+    let (Some(d), Some(target)) = (durations, target) else {
+        if durations.is_some() || target.is_some() {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "durations_secs and target_duration_secs must be given together",
+            ));
+        }
+        return Ok(());
+    };
+    if d.len() != n {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "durations_secs has {} entries, expected {n}", d.len()
+        )));
+    }
+    if let Some(i) = d.iter().position(|s| !(s.is_finite() && *s >= 0.0)) {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "durations_secs[{i}] is {}, expected a finite value >= 0", d[i]
+        )));
+    }
+    let values = [("target_duration_secs", target), ("duration_tolerance_secs", tolerance), ("duration_penalty", penalty)];
+    for (name, value) in values {
+        if !(value.is_finite() && value >= 0.0) {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "{name} must be a finite value >= 0, got {value}"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Per-track harmonic scale from `outro_blend_secs`: proportional to the blend length, with
+/// a blend of `reference_secs` counting as 1.
+fn build_blend_scale(
+    secs: Option<Vec<f64>>,
+    reference_secs: f64,
+    n: usize,
+) -> PyResult<Option<Vec<f64>>> {
+    let Some(secs) = secs else {
+        return Ok(None);
+    };
+    if !(reference_secs.is_finite() && reference_secs > 0.0) {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "blend_reference_secs must be positive, got {reference_secs}"
+        )));
+    }
+    if secs.len() != n {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "outro_blend_secs has {} entries, expected {n}", secs.len()
+        )));
+    }
+    if let Some(i) = secs.iter().position(|s| !(s.is_finite() && *s >= 0.0)) {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "outro_blend_secs[{i}] is {}, expected a finite value >= 0", secs[i]
+        )));
+    }
+    Ok(Some(secs.iter().map(|s| s / reference_secs).collect()))
+}
+
+/// The track and key tables a scoring or optimising function takes, with the optional
+/// arguments that refine them (as in `optimize_mix`).  `build` checks them against the
+/// cost parameters once; a function without some of the options leaves them at their
+/// `Default`.
+struct TableArgs {
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    direct_costs: Array<f64>,
+    indirect_costs: Array<f64>,
+    harmonic_mask: Option<Vec<u8>>,
+    key_confidence: Option<Vec<f64>>,
+    prefer_adjacent: Option<Vec<(usize, usize, f64)>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    compat_costs: Option<Array<f64>>,
+    compat_weight: f64,
+    compat_replaces_harmonic: bool,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+    /// `+inf` in `sparse_costs` and `compat_costs` marks a forbidden transition.
+    inf_forbidden: bool,
+}
+
+impl Default for TableArgs {
+    /// The Python defaults of the optional arguments, around empty tables.
+    fn default() -> Self {
+        TableArgs {
+            bpms: Vec::new().into(),
+            base_key_ids: Vec::new().into(),
+            shift_table: Vec::new().into(),
+            direct_costs: Vec::new().into(),
+            indirect_costs: Vec::new().into(),
+            harmonic_mask: None,
+            key_confidence: None,
+            prefer_adjacent: None,
+            intro_bpms: None,
+            outro_bpms: None,
+            outro_blend_secs: None,
+            blend_reference_secs: 30.0,
+            compat_costs: None,
+            compat_weight: 1.0,
+            compat_replaces_harmonic: false,
+            sparse_costs: None,
+            inf_forbidden: false,
+        }
+    }
+}
+
+impl TableArgs {
+    /// Check every table against `cp` (one entry per track in `bpms`) and build the
+    /// derived ones.
+    fn build(mut self, cp: &CostParams) -> PyResult<TableData> {
+        let n = self.bpms.len();
+        let sparse = build_sparse_costs(self.sparse_costs, cp.num_keys, self.inf_forbidden)?;
+        validate_key_tables(
+            n, &self.base_key_ids, &self.shift_table, &mut self.direct_costs, &mut self.indirect_costs, cp,
+            sparse.is_some(),
+        )?;
+        validate_harmonic_mask(self.harmonic_mask.as_deref(), cp.num_keys)?;
+        validate_key_confidence(self.key_confidence.as_deref(), n)?;
+        let adjacency = build_adjacency(n, self.prefer_adjacent)?;
+        let compat = build_compat(
+            n, self.compat_costs, self.compat_weight, self.compat_replaces_harmonic, self.inf_forbidden,
+        )?;
+        validate_track_bpms(&self.bpms, self.intro_bpms.as_deref(), self.outro_bpms.as_deref(), n)?;
+        let blend_scale = build_blend_scale(self.outro_blend_secs, self.blend_reference_secs, n)?;
+        Ok(TableData {
+            bpms: self.bpms,
+            base_key_ids: self.base_key_ids,
+            shift_table: self.shift_table,
+            direct_costs: self.direct_costs,
+            indirect_costs: self.indirect_costs,
+            harmonic_mask: self.harmonic_mask,
+            key_confidence: self.key_confidence,
+            intro_bpms: self.intro_bpms,
+            outro_bpms: self.outro_bpms,
+            sparse,
+            blend_scale,
+            adjacency,
+            compat,
+        })
+    }
+}
+
+/// Checked `TableArgs`: everything their `Tables` view borrows.
+struct TableData {
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    direct_costs: Array<f64>,
+    indirect_costs: Array<f64>,
+    harmonic_mask: Option<Vec<u8>>,
+    key_confidence: Option<Vec<f64>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    sparse: Option<SparseKeyCosts>,
+    blend_scale: Option<Vec<f64>>,
+    adjacency: Option<AdjacencyBonus>,
+    compat: Option<CompatCosts>,
+}
+
+impl TableData {
+    /// The plain tracks' tables, with every option given.
+    fn tables(&self) -> Tables<'_> {
+        Tables {
+            bpms: self.intro_bpms.as_deref().unwrap_or(&self.bpms),
+            exit_bpms: self.outro_bpms.as_deref().unwrap_or(&self.bpms),
+            harmonic_mask: self.harmonic_mask.as_deref(),
+            sparse_costs: self.sparse.as_ref(),
+            key_confidence: self.key_confidence.as_deref(),
+            exit_key_confidence: self.key_confidence.as_deref(),
+            blend_scale: self.blend_scale.as_deref(),
+            adjacency: self.adjacency.as_ref(),
+            compat: self.compat.as_ref(),
+            ..Tables::new(&self.bpms, &self.base_key_ids, &self.shift_table, &self.direct_costs, &self.indirect_costs)
+        }
+    }
+
+    /// Copy the arrays out of any borrowed buffers, for a call that releases the GIL or
+    /// runs Python code while the tables are read: either lets other Python code write to
+    /// the caller's buffers underneath them.
+    fn detach(&mut self) {
+        self.bpms.to_mut();
+        self.base_key_ids.to_mut();
+        self.shift_table.to_mut();
+        self.direct_costs.to_mut();
+        self.indirect_costs.to_mut();
+    }
+}
+
+/// Record the blend scale of each edge of the final track order (edge j = position j → j+1)
+/// under `edge_blend_scale`, when blend lengths were given.
+fn report_blend_scale(report: &Bound<'_, PyDict>, order: &[usize], plain: &Tables) -> PyResult<()> {
+    if let Some(b) = plain.blend_scale {
+        let scales: Vec<f64> = order[..order.len() - 1].iter().map(|&i| b[i]).collect();
+        report.set_item("edge_blend_scale", scales)?;
+    }
+    Ok(())
+}
+
+/// Record the harmonic weight of each edge of the final track order (edge j = position
+/// j → j+1) under `edge_key_confidence`, when key confidences were given.
+fn report_key_confidence(
+    report: &Bound<'_, PyDict>,
+    order: &[usize],
+    plain: &Tables,
+    cp: &CostParams,
+) -> PyResult<()> {
+    if plain.key_confidence.is_some() {
+        let weights: Vec<f64> = order.windows(2).map(|w| plain.edge_confidence(w[0], w[1], cp)).collect();
+        report.set_item("edge_key_confidence", weights)?;
+    }
+    Ok(())
+}
+
+/// Record whether each edge of the final track order (edge j = position j → j+1) is played
+/// as a "cut" or a "blend" under `transition_types`, when cuts are enabled.
+/// Camelot relationship label of every edge (see `classify_transition`); 24-key systems only.
+fn report_key_relations(
+    report: &Bound<'_, PyDict>,
+    order: &[usize],
+    shifts: &[i8],
+    plain: &Tables,
+    cp: &CostParams,
+) -> PyResult<()> {
+    if cp.num_keys == 24 {
+        let labels: Vec<Option<&str>> = order
+            .windows(2)
+            .map(|w| {
+                let e = cost::explain_edge(w[0], w[1], shifts[w[0]], shifts[w[1]], plain, cp);
+                e.key_relation.map(cost::KeyRelation::label)
+            })
+            .collect();
+        report.set_item("key_relations", labels)?;
+    }
+    Ok(())
+}
+
+fn report_transition_types(
+    report: &Bound<'_, PyDict>,
+    order: &[usize],
+    shifts: &[i8],
+    plain: &Tables,
+    cp: &CostParams,
+) -> PyResult<()> {
+    if cp.cut_penalty.is_some() {
+        let types: Vec<&str> = order
+            .windows(2)
+            .map(|w| {
+                let e = cost::explain_edge(w[0], w[1], shifts[w[0]], shifts[w[1]], plain, cp);
+                if e.cut { "cut" } else { "blend" }
+            })
+            .collect();
+        report.set_item("transition_types", types)?;
+    }
+    Ok(())
+}
+
+/// Build the preferred-adjacent pairs from the `prefer_adjacent` keyword argument.
+fn build_adjacency(
+    n: usize,
+    pairs: Option<Vec<(usize, usize, f64)>>,
+) -> PyResult<Option<AdjacencyBonus>> {
+    pairs
+        .map(|p| AdjacencyBonus::from_pairs(n, &p).map_err(pyo3::exceptions::PyValueError::new_err))
+        .transpose()
+}
+
+/// Build the order-stability penalty; none when the weight is 0, so the objective is unchanged.
+fn build_stability(
+    n: usize,
+    weight: f64,
+    reference: Option<Vec<usize>>,
+) -> PyResult<Option<StabilityPenalty>> {
+    let reference = reference.unwrap_or_else(|| (0..n).collect());
+    if reference.len() != n {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "reference_order has {} entries, expected {n}", reference.len()
+        )));
+    }
+    let stability = StabilityPenalty::new(&reference, weight).map_err(pyo3::exceptions::PyValueError::new_err)?;
+    Ok((weight > 0.0).then_some(stability))
+}
+
+/// Record the stability term under `stability_cost` and the reference pairs broken under
+/// `broken_adjacencies` (with `wrap`, the edge from the last track back to the first
+/// counts too), when a stability weight is set.
+fn report_stability(report: &Bound<'_, PyDict>, order: &[usize], wrap: bool, plain: &Tables) -> PyResult<()> {
+    if let Some(st) = plain.stability {
+        let mut broken = st.broken(order);
+        if wrap && st.get(order[order.len() - 1], order[0]) > 0.0 {
+            broken += 1;
+        }
+        report.set_item("stability_cost", st.weight * broken as f64)?;
+        report.set_item("broken_adjacencies", broken)?;
+    }
+    Ok(())
+}
+
+/// Record the total bonus earned by the final track order under `adjacency_bonus`, when
+/// preferred pairs were given.
+fn report_adjacency(report: &Bound<'_, PyDict>, order: &[usize], plain: &Tables) -> PyResult<()> {
+    if let Some(adj) = plain.adjacency {
+        report.set_item("adjacency_bonus", adj.total(order))?;
+    }
+    Ok(())
+}
+
+/// Record the tracks the final order opens and closes with under `opener` and `closer`.
+fn report_endpoints(report: &Bound<'_, PyDict>, order: &[usize]) -> PyResult<()> {
+    report.set_item("opener", order[0])?;
+    report.set_item("closer", order[order.len() - 1])?;
+    Ok(())
+}
+
+/// The tracks a `bpm_range` keeps, as nodes of the optimised sub-instance: node `i` is
+/// track `kept[i]`, so nodes keep the tracks' relative order.
+struct BpmFilter {
+    n: usize,
+    kept: Vec<usize>,
+    excluded: Vec<usize>,
+    node: Vec<Option<usize>>,
+}
+
+impl BpmFilter {
+    /// The kept tracks' entries of the per-track argument `name`.
+    fn pick<T: Copy>(&self, name: &str, values: &[T]) -> PyResult<Vec<T>> {
+        if values.len() != self.n {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "{name} has {} entries, expected {}", values.len(), self.n
+            )));
+        }
+        Ok(self.kept.iter().map(|&t| values[t]).collect())
+    }
+
+    fn pick_opt<T: Copy>(&self, name: &str, values: Option<Vec<T>>) -> PyResult<Option<Vec<T>>> {
+        values.map(|v| self.pick(name, &v)).transpose()
+    }
+
+    /// The node of track `t`, `None` when excluded.
+    fn node_of(&self, name: &str, t: usize) -> PyResult<Option<usize>> {
+        self.node.get(t).copied().ok_or_else(|| {
+            pyo3::exceptions::PyValueError::new_err(format!(
+                "{name}: track {t} is out of range (0-{})", self.n - 1
+            ))
+        })
+    }
+
+    /// The kept tracks of `tracks`, as nodes.
+    fn nodes(&self, name: &str, tracks: &[usize]) -> PyResult<Vec<usize>> {
+        let mut nodes = Vec::with_capacity(tracks.len());
+        for &t in tracks {
+            nodes.extend(self.node_of(name, t)?);
+        }
+        Ok(nodes)
+    }
+
+    /// `groups` with the excluded tracks dropped, and groups left empty dropped with them.
+    fn groups(&self, groups: Vec<Vec<usize>>) -> PyResult<Vec<Vec<usize>>> {
+        let mut kept = Vec::with_capacity(groups.len());
+        for (k, g) in groups.iter().enumerate() {
+            let nodes = self.nodes(&format!("groups[{k}]"), g)?;
+            if !nodes.is_empty() {
+                kept.push(nodes);
+            }
+        }
+        Ok(kept)
+    }
+
+    /// The `prefer_adjacent` pairs whose tracks are both kept.
+    fn pairs(&self, pairs: Vec<(usize, usize, f64)>) -> PyResult<Vec<(usize, usize, f64)>> {
+        let mut kept = Vec::with_capacity(pairs.len());
+        for (a, b, bonus) in pairs {
+            if let (Some(a), Some(b)) = (self.node_of("prefer_adjacent", a)?, self.node_of("prefer_adjacent", b)?) {
+                kept.push((a, b, bonus));
+            }
+        }
+        Ok(kept)
+    }
+
+    /// The kept rows and columns of an n×n matrix.
+    fn matrix(&self, name: &str, values: &[f64]) -> PyResult<Vec<f64>> {
+        if values.len() != self.n * self.n {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "{name} has {} entries, expected {}", values.len(), self.n * self.n
+            )));
+        }
+        Ok(self.kept.iter().flat_map(|&i| self.kept.iter().map(move |&j| values[i * self.n + j])).collect())
+    }
+
+    /// Node-indexed `values` spread back over all n tracks, `fill` for the excluded ones.
+    fn spread<T: Copy>(&self, values: &[T], fill: T) -> Vec<T> {
+        let mut all = vec![fill; self.n];
+        for (&t, &v) in self.kept.iter().zip(values) {
+            all[t] = v;
+        }
+        all
+    }
+}
+
+/// Build the track filter from the `bpm_range` keyword argument: the tracks whose BPM is
+/// within `[low, high]`.  At least 2 tracks must remain.
+fn build_bpm_filter(range: Option<(f64, f64)>, bpms: &[i32]) -> PyResult<Option<BpmFilter>> {
+    let Some((low, high)) = range else { return Ok(None) };
+    if !(low.is_finite() && high.is_finite() && low <= high) {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "bpm_range must be finite (low, high) with low <= high, got ({low}, {high})"
+        )));
+    }
+    let n = bpms.len();
+    let (kept, excluded): (Vec<usize>, Vec<usize>) =
+        (0..n).partition(|&t| (low..=high).contains(&f64::from(bpms[t])));
+    if kept.len() < 2 {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "bpm_range ({low}, {high}) keeps {} of {n} tracks; need at least 2", kept.len()
+        )));
+    }
+    let mut node = vec![None; n];
+    for (i, &t) in kept.iter().enumerate() {
+        node[t] = Some(i);
+    }
+    Ok(Some(BpmFilter { n, kept, excluded, node }))
+}
+
+/// Build the compatibility matrix from the `compat_costs` keyword argument.  `+inf` entries
+/// follow `inf_forbidden` as in the key cost tables.
+fn build_compat(
+    n: usize,
+    costs: Option<Array<f64>>,
+    weight: f64,
+    replaces_harmonic: bool,
+    inf_forbidden: bool,
+) -> PyResult<Option<CompatCosts>> {
+    let Some(mut costs) = costs else { return Ok(None) };
+    validate_cost_table("compat_costs", &mut costs, inf_forbidden)?;
+    CompatCosts::new(n, &costs, weight, replaces_harmonic)
+        .map(Some)
+        .map_err(pyo3::exceptions::PyValueError::new_err)
+}
+
+/// Sample the `edge_cost_fn` callable once over every edge and shift pair into a
+/// compatibility matrix that replaces the key model's harmonic cost, so the solvers never
+/// call back into Python.  Exceptions raised by the callable propagate; a non-finite value
+/// is a ValueError.  Cannot be combined with `compat_costs`.  With `tracks`, node `i` is
+/// track `tracks[i]` and the callable is given track indices.
+fn build_edge_cost_fn(
+    n: usize,
+    f: &Bound<'_, PyAny>,
+    compat_costs: bool,
+    tracks: Option<&[usize]>,
+) -> PyResult<CompatCosts> {
+    if compat_costs {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "edge_cost_fn cannot be combined with compat_costs",
+        ));
+    }
+    let track = |node: usize| tracks.map_or(node, |t| t[node]);
+    let mut costs = Vec::with_capacity(n * n * 9);
+    for i in (0..n).map(track) {
+        for j in (0..n).map(track) {
+            for s1 in -1i8..=1 {
+                for s2 in -1i8..=1 {
+                    let c: f64 = f.call1((i, j, s1, s2))?.extract()?;
+                    if !c.is_finite() {
+                        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                            "edge_cost_fn({i}, {j}, {s1}, {s2}) returned {c}, expected a finite value"
+                        )));
+                    }
+                    costs.push(c);
+                }
+            }
+        }
+    }
+    CompatCosts::with_shifts(n, &costs, 1.0, true).map_err(pyo3::exceptions::PyValueError::new_err)
+}
+
+/// Record the compatibility cost of the final track order under `compat_cost`, and the
+/// cost breakdown with it split out of `h` under `extended_breakdown` (h, t, s, compat),
+/// when a compatibility matrix was given.  `wrap` counts the closing edge of a cycle.
+fn report_compat(
+    report: &Bound<'_, PyDict>,
+    order: &[usize],
+    shifts: &[i8],
+    wrap: bool,
+    (h, t, s): (f64, f64, f64),
+    plain: &Tables,
+) -> PyResult<()> {
+    if let Some(c) = plain.compat {
+        let mut total = c.total(order, shifts);
+        if wrap {
+            let (last, first) = (order[order.len() - 1], order[0]);
+            total += c.get(last, first, shifts[last], shifts[first]);
+        }
+        report.set_item("compat_cost", total)?;
+        report.set_item("extended_breakdown", (h - total, t, s, total))?;
+    }
+    Ok(())
+}
+
+/// Build the optional cost cap from the `tempo_cost_cap` / `harmonic_cost_cap` keyword
+/// arguments (at most one).
+fn build_cost_cap(tempo: Option<f64>, harmonic: Option<f64>) -> PyResult<Option<CostCap>> {
+    let check = |name: &str, limit: f64| -> PyResult<f64> {
+        if limit.is_finite() && limit >= 0.0 {
+            Ok(limit)
+        } else {
+            Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "{name} is {limit}, expected a finite value >= 0"
+            )))
+        }
+    };
+    match (tempo, harmonic) {
+        (Some(_), Some(_)) => Err(pyo3::exceptions::PyValueError::new_err(
+            "pass at most one of tempo_cost_cap and harmonic_cost_cap",
+        )),
+        (Some(t), None) => Ok(Some(CostCap::Tempo(check("tempo_cost_cap", t)?))),
+        (None, Some(h)) => Ok(Some(CostCap::Harmonic(check("harmonic_cost_cap", h)?))),
+        (None, None) => Ok(None),
+    }
+}
+
+/// Build the key-family run limit from `max_same_family_run` / `family_run_penalty`.
+/// Families are decoded from Camelot key IDs, so it needs the 24-key system; contracted
+/// `groups` would hide the runs inside their blocks.
+fn build_family_run(
+    max_run: Option<i64>,
+    penalty: f64,
+    num_keys: usize,
+    groups: Option<&[Vec<usize>]>,
+) -> PyResult<Option<FamilyRunLimit>> {
+    let Some(max_run) = max_run else { return Ok(None) };
+    if max_run < 1 {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "max_same_family_run must be >= 1, got {max_run}"
+        )));
+    }
+    if !(penalty.is_finite() && penalty >= 0.0) {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "family_run_penalty must be a finite value >= 0, got {penalty}"
+        )));
+    }
+    if num_keys != 24 {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "max_same_family_run decodes Camelot key IDs and needs num_keys = 24, got {num_keys}"
+        )));
+    }
+    if groups.is_some_and(|g| !g.is_empty()) {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "max_same_family_run cannot be combined with groups",
+        ));
+    }
+    Ok(Some(FamilyRunLimit { max_run: max_run as usize, penalty }))
+}
+
+/// Record the longest same-family run under `longest_family_run`, when a limit is set.
+fn report_family_run(
+    report: &Bound<'_, PyDict>,
+    order: &[usize],
+    shifts: &[i8],
+    plain: &Tables,
+    cp: &CostParams,
+) -> PyResult<()> {
+    if cp.family_run.is_some() {
+        report.set_item("longest_family_run", family::longest_run(order, shifts, plain))?;
+    }
+    Ok(())
+}
+
+/// Build the separation groupings from the Python keyword arguments.
+///
+/// `artist_ids`/`min_artist_gap`/`artist_gap_penalty` are shorthand for one grouping and,
+/// when given, come first; `groupings` entries are `(group_ids, min_gap, penalty)` tuples.
+fn build_separation(
+    n: usize,
+    artist_ids: Option<Vec<u32>>,
+    min_artist_gap: usize,
+    artist_gap_penalty: f64,
+    groupings: Option<Vec<(Vec<u32>, usize, f64)>>,
+) -> PyResult<Option<Separation>> {
+    let mut all = Vec::new();
+    if let Some(ids) = artist_ids {
+        all.push(("artist_ids".to_string(), ids, min_artist_gap, artist_gap_penalty));
+    }
+    for (k, (ids, gap, penalty)) in groupings.into_iter().flatten().enumerate() {
+        all.push((format!("groupings[{k}]"), ids, gap, penalty));
+    }
+
+    let mut sep = Separation::default();
+    for (name, ids, gap, penalty) in all {
+        if ids.len() != n {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "{name} has {} entries, expected {n}", ids.len()
+            )));
+        }
+        if penalty.is_nan() || penalty < 0.0 {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "{name}: penalty must be non-negative"
+            )));
+        }
+        sep.push(Grouping::new(ids, gap, penalty));
+    }
+    Ok(sep.is_active().then_some(sep))
+}
+
+/// The spacing rule of `spread_tracks` / `min_spread_spacing`, `None` without tracks or
+/// with a spacing of 1 or less, which any order meets.  The rule is always hard, so its
+/// penalty only steers a start order that breaks it.
+fn build_spread(n: usize, tracks: Option<Vec<usize>>, min_spacing: usize) -> PyResult<Option<Grouping>> {
+    let Some(tracks) = tracks else { return Ok(None) };
+    let spread = Grouping::spread(n, &tracks, min_spacing, 10.0)
+        .map_err(|e| pyo3::exceptions::PyValueError::new_err(format!("spread_tracks: {e}")))?;
+    Ok(spread.is_active().then_some(spread))
+}
+
+/// Record the fewest positions between two spread tracks, when they have a spacing rule.
+fn report_spread(
+    report: &Bound<'_, PyDict>,
+    separation: Option<&Separation>,
+    index: Option<usize>,
+    order: &[usize],
+) -> PyResult<()> {
+    if let Some(spread) = index.and_then(|k| separation?.groupings.get(k)) {
+        report.set_item("spread_spacing", spread.closest_repeat(order))?;
+    }
+    Ok(())
+}
+
+/// Store the per-grouping separation outcome in the result report.
+///
+/// `hard_supported` is false for Held-Karp, which only ever applies the adjacent penalty.
+fn report_separation(
+    report: &Bound<'_, PyDict>,
+    separation: Option<&Separation>,
+    violations: &[usize],
+    hard_supported: bool,
+) -> PyResult<()> {
+    if let Some(sep) = separation.filter(|sep| !sep.groupings.is_empty()) {
+        let modes: Vec<&str> = sep
+            .groupings
+            .iter()
+            .map(|g| if hard_supported { g.mode() } else { "penalty" })
+            .collect();
+        report.set_item("grouping_modes", modes)?;
+        report.set_item("grouping_violations", violations)?;
+    }
+    Ok(())
+}
+
+/// Build the tempo coverage rules of `bpm_coverage` over the tracks' intro BPMs.
+fn build_coverage(
+    rules: &[((f64, f64), (i32, i32), usize)],
+    penalty: f64,
+    bpms: &[i32],
+) -> PyResult<Coverage> {
+    let rules: Vec<CoverageRule> = rules
+        .iter()
+        .map(|&(positions, bpms, min_count)| CoverageRule { positions, bpms, min_count, penalty })
+        .collect();
+    Coverage::new(&rules, bpms)
+        .map_err(|e| pyo3::exceptions::PyValueError::new_err(format!("bpm_coverage {e}")))
+}
+
+/// Record the winning attempt's move outcomes and the share a hard constraint vetoed.
+fn report_move_stats(report: &Bound<'_, PyDict>, moves: &annealing::MoveStats) -> PyResult<()> {
+    let d = PyDict::new(report.py());
+    d.set_item("improved", moves.improved)?;
+    d.set_item("accepted_worse", moves.accepted_worse)?;
+    d.set_item("escape", moves.escape)?;
+    d.set_item("rejected_worse", moves.rejected_worse)?;
+    d.set_item("rejected_separation", moves.rejected_separation)?;
+    d.set_item("rejected_shift_budget", moves.rejected_shift_budget)?;
+    d.set_item("rejected_family_run", moves.rejected_family_run)?;
+    report.set_item("move_stats", d)?;
+    let rate = moves.constraint_rejected() as f64 / moves.total().max(1) as f64;
+    report.set_item("constraint_rejection_rate", rate)
+}
+
+/// Record each coverage rule's mode, shortfall and whether it is met, when there are rules.
+fn report_coverage(report: &Bound<'_, PyDict>, separation: Option<&Separation>, order: &[usize]) -> PyResult<()> {
+    if let Some(c) = separation.and_then(|sep| sep.coverage.as_ref()) {
+        let shortfall = c.shortfalls(order);
+        report.set_item("coverage_modes", c.modes())?;
+        report.set_item("coverage_satisfied", shortfall.iter().map(|&s| s == 0).collect::<Vec<_>>())?;
+        report.set_item("coverage_shortfall", shortfall)?;
+    }
+    Ok(())
+}
+
+/// Build the owner alternation of `owners` / `max_owner_run` / `alternation_weight`.
+fn build_alternation(n: usize, owners: Vec<u8>, max_run: usize, weight: f64) -> PyResult<Alternation> {
+    if owners.len() != n {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "owners has {} entries, expected {n}", owners.len()
+        )));
+    }
+    if max_run == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err("max_owner_run must be >= 1"));
+    }
+    if !(weight.is_finite() && weight >= 0.0) {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "alternation_weight must be a finite value >= 0, got {weight}"
+        )));
+    }
+    Ok(Alternation::new(owners, max_run, weight))
+}
+
+/// Record the owner at each position and the run lengths, when owners are given.
+fn report_alternation(report: &Bound<'_, PyDict>, separation: Option<&Separation>, order: &[usize]) -> PyResult<()> {
+    if let Some(alt) = separation.and_then(|sep| sep.alternation.as_ref()) {
+        // u32 rather than u8, which pyo3 would return as bytes
+        let pattern: Vec<u32> = alt.pattern(order).into_iter().map(u32::from).collect();
+        report.set_item("owner_pattern", pattern)?;
+        report.set_item("longest_owner_run", alt.longest_run(order))?;
+        report.set_item("owner_run_excess", alt.excess(order))?;
+    }
+    Ok(())
+}
+
+/// Check the `max_shift_cost` budget: finite and >= 0.
+fn validate_max_shift_cost(budget: Option<f64>) -> PyResult<()> {
+    match budget {
+        Some(c) if !(c.is_finite() && c >= 0.0) => Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "max_shift_cost must be a finite value >= 0, got {c}"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Check `fixed_shifts` (track-indexed) against the track count and the -1/0/+1 range.
+fn validate_fixed_shifts(shifts: Option<&[i8]>, n: usize) -> PyResult<()> {
+    if let Some(fs) = shifts {
+        if fs.len() != n {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "fixed_shifts has {} entries, expected {n}", fs.len()
+            )));
+        }
+        if let Some(i) = fs.iter().position(|s| !(-1..=1).contains(s)) {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "fixed_shifts[{i}] is {}, shifts must be -1, 0 or +1", fs[i]
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Build the virtual boundary anchors; each side needs both its key and its BPM.
+fn build_anchors(
+    entry_key_id: Option<u8>,
+    entry_bpm: Option<i32>,
+    exit_key_id: Option<u8>,
+    exit_bpm: Option<i32>,
+    num_keys: usize,
+) -> PyResult<Anchors> {
+    let side = |name: &str, key: Option<u8>, bpm: Option<i32>| -> PyResult<Option<(i32, u8)>> {
+        match (key, bpm) {
+            (None, None) => Ok(None),
+            (Some(k), Some(b)) if (k as usize) < num_keys => {
+                validate_bpm(&format!("{name}_bpm"), b)?;
+                Ok(Some((b, k)))
+            }
+            (Some(k), Some(_)) => Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "{name}_key_id {k} is out of range (0-{})", num_keys - 1
+            ))),
+            _ => Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "{name}_key_id and {name}_bpm must be given together"
+            ))),
+        }
+    };
+    Ok(Anchors {
+        entry: side("entry", entry_key_id, entry_bpm)?,
+        exit: side("exit", exit_key_id, exit_bpm)?,
+    })
+}
+
+/// Store the weighted boundary edge costs `(entry, exit)` in the result report.
+fn report_anchors(
+    report: &Bound<'_, PyDict>,
+    tables: &Tables,
+    order: &[usize],
+    shifts: &[i8],
+    params: &CostParams,
+) -> PyResult<()> {
+    let anchors = tables.anchors;
+    if anchors.entry.is_some() || anchors.exit.is_some() {
+        let (first, last) = (order[0], order[order.len() - 1]);
+        report.set_item(
+            "boundary_costs",
+            (
+                tables.entry_cost(first, shifts[first], params),
+                tables.exit_cost(last, shifts[last], params),
+            ),
+        )?;
+    }
+    Ok(())
+}
+
+/// Store the edges costing more than `clash_threshold` in the result report: their count
+/// (`clash_count`) and start positions (`clash_positions`, edge j runs from position j to
+/// j+1).  Reporting only; the objective is unaffected.
+fn report_clashes(
+    report: &Bound<'_, PyDict>,
+    clash_threshold: Option<f64>,
+    order: &[usize],
+    shifts: &[i8],
+    tables: &Tables,
+    params: &CostParams,
+) -> PyResult<()> {
+    if let Some(threshold) = clash_threshold {
+        let positions: Vec<usize> = cost::edge_costs(order, shifts, tables, params)
+            .iter()
+            .enumerate()
+            .filter(|&(_, &c)| c > threshold)
+            .map(|(j, _)| j)
+            .collect();
+        report.set_item("clash_count", positions.len())?;
+        report.set_item("clash_positions", positions)?;
+    }
+    Ok(())
+}
+
+/// Contract the optional contiguous `groups` into block nodes (see `blocks.rs`).
+fn build_contraction(
+    n: usize,
+    groups: Option<Vec<Vec<usize>>>,
+    tables: &Tables,
+    params: &CostParams,
+    separation: Option<&Separation>,
+) -> PyResult<Option<Contraction>> {
+    let Some(groups) = groups else {
+        return Ok(None);
+    };
+    if separation.is_some() {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "groups cannot be combined with separation groupings, bpm_coverage or owners",
+        ));
+    }
+    let contraction = Contraction::new(n, &groups, tables, params)
+        .map_err(pyo3::exceptions::PyValueError::new_err)?;
+    if contraction.len() < 2 {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "groups must leave at least 2 blocks to order",
+        ));
+    }
+    Ok(Some(contraction))
+}
+
+/// optimize_mix(bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+///              cost_params, annealing_params, time_limit_secs)
+///
+/// Runs simulated annealing for `time_limit_secs` seconds (at least one attempt).
+///
+/// Args (matching precomputed Python tables):
+///   bpms           - list[int]   track BPMs (length n)
+///   base_key_ids   - list[int]   key IDs 0..num_keys (length n; Camelot 0-23 by default,
+///                                see key_to_id)
+///   shift_table    - list[int]   num_keys*3 entries: shift_table[key_id*3+(shift+1)] = eff_key_id
+///   direct_costs   - list[float] num_keys² entries: direct_costs[ek1*num_keys+ek2]
+///   indirect_costs - list[float] num_keys² entries: indirect_costs[ek1*num_keys+ek2]
+///                    Both empty (without sparse_costs): the built-in Camelot wheel model,
+///                    for num_keys = 24 — 0 for the same key, 1 for ±1 on the wheel or the
+///                    relative major/minor, 2 for ±2 or one semitone up (energy boost),
+///                    non_harmonic_cost plus the surcharge otherwise.  Every function
+///                    taking these tables accepts the same.
+///                    These five (and compat_costs) also accept 1-D NumPy arrays, or any
+///                    buffer: contiguous int32 bpms, uint8 key IDs and shift table and
+///                    float64 costs are read in place while the call holds the GIL (not
+///                    copied; they are copied first when checkpoint_fn is given, as it
+///                    runs Python code mid-run), and float32 costs are widened in one
+///                    bulk copy.  Any other dtype (e.g. int64), a strided view or more
+///                    than one dimension raises ValueError naming the argument, the
+///                    expected dtype and the dtype or strides given; cast with
+///                    np.ascontiguousarray(x, dtype=...) first.  In a float32 table,
+///                    entries equal to non_harmonic_cost rounded to float32 count as
+///                    non_harmonic_cost.
+///   cost_params    - dict[str, float] keys: tempo_threshold, tempo_penalty, tempo_break_factor,
+///                                           tempo_cost_weight, non_harmonic_cost,
+///                                           shift_penalty, shift_weight (each finite and
+///                                           >= 0, tempo_break_factor >= 1; ValueError
+///                                           otherwise; shift_penalty may instead be a
+///                                           dict {-1: down, 1: up} charging each shift
+///                                           direction its own penalty); optional tempo_penalty_slope (extra tempo cost per BPM
+///                                           over tempo_threshold, up to the break; default
+///                                           0, a flat tempo_penalty step),
+///                                           harmonic_across_breaks (nonzero = also charge
+///                                           the harmonic cost on tempo-break edges),
+///                                           harmonic_only (nonzero = ignore tempo
+///                                           entirely, so every edge charges its harmonic
+///                                           cost and the tempo component is 0),
+///                                           perceptual_tempo (nonzero = compare tempos by
+///                                           ratio: the gap is 120 × |ln bpm1 - ln bpm2|,
+///                                           i.e. BPM at 120 BPM, more per BPM below and
+///                                           less above; the thresholds and slope apply
+///                                           to it unchanged),
+///                                           num_keys (key-system size, default 24) and
+///                                           confidence_product (nonzero = combine key
+///                                           confidences by product instead of min),
+///                                           cut_penalty and cut_harmonic_discount (each
+///                                           edge is played as the cheaper of a blend and a
+///                                           hard cut costing harmonic × (1 - discount) +
+///                                           cut_penalty; cuts are off without cut_penalty,
+///                                           discount defaults to 0) and objective_mode
+///                                           (str: "weighted", the default, or
+///                                           "lexicographic_h_then_t" = minimise the
+///                                           harmonic total first and only then the
+///                                           weighted tempo + shift cost; costs are then
+///                                           reported as h + (tempo + shift) /
+///                                           lexicographic_scale, or "perfect_transitions" =
+///                                           minimise the number of edges whose weighted
+///                                           cost is not below perfect_threshold (default
+///                                           1e-9, i.e. only free edges are perfect), fewer
+///                                           shifts breaking ties; best_cost, attempt_costs
+///                                           and the per-track stats then count imperfect
+///                                           edges, cost_breakdown and the report use the
+///                                           normal weighted model; optimize_mix_fast and
+///                                           optimize_mix_segments report everything in
+///                                           counts; cannot be combined with cost caps).
+///                    A `CostParams` instance (same names as keyword arguments, with
+///                    defaults) is accepted anywhere in place of the dict.
+///   annealing_params - dict[str, float] keys: total_iterations, initial_temp, final_temp,
+///                                              multi_swap_factor; optional
+///                                              auto_initial_temp (a target acceptance rate
+///                                              in (0, 1): each attempt samples random swaps
+///                                              and starts at the temperature accepting a
+///                                              typical one at that rate, e.g. 0.8, so
+///                                              initial_temp need not match the cost scale;
+///                                              initial_temp is used only if no swap changes
+///                                              the cost), or an
+///                    `AnnealingParams` instance (validated, with defaults) anywhere
+///   time_limit_secs - float  wall-clock budget in seconds
+///   artist_ids      - list[int] | None  artist ID per track (length n), optional
+///   min_artist_gap  - int   same-artist tracks may not be within this many positions (0 = off)
+///   artist_gap_penalty - float  cost per violating pair when no violation-free order exists
+///   groupings       - list[(list[int], int, float)] | None  extra separation groupings
+///                     (album, label, ...) as (group_ids, min_gap, penalty), checked alongside
+///                     the artist grouping
+///   bpm_coverage    - list[((float, float), (int, int), int)] | None  tempo coverage rules
+///                     ((start, end), (bpm_lo, bpm_hi), min_count): at least min_count tracks
+///                     with an intro BPM in bpm_lo..=bpm_hi among positions start·n .. end·n,
+///                     e.g. ((0.0, 0.25), (0, 99), 1) for a slow track in the first quarter.
+///                     Hard when a rule can be met on its own, as with groupings
+///   coverage_penalty - float  cost per missing track of a rule (default 10.0)
+///   owners          - list[int] | None  owner tag per track (length n, 0-255), for a
+///                     back-to-back set built from several DJs' tracks: runs of consecutive
+///                     tracks from one owner longer than max_owner_run (default 1, strict
+///                     alternation) cost alternation_weight (default 10.0) per extra track,
+///                     weighed against the mix cost like a penalty-mode grouping
+///   spread_tracks   - list[int] | None  tracks to spread through the set (must-play peaks):
+///                     no two of them may play fewer than min_spread_spacing (default 2,
+///                     not back to back) positions apart.  An error when they cannot all
+///                     fit; otherwise hard, like a feasible grouping, and reported with the
+///                     groupings after them
+///   inf_forbidden   - bool  treat +inf table entries as forbidden transitions instead of
+///                           rejecting them (NaN is always an error)
+///   sparse_costs    - (float, float, list[(int, int, float, float)]) | None  sparse form of
+///                     the key cost tables for large key systems: default (direct, indirect)
+///                     cost plus (ek1, ek2, direct, indirect) overrides.  When given, pass
+///                     direct_costs and indirect_costs as empty lists.  The dense tables are
+///                     faster for the usual 24 keys; see sparse_key_costs_to_dense.
+///   harmonic_mask   - list[int] | None  num_keys² entries: harmonic_mask[ek1*num_keys+ek2]
+///                     = 1 when the keys are harmonically related.  When given, unrelated pairs get the
+///                     2 × non_harmonic_cost surcharge; otherwise the legacy rule applies
+///                     (direct == non_harmonic_cost and indirect >= non_harmonic_cost)
+///   groups          - list[list[int]] | None  contiguous blocks: each inner list is played
+///                     back to back in the given order, while blocks move freely.  A block's
+///                     first and last tracks share one shift; interior shifts are optimised
+///                     once up front.  Cannot be combined with separation groupings,
+///                     bpm_coverage or owners.
+///   clash_threshold - float | None  report edges whose cost exceeds this value (see report)
+///   key_confidence  - list[float] | None  key-detection confidence per track in [0, 1]; each
+///                     edge's harmonic cost is scaled by the min (or, with confidence_product,
+///                     the product) of its two tracks' confidences, so uncertain keys matter
+///                     less and tempo decides.  Anchor tracks count as fully confident.
+///   prefer_adjacent - list[(int, int, float)] | None  soft pairings (a, b, bonus): whenever
+///                     tracks a and b are played back to back (either order) the bonus is
+///                     subtracted from the cost.  Unlike `groups`, nothing is forced.
+///   stability_weight - float  keep re-optimised mixes recognisable (default 0.0, off): each
+///                     back-to-back pair of reference_order (in its direction) that the
+///                     order breaks costs this much, charged to h.  A large weight returns
+///                     the reference itself.
+///   reference_order - list[int] | None  the order to stay close to (default 0..n-1, the
+///                     input order)
+///   intro_bpms, outro_bpms - list[int] | None  per-track tempo at the start and end of the
+///                     track (length n, default `bpms`).  A transition compares the outgoing
+///                     track's outro BPM with the incoming track's intro BPM, e.g. a half-time
+///                     DnB intro.  Break detection uses the same thresholds.
+///   outro_blend_secs - list[float] | None  planned overlap (seconds) of each track's outro
+///                     with the next track.  The harmonic cost of the outgoing edge is scaled
+///                     by outro_blend_secs / blend_reference_secs, so quick cuts tolerate key
+///                     clashes and long blends demand compatibility.  Missing = scale 1.
+///   blend_reference_secs - float  blend length that counts as scale 1 (default 30.0)
+///   compat_costs    - list[float] | None  n² entries: compat_costs[i*n+j] = measured cost of
+///                     mixing track i into track j (beatgrid alignment, spectral clash, ...),
+///                     indexed by track rather than key.  Times compat_weight (default 1.0),
+///                     it is added to every edge's harmonic cost, or replaces it with
+///                     compat_replaces_harmonic=True.  Edges to entry/exit anchors keep the
+///                     key model.  +inf follows inf_forbidden; NaN is an error.
+///   edge_cost_fn    - callable | None  f(i, j, s1, s2) -> float, a custom cost for mixing
+///                     track i at shift s1 into track j at shift s2 that replaces the key
+///                     model's harmonic cost, for experiments without rebuilding the engine.
+///                     It is sampled once up front (n² × 9 calls) and never consulted
+///                     during the search, so it must be a pure function of its arguments.
+///                     Reported as compat_cost; add harmonic_only to cost_params to make it
+///                     the whole edge cost.  Exceptions it raises propagate.  Cannot be
+///                     combined with compat_costs.
+///   tempo_cost_cap, harmonic_cost_cap - float | None  alternative to tuning weights: keep
+///                     the tempo (or harmonic) component of cost_breakdown at or below the cap
+///                     and minimise the rest.  The time budget is split into rounds that
+///                     escalate the capped component's weight until a round meets the cap;
+///                     if none does, the least-violating order is returned.  best_cost is
+///                     scored with the original weights; attempt_costs and the per-track
+///                     stats come from the winning round and its weights.  At most one cap.
+///   max_same_family_run - int | None  at most this many consecutive tracks (>= 1) whose
+///                     effective keys share a Camelot number (8A/8B), at their chosen
+///                     shifts.  Moves that lengthen an over-long run are rejected; each
+///                     track beyond the limit costs family_run_penalty (default 10.0) while a
+///                     random start is cleaned up.  Needs the 24-key Camelot IDs; cannot be
+///                     combined with `groups`.
+///   fixed_shifts    - list[int] | None  shift per track (-1/0/+1) held constant while only
+///                     the order is optimised.  Cannot be combined with `groups`.
+///   max_shift_cost  - float | None  budget (>= 0) for the weighted shift cost, shift_weight
+///                     × the s of cost_breakdown: moves whose shifts would overrun it are
+///                     rejected, and a random start over it is unshifted until it fits.
+///                     Ignored with fixed_shifts.
+///   minmax_blend    - float  λ in [0, 1]: minimise (1 - λ) × the usual cost + λ × the
+///                     costliest weighted edge, so that one bad transition weighs more than
+///                     its share of the sum (default 0, the plain sum; 1 minimises only the
+///                     worst edge).  best_cost and attempt_costs are then the blended value,
+///                     while cost_breakdown stays the plain sum.  Needs the weighted
+///                     objective_mode; cannot be combined with `groups`.
+///   canonical_orientation - bool  return the order reversed when that puts the
+///                     lower-indexed end track first and costs the same, so a symmetric
+///                     configuration (where either orientation may come back) always yields
+///                     the same one.  Orders whose reversal costs differently (asymmetric
+///                     tables, intro/outro BPMs, anchors, coverage rules, ...) are left as
+///                     they are.  Cannot be combined with `groups` (default False).
+///   shift_init_weights - (float, float, float) | None  relative odds of starting each track
+///                     of an attempt at shift -1 / 0 / +1 (default uniform).  Leaning on 0,
+///                     e.g. (1, 8, 1), starts shift-averse configs near their optimum.  Each
+///                     >= 0, not all zero.
+///   stats_weighting - str  how attempts feed the per-track stats: "uniform" (default, all
+///                     attempts equal), "inverse_cost" (mean weighted by 1 / (1 + gap), the
+///                     gap being (cost - best) / max(|best|, 1)) or "within_pct" (only
+///                     attempts whose gap is at most stats_within_pct %); defined for
+///                     negative costs too
+///   stats_within_pct - float  cutoff for "within_pct" (default 10.0)
+///   resume_from     - bytes | None  a checkpoint from checkpoint_fn: continue that run, on
+///                     the same inputs and stats_weighting, instead of starting over.  Its
+///                     attempts count towards every result; time_limit_secs is the extra
+///                     budget.  ValueError for a checkpoint of another version, size or
+///                     weighting.
+///   checkpoint_fn   - callable | None  f(bytes) called with a checkpoint of the run (best
+///                     order, every attempt's cost, the per-track stats so far) after the
+///                     first attempt to finish checkpoint_interval_secs (default 60.0) after
+///                     the previous save, and once more at the end, e.g. to write it to
+///                     disk.  An exception it raises ends the run and propagates.
+///                     Neither can be combined with a cost cap.
+///   entry_key_id, entry_bpm - int | None  virtual track played just before the first track
+///                     (e.g. the previous DJ's closer); adds one edge into position 0
+///   exit_key_id, exit_bpm - int | None  virtual track played just after the last track
+///                     Virtual tracks are never shifted; best_cost includes both edges but
+///                     cost_breakdown does not.
+///   bpm_range       - (low, high) | None  only mix the tracks whose BPM is within
+///                     [low, high]; the others are left out of best_order (shift 0, NaN
+///                     per-track stats) and listed in report["excluded"].  Every per-track
+///                     argument still covers all tracks, and groups, prefer_adjacent and
+///                     reference_order name track indices.  ValueError if low > high or
+///                     fewer than 2 tracks are within the range.
+///
+/// Returns:
+///   (best_order:     list[int],
+///    best_shifts:    list[int],
+///    best_cost:      float,
+///    cost_breakdown: (h, t, s),
+///    attempt_costs:  list[(overall, h, t, s)],
+///    n_attempts:     int,
+///    per_track_min:  list[float],   # indexed by track index
+///    per_track_max:  list[float],
+///    per_track_avg:  list[float],
+///    report:         dict,          # attempt_secs (wall time per attempt, parallel to
+///                                   # attempt_costs); resumed_attempts (attempts loaded
+///                                   # from the checkpoint) — only with resume_from;
+///                                   # distinct_attempt_costs (number of
+///                                   # different overall attempt costs, within 1e-6: 1 =
+///                                   # every attempt agreed, many = under-converged);
+///                                   # move_stats (the winning attempt's moves by outcome:
+///                                   # improved, accepted_worse, escape, rejected_worse,
+///                                   # rejected_separation, rejected_shift_budget,
+///                                   # rejected_family_run) and constraint_rejection_rate
+///                                   # (share of its moves a hard constraint vetoed; a high
+///                                   # rate means an over-constrained instance);
+///                                   # opener and closer (first and last
+///                                   # track of best_order); grouping_modes ("hard"/"penalty" per
+///                                   # grouping, artist first), grouping_violations — only with
+///                                   # active groupings; coverage_modes, coverage_shortfall
+///                                   # (missing tracks per rule) and coverage_satisfied — only
+///                                   # with bpm_coverage; owner_pattern (owner per
+///                                   # position), longest_owner_run and owner_run_excess
+///                                   # (tracks beyond max_owner_run) — only with owners;
+///                                   # spread_spacing (fewest positions between two spread
+///                                   # tracks) — only with spread_tracks;
+///                                   # boundary_costs (entry, exit) — only with anchors;
+///                                   # clash_count, clash_positions (edge j = position j → j+1)
+///                                   # — only with clash_threshold; edge_key_confidence (harmonic
+///                                   # weight per edge) — only with key_confidence;
+///                                   # edge_blend_scale — only with outro_blend_secs;
+///                                   # adjacency_bonus (total earned) — only with prefer_adjacent;
+///                                   # transition_types ("cut"/"blend" per edge) — only with
+///                                   # cut_penalty; compat_cost (total) and extended_breakdown
+///                                   # (h, t, s, compat), with compat split out of h — only
+///                                   # with compat_costs; lexicographic_scale — only with the
+///                                   # lexicographic objective_mode; cap_satisfied and
+///                                   # cap_weight_factor (escalation of the winning round) —
+///                                   # only with a cost cap; perfect_transitions (number of
+///                                   # perfect edges) — only with the perfect_transitions
+///                                   # objective_mode; longest_family_run — only with
+///                                   # max_same_family_run; stability_cost (included in h)
+///                                   # and broken_adjacencies — only with stability_weight;
+///                                   # shift_cost (weighted shift cost spent) — only with
+///                                   # max_shift_cost; max_edge_cost (costliest weighted
+///                                   # edge) — only with minmax_blend; key_relations
+///                                   # (classify_transition label per edge) — only with
+///                                   # num_keys = 24; excluded (tracks outside
+///                                   # bpm_range) — only with bpm_range
+///    per_position_costs: list[float])  # best order's average adjacent-edge cost at each
+///                                   # position (per_track_* are indexed by track)
+///
+/// An attempt that panics raises RuntimeError naming the attempt and the seed of its RNG,
+/// so the failure can be replayed with `annealing::run_attempt` in Rust.
+#[pyfunction]
+#[pyo3(signature = (
+    bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+    cost_params_dict, annealing_params_dict, time_limit_secs,
+    artist_ids=None, min_artist_gap=0, artist_gap_penalty=10.0, groupings=None,
+    inf_forbidden=false, harmonic_mask=None, groups=None, clash_threshold=None,
+    fixed_shifts=None, stats_weighting="uniform", stats_within_pct=10.0,
+    entry_key_id=None, entry_bpm=None, exit_key_id=None, exit_bpm=None, key_confidence=None,
+    prefer_adjacent=None, intro_bpms=None, outro_bpms=None,
+    outro_blend_secs=None, blend_reference_secs=30.0, compat_costs=None, compat_weight=1.0,
+    compat_replaces_harmonic=false, sparse_costs=None, tempo_cost_cap=None,
+    harmonic_cost_cap=None, max_same_family_run=None, family_run_penalty=10.0,
+    shift_init_weights=None, stability_weight=0.0, reference_order=None, bpm_coverage=None,
+    coverage_penalty=10.0, max_shift_cost=None, canonical_orientation=false, edge_cost_fn=None,
+    minmax_blend=0.0, owners=None, max_owner_run=1, alternation_weight=10.0, resume_from=None,
+    checkpoint_fn=None, checkpoint_interval_secs=60.0, bpm_range=None, spread_tracks=None,
+    min_spread_spacing=2,
+))]
+fn optimize_mix<'py>(
+    py: Python<'py>,
+    mut bpms: Array<i32>,
+    mut base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    mut direct_costs: Array<f64>,
+    mut indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    annealing_params_dict: AnnealingParamsArg,
+    time_limit_secs: f64,
+    mut artist_ids: Option<Vec<u32>>,
+    min_artist_gap: usize,
+    artist_gap_penalty: f64,
+    mut groupings: Option<Vec<(Vec<u32>, usize, f64)>>,
+    inf_forbidden: bool,
+    harmonic_mask: Option<Vec<u8>>,
+    mut groups: Option<Vec<Vec<usize>>>,
+    clash_threshold: Option<f64>,
+    mut fixed_shifts: Option<Vec<i8>>,
+    stats_weighting: &str,
+    stats_within_pct: f64,
+    entry_key_id: Option<u8>,
+    entry_bpm: Option<i32>,
+    exit_key_id: Option<u8>,
+    exit_bpm: Option<i32>,
+    mut key_confidence: Option<Vec<f64>>,
+    mut prefer_adjacent: Option<Vec<(usize, usize, f64)>>,
+    mut intro_bpms: Option<Vec<i32>>,
+    mut outro_bpms: Option<Vec<i32>>,
+    mut outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    mut compat_costs: Option<Array<f64>>,
+    compat_weight: f64,
+    compat_replaces_harmonic: bool,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+    tempo_cost_cap: Option<f64>,
+    harmonic_cost_cap: Option<f64>,
+    max_same_family_run: Option<i64>,
+    family_run_penalty: f64,
+    shift_init_weights: Option<(f64, f64, f64)>,
+    stability_weight: f64,
+    mut reference_order: Option<Vec<usize>>,
+    bpm_coverage: Option<Vec<((f64, f64), (i32, i32), usize)>>,
+    coverage_penalty: f64,
+    max_shift_cost: Option<f64>,
+    canonical_orientation: bool,
+    edge_cost_fn: Option<Bound<'py, PyAny>>,
+    minmax_blend: f64,
+    mut owners: Option<Vec<u8>>,
+    max_owner_run: usize,
+    alternation_weight: f64,
+    resume_from: Option<Bound<'py, PyBytes>>,
+    checkpoint_fn: Option<Bound<'py, PyAny>>,
+    checkpoint_interval_secs: f64,
+    bpm_range: Option<(f64, f64)>,
+    mut spread_tracks: Option<Vec<usize>>,
+    min_spread_spacing: usize,
+) -> PyResult<(
+    Vec<usize>, Vec<i8>, f64,
+    (f64, f64, f64),
+    Vec<(f64, f64, f64, f64)>,
+    usize,
+    Vec<f64>, Vec<f64>, Vec<f64>,
+    Bound<'py, PyDict>,
+    Vec<f64>,
+)> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs), ("compat_costs", &compat_costs),
+    ])?;
+    let filter = build_bpm_filter(bpm_range, &bpms)?;
+    if let Some(f) = &filter {
+        bpms = f.pick("bpms", &bpms)?.into();
+        base_key_ids = f.pick("base_key_ids", &base_key_ids)?.into();
+        artist_ids = f.pick_opt("artist_ids", artist_ids)?;
+        if let Some(gs) = &mut groupings {
+            for (k, (ids, _, _)) in gs.iter_mut().enumerate() {
+                *ids = f.pick(&format!("groupings[{k}]"), ids)?;
+            }
+        }
+        groups = groups.map(|g| f.groups(g)).transpose()?;
+        fixed_shifts = f.pick_opt("fixed_shifts", fixed_shifts)?;
+        key_confidence = f.pick_opt("key_confidence", key_confidence)?;
+        prefer_adjacent = prefer_adjacent.map(|p| f.pairs(p)).transpose()?;
+        intro_bpms = f.pick_opt("intro_bpms", intro_bpms)?;
+        outro_bpms = f.pick_opt("outro_bpms", outro_bpms)?;
+        outro_blend_secs = f.pick_opt("outro_blend_secs", outro_blend_secs)?;
+        if let Some(c) = &mut compat_costs {
+            let picked = f.matrix("compat_costs", c)?;
+            *c = Array { from_f32: c.from_f32, ..Array::from(picked) };
+        }
+        reference_order = reference_order.map(|o| f.nodes("reference_order", &o)).transpose()?;
+        owners = f.pick_opt("owners", owners)?;
+        spread_tracks = spread_tracks.map(|t| f.nodes("spread_tracks", &t)).transpose()?;
+    }
+
+    let n = bpms.len();
+    if n < 2 {
+        return Err(pyo3::exceptions::PyValueError::new_err("Need at least 2 tracks"));
+    }
+
+    validate_cost_table("direct_costs", &mut direct_costs, inf_forbidden)?;
+    validate_cost_table("indirect_costs", &mut indirect_costs, inf_forbidden)?;
+
+    let mut cp = build_cost_params(&cost_params_dict)?;
+
+    let mut ap = build_annealing_params(&annealing_params_dict)?;
+    ap.shift_init_weights = build_shift_init_weights(shift_init_weights)?;
+
+    let weighting = build_stats_weighting(stats_weighting, stats_within_pct)?;
+    let cap = build_cost_cap(tempo_cost_cap, harmonic_cost_cap)?;
+    if cap.is_some() && cp.objective_mode == ObjectiveMode::PerfectTransitions {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "cost caps cannot be combined with the perfect_transitions objective_mode",
+        ));
+    }
+
+    let mut separation = build_separation(n, artist_ids, min_artist_gap, artist_gap_penalty, groupings)?;
+    let spread_index = match build_spread(n, spread_tracks, min_spread_spacing)? {
+        Some(spread) => {
+            let sep = separation.get_or_insert_with(Separation::default);
+            sep.push(spread);
+            Some(sep.groupings.len() - 1)
+        }
+        None => None,
+    };
+    cp.family_run = build_family_run(max_same_family_run, family_run_penalty, cp.num_keys, groups.as_deref())?;
+    validate_max_shift_cost(max_shift_cost)?;
+    cp.max_shift_cost = max_shift_cost;
+    cp.minmax_blend = minmax_blend;
+    cp.validate().map_err(pyo3::exceptions::PyValueError::new_err)?;
+    if minmax_blend > 0.0 && cp.objective_mode != ObjectiveMode::Weighted {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "minmax_blend needs the weighted objective_mode",
+        ));
+    }
+
+    let stability = build_stability(n, stability_weight, reference_order)?;
+    let edge_compat = match edge_cost_fn {
+        Some(f) => Some(build_edge_cost_fn(n, &f, compat_costs.is_some(), filter.as_ref().map(|f| &f.kept[..]))?),
+        None => None,
+    };
+    let mut data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        prefer_adjacent, intro_bpms, outro_bpms, outro_blend_secs, blend_reference_secs, compat_costs,
+        compat_weight, compat_replaces_harmonic, sparse_costs, inf_forbidden,
+    }
+    .build(&cp)?;
+    if checkpoint_fn.is_some() {
+        data.detach();
+    }
+    let mut plain = data.tables();
+    plain.stability = stability.as_ref();
+    plain.compat = edge_compat.as_ref().or(plain.compat);
+    if let Some(rules) = bpm_coverage {
+        let coverage = build_coverage(&rules, coverage_penalty, plain.bpms)?;
+        separation.get_or_insert_with(Separation::default).coverage = Some(coverage);
+    }
+    if let Some(owners) = owners {
+        let alternation = build_alternation(n, owners, max_owner_run, alternation_weight)?;
+        separation.get_or_insert_with(Separation::default).alternation = Some(alternation);
+    }
+    plain.anchors = build_anchors(entry_key_id, entry_bpm, exit_key_id, exit_bpm, cp.num_keys)?;
+    let normal = CostParams { objective_mode: ObjectiveMode::Weighted, ..cp };
+    let shift_weight = cp.shift_weight;
+    let lexicographic_scale = resolve_objective(&mut cp, n, &plain)?;
+    let contraction = build_contraction(n, groups, &plain, &cp, separation.as_ref())?;
+    let tables = match &contraction {
+        Some(c) => c.tables(&plain),
+        None => plain,
+    };
+    let m = contraction.as_ref().map_or(n, Contraction::len);
+
+    validate_fixed_shifts(fixed_shifts.as_deref(), n)?;
+    if fixed_shifts.is_some() && contraction.is_some() {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "fixed_shifts cannot be combined with groups",
+        ));
+    }
+    if canonical_orientation && contraction.is_some() {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "canonical_orientation cannot be combined with groups, which fix each block's direction",
+        ));
+    }
+    if minmax_blend > 0.0 && contraction.is_some() {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "minmax_blend cannot be combined with groups",
+        ));
+    }
+
+    let checkpointing = resume_from.is_some() || checkpoint_fn.is_some();
+    if checkpointing && cap.is_some() {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "resume_from and checkpoint_fn cannot be combined with cost caps",
+        ));
+    }
+    let mut state = match &resume_from {
+        Some(bytes) => checkpoint::load(bytes.as_bytes(), m, weighting).map_err(pyo3::exceptions::PyValueError::new_err)?,
+        None => annealing::RunState::new(m, weighting),
+    };
+    let resumed_attempts = state.attempts();
+    let ((mut best, attempt_costs, mut stats, attempt_secs), cap_outcome) = match cap {
+        Some(cap) => {
+            let (run, outcome) = annealing::run_capped(
+                m, &tables, &cp, &ap, separation.as_ref(), fixed_shifts.as_deref(), weighting,
+                time_limit_secs, cap,
+            )?;
+            (run, Some(outcome))
+        }
+        None => {
+            // Save every checkpoint_interval_secs; an exception in checkpoint_fn ends the run
+            let mut last_save = std::time::Instant::now();
+            let mut save_error = None;
+            let mut save = |state: &annealing::RunState| -> bool {
+                let Some(f) = &checkpoint_fn else { return true };
+                if last_save.elapsed().as_secs_f64() < checkpoint_interval_secs {
+                    return true;
+                }
+                last_save = std::time::Instant::now();
+                match f.call1((PyBytes::new(py, &checkpoint::save(state)),)) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        save_error = Some(e);
+                        false
+                    }
+                }
+            };
+            let run = annealing::run_resumed(
+                m, &tables, &cp, &ap, separation.as_ref(), fixed_shifts.as_deref(), &mut state,
+                time_limit_secs, &mut save,
+            )?;
+            if let Some(e) = save_error {
+                return Err(e);
+            }
+            if let Some(f) = &checkpoint_fn {
+                f.call1((PyBytes::new(py, &checkpoint::save(&state)),))?;
+            }
+            (run, None)
+        }
+    };
+    // The solvers saw 0/1 edges; everything below is reported under the normal model
+    let perfect = cp.objective_mode == ObjectiveMode::PerfectTransitions;
+    if perfect {
+        cp = normal;
+    }
+    let report = PyDict::new(py);
+    report.set_item("attempt_secs", attempt_secs)?;
+    if resume_from.is_some() {
+        report.set_item("resumed_attempts", resumed_attempts)?;
+    }
+    let overall: Vec<f64> = attempt_costs.iter().map(|c| c.0).collect();
+    report.set_item("distinct_attempt_costs", annealing::distinct_costs(&overall))?;
+    report_move_stats(&report, &best.moves)?;
+    if let Some(outcome) = cap_outcome {
+        report.set_item("cap_satisfied", outcome.satisfied)?;
+        report.set_item("cap_weight_factor", outcome.weight_factor)?;
+    }
+    report_anchors(&report, &tables, &best.best_order, &best.best_shifts, &cp)?;
+    if let Some(c) = &contraction {
+        (best.best_order, best.best_shifts) = c.expand(&best.best_order, &best.best_shifts);
+        stats = PerTrackStats {
+            min: c.spread(&stats.min),
+            max: c.spread(&stats.max),
+            avg: c.spread(&stats.avg),
+        };
+    }
+    let max_edge = |o: &[usize]| cost::edge_costs(o, &best.best_shifts, &plain, &cp).into_iter().fold(0.0, f64::max);
+    if canonical_orientation {
+        let before = max_edge(&best.best_order);
+        let reversed = cost::canonicalize_orientation(&mut best.best_order, &best.best_shifts, &plain, &cp, |o| {
+            separation.as_ref().map_or(0.0, |sep| sep.penalty(o))
+        });
+        // Equal sums can still hide a different costliest edge
+        if reversed && minmax_blend > 0.0 && max_edge(&best.best_order) != before {
+            best.best_order.reverse();
+        }
+    }
+
+    let mut breakdown = (best.h_cost, best.t_cost, best.s_cost);
+    report_perfect(&report, perfect, &best.best_order, &best.best_shifts, false, &mut breakdown, &plain, &cp)?;
+    report_endpoints(&report, &best.best_order)?;
+    report_separation(&report, separation.as_ref(), &best.violations, true)?;
+    report_coverage(&report, separation.as_ref(), &best.best_order)?;
+    report_alternation(&report, separation.as_ref(), &best.best_order)?;
+    report_spread(&report, separation.as_ref(), spread_index, &best.best_order)?;
+    report_clashes(&report, clash_threshold, &best.best_order, &best.best_shifts, &plain, &cp)?;
+    report_key_confidence(&report, &best.best_order, &plain, &cp)?;
+    report_blend_scale(&report, &best.best_order, &plain)?;
+    report_adjacency(&report, &best.best_order, &plain)?;
+    report_stability(&report, &best.best_order, false, &plain)?;
+    report_transition_types(&report, &best.best_order, &best.best_shifts, &plain, &cp)?;
+    report_key_relations(&report, &best.best_order, &best.best_shifts, &plain, &cp)?;
+    report_compat(&report, &best.best_order, &best.best_shifts, false, breakdown, &plain)?;
+    report_objective(&report, lexicographic_scale)?;
+    report_family_run(&report, &best.best_order, &best.best_shifts, &plain, &cp)?;
+    if max_shift_cost.is_some() {
+        report.set_item("shift_cost", shift_weight * breakdown.2)?;
+    }
+    if minmax_blend > 0.0 {
+        report.set_item("max_edge_cost", max_edge(&best.best_order))?;
+    }
+    let per_position = annealing::compute_per_position_costs(&best.best_order, &best.best_shifts, &plain, &cp);
+
+    if let Some(f) = &filter {
+        best.best_order = best.best_order.iter().map(|&i| f.kept[i]).collect();
+        best.best_shifts = f.spread(&best.best_shifts, 0);
+        stats.min = f.spread(&stats.min, f64::NAN);
+        stats.max = f.spread(&stats.max, f64::NAN);
+        stats.avg = f.spread(&stats.avg, f64::NAN);
+        report_endpoints(&report, &best.best_order)?;
+        report.set_item("excluded", &f.excluded)?;
+    }
+
+    let n_attempts = attempt_costs.len();
+    Ok((
+        best.best_order,
+        best.best_shifts,
+        best.best_cost,
+        breakdown,
+        attempt_costs,
+        n_attempts,
+        stats.min,
+        stats.max,
+        stats.avg,
+        report,
+        per_position,
+    ))
+}
+
+/// optimize_mix_exact(bpms, base_key_ids, shift_table, direct_costs, indirect_costs, cost_params)
+///
+/// Runs the Held-Karp exact dynamic-programming algorithm to find the global optimum
+/// ordering and per-track shifts.  No time limit — runs to completion.
+///
+/// The DP table takes 2ⁿ · n · 3 · 8 bytes (~503 MB at n = 20, doubling per extra track), so
+/// the solver runs only while that fits in `max_memory_mb`; with `groups`, n is the number
+/// of blocks after contraction.  Over budget it raises PyValueError stating the memory
+/// needed, or, when `sa_fallback` is given, runs simulated annealing instead and sets
+/// `fallback_to_sa` in the report — the result is then no longer guaranteed optimal.
+///
+/// Separation groupings are penalty-only: each back-to-back same-group pair costs the
+/// grouping's penalty.  Wider gaps cannot be expressed in the DP state, so
+/// `grouping_violations` in the report counts violations over each grouping's full window.
+/// `inf_forbidden`, `harmonic_mask`, `groups`, the entry/exit anchors, `clash_threshold`,
+/// `key_confidence`, `prefer_adjacent`, `intro_bpms`/`outro_bpms`, `outro_blend_secs`,
+/// `compat_costs`, `edge_cost_fn`, `sparse_costs`, `stability_weight`/`reference_order` and
+/// the cut options in `cost_params` behave as in `optimize_mix` (with `cyclic`, the wrap
+/// edge counts towards broken_adjacencies).
+///
+///   cyclic      - bool  find the cheapest closed loop instead of an open path: best_cost and
+///                 cost_breakdown include the wrap edge from the last track back to the
+///                 first.  Cannot be combined with entry/exit anchors.
+///   start_track - int | None  the order starts at this track (with `cyclic`, the loop is
+///                 returned rotated to start here).  With `groups` it must be the first
+///                 track of its block.
+///   max_memory_mb - float  memory budget for the DP table (default 512, which admits
+///                 n ≤ 20)
+///   max_same_family_run, family_run_penalty - as in `optimize_mix`, but the DP prunes
+///                 every longer run, so the result always satisfies the limit (PyValueError
+///                 when no order can; with `cyclic`, runs are not counted across the wrap
+///                 edge).  The DP table grows by a factor of max_same_family_run.
+///   sa_fallback - (dict[str, float], float) | None  (annealing_params, time_limit_secs) for
+///                 an `optimize_mix` run when the DP exceeds max_memory_mb.  Separation
+///                 groupings are then enforced as in `optimize_mix`.  Cannot be combined
+///                 with cyclic or start_track (raises when the fallback would be needed).
+///   canonical_orientation - bool  as in `optimize_mix`; cannot be combined with cyclic or
+///                 start_track either (default False)
+///   optimizer   - MixOptimizer | None  keep the DP table in this object between calls
+///                 (only its DP table is used, so any MixOptimizer will do; the result is
+///                 the same)
+///
+/// Returns:
+///   (best_order:     list[int],
+///    best_shifts:    list[int],
+///    best_cost:      float,
+///    cost_breakdown: (h, t, s),
+///    report:         dict,              # as in optimize_mix (opener is start_track when
+///                                       # given), plus fallback_to_sa (bool)
+///    shift_counts:   (down, none, up))   # number of tracks at shift -1 / 0 / +1
+#[pyfunction]
+#[pyo3(signature = (
+    bpms, base_key_ids, shift_table, direct_costs, indirect_costs, cost_params_dict,
+    artist_ids=None, min_artist_gap=0, artist_gap_penalty=10.0, groupings=None,
+    inf_forbidden=false, harmonic_mask=None, groups=None, clash_threshold=None,
+    entry_key_id=None, entry_bpm=None, exit_key_id=None, exit_bpm=None, key_confidence=None,
+    prefer_adjacent=None, intro_bpms=None, outro_bpms=None,
+    outro_blend_secs=None, blend_reference_secs=30.0, compat_costs=None, compat_weight=1.0,
+    compat_replaces_harmonic=false, sparse_costs=None, cyclic=false, start_track=None,
+    max_memory_mb=512.0, sa_fallback=None, max_same_family_run=None, family_run_penalty=10.0,
+    stability_weight=0.0, reference_order=None, canonical_orientation=false, edge_cost_fn=None,
+    optimizer=None,
+))]
+fn optimize_mix_exact<'py>(
+    py: Python<'py>,
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    mut direct_costs: Array<f64>,
+    mut indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    artist_ids: Option<Vec<u32>>,
+    min_artist_gap: usize,
+    artist_gap_penalty: f64,
+    groupings: Option<Vec<(Vec<u32>, usize, f64)>>,
+    inf_forbidden: bool,
+    harmonic_mask: Option<Vec<u8>>,
+    groups: Option<Vec<Vec<usize>>>,
+    clash_threshold: Option<f64>,
+    entry_key_id: Option<u8>,
+    entry_bpm: Option<i32>,
+    exit_key_id: Option<u8>,
+    exit_bpm: Option<i32>,
+    key_confidence: Option<Vec<f64>>,
+    prefer_adjacent: Option<Vec<(usize, usize, f64)>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    compat_costs: Option<Array<f64>>,
+    compat_weight: f64,
+    compat_replaces_harmonic: bool,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+    cyclic: bool,
+    start_track: Option<usize>,
+    max_memory_mb: f64,
+    sa_fallback: Option<(AnnealingParamsArg, f64)>,
+    max_same_family_run: Option<i64>,
+    family_run_penalty: f64,
+    stability_weight: f64,
+    reference_order: Option<Vec<usize>>,
+    canonical_orientation: bool,
+    edge_cost_fn: Option<Bound<'py, PyAny>>,
+    optimizer: Option<Bound<'py, MixOptimizer>>,
+) -> PyResult<(
+    Vec<usize>, Vec<i8>, f64, (f64, f64, f64), Bound<'py, PyDict>, (usize, usize, usize),
+)> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs), ("compat_costs", &compat_costs),
+    ])?;
+    let n = bpms.len();
+    if n < 2 {
+        return Err(pyo3::exceptions::PyValueError::new_err("Need at least 2 tracks"));
+    }
+    if !(max_memory_mb.is_finite() && max_memory_mb > 0.0) {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "max_memory_mb must be a finite value > 0, got {max_memory_mb}"
+        )));
+    }
+
+    validate_cost_table("direct_costs", &mut direct_costs, inf_forbidden)?;
+    validate_cost_table("indirect_costs", &mut indirect_costs, inf_forbidden)?;
+
+    let mut cp = build_cost_params(&cost_params_dict)?;
+
+    let separation = build_separation(n, artist_ids, min_artist_gap, artist_gap_penalty, groupings)?;
+    cp.family_run = build_family_run(max_same_family_run, family_run_penalty, cp.num_keys, groups.as_deref())?;
+
+    let stability = build_stability(n, stability_weight, reference_order)?;
+    let edge_compat = match edge_cost_fn {
+        Some(f) => Some(build_edge_cost_fn(n, &f, compat_costs.is_some(), None)?),
+        None => None,
+    };
+    let data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        prefer_adjacent, intro_bpms, outro_bpms, outro_blend_secs, blend_reference_secs, compat_costs,
+        compat_weight, compat_replaces_harmonic, sparse_costs, inf_forbidden,
+    }
+    .build(&cp)?;
+    let mut plain = data.tables();
+    plain.stability = stability.as_ref();
+    plain.compat = edge_compat.as_ref().or(plain.compat);
+    plain.anchors = build_anchors(entry_key_id, entry_bpm, exit_key_id, exit_bpm, cp.num_keys)?;
+    let normal = CostParams { objective_mode: ObjectiveMode::Weighted, ..cp };
+    let lexicographic_scale = resolve_objective(&mut cp, n, &plain)?;
+    let contraction = build_contraction(n, groups, &plain, &cp, separation.as_ref())?;
+    let tables = match &contraction {
+        Some(c) => c.tables(&plain),
+        None => plain,
+    };
+    let m = contraction.as_ref().map_or(n, Contraction::len);
+    if cyclic && (plain.anchors.entry.is_some() || plain.anchors.exit.is_some()) {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "cyclic cannot be combined with entry/exit anchors",
+        ));
+    }
+    if canonical_orientation && (cyclic || start_track.is_some() || contraction.is_some()) {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "canonical_orientation cannot be combined with cyclic, start_track or groups",
+        ));
+    }
+    let start = match start_track {
+        None => None,
+        Some(t) if t >= n => {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "start_track {t} is out of range (0-{})", n - 1
+            )));
+        }
+        Some(t) => match &contraction {
+            None => Some(t),
+            Some(c) => Some(c.node_starting_with(t).ok_or_else(|| {
+                pyo3::exceptions::PyValueError::new_err(format!(
+                    "start_track {t} is inside a group but not its first track"
+                ))
+            })?),
+        },
+    };
+
+    // The DP table doubles with every node; past the budget, refuse or hand over to SA
+    let needed = held_karp::memory_bytes(m, &cp);
+    let fits = needed.is_some_and(|b| b as f64 <= max_memory_mb * 1024.0 * 1024.0);
+    let (mut order, mut shifts, cost, mut breakdown, violations) = if fits {
+        match &optimizer {
+            Some(o) => {
+                let mut workspace = o.get().workspace.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+                held_karp::run_in(&mut workspace, m, &tables, &cp, separation.as_ref(), start, cyclic)
+            }
+            None => held_karp::run(m, &tables, &cp, separation.as_ref(), start, cyclic),
+        }
+    } else {
+        let Some((ap_dict, time_limit_secs)) = sa_fallback else {
+            let needed = match needed.map(|b| b as f64 / (1024.0 * 1024.0)) {
+                Some(mb) if mb >= 1000.0 => format!("~{:.1} GB", mb / 1024.0),
+                Some(mb) if mb >= 10.0 => format!("~{mb:.0} MB"),
+                Some(mb) => format!("~{mb:.1} MB"),
+                None => "more memory than this platform can address".to_string(),
+            };
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "Held-Karp with n={m} needs {needed}, budget is {max_memory_mb} MB \
+                 (max_memory_mb); raise it, pass sa_fallback or use optimize_mix"
+            )));
+        };
+        if cyclic || start.is_some() {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "sa_fallback cannot honour cyclic or start_track; raise max_memory_mb instead",
+            ));
+        }
+        let ap = build_annealing_params(&ap_dict)?;
+        let (best, _, _, _) = annealing::run_timed(
+            m, &tables, &cp, &ap, separation.as_ref(), None, StatsWeighting::Uniform,
+            time_limit_secs,
+        )?;
+        let breakdown = (best.h_cost, best.t_cost, best.s_cost);
+        (best.best_order, best.best_shifts, best.best_cost, breakdown, best.violations)
+    };
+    if cost == f64::INFINITY && cp.family_run.is_some() {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "no order keeps every key-family run within max_same_family_run",
+        ));
+    }
+    // The solvers saw 0/1 edges; everything below is reported under the normal model
+    let perfect = cp.objective_mode == ObjectiveMode::PerfectTransitions;
+    if perfect {
+        cp = normal;
+    }
+    let report = PyDict::new(py);
+    report.set_item("fallback_to_sa", !fits)?;
+    report_anchors(&report, &tables, &order, &shifts, &cp)?;
+    if let Some(c) = &contraction {
+        (order, shifts) = c.expand(&order, &shifts);
+    }
+    if canonical_orientation {
+        cost::canonicalize_orientation(&mut order, &shifts, &plain, &cp, |o| {
+            separation.as_ref().map_or(0.0, |sep| sep.penalty(o))
+        });
+    }
+
+    report_perfect(&report, perfect, &order, &shifts, cyclic, &mut breakdown, &plain, &cp)?;
+    report_endpoints(&report, &order)?;
+    report_separation(&report, separation.as_ref(), &violations, !fits)?;
+    report_clashes(&report, clash_threshold, &order, &shifts, &plain, &cp)?;
+    report_key_confidence(&report, &order, &plain, &cp)?;
+    report_blend_scale(&report, &order, &plain)?;
+    report_adjacency(&report, &order, &plain)?;
+    report_stability(&report, &order, cyclic, &plain)?;
+    report_transition_types(&report, &order, &shifts, &plain, &cp)?;
+    report_key_relations(&report, &order, &shifts, &plain, &cp)?;
+    report_compat(&report, &order, &shifts, cyclic, breakdown, &plain)?;
+    report_objective(&report, lexicographic_scale)?;
+    report_family_run(&report, &order, &shifts, &plain, &cp)?;
+
+    let shift_counts = shift_counts(&shifts);
+    Ok((order, shifts, cost, breakdown, report, shift_counts))
+}
+
+/// The tuple `optimize_mix` returns.
+type MixTuple = (
+    Vec<usize>, Vec<i8>, f64, (f64, f64, f64), Vec<(f64, f64, f64, f64)>, usize,
+    Vec<f64>, Vec<f64>, Vec<f64>, Py<PyDict>, Vec<f64>,
+);
+
+/// optimize_mix_v2(...)
+///
+/// Same arguments as `optimize_mix`, but returns a `MixResult` instead of the tuple.
+#[pyfunction]
+#[pyo3(signature = (*args, **kwargs))]
+fn optimize_mix_v2(
+    py: Python<'_>,
+    args: &Bound<'_, PyTuple>,
+    kwargs: Option<&Bound<'_, PyDict>>,
+) -> PyResult<MixResult> {
+    let (
+        order, shifts, cost, (h_cost, t_cost, s_cost), attempt_costs, n_attempts,
+        per_track_min, per_track_max, per_track_avg, report, per_position_costs,
+    ): MixTuple = wrap_pyfunction!(optimize_mix, py)?.call(args, kwargs)?.extract()?;
+    Ok(MixResult {
+        shift_counts: shift_counts(&shifts),
+        order,
+        shifts,
+        cost,
+        h_cost,
+        t_cost,
+        s_cost,
+        attempt_costs: Some(attempt_costs),
+        n_attempts: Some(n_attempts),
+        per_track_min: Some(per_track_min),
+        per_track_max: Some(per_track_max),
+        per_track_avg: Some(per_track_avg),
+        per_position_costs: Some(per_position_costs),
+        proven_optimal: false,
+        report,
+    })
+}
+
+/// optimize_mix_exact_v2(...)
+///
+/// Same arguments as `optimize_mix_exact`, but returns a `MixResult`; `proven_optimal` is
+/// False when the run fell back to annealing.
+#[pyfunction]
+#[pyo3(signature = (*args, **kwargs))]
+fn optimize_mix_exact_v2(
+    py: Python<'_>,
+    args: &Bound<'_, PyTuple>,
+    kwargs: Option<&Bound<'_, PyDict>>,
+) -> PyResult<MixResult> {
+    let (order, shifts, cost, (h_cost, t_cost, s_cost), report, shift_counts): (
+        Vec<usize>, Vec<i8>, f64, (f64, f64, f64), Py<PyDict>, (usize, usize, usize),
+    ) = wrap_pyfunction!(optimize_mix_exact, py)?.call(args, kwargs)?.extract()?;
+    let fell_back: bool = report.bind(py).as_any().get_item("fallback_to_sa")?.extract()?;
+    Ok(MixResult {
+        order,
+        shifts,
+        cost,
+        h_cost,
+        t_cost,
+        s_cost,
+        attempt_costs: None,
+        n_attempts: None,
+        per_track_min: None,
+        per_track_max: None,
+        per_track_avg: None,
+        per_position_costs: None,
+        shift_counts,
+        proven_optimal: !fell_back,
+        report,
+    })
+}
+
+/// One track dict's field, `None` when absent; extraction errors name the track.
+fn track_field<'py, T: FromPyObject<'py>>(track: &Bound<'py, PyDict>, i: usize, name: &str) -> PyResult<Option<T>> {
+    match track.get_item(name)? {
+        None => Ok(None),
+        Some(value) => value.extract().map(Some).map_err(|e| {
+            pyo3::exceptions::PyValueError::new_err(format!("track {i}: invalid {name}: {e}"))
+        }),
+    }
+}
+
+/// A track's key: a string in `notation` or a key ID.
+fn track_key(track: &Bound<'_, PyDict>, i: usize, notation: cost::KeyNotation) -> PyResult<u8> {
+    let Some(key) = track.get_item("key")? else {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!("track {i} is missing 'key'")));
+    };
+    if let Ok(key) = key.extract::<&str>() {
+        return cost::parse_key(key, notation)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(format!("track {i}: {e}")));
+    }
+    key.extract().map_err(|e| pyo3::exceptions::PyValueError::new_err(format!("track {i}: invalid key: {e}")))
+}
+
+/// optimize_mix_tracks(tracks, cost_params, annealing_params, time_limit_secs,
+///                     key_notation="camelot", **kwargs)
+///
+/// `optimize_mix` over a list of track dicts instead of parallel lists, e.g.
+/// `[{"bpm": 126, "key": "8A", "artist_id": 3}, ...]`, with the built-in Camelot key
+/// tables (cost_params must keep num_keys = 24).  Fields:
+///   bpm            - int, required
+///   key            - str key in `key_notation`, parsed as by `key_to_id`, or int key
+///                    ID, required
+///   artist_id      - int, optional; tracks without one share an artist with nobody
+///   key_confidence - float, optional (default 1.0)
+///   intro_bpm, outro_bpm - int, optional (default bpm)
+/// Other fields are ignored.  A missing or malformed required field raises ValueError
+/// naming the track's index.  A per-track list (artist_ids, key_confidence, intro_bpms,
+/// outro_bpms) is built only when some track has the field; the other keyword arguments
+/// are passed through to `optimize_mix`, so giving one of those lists as well raises
+/// ValueError.
+///
+/// Returns the `optimize_mix` tuple; track indices are positions in `tracks`.
+#[pyfunction]
+#[pyo3(signature = (
+    tracks, cost_params_dict, annealing_params_dict, time_limit_secs, key_notation="camelot", **kwargs
+))]
+fn optimize_mix_tracks<'py>(
+    py: Python<'py>,
+    tracks: Vec<Bound<'py, PyDict>>,
+    cost_params_dict: Bound<'py, PyAny>,
+    annealing_params_dict: Bound<'py, PyAny>,
+    time_limit_secs: f64,
+    key_notation: &str,
+    kwargs: Option<&Bound<'py, PyDict>>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let notation = self::key_notation(key_notation)?;
+    let n = tracks.len();
+    let mut bpms = Vec::with_capacity(n);
+    let mut key_ids = Vec::with_capacity(n);
+    let (mut artist_ids, mut key_confidence) = (Vec::with_capacity(n), Vec::with_capacity(n));
+    let (mut intro_bpms, mut outro_bpms) = (Vec::with_capacity(n), Vec::with_capacity(n));
+    for (i, track) in tracks.iter().enumerate() {
+        let Some(bpm) = track_field::<i32>(track, i, "bpm")? else {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!("track {i} is missing 'bpm'")));
+        };
+        bpms.push(bpm);
+        key_ids.push(track_key(track, i, notation)?);
+        artist_ids.push(track_field::<u32>(track, i, "artist_id")?);
+        key_confidence.push(track_field::<f64>(track, i, "key_confidence")?);
+        intro_bpms.push(track_field::<i32>(track, i, "intro_bpm")?);
+        outro_bpms.push(track_field::<i32>(track, i, "outro_bpm")?);
+    }
+
+    let kw = match kwargs {
+        Some(kwargs) => kwargs.copy()?,
+        None => PyDict::new(py),
+    };
+    let set = |name: &str, value: Option<Bound<'py, PyAny>>| -> PyResult<()> {
+        let Some(value) = value else { return Ok(()) };
+        if kw.contains(name)? {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "{name} is built from the track dicts and cannot also be passed"
+            )));
+        }
+        kw.set_item(name, value)
+    };
+    // Tracks without an artist get IDs past every given one, so they never clash
+    let next_artist = artist_ids.iter().flatten().max().map_or(0, |&id| id as u64 + 1);
+    let given = artist_ids.iter().any(Option::is_some);
+    let artist_ids: Vec<u64> = artist_ids
+        .iter()
+        .enumerate()
+        .map(|(i, id)| id.map_or(next_artist + i as u64, u64::from))
+        .collect();
+    set("artist_ids", given.then(|| artist_ids.into_pyobject(py)).transpose()?)?;
+    let given = key_confidence.iter().any(Option::is_some);
+    let key_confidence: Vec<f64> = key_confidence.iter().map(|c| c.unwrap_or(1.0)).collect();
+    set("key_confidence", given.then(|| key_confidence.into_pyobject(py)).transpose()?)?;
+    for (name, values) in [("intro_bpms", &intro_bpms), ("outro_bpms", &outro_bpms)] {
+        let given = values.iter().any(Option::is_some);
+        let values: Vec<i32> = values.iter().zip(&bpms).map(|(v, &bpm)| v.unwrap_or(bpm)).collect();
+        set(name, given.then(|| values.into_pyobject(py)).transpose()?)?;
+    }
+
+    let args = (
+        bpms, key_ids, cost::camelot_shift_table(), Vec::<f64>::new(), Vec::<f64>::new(),
+        cost_params_dict, annealing_params_dict, time_limit_secs,
+    );
+    wrap_pyfunction!(optimize_mix, py)?.call(args, Some(&kw))
+}
+
+/// reoptimize_incremental(bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+///                        cost_params, annealing_params, time_limit_secs, previous_order,
+///                        previous_shifts, added_tracks, removed_tracks)
+///
+/// Re-optimize a set after a small edit instead of solving it again from scratch.  The
+/// removed tracks are dropped from `previous_order`, each added track is inserted where it
+/// adds the least cost (over its three shifts), and the splice is then polished by
+/// annealing attempts warm-started from it on a cold schedule (start temperature at most
+/// 10 × final_temp) for `time_limit_secs`, typically a second or two.  The result is never
+/// worse than the splice and keeps most of the previous order.  The per-track arguments
+/// cover every track involved (the previous set and the added tracks; other entries are
+/// ignored), so track indices stay stable across edits.  `harmonic_mask`,
+/// `key_confidence`, `intro_bpms`/`outro_bpms`, `outro_blend_secs` and `sparse_costs`
+/// behave as in `optimize_mix`; separation, anchors and groups are not available.
+///
+///   previous_order  - list[int]  the current order, as returned by an optimizer
+///   previous_shifts - list[int]  indexed by track index, each -1, 0 or +1; the kept
+///                     tracks start from these
+///   added_tracks    - list[int]  tracks to add, none of them in previous_order
+///   removed_tracks  - list[int]  tracks of previous_order to drop
+///
+/// Returns the `optimize_mix` tuple over the new set.  Shifts and per_track_* are indexed
+/// by track index: shifts are 0 and the stats NaN for tracks outside the set.  The report
+/// holds splice_cost (the cost before polishing), attempt_secs, distinct_attempt_costs,
+/// move_stats, constraint_rejection_rate, opener/closer and the transition summaries.
+#[pyfunction]
+#[pyo3(signature = (
+    bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+    cost_params_dict, annealing_params_dict, time_limit_secs, previous_order, previous_shifts,
+    added_tracks, removed_tracks, inf_forbidden=false, harmonic_mask=None, key_confidence=None,
+    intro_bpms=None, outro_bpms=None, outro_blend_secs=None, blend_reference_secs=30.0,
+    sparse_costs=None,
+))]
+fn reoptimize_incremental<'py>(
+    py: Python<'py>,
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    mut direct_costs: Array<f64>,
+    mut indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    annealing_params_dict: AnnealingParamsArg,
+    time_limit_secs: f64,
+    previous_order: Vec<usize>,
+    previous_shifts: Vec<i8>,
+    added_tracks: Vec<usize>,
+    removed_tracks: Vec<usize>,
+    inf_forbidden: bool,
+    harmonic_mask: Option<Vec<u8>>,
+    key_confidence: Option<Vec<f64>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+) -> PyResult<(
+    Vec<usize>, Vec<i8>, f64, (f64, f64, f64),
+    Vec<(f64, f64, f64, f64)>, usize,
+    Vec<f64>, Vec<f64>, Vec<f64>,
+    Bound<'py, PyDict>,
+    Vec<f64>,
+)> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs),
+    ])?;
+    let n = bpms.len();
+    if previous_shifts.len() != n {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "previous_shifts has {} entries, expected {n}", previous_shifts.len()
+        )));
+    }
+    if let Some(i) = previous_shifts.iter().position(|s| !(-1..=1).contains(s)) {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "previous_shifts[{i}] is {}, shifts must be -1, 0 or +1", previous_shifts[i]
+        )));
+    }
+    // 1 = in previous_order, 2 = also removed, 3 = added
+    let mut state = vec![0u8; n];
+    for (name, tracks, from, to, unless) in [
+        ("previous_order", &previous_order, 0, 1, " or repeated"),
+        ("removed_tracks", &removed_tracks, 1, 2, ", repeated or not in previous_order"),
+        ("added_tracks", &added_tracks, 0, 3, ", repeated or already in previous_order"),
+    ] {
+        for &t in tracks {
+            if t >= n || state[t] != from {
+                return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "{name}: track {t} is out of range{unless}"
+                )));
+            }
+            state[t] = to;
+        }
+    }
+    let kept: Vec<usize> = previous_order.iter().copied().filter(|&t| state[t] == 1).collect();
+    let tracks: Vec<usize> = kept.iter().chain(&added_tracks).copied().collect();
+    let m = tracks.len();
+    if m < 2 {
+        return Err(pyo3::exceptions::PyValueError::new_err("Need at least 2 tracks"));
+    }
+
+    validate_cost_table("direct_costs", &mut direct_costs, inf_forbidden)?;
+    validate_cost_table("indirect_costs", &mut indirect_costs, inf_forbidden)?;
+    let mut cp = build_cost_params(&cost_params_dict)?;
+    let ap = build_annealing_params(&annealing_params_dict)?;
+    let data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        intro_bpms, outro_bpms, outro_blend_secs, blend_reference_secs, sparse_costs, inf_forbidden,
+        ..TableArgs::default()
+    }
+    .build(&cp)?;
+    let plain = data.tables();
+
+    // Solve over the set alone, re-indexed kept tracks first (in their old order), then added
+    let subset = cost::Subset::new(&plain, &tracks);
+    let tables = subset.tables(&plain);
+    let normal = CostParams { objective_mode: ObjectiveMode::Weighted, ..cp };
+    let lexicographic_scale = resolve_objective(&mut cp, m, &tables)?;
+    let start_shifts: Vec<i8> = tracks.iter().map(|&t| previous_shifts[t]).collect();
+    let (kept_nodes, added_nodes): (Vec<usize>, Vec<usize>) = ((0..kept.len()).collect(), (kept.len()..m).collect());
+    let mut splice_shifts = start_shifts.clone();
+    let splice = incremental::splice(&kept_nodes, &mut splice_shifts, &added_nodes, &tables, &cp);
+    let splice_cost =
+        cost::score_orders(std::slice::from_ref(&splice), std::slice::from_ref(&splice_shifts), &tables, &cp, 1)[0];
+    let (best, attempt_costs, stats, attempt_secs) = incremental::reoptimize(
+        m, &tables, &cp, &ap, &kept_nodes, &start_shifts, &added_nodes, time_limit_secs,
+    )?;
+
+    let perfect = cp.objective_mode == ObjectiveMode::PerfectTransitions;
+    if perfect {
+        cp = normal;
+    }
+    let order: Vec<usize> = best.best_order.iter().map(|&i| tracks[i]).collect();
+    let mut shifts = vec![0i8; n];
+    let spread = |values: &[f64]| {
+        let mut out = vec![f64::NAN; n];
+        for (i, &v) in values.iter().enumerate() {
+            out[tracks[i]] = v;
+        }
+        out
+    };
+    let (per_track_min, per_track_max, per_track_avg) = (spread(&stats.min), spread(&stats.max), spread(&stats.avg));
+    for (i, &s) in best.best_shifts.iter().enumerate() {
+        shifts[tracks[i]] = s;
+    }
+
+    let report = PyDict::new(py);
+    report.set_item("splice_cost", splice_cost)?;
+    report.set_item("attempt_secs", attempt_secs)?;
+    let overall: Vec<f64> = attempt_costs.iter().map(|c| c.0).collect();
+    report.set_item("distinct_attempt_costs", annealing::distinct_costs(&overall))?;
+    report_move_stats(&report, &best.moves)?;
+    let mut breakdown = (best.h_cost, best.t_cost, best.s_cost);
+    report_perfect(&report, perfect, &order, &shifts, false, &mut breakdown, &plain, &cp)?;
+    report_endpoints(&report, &order)?;
+    report_key_confidence(&report, &order, &plain, &cp)?;
+    report_blend_scale(&report, &order, &plain)?;
+    report_transition_types(&report, &order, &shifts, &plain, &cp)?;
+    report_key_relations(&report, &order, &shifts, &plain, &cp)?;
+    report_objective(&report, lexicographic_scale)?;
+    let per_position = annealing::compute_per_position_costs(&order, &shifts, &plain, &cp);
+
+    let n_attempts = attempt_costs.len();
+    Ok((
+        order, shifts, best.best_cost, breakdown, attempt_costs, n_attempts,
+        per_track_min, per_track_max, per_track_avg, report, per_position,
+    ))
+}
+
+/// optimize_mix_fast(bpms, base_key_ids, shift_table, direct_costs, indirect_costs, cost_params)
+///
+/// Near-instant approximate ordering for very large pools (thousands of tracks): tracks are
+/// sorted along a space-filling curve over (BPM, key) and then tidied by a few greedy
+/// passes of nearby swaps and shift choices (see `fast.rs`).  Runs in milliseconds but is
+/// not optimal — use it as a quick preview or as a starting point for `optimize_mix`.
+/// `inf_forbidden`, `harmonic_mask`, `key_confidence`, `prefer_adjacent`,
+/// `intro_bpms`/`outro_bpms`, `outro_blend_secs`, `compat_costs` and `sparse_costs` behave
+/// as in `optimize_mix`.
+///
+/// Returns:
+///   (order:          list[int],
+///    shifts:         list[int],
+///    cost:           float,
+///    cost_breakdown: (h, t, s))
+#[pyfunction]
+#[pyo3(signature = (
+    bpms, base_key_ids, shift_table, direct_costs, indirect_costs, cost_params_dict,
+    inf_forbidden=false, harmonic_mask=None, key_confidence=None, prefer_adjacent=None,
+    intro_bpms=None, outro_bpms=None,
+    outro_blend_secs=None, blend_reference_secs=30.0, compat_costs=None, compat_weight=1.0,
+    compat_replaces_harmonic=false, sparse_costs=None,
+))]
+fn optimize_mix_fast(
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    mut direct_costs: Array<f64>,
+    mut indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    inf_forbidden: bool,
+    harmonic_mask: Option<Vec<u8>>,
+    key_confidence: Option<Vec<f64>>,
+    prefer_adjacent: Option<Vec<(usize, usize, f64)>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    compat_costs: Option<Array<f64>>,
+    compat_weight: f64,
+    compat_replaces_harmonic: bool,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+) -> PyResult<(Vec<usize>, Vec<i8>, f64, (f64, f64, f64))> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs), ("compat_costs", &compat_costs),
+    ])?;
+    let n = bpms.len();
+    if n < 2 {
+        return Err(pyo3::exceptions::PyValueError::new_err("Need at least 2 tracks"));
+    }
+
+    validate_cost_table("direct_costs", &mut direct_costs, inf_forbidden)?;
+    validate_cost_table("indirect_costs", &mut indirect_costs, inf_forbidden)?;
+
+    let mut cp = build_cost_params(&cost_params_dict)?;
+
+    let data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        prefer_adjacent, intro_bpms, outro_bpms, outro_blend_secs, blend_reference_secs, compat_costs,
+        compat_weight, compat_replaces_harmonic, sparse_costs, inf_forbidden,
+    }
+    .build(&cp)?;
+    let tables = data.tables();
+    resolve_objective(&mut cp, n, &tables)?;
+    Ok(fast::run(n, &tables, &cp))
+}
+
+/// optimize_mix_segments(bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+///                       cost_params, annealing_params, time_limit_secs,
+///                       segment_sizes, segment_of)
+///
+/// Optimises a set with planned breaks (MC segment, genre switch) as independent segments
+/// in one call.  Transitions across a segment boundary cost nothing, so each segment is
+/// ordered on its own; the time budget is shared between segments in proportion to
+/// size² (a rough proxy for difficulty).
+///
+/// Args (in addition to the `optimize_mix` tables and params):
+///   segment_sizes - list[int]  number of tracks per segment, e.g. [15, 20, 13]
+///   segment_of    - list[int]  segment index of each track (length n)
+///   harmonic_mask - list[int] | None  as in `optimize_mix`
+///   key_confidence - list[float] | None  as in `optimize_mix`
+///   intro_bpms, outro_bpms - list[int] | None  as in `optimize_mix`
+///   outro_blend_secs, blend_reference_secs - as in `optimize_mix`
+///   sparse_costs  - as in `optimize_mix`
+///
+/// Returns:
+///   (segment_orders:     list[list[int]],   # track indices in play order, per segment
+///    best_shifts:        list[int],         # indexed by track index
+///    segment_costs:      list[float],
+///    segment_breakdowns: list[(h, t, s)],
+///    segment_attempts:   list[int],
+///    total_cost:         float)             # sum of segment costs
+#[pyfunction]
+#[pyo3(signature = (
+    bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+    cost_params_dict, annealing_params_dict, time_limit_secs, segment_sizes, segment_of,
+    harmonic_mask=None, key_confidence=None, intro_bpms=None, outro_bpms=None,
+    outro_blend_secs=None, blend_reference_secs=30.0, sparse_costs=None,
+))]
+fn optimize_mix_segments(
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    direct_costs: Array<f64>,
+    indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    annealing_params_dict: AnnealingParamsArg,
+    time_limit_secs: f64,
+    segment_sizes: Vec<usize>,
+    segment_of: Vec<usize>,
+    harmonic_mask: Option<Vec<u8>>,
+    key_confidence: Option<Vec<f64>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+) -> PyResult<(
+    Vec<Vec<usize>>, Vec<i8>, Vec<f64>, Vec<(f64, f64, f64)>, Vec<usize>, f64,
+)> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs),
+    ])?;
+    let n = bpms.len();
+    if segment_of.len() != n {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "segment_of has {} entries, expected {n}", segment_of.len()
+        )));
+    }
+    let mut segments: Vec<Vec<usize>> = vec![Vec::new(); segment_sizes.len()];
+    for (t, &g) in segment_of.iter().enumerate() {
+        let seg = segments.get_mut(g).ok_or_else(|| {
+            pyo3::exceptions::PyValueError::new_err(format!(
+                "segment_of[{t}] = {g}, but there are only {} segments", segment_sizes.len()
+            ))
+        })?;
+        seg.push(t);
+    }
+    for (g, (seg, &size)) in segments.iter().zip(&segment_sizes).enumerate() {
+        if size == 0 || seg.len() != size {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "segment {g} has {} tracks assigned but segment_sizes[{g}] = {size}", seg.len()
+            )));
+        }
+    }
+
+    let mut cp = build_cost_params(&cost_params_dict)?;
+    let ap = build_annealing_params(&annealing_params_dict)?;
+    let data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        intro_bpms, outro_bpms, outro_blend_secs, blend_reference_secs, sparse_costs,
+        ..TableArgs::default()
+    }
+    .build(&cp)?;
+    let tables = data.tables();
+
+    resolve_objective(&mut cp, n, &tables)?;
+    let results = annealing::run_segments(&segments, &tables, &cp, &ap, time_limit_secs)?;
+
+    let mut shifts = vec![0i8; n];
+    for r in &results {
+        for (&t, &s) in r.order.iter().zip(&r.shifts) {
+            shifts[t] = s;
+        }
+    }
+    let total_cost = results.iter().map(|r| r.cost).sum();
+    Ok((
+        results.iter().map(|r| r.order.clone()).collect(),
+        shifts,
+        results.iter().map(|r| r.cost).collect(),
+        results.iter().map(|r| (r.h_cost, r.t_cost, r.s_cost)).collect(),
+        results.iter().map(|r| r.n_attempts).collect(),
+        total_cost,
+    ))
+}
+
+/// optimize_mix_shift_sweep(bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+///                          cost_params, annealing_params, time_limit_secs, max_k)
+///
+/// How much each extra shifted track is worth: the best order under a hard cap of at most
+/// k tracks played at a nonzero shift, for every k = 0..max_k, in one call sharing the
+/// precomputed tables.  For n ≤ 20, when the DP table fits `max_memory_mb`, one Held-Karp
+/// run counts shifted tracks in its state and yields the exact optimum for every k (the
+/// table grows by a factor of min(max_k, n) + 1).  Otherwise simulated annealing runs once
+/// per k, splitting `time_limit_secs` evenly.  Either way best_cost is non-increasing in k:
+/// an annealing result worse than the previous cap's is replaced by that result.
+/// `harmonic_mask`, `key_confidence`, `intro_bpms`/`outro_bpms`, `outro_blend_secs` and
+/// `sparse_costs` behave as in `optimize_mix`.
+///
+/// Returns:
+///   list[(k:         int,
+///         best_cost: float,
+///         order:     list[int],
+///         shifts:    list[int])]    # indexed by track index; at most k nonzero
+#[pyfunction]
+#[pyo3(signature = (
+    bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+    cost_params_dict, annealing_params_dict, time_limit_secs, max_k,
+    harmonic_mask=None, key_confidence=None, intro_bpms=None, outro_bpms=None,
+    outro_blend_secs=None, blend_reference_secs=30.0, sparse_costs=None, max_memory_mb=512.0,
+))]
+fn optimize_mix_shift_sweep(
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    direct_costs: Array<f64>,
+    indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    annealing_params_dict: AnnealingParamsArg,
+    time_limit_secs: f64,
+    max_k: usize,
+    harmonic_mask: Option<Vec<u8>>,
+    key_confidence: Option<Vec<f64>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+    max_memory_mb: f64,
+) -> PyResult<Vec<(usize, f64, Vec<usize>, Vec<i8>)>> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs),
+    ])?;
+    let n = bpms.len();
+    if n < 2 {
+        return Err(pyo3::exceptions::PyValueError::new_err("Need at least 2 tracks"));
+    }
+    if !(max_memory_mb.is_finite() && max_memory_mb > 0.0) {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "max_memory_mb must be a finite value > 0, got {max_memory_mb}"
+        )));
+    }
+
+    let mut cp = build_cost_params(&cost_params_dict)?;
+    let ap = build_annealing_params(&annealing_params_dict)?;
+    let data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        intro_bpms, outro_bpms, outro_blend_secs, blend_reference_secs, sparse_costs,
+        ..TableArgs::default()
+    }
+    .build(&cp)?;
+    let tables = data.tables();
+    resolve_objective(&mut cp, n, &tables)?;
+
+    let swept = CostParams { max_shifted: Some(max_k), ..cp };
+    let fits = n <= 20
+        && held_karp::memory_bytes(n, &swept).is_some_and(|b| b as f64 <= max_memory_mb * 1024.0 * 1024.0);
+    let results: Vec<(f64, Vec<usize>, Vec<i8>)> = if fits {
+        held_karp::shift_sweep(n, &tables, &cp, None, max_k)
+            .into_iter()
+            .map(|(order, shifts, cost, _, _)| (cost, order, shifts))
+            .collect()
+    } else {
+        annealing::run_shift_sweep(n, &tables, &cp, &ap, max_k, time_limit_secs)?
+            .into_iter()
+            .map(|r| (r.best_cost, r.best_order, r.best_shifts))
+            .collect()
+    };
+    Ok(results
+        .into_iter()
+        .enumerate()
+        .map(|(k, (cost, order, shifts))| (k, cost, order, shifts))
+        .collect())
+}
+
+/// optimize_mix_pareto(bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+///                     cost_params, annealing_params, time_limit_secs, weights)
+///
+/// Trace the harmonic vs tempo trade-off: run simulated annealing once per
+/// `tempo_cost_weight` in `weights` (which replaces the one in `cost_params`), sharing the
+/// precomputed tables and splitting `time_limit_secs` evenly.  Solutions are returned by
+/// increasing weight; a weight whose order and shifts repeat an earlier result is dropped.
+/// `harmonic_mask`, `key_confidence`, `intro_bpms`/`outro_bpms`, `outro_blend_secs` and
+/// `sparse_costs` behave as in `optimize_mix`.
+///
+///   weights            - list[float]  tempo weights to try, each finite and >= 0
+///                        (repeats are ignored)
+///   seed               - int | None  make the sweep reproducible: each weight runs exactly
+///                        `attempts_per_weight` attempts from a seeded RNG and
+///                        time_limit_secs is ignored (a time budget fits a machine-dependent
+///                        number of attempts)
+///   attempts_per_weight - int | None  required with `seed`, at least 1
+///
+/// Returns:
+///   list[(tempo_cost_weight: float,
+///         best_cost:         float,          # under that weight
+///         order:             list[int],
+///         shifts:            list[int],      # indexed by track index
+///         cost_breakdown:    (h, t, s))]
+#[pyfunction]
+#[pyo3(signature = (
+    bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+    cost_params_dict, annealing_params_dict, time_limit_secs, weights,
+    harmonic_mask=None, key_confidence=None, intro_bpms=None, outro_bpms=None,
+    outro_blend_secs=None, blend_reference_secs=30.0, sparse_costs=None, seed=None,
+    attempts_per_weight=None,
+))]
+fn optimize_mix_pareto(
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    direct_costs: Array<f64>,
+    indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    annealing_params_dict: AnnealingParamsArg,
+    time_limit_secs: f64,
+    weights: Vec<f64>,
+    harmonic_mask: Option<Vec<u8>>,
+    key_confidence: Option<Vec<f64>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+    seed: Option<u64>,
+    attempts_per_weight: Option<usize>,
+) -> PyResult<Vec<(f64, f64, Vec<usize>, Vec<i8>, (f64, f64, f64))>> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs),
+    ])?;
+    let n = bpms.len();
+    if n < 2 {
+        return Err(pyo3::exceptions::PyValueError::new_err("Need at least 2 tracks"));
+    }
+    if weights.is_empty() {
+        return Err(pyo3::exceptions::PyValueError::new_err("weights must not be empty"));
+    }
+    if let Some(&w) = weights.iter().find(|w| !(w.is_finite() && **w >= 0.0)) {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "weights must be finite values >= 0, got {w}"
+        )));
+    }
+    let seeded = match (seed, attempts_per_weight) {
+        (None, None) => None,
+        (Some(seed), Some(attempts)) if attempts >= 1 => Some((seed, attempts)),
+        (Some(_), Some(_)) => {
+            return Err(pyo3::exceptions::PyValueError::new_err("attempts_per_weight must be >= 1"));
+        }
+        (Some(_), None) => {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "seed needs attempts_per_weight: a time budget runs a machine-dependent number of attempts",
+            ));
+        }
+        (None, Some(_)) => {
+            return Err(pyo3::exceptions::PyValueError::new_err("attempts_per_weight needs a seed"));
+        }
+    };
+
+    let cp = build_cost_params(&cost_params_dict)?;
+    if cp.objective_mode != ObjectiveMode::Weighted {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "optimize_mix_pareto sweeps tempo_cost_weight and needs objective_mode \"weighted\"",
+        ));
+    }
+    let ap = build_annealing_params(&annealing_params_dict)?;
+    let data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        intro_bpms, outro_bpms, outro_blend_secs, blend_reference_secs, sparse_costs,
+        ..TableArgs::default()
+    }
+    .build(&cp)?;
+    let tables = data.tables();
+
+    let points = annealing::run_pareto(n, &tables, &cp, &ap, &weights, time_limit_secs, seeded)?;
+    Ok(points
+        .into_iter()
+        .map(|p| {
+            let r = p.result;
+            (p.tempo_cost_weight, r.best_cost, r.best_order, r.best_shifts, (r.h_cost, r.t_cost, r.s_cost))
+        })
+        .collect())
+}
+
+/// optimize_mix_hybrid(bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+///                     cost_params, annealing_params, time_limit_secs)
+///
+/// Heuristic for sets just past the exact limit (n ≈ 21–40): tracks are sorted by tempo and
+/// cut into clusters of at most `cluster_size`, each cluster is ordered exactly by
+/// Held-Karp, the cluster paths are stitched in rising or falling tempo (whichever is
+/// cheaper), and simulated annealing warm-started from the stitched order polishes the
+/// seams for `time_limit_secs`.  Usually well ahead of `optimize_mix` from a random start
+/// on such sets, but not guaranteed optimal.  `harmonic_mask`, `key_confidence`,
+/// `intro_bpms`/`outro_bpms`, `outro_blend_secs` and `sparse_costs` behave as in
+/// `optimize_mix`.
+///
+///   cluster_size - int  largest cluster solved exactly, 2..=20 (default 12; the DP table of
+///                  a cluster of 20 takes ~500 MB)
+///
+/// Returns:
+///   (best_order:     list[int],
+///    best_shifts:    list[int],        # indexed by track index
+///    best_cost:      float,
+///    cost_breakdown: (h, t, s),
+///    stitched_cost:  float,            # before polishing
+///    clusters:       list[list[int]],  # track indices per cluster, in stitched play order
+///    n_attempts:     int)              # polishing attempts
+#[pyfunction]
+#[pyo3(signature = (
+    bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+    cost_params_dict, annealing_params_dict, time_limit_secs, cluster_size=12,
+    harmonic_mask=None, key_confidence=None, intro_bpms=None, outro_bpms=None,
+    outro_blend_secs=None, blend_reference_secs=30.0, sparse_costs=None,
+))]
+fn optimize_mix_hybrid(
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    direct_costs: Array<f64>,
+    indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    annealing_params_dict: AnnealingParamsArg,
+    time_limit_secs: f64,
+    cluster_size: usize,
+    harmonic_mask: Option<Vec<u8>>,
+    key_confidence: Option<Vec<f64>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+) -> PyResult<(Vec<usize>, Vec<i8>, f64, (f64, f64, f64), f64, Vec<Vec<usize>>, usize)> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs),
+    ])?;
+    let n = bpms.len();
+    if n < 2 {
+        return Err(pyo3::exceptions::PyValueError::new_err("Need at least 2 tracks"));
+    }
+    if !(2..=20).contains(&cluster_size) {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "cluster_size must be between 2 and 20, got {cluster_size}"
+        )));
+    }
+
+    let mut cp = build_cost_params(&cost_params_dict)?;
+    let ap = build_annealing_params(&annealing_params_dict)?;
+    let data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        intro_bpms, outro_bpms, outro_blend_secs, blend_reference_secs, sparse_costs,
+        ..TableArgs::default()
+    }
+    .build(&cp)?;
+    let tables = data.tables();
+    resolve_objective(&mut cp, n, &tables)?;
+
+    let h = hybrid::run(n, &tables, &cp, &ap, cluster_size, time_limit_secs);
+    let r = h.result;
+    Ok((
+        r.best_order, r.best_shifts, r.best_cost, (r.h_cost, r.t_cost, r.s_cost), h.stitched_cost, h.clusters,
+        h.n_attempts,
+    ))
+}
+
+/// optimize_mix_select(bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+///                     cost_params, annealing_params, time_limit_secs, k=None)
+///
+/// Pick the best k tracks of a larger library and their order in one run, instead of
+/// pre-selecting by hand: simulated annealing over the selected order mixes swaps with
+/// moves that replace a selected track by an unused one or drop one track and insert an
+/// unused one elsewhere.  The cost is that of the selected order as in `optimize_mix`, minus
+/// the inclusion value of every selected track.  Separation, family-run and stability
+/// options are not available here, and there is no exact solver.  `harmonic_mask`,
+/// `key_confidence`, `intro_bpms`/`outro_bpms`, `outro_blend_secs` and `sparse_costs`
+/// behave as in `optimize_mix`.
+///
+///   k               - int | None  number of tracks to play, 2 <= k <= n; may be left
+///                     out when a target duration is given
+///   inclusion_value - list[float] | None  per-track bonus (objective units) subtracted
+///                     from the cost when the track is selected, so good tracks are not
+///                     dropped just because they are hard to place; default 0
+///   required        - list[bool] | None  must-play tracks (at most k): always selected,
+///                     while the optional rest serve as fillers; default none
+///
+/// Set duration (instead of, or as well as, k; without k the number of tracks varies):
+///   durations_secs          - list[float] | None  length of each track
+///   target_duration_secs    - float | None  wanted length of the set
+///   duration_tolerance_secs - float  no penalty within target ± tolerance (default 0)
+///   duration_penalty        - float  cost per second outside the window (default 0.1)
+///   hard_duration           - bool  never let the set move further outside the window;
+///                             the penalty only steers the start into it (default False)
+///   subtract_overlap        - bool  subtract each transition's outro_blend_secs from the
+///                             length (requires outro_blend_secs; default False)
+///
+/// Returns:
+///   (order:          list[int],   # the selected tracks in play order
+///    shifts:         list[int],   # indexed by track index; 0 for excluded tracks
+///    best_cost:      float,       # net of the selected tracks' inclusion values, plus
+///                                 # the duration penalty
+///    cost_breakdown: (h, t, s),   # of the selected order
+///    excluded:       list[int],   # tracks left out, in increasing order
+///    fillers:        list[(int, float)],  # optional tracks selected, in play order, with
+///                                         # their average adjacent-edge cost
+///    total_duration: float | None)  # length of the set in seconds, with durations_secs
+#[pyfunction]
+#[pyo3(signature = (
+    bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+    cost_params_dict, annealing_params_dict, time_limit_secs, k=None, inclusion_value=None,
+    required=None, harmonic_mask=None, key_confidence=None, intro_bpms=None, outro_bpms=None,
+    outro_blend_secs=None, blend_reference_secs=30.0, sparse_costs=None, durations_secs=None,
+    target_duration_secs=None, duration_tolerance_secs=0.0, duration_penalty=0.1,
+    hard_duration=false, subtract_overlap=false,
+))]
+fn optimize_mix_select(
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    direct_costs: Array<f64>,
+    indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    annealing_params_dict: AnnealingParamsArg,
+    time_limit_secs: f64,
+    k: Option<usize>,
+    inclusion_value: Option<Vec<f64>>,
+    required: Option<Vec<bool>>,
+    harmonic_mask: Option<Vec<u8>>,
+    key_confidence: Option<Vec<f64>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+    durations_secs: Option<Vec<f64>>,
+    target_duration_secs: Option<f64>,
+    duration_tolerance_secs: f64,
+    duration_penalty: f64,
+    hard_duration: bool,
+    subtract_overlap: bool,
+) -> PyResult<(Vec<usize>, Vec<i8>, f64, (f64, f64, f64), Vec<usize>, Vec<(usize, f64)>, Option<f64>)> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs),
+    ])?;
+    let n = bpms.len();
+    if let Some(k) = k {
+        if !(2..=n).contains(&k) {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "k must be between 2 and the number of tracks ({n}), got {k}"
+            )));
+        }
+    } else if target_duration_secs.is_none() {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "either k or target_duration_secs must be given",
+        ));
+    }
+    validate_duration_target(
+        durations_secs.as_deref(), target_duration_secs, duration_tolerance_secs, duration_penalty, n,
+    )?;
+    if subtract_overlap && outro_blend_secs.is_none() {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "subtract_overlap requires outro_blend_secs",
+        ));
+    }
+    validate_inclusion_value(inclusion_value.as_deref(), n)?;
+    if let Some(r) = &required {
+        if r.len() != n {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "required has {} entries, expected {n}", r.len()
+            )));
+        }
+        let count = r.iter().filter(|&&x| x).count();
+        if let Some(k) = k.filter(|&k| count > k) {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "{count} tracks are required but only k = {k} are played"
+            )));
+        }
+    }
+
+    let mut cp = build_cost_params(&cost_params_dict)?;
+    let ap = build_annealing_params(&annealing_params_dict)?;
+    let overlap = outro_blend_secs.clone().filter(|_| subtract_overlap);
+    let data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        intro_bpms, outro_bpms, outro_blend_secs, blend_reference_secs, sparse_costs,
+        ..TableArgs::default()
+    }
+    .build(&cp)?;
+    let tables = data.tables();
+    resolve_objective(&mut cp, n, &tables)?;
+
+    let duration = durations_secs.as_deref().zip(target_duration_secs).map(|(durations, target)| {
+        select::DurationTarget {
+            durations,
+            overlap: overlap.as_deref(),
+            target,
+            tolerance: duration_tolerance_secs,
+            penalty: duration_penalty,
+            hard: hard_duration,
+        }
+    });
+    let sel = select::run(
+        n, k, &tables, &cp, &ap, inclusion_value.as_deref(), required.as_deref(), duration.as_ref(),
+        time_limit_secs,
+    );
+    let r = sel.result;
+    Ok((
+        r.best_order, r.best_shifts, r.best_cost, (r.h_cost, r.t_cost, r.s_cost), sel.excluded, sel.fillers,
+        sel.duration,
+    ))
+}
+
+/// optimize_mix_repeat(bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+///                     cost_params, annealing_params, time_limit_secs, max_plays)
+///
+/// Order a set in which some tracks are played more than once, far apart (e.g. a theme
+/// that returns in a long ambient mix).  Each extra play is a copy of its track with the
+/// same BPMs and key, every copy is placed, and two plays of a track always have at least
+/// `min_repeat_gap` other tracks between them.  Simulated annealing as in `optimize_mix`;
+/// `harmonic_mask`, `key_confidence`, `intro_bpms`/`outro_bpms`, `outro_blend_secs` and
+/// `sparse_costs` behave as there.
+///
+///   max_plays      - list[int]  times each track is played, >= 1 (length n)
+///   min_repeat_gap - int  other tracks between two plays of a track, as min_artist_gap
+///                    (default 1: never back to back); a gap the plays cannot meet is an
+///                    error
+///
+/// Returns:
+///   (order:          list[int],   # track indices in play order, once per play
+///    shifts:         list[int],   # aligned with order: each play's shift
+///    best_cost:      float,
+///    cost_breakdown: (h, t, s),
+///    n_attempts:     int)
+#[pyfunction]
+#[pyo3(signature = (
+    bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+    cost_params_dict, annealing_params_dict, time_limit_secs, max_plays, min_repeat_gap=1,
+    harmonic_mask=None, key_confidence=None, intro_bpms=None, outro_bpms=None,
+    outro_blend_secs=None, blend_reference_secs=30.0, sparse_costs=None,
+))]
+fn optimize_mix_repeat(
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    direct_costs: Array<f64>,
+    indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    annealing_params_dict: AnnealingParamsArg,
+    time_limit_secs: f64,
+    max_plays: Vec<usize>,
+    min_repeat_gap: usize,
+    harmonic_mask: Option<Vec<u8>>,
+    key_confidence: Option<Vec<f64>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+) -> PyResult<(Vec<usize>, Vec<i8>, f64, (f64, f64, f64), usize)> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs),
+    ])?;
+    let n = bpms.len();
+    if max_plays.len() != n {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "max_plays has {} entries, expected {n}", max_plays.len()
+        )));
+    }
+
+    let mut cp = build_cost_params(&cost_params_dict)?;
+    let ap = build_annealing_params(&annealing_params_dict)?;
+    let data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        intro_bpms, outro_bpms, outro_blend_secs, blend_reference_secs, sparse_costs,
+        ..TableArgs::default()
+    }
+    .build(&cp)?;
+    let tables = data.tables();
+    let repeats = repeat::Repeats::new(&tables, &max_plays, min_repeat_gap)
+        .map_err(pyo3::exceptions::PyValueError::new_err)?;
+    if repeats.node_count() < 2 {
+        return Err(pyo3::exceptions::PyValueError::new_err("Need at least 2 plays"));
+    }
+    resolve_objective(&mut cp, repeats.node_count(), &repeats.tables(&tables))?;
+
+    let r = repeat::run(&repeats, &tables, &cp, &ap, time_limit_secs)?;
+    let best = r.result;
+    Ok((r.order, r.shifts, best.best_cost, (best.h_cost, best.t_cost, best.s_cost), r.n_attempts))
+}
+
+/// estimate_iterations(bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+///                     cost_params, annealing_params, target_gap=0.01)
+///
+/// "How long should I run this?"  Runs `attempts` short annealing attempts of
+/// `annealing_params` (seeded with `seed`) recording their best cost as they go, and
+/// estimates the total_iterations per attempt at which the average attempt comes within
+/// `target_gap` (relative: 0.01 = 1%) of the best cost seen.  When the calibration curve
+/// gets there the estimate is read off it; otherwise a power law fitted to the curve's tail
+/// is extrapolated.  This is a rough model, not a guarantee: a longer schedule cools more
+/// slowly than the one measured, the best seen is not the optimum, and extrapolating far
+/// beyond the calibration budget is a guess — check `confidence`.  Keep total_iterations
+/// small; the calibration costs attempts × total_iterations iterations.
+/// `harmonic_mask`, `key_confidence`, `intro_bpms`/`outro_bpms`, `outro_blend_secs` and
+/// `sparse_costs` behave as in `optimize_mix`.
+///
+/// Returns a dict with:
+///   iterations          - int | None  estimated total_iterations per attempt (None when
+///                         the curve shows no decay to extrapolate)
+///   secs                - float | None  wall time of one such attempt on this machine
+///   confidence          - "high" (read off the curve), "medium" (good fit, extrapolated
+///                         at most 10-fold) or "low"
+///   extrapolated        - bool, extrapolation (iterations / calibration budget)
+///   best_seen, final_gap - lowest cost reached, and the average attempt's gap at the end
+///   decay_exponent, fit_r2 - the fitted gap ∝ iterations^-decay_exponent and its R²
+///                         (None without a fit)
+///   attempts, calibration_iterations
+#[pyfunction]
+#[pyo3(signature = (
+    bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+    cost_params_dict, annealing_params_dict, target_gap=0.01, attempts=4, seed=0,
+    harmonic_mask=None, key_confidence=None, intro_bpms=None, outro_bpms=None,
+    outro_blend_secs=None, blend_reference_secs=30.0, sparse_costs=None,
+))]
+fn estimate_iterations<'py>(
+    py: Python<'py>,
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    direct_costs: Array<f64>,
+    indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    annealing_params_dict: AnnealingParamsArg,
+    target_gap: f64,
+    attempts: usize,
+    seed: u64,
+    harmonic_mask: Option<Vec<u8>>,
+    key_confidence: Option<Vec<f64>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+) -> PyResult<Bound<'py, PyDict>> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs),
+    ])?;
+    let n = bpms.len();
+    if n < 2 {
+        return Err(pyo3::exceptions::PyValueError::new_err("Need at least 2 tracks"));
+    }
+
+    let mut cp = build_cost_params(&cost_params_dict)?;
+    let ap = build_annealing_params(&annealing_params_dict)?;
+    let data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        intro_bpms, outro_bpms, outro_blend_secs, blend_reference_secs, sparse_costs,
+        ..TableArgs::default()
+    }
+    .build(&cp)?;
+    let tables = data.tables();
+    resolve_objective(&mut cp, n, &tables)?;
+
+    let est = calibrate::estimate_budget(n, &tables, &cp, &ap, None, attempts, target_gap, seed)
+        .map_err(pyo3::exceptions::PyValueError::new_err)?;
+    let out = PyDict::new(py);
+    out.set_item("iterations", est.iterations)?;
+    out.set_item("secs", est.secs)?;
+    out.set_item("confidence", est.confidence())?;
+    out.set_item("extrapolated", est.extrapolated)?;
+    out.set_item("extrapolation", est.extrapolation)?;
+    out.set_item("best_seen", est.best_seen)?;
+    out.set_item("final_gap", est.final_gap)?;
+    out.set_item("decay_exponent", est.decay_exponent)?;
+    out.set_item("fit_r2", est.fit_r2)?;
+    out.set_item("attempts", est.attempts)?;
+    out.set_item("calibration_iterations", est.calibration_iterations)?;
+    Ok(out)
+}
+
+/// optimize_mix_custom(bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+///                     cost_params, annealing_params, propose, move_interval=1000)
+///
+/// One simulated annealing attempt that takes every `move_interval`-th move from a Python
+/// callable, for experimenting with domain-specific moves (e.g. reversing a whole tempo
+/// run).  `propose(order, shifts)` gets the current order and shifts (indexed by track
+/// index) and returns a new order, a permutation of 0..n-1, or None to make a native swap
+/// instead.  The proposed order keeps the current shifts, is scored in full and is
+/// accepted or rejected like a native swap.  Calling into Python is slow next to a swap,
+/// so keep the interval coarse.  An exception raised by `propose` ends the run and is
+/// re-raised.  Separation, stability and anchor options are not available here.
+/// `harmonic_mask`, `key_confidence`, `intro_bpms`/`outro_bpms`, `outro_blend_secs` and
+/// `sparse_costs` behave as in `optimize_mix`.
+///
+///   move_interval - int  iterations per custom move, at least 1 (default 1000)
+///   seed          - int | None  seed of the attempt's RNG (default random)
+///
+/// Returns:
+///   (best_order:     list[int],
+///    best_shifts:    list[int],   # indexed by track index
+///    best_cost:      float,
+///    cost_breakdown: (h, t, s))
+#[pyfunction]
+#[pyo3(signature = (
+    bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+    cost_params_dict, annealing_params_dict, propose, move_interval=1000, seed=None,
+    harmonic_mask=None, key_confidence=None, intro_bpms=None, outro_bpms=None,
+    outro_blend_secs=None, blend_reference_secs=30.0, sparse_costs=None,
+))]
+fn optimize_mix_custom(
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    direct_costs: Array<f64>,
+    indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    annealing_params_dict: AnnealingParamsArg,
+    propose: Bound<'_, PyAny>,
+    move_interval: usize,
+    seed: Option<u64>,
+    harmonic_mask: Option<Vec<u8>>,
+    key_confidence: Option<Vec<f64>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+) -> PyResult<(Vec<usize>, Vec<i8>, f64, (f64, f64, f64))> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs),
+    ])?;
+    let n = bpms.len();
+    if n < 2 {
+        return Err(pyo3::exceptions::PyValueError::new_err("Need at least 2 tracks"));
+    }
+    if move_interval == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err("move_interval must be at least 1"));
+    }
+    if !propose.is_callable() {
+        return Err(pyo3::exceptions::PyTypeError::new_err("propose must be callable"));
+    }
+
+    let mut cp = build_cost_params(&cost_params_dict)?;
+    let ap = build_annealing_params(&annealing_params_dict)?;
+    let mut data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        intro_bpms, outro_bpms, outro_blend_secs, blend_reference_secs, sparse_costs,
+        ..TableArgs::default()
+    }
+    .build(&cp)?;
+    data.detach();
+    let tables = data.tables();
+    resolve_objective(&mut cp, n, &tables)?;
+
+    let mut call = |order: &[usize], shifts: &[i8]| -> PyResult<Option<Vec<usize>>> {
+        let proposal: Option<Vec<usize>> = propose.call1((order.to_vec(), shifts.to_vec()))?.extract()?;
+        if let Some(p) = &proposal {
+            let mut seen = vec![false; n];
+            if p.len() != n || p.iter().any(|&t| t >= n || std::mem::replace(&mut seen[t], true)) {
+                return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "propose returned {p:?}, which is not a permutation of 0..{}", n - 1
+                )));
+            }
+        }
+        Ok(proposal)
+    };
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_rng(&mut rand::rng()),
+    };
+    let r = annealing::run_attempt_with_moves(n, &tables, &cp, &ap, move_interval, &mut call, &mut rng)?;
+    Ok((r.best_order, r.best_shifts, r.best_cost, (r.h_cost, r.t_cost, r.s_cost)))
+}
+
+/// score_orders(bpms, base_key_ids, shift_table, direct_costs, indirect_costs, cost_params,
+///              orders, shifts_list)
+///
+/// Score many candidate orders in one call with the optimizers' own cost model, e.g. for a
+/// Python-side heuristic that generates thousands of them.  Each cost is what
+/// `optimize_mix` would report as best_cost for that order and shifts (under the same
+/// `objective_mode`).  `harmonic_mask`, `key_confidence`, `prefer_adjacent`,
+/// `intro_bpms`/`outro_bpms`, `outro_blend_secs`, `compat_costs` and `sparse_costs` behave
+/// as in `optimize_mix`.
+///
+///   orders      - list[list[int]]  each a permutation of 0..n-1
+///   shifts_list - list[list[int]]  one per order, indexed by track index, each -1, 0 or +1
+///   threads     - int  split the orders over this many threads (default 1)
+///
+/// Returns:
+///   costs: list[float]    # aligned with orders
+#[pyfunction]
+#[pyo3(signature = (
+    bpms, base_key_ids, shift_table, direct_costs, indirect_costs, cost_params_dict,
+    orders, shifts_list, harmonic_mask=None, key_confidence=None, prefer_adjacent=None,
+    intro_bpms=None, outro_bpms=None, outro_blend_secs=None, blend_reference_secs=30.0,
+    compat_costs=None, compat_weight=1.0, compat_replaces_harmonic=false, sparse_costs=None,
+    threads=1,
+))]
+fn score_orders(
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    direct_costs: Array<f64>,
+    indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    orders: Vec<Vec<usize>>,
+    shifts_list: Vec<Vec<i8>>,
+    harmonic_mask: Option<Vec<u8>>,
+    key_confidence: Option<Vec<f64>>,
+    prefer_adjacent: Option<Vec<(usize, usize, f64)>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    compat_costs: Option<Array<f64>>,
+    compat_weight: f64,
+    compat_replaces_harmonic: bool,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+    threads: usize,
+) -> PyResult<Vec<f64>> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs), ("compat_costs", &compat_costs),
+    ])?;
+    let n = bpms.len();
+    if n == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err("Need at least 1 track"));
+    }
+    if shifts_list.len() != orders.len() {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "shifts_list has {} entries but there are {} orders", shifts_list.len(), orders.len()
+        )));
+    }
+    if threads == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err("threads must be >= 1"));
+    }
+    let mut seen = vec![usize::MAX; n];
+    for (k, (order, shifts)) in orders.iter().zip(&shifts_list).enumerate() {
+        if order.len() != n || shifts.len() != n {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "orders[{k}] and shifts_list[{k}] must have {n} entries, got {} and {}",
+                order.len(), shifts.len()
+            )));
+        }
+        // `seen[t] == k` marks track t as already placed in order k
+        for &t in order {
+            if t >= n || std::mem::replace(&mut seen[t], k) == k {
+                return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "orders[{k}] is not a permutation of 0..{}", n - 1
+                )));
+            }
+        }
+        if let Some(i) = shifts.iter().position(|s| !(-1..=1).contains(s)) {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "shifts_list[{k}][{i}] is {}, shifts must be -1, 0 or +1", shifts[i]
+            )));
+        }
+    }
+
+    let mut cp = build_cost_params(&cost_params_dict)?;
+    let data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        prefer_adjacent, intro_bpms, outro_bpms, outro_blend_secs, blend_reference_secs, compat_costs,
+        compat_weight, compat_replaces_harmonic, sparse_costs, ..TableArgs::default()
+    }
+    .build(&cp)?;
+    let tables = data.tables();
+    resolve_objective(&mut cp, n, &tables)?;
+
+    Ok(cost::score_orders(&orders, &shifts_list, &tables, &cp, threads))
+}
+
+/// evaluate_order(order, shifts, bpms, base_key_ids, shift_table, direct_costs,
+///                indirect_costs, cost_params)
+///
+/// The engine's score of one complete arrangement, e.g. a hand-made order, with every
+/// input checked: the cost `optimize_mix` would report as best_cost for it and its
+/// unweighted (h, t, s) breakdown.  `score_orders` gives the same cost for many orders at
+/// once.  The optional arguments behave as in `score_orders`.
+///
+///   order  - list[int]  a permutation of 0..n-1
+///   shifts - list[int]  indexed by track index, each -1, 0 or +1
+///
+/// Returns:
+///   (total_cost: float,
+///    breakdown:  (float, float, float))   # harmonic, tempo, shift
+#[pyfunction]
+#[pyo3(signature = (
+    order, shifts, bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+    cost_params_dict, harmonic_mask=None, key_confidence=None, prefer_adjacent=None,
+    intro_bpms=None, outro_bpms=None, outro_blend_secs=None, blend_reference_secs=30.0,
+    compat_costs=None, compat_weight=1.0, compat_replaces_harmonic=false, sparse_costs=None,
+))]
+fn evaluate_order(
+    order: Vec<usize>,
+    shifts: Vec<i8>,
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    direct_costs: Array<f64>,
+    indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    harmonic_mask: Option<Vec<u8>>,
+    key_confidence: Option<Vec<f64>>,
+    prefer_adjacent: Option<Vec<(usize, usize, f64)>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    compat_costs: Option<Array<f64>>,
+    compat_weight: f64,
+    compat_replaces_harmonic: bool,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+) -> PyResult<(f64, (f64, f64, f64))> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs), ("compat_costs", &compat_costs),
+    ])?;
+    let n = bpms.len();
+    if n == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err("Need at least 1 track"));
+    }
+    validate_order_and_shifts(&order, &shifts, n)?;
+
+    let mut cp = build_cost_params(&cost_params_dict)?;
+    let data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        prefer_adjacent, intro_bpms, outro_bpms, outro_blend_secs, blend_reference_secs, compat_costs,
+        compat_weight, compat_replaces_harmonic, sparse_costs, ..TableArgs::default()
+    }
+    .build(&cp)?;
+    let tables = data.tables();
+    resolve_objective(&mut cp, n, &tables)?;
+
+    cost::evaluate_order(&order, &shifts, &tables, &cp).map_err(pyo3::exceptions::PyValueError::new_err)
+}
+
+/// per_track_costs(order, shifts, bpms, base_key_ids, shift_table, direct_costs,
+///                 indirect_costs, cost_params)
+///
+/// How well each track of a given (e.g. hand-made) order is mixed, for colour-coding a
+/// track list without running an optimizer: the per-track average `optimize_mix` reports
+/// as per_track_* for its best order, and the two edges it averages, so a track that is
+/// bad on the way in can be told from one that is bad on the way out.  The optional
+/// arguments behave as in `score_orders`.
+///
+/// Returns, each indexed by track index:
+///   (avg:      list[float],          # mean of the track's incoming and outgoing edge
+///                                    # costs (the one edge of the opener and closer)
+///    incoming: list[float | None],   # cost of the edge into the track; None for the opener
+///    outgoing: list[float | None])   # cost of the edge out of it; None for the closer
+#[pyfunction]
+#[pyo3(signature = (
+    order, shifts, bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+    cost_params_dict, harmonic_mask=None, key_confidence=None, prefer_adjacent=None,
+    intro_bpms=None, outro_bpms=None, outro_blend_secs=None, blend_reference_secs=30.0,
+    compat_costs=None, compat_weight=1.0, compat_replaces_harmonic=false, sparse_costs=None,
+))]
+fn per_track_costs(
+    order: Vec<usize>,
+    shifts: Vec<i8>,
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    direct_costs: Array<f64>,
+    indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    harmonic_mask: Option<Vec<u8>>,
+    key_confidence: Option<Vec<f64>>,
+    prefer_adjacent: Option<Vec<(usize, usize, f64)>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    compat_costs: Option<Array<f64>>,
+    compat_weight: f64,
+    compat_replaces_harmonic: bool,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+) -> PyResult<(Vec<f64>, Vec<Option<f64>>, Vec<Option<f64>>)> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs), ("compat_costs", &compat_costs),
+    ])?;
+    let n = bpms.len();
+    if n == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err("Need at least 1 track"));
+    }
+    validate_order_and_shifts(&order, &shifts, n)?;
+
+    let mut cp = build_cost_params(&cost_params_dict)?;
+    let data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        prefer_adjacent, intro_bpms, outro_bpms, outro_blend_secs, blend_reference_secs, compat_costs,
+        compat_weight, compat_replaces_harmonic, sparse_costs, ..TableArgs::default()
+    }
+    .build(&cp)?;
+    let tables = data.tables();
+    resolve_objective(&mut cp, n, &tables)?;
+
+    let costs = annealing::compute_track_costs(n, &order, &shifts, &tables, &cp);
+    Ok((costs.avg, costs.incoming, costs.outgoing))
+}
+
+/// compare_orders(order_a, shifts_a, order_b, shifts_b, bpms, base_key_ids, shift_table,
+///                direct_costs, indirect_costs, cost_params)
+///
+/// Side-by-side score of two complete orders of the same tracks, e.g. the user's current
+/// arrangement and an optimizer's proposal, to show which transitions got better or worse.
+/// Deterministic and solver-free: each order is scored as `evaluate_order` does.  The
+/// optional arguments behave as in `score_orders`.
+///
+/// Returns a dict:
+///   cost_a, cost_b           - float  totals, as evaluate_order
+///   breakdown_a, breakdown_b - (float, float, float)  harmonic, tempo, shift
+///   edge_costs_a, edge_costs_b - list[float]  weighted cost of each edge, position j → j + 1
+///   only_in_a, only_in_b     - list[(int, int)]  transitions (i, j), i directly before j,
+///                              the order plays and the other does not, in play order;
+///                              direction matters (i → j is not j → i)
+#[pyfunction]
+#[pyo3(signature = (
+    order_a, shifts_a, order_b, shifts_b, bpms, base_key_ids, shift_table, direct_costs,
+    indirect_costs, cost_params_dict, harmonic_mask=None, key_confidence=None,
+    prefer_adjacent=None, intro_bpms=None, outro_bpms=None, outro_blend_secs=None,
+    blend_reference_secs=30.0, compat_costs=None, compat_weight=1.0,
+    compat_replaces_harmonic=false, sparse_costs=None,
+))]
+fn compare_orders<'py>(
+    py: Python<'py>,
+    order_a: Vec<usize>,
+    shifts_a: Vec<i8>,
+    order_b: Vec<usize>,
+    shifts_b: Vec<i8>,
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    direct_costs: Array<f64>,
+    indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    harmonic_mask: Option<Vec<u8>>,
+    key_confidence: Option<Vec<f64>>,
+    prefer_adjacent: Option<Vec<(usize, usize, f64)>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    compat_costs: Option<Array<f64>>,
+    compat_weight: f64,
+    compat_replaces_harmonic: bool,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+) -> PyResult<Bound<'py, PyDict>> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs), ("compat_costs", &compat_costs),
+    ])?;
+    let n = bpms.len();
+    if n == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err("Need at least 1 track"));
+    }
+
+    let mut cp = build_cost_params(&cost_params_dict)?;
+    let data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        prefer_adjacent, intro_bpms, outro_bpms, outro_blend_secs, blend_reference_secs, compat_costs,
+        compat_weight, compat_replaces_harmonic, sparse_costs, ..TableArgs::default()
+    }
+    .build(&cp)?;
+    let tables = data.tables();
+    resolve_objective(&mut cp, n, &tables)?;
+
+    let c = cost::compare_orders(&order_a, &shifts_a, &order_b, &shifts_b, &tables, &cp)
+        .map_err(pyo3::exceptions::PyValueError::new_err)?;
+    let d = PyDict::new(py);
+    d.set_item("cost_a", c.cost_a)?;
+    d.set_item("cost_b", c.cost_b)?;
+    d.set_item("breakdown_a", c.breakdown_a)?;
+    d.set_item("breakdown_b", c.breakdown_b)?;
+    d.set_item("edge_costs_a", c.edge_costs_a)?;
+    d.set_item("edge_costs_b", c.edge_costs_b)?;
+    d.set_item("only_in_a", c.only_in_a)?;
+    d.set_item("only_in_b", c.only_in_b)?;
+    Ok(d)
+}
+
+/// polish_2opt(order, shifts, bpms, base_key_ids, shift_table, direct_costs,
+///             indirect_costs, cost_params, max_moves=10000)
+///
+/// Deterministic cleanup of any complete order, e.g. an annealing result or a hand-made
+/// one: the segment reversal that lowers the cost most is applied, again and again, until
+/// none does (a 2-opt local optimum) or max_moves reversals have been made.  Each step
+/// scores all n²/2 reversals in O(n²).  Shifts are kept, moving with their tracks;
+/// separation and the other positional rules are not applied.  The optional arguments
+/// behave as in `score_orders`.
+///
+/// Returns:
+///   (order:     list[int],
+///    cost:      float,   # as evaluate_order; never above the start's
+///    moves:     int,     # reversals applied
+///    converged: bool)    # False when max_moves stopped the polish first
+#[pyfunction]
+#[pyo3(signature = (
+    order, shifts, bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+    cost_params_dict, max_moves=10000, harmonic_mask=None, key_confidence=None,
+    prefer_adjacent=None, intro_bpms=None, outro_bpms=None, outro_blend_secs=None,
+    blend_reference_secs=30.0, compat_costs=None, compat_weight=1.0,
+    compat_replaces_harmonic=false, sparse_costs=None,
+))]
+fn polish_2opt(
+    py: Python<'_>,
+    order: Vec<usize>,
+    shifts: Vec<i8>,
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    direct_costs: Array<f64>,
+    indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    max_moves: usize,
+    harmonic_mask: Option<Vec<u8>>,
+    key_confidence: Option<Vec<f64>>,
+    prefer_adjacent: Option<Vec<(usize, usize, f64)>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    compat_costs: Option<Array<f64>>,
+    compat_weight: f64,
+    compat_replaces_harmonic: bool,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+) -> PyResult<(Vec<usize>, f64, usize, bool)> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs), ("compat_costs", &compat_costs),
+    ])?;
+    let n = bpms.len();
+    if n == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err("Need at least 1 track"));
+    }
+    validate_order_and_shifts(&order, &shifts, n)?;
+
+    let mut cp = build_cost_params(&cost_params_dict)?;
+    let mut data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        prefer_adjacent, intro_bpms, outro_bpms, outro_blend_secs, blend_reference_secs, compat_costs,
+        compat_weight, compat_replaces_harmonic, sparse_costs, ..TableArgs::default()
+    }
+    .build(&cp)?;
+    data.detach();
+    let tables = data.tables();
+    resolve_objective(&mut cp, n, &tables)?;
+
+    let p = py.allow_threads(|| polish::two_opt(&order, &shifts, &tables, &cp, max_moves));
+    Ok((p.order, p.cost, p.moves, p.converged))
+}
+
+/// transition_report(order, shifts, bpms, base_key_ids, shift_table, direct_costs,
+///                   indirect_costs, cost_params)
+///
+/// Per-transition detail of an order, e.g. `optimize_mix`'s best_order with its shifts,
+/// for performing the mix.  The harmonic and tempo columns sum exactly to the h and t of
+/// the (h, t, s) breakdown `evaluate_order` and `optimize_mix` report for the order
+/// (without groups), and cost + shift_cost + anchor_cost summed over the transitions is
+/// its total.  The optional arguments behave as in `score_orders`.
+///
+/// Returns one dict per edge, position j → j + 1, in play order:
+///   from_track, to_track         - int    track indices
+///   from_key, to_key             - int    effective key IDs after the shifts
+///   bpm_diff                     - float  |outro BPM - intro BPM|
+///   over_threshold, tempo_break  - bool   tempo difference past tempo_threshold / the
+///                                         break threshold
+///   key_confidence               - float  weight of the harmonic cost from the two
+///                                         tracks' key_confidence (1.0 without), i.e. how
+///                                         far the key match was trusted
+///   blend_scale                  - float  scale of the harmonic cost from the outgoing
+///                                         track's outro_blend_secs (1.0 without)
+///   harmonic                     - float  unweighted harmonic component (h)
+///   tempo                        - float  unweighted tempo component (t)
+///   cost                         - float  weighted edge cost, as the optimizer sums it
+///   shift_cost                   - float  weighted shift penalty of to_track, and of
+///                                         from_track too on the first transition
+///   anchor_cost                  - float  anchor edges: into from_track on the first
+///                                         transition, out of to_track on the last
+#[pyfunction]
+#[pyo3(signature = (
+    order, shifts, bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+    cost_params_dict, harmonic_mask=None, key_confidence=None, prefer_adjacent=None,
+    intro_bpms=None, outro_bpms=None, outro_blend_secs=None, blend_reference_secs=30.0,
+    compat_costs=None, compat_weight=1.0, compat_replaces_harmonic=false, sparse_costs=None,
+))]
+fn transition_report<'py>(
+    py: Python<'py>,
+    order: Vec<usize>,
+    shifts: Vec<i8>,
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    direct_costs: Array<f64>,
+    indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    harmonic_mask: Option<Vec<u8>>,
+    key_confidence: Option<Vec<f64>>,
+    prefer_adjacent: Option<Vec<(usize, usize, f64)>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    compat_costs: Option<Array<f64>>,
+    compat_weight: f64,
+    compat_replaces_harmonic: bool,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+) -> PyResult<Vec<Bound<'py, PyDict>>> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs), ("compat_costs", &compat_costs),
+    ])?;
+    let n = bpms.len();
+    if n == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err("Need at least 1 track"));
+    }
+    validate_order_and_shifts(&order, &shifts, n)?;
+
+    let mut cp = build_cost_params(&cost_params_dict)?;
+    let data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        prefer_adjacent, intro_bpms, outro_bpms, outro_blend_secs, blend_reference_secs, compat_costs,
+        compat_weight, compat_replaces_harmonic, sparse_costs, ..TableArgs::default()
+    }
+    .build(&cp)?;
+    let tables = data.tables();
+    resolve_objective(&mut cp, n, &tables)?;
+
+    cost::transition_report(&order, &shifts, &tables, &cp)
+        .into_iter()
+        .map(|r| {
+            let d = PyDict::new(py);
+            d.set_item("from_track", r.from_track)?;
+            d.set_item("to_track", r.to_track)?;
+            d.set_item("from_key", r.from_key)?;
+            d.set_item("to_key", r.to_key)?;
+            d.set_item("bpm_diff", r.bpm_diff)?;
+            d.set_item("over_threshold", r.over_threshold)?;
+            d.set_item("tempo_break", r.tempo_break)?;
+            d.set_item("key_confidence", r.key_confidence)?;
+            d.set_item("blend_scale", r.blend_scale)?;
+            d.set_item("harmonic", r.harmonic)?;
+            d.set_item("tempo", r.tempo)?;
+            d.set_item("cost", r.cost)?;
+            d.set_item("shift_cost", r.shift_cost)?;
+            d.set_item("anchor_cost", r.anchor_cost)?;
+            Ok(d)
+        })
+        .collect()
+}
+
+/// pairwise_best_costs(bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+///                     cost_params)
+///
+/// Compatibility matrix for a heatmap view: the cheapest edge cost from track i into track
+/// j over the nine shift pairs, for every ordered pair, independent of any order.  Each
+/// entry is the edge cost `optimize_mix` would sum for that transition (under the same
+/// `objective_mode`); shift penalties are left out and the diagonal is 0.
+/// `harmonic_mask`, `key_confidence`, `prefer_adjacent`, `intro_bpms`/`outro_bpms`,
+/// `outro_blend_secs`, `compat_costs` and `sparse_costs` behave as in `optimize_mix`.
+///
+/// Returns:
+///   costs: list[float]    # flat n×n, row-major: entry i * n + j is the edge i -> j
+#[pyfunction]
+#[pyo3(signature = (
+    bpms, base_key_ids, shift_table, direct_costs, indirect_costs, cost_params_dict,
+    harmonic_mask=None, key_confidence=None, prefer_adjacent=None,
+    intro_bpms=None, outro_bpms=None, outro_blend_secs=None, blend_reference_secs=30.0,
+    compat_costs=None, compat_weight=1.0, compat_replaces_harmonic=false, sparse_costs=None,
+))]
+fn pairwise_best_costs(
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    direct_costs: Array<f64>,
+    indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    harmonic_mask: Option<Vec<u8>>,
+    key_confidence: Option<Vec<f64>>,
+    prefer_adjacent: Option<Vec<(usize, usize, f64)>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    compat_costs: Option<Array<f64>>,
+    compat_weight: f64,
+    compat_replaces_harmonic: bool,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+) -> PyResult<Vec<f64>> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs), ("compat_costs", &compat_costs),
+    ])?;
+    let n = bpms.len();
+    if n == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err("Need at least 1 track"));
+    }
+
+    let mut cp = build_cost_params(&cost_params_dict)?;
+    let data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        prefer_adjacent, intro_bpms, outro_bpms, outro_blend_secs, blend_reference_secs, compat_costs,
+        compat_weight, compat_replaces_harmonic, sparse_costs, ..TableArgs::default()
+    }
+    .build(&cp)?;
+    let tables = data.tables();
+    resolve_objective(&mut cp, n, &tables)?;
+
+    Ok(cost::pairwise_best_costs(n, &tables, &cp))
+}
+
+/// An `order` (a permutation of 0..n-1) and its track-indexed `shifts`, as scored orders
+/// are passed in.
+fn validate_order_and_shifts(order: &[usize], shifts: &[i8], n: usize) -> PyResult<()> {
+    cost::validate_order_and_shifts(order, shifts, n).map_err(pyo3::exceptions::PyValueError::new_err)
+}
+
+/// leave_one_out_costs(bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+///                     cost_params, order, shifts)
+///
+/// How much each track of an optimized set costs it, to help pick the track to cut from a
+/// set that runs long.  Each track is removed in turn and the order re-stitched around the
+/// gap: its two neighbours become adjacent (or the new end of the set), their shifts are
+/// re-chosen for their new edges and everything else stays as given.  The set is not
+/// re-optimized, so each cost is an upper bound on what a fresh `optimize_mix` run without
+/// the track could reach — good for ranking, cheap enough to run on every result.  The
+/// optional arguments behave as in `score_orders`.
+///
+///   order  - list[int]  a permutation of 0..n-1, normally an optimizer's best_order
+///   shifts - list[int]  indexed by track index, each -1, 0 or +1
+///
+/// Returns:
+///   (base_cost: float,        # the order's own cost, as score_orders reports it
+///    costs:     list[float],  # indexed by track: cost of the set without that track
+///    deltas:    list[float])  # costs - base_cost; the most negative is hurting the mix most
+#[pyfunction]
+#[pyo3(signature = (
+    bpms, base_key_ids, shift_table, direct_costs, indirect_costs, cost_params_dict,
+    order, shifts, harmonic_mask=None, key_confidence=None, prefer_adjacent=None,
+    intro_bpms=None, outro_bpms=None, outro_blend_secs=None, blend_reference_secs=30.0,
+    compat_costs=None, compat_weight=1.0, compat_replaces_harmonic=false, sparse_costs=None,
+))]
+fn leave_one_out_costs(
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    direct_costs: Array<f64>,
+    indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    order: Vec<usize>,
+    shifts: Vec<i8>,
+    harmonic_mask: Option<Vec<u8>>,
+    key_confidence: Option<Vec<f64>>,
+    prefer_adjacent: Option<Vec<(usize, usize, f64)>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    compat_costs: Option<Array<f64>>,
+    compat_weight: f64,
+    compat_replaces_harmonic: bool,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+) -> PyResult<(f64, Vec<f64>, Vec<f64>)> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs), ("compat_costs", &compat_costs),
+    ])?;
+    let n = bpms.len();
+    if n == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err("Need at least 1 track"));
+    }
+    validate_order_and_shifts(&order, &shifts, n)?;
+
+    let mut cp = build_cost_params(&cost_params_dict)?;
+    let data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        prefer_adjacent, intro_bpms, outro_bpms, outro_blend_secs, blend_reference_secs, compat_costs,
+        compat_weight, compat_replaces_harmonic, sparse_costs, ..TableArgs::default()
+    }
+    .build(&cp)?;
+    let tables = data.tables();
+    resolve_objective(&mut cp, n, &tables)?;
+
+    let base = cost::score_orders(std::slice::from_ref(&order), std::slice::from_ref(&shifts), &tables, &cp, 1)[0];
+    let costs = cost::leave_one_out_costs(&order, &shifts, &tables, &cp);
+    let deltas = costs.iter().map(|c| c - base).collect();
+    Ok((base, costs, deltas))
+}
+
+/// reverse_segment_delta(bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+///                       cost_params, order, shifts, i, j)
+///
+/// Preview a manual 2-opt edit: how the cost of `order` would change if positions
+/// `i..=j` were played in reverse, without changing anything.  Only the edges the flip
+/// rewrites are rescored (the two boundary edges and, since intro/outro BPMs and directed
+/// tables make direction matter, the edges inside the segment), so it is cheap enough to
+/// call on every hover.  Shifts stay with their tracks.  The optional arguments behave as
+/// in `score_orders`.
+///
+///   order  - list[int]  a permutation of 0..n-1
+///   shifts - list[int]  indexed by track index, each -1, 0 or +1
+///   i, j   - int        first and last position of the segment, 0 <= i <= j < n
+///
+/// Returns:
+///   (delta:      float,        # cost after the flip - cost before (negative = better)
+///    first_edge: int,          # max(i - 1, 0)
+///    edge_costs: list[float])  # weighted cost after the flip of each edge from position
+///                              # first_edge + k to first_edge + k + 1, up to min(j, n - 2)
+#[pyfunction]
+#[pyo3(signature = (
+    bpms, base_key_ids, shift_table, direct_costs, indirect_costs, cost_params_dict,
+    order, shifts, i, j, harmonic_mask=None, key_confidence=None, prefer_adjacent=None,
+    intro_bpms=None, outro_bpms=None, outro_blend_secs=None, blend_reference_secs=30.0,
+    compat_costs=None, compat_weight=1.0, compat_replaces_harmonic=false, sparse_costs=None,
+))]
+fn reverse_segment_delta(
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    direct_costs: Array<f64>,
+    indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    order: Vec<usize>,
+    shifts: Vec<i8>,
+    i: usize,
+    j: usize,
+    harmonic_mask: Option<Vec<u8>>,
+    key_confidence: Option<Vec<f64>>,
+    prefer_adjacent: Option<Vec<(usize, usize, f64)>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    compat_costs: Option<Array<f64>>,
+    compat_weight: f64,
+    compat_replaces_harmonic: bool,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+) -> PyResult<(f64, usize, Vec<f64>)> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs), ("compat_costs", &compat_costs),
+    ])?;
+    let n = bpms.len();
+    if n == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err("Need at least 1 track"));
+    }
+    validate_order_and_shifts(&order, &shifts, n)?;
+    if i > j || j >= n {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "segment {i}..={j} must satisfy 0 <= i <= j < {n}"
+        )));
+    }
+
+    let mut cp = build_cost_params(&cost_params_dict)?;
+    let data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        prefer_adjacent, intro_bpms, outro_bpms, outro_blend_secs, blend_reference_secs, compat_costs,
+        compat_weight, compat_replaces_harmonic, sparse_costs, ..TableArgs::default()
+    }
+    .build(&cp)?;
+    let tables = data.tables();
+    resolve_objective(&mut cp, n, &tables)?;
+
+    let (delta, edge_costs) = cost::reverse_segment_delta(&order, &shifts, i, j, &tables, &cp);
+    Ok((delta, i.saturating_sub(1), edge_costs))
+}
+
+/// evaluate_swap(order, shifts, pos_a, pos_b, bpms, base_key_ids, shift_table, direct_costs,
+///               indirect_costs, cost_params)
+///
+/// Preview dragging two tracks onto each other's slots: the exact cost change of swapping
+/// positions `pos_a` and `pos_b`, computed the way the annealer scores its own swaps (both
+/// tracks' shifts re-chosen for their new neighbours, then the edges around them
+/// rescored), without changing anything.  Separation, family-run and shift-cap rules are
+/// not applied.  The optional arguments behave as in `score_orders`.
+///
+///   order  - list[int]  a permutation of 0..n-1
+///   shifts - list[int]  indexed by track index, each -1, 0 or +1
+///   reoptimize_shifts - bool  re-choose the two tracks' shifts (default); False keeps
+///                       them, as the annealer does under fixed_shifts
+///
+/// Returns:
+///   (delta:      float,                 # cost after the swap - cost before
+///    edge_costs: list[(int, float)],    # (j, cost) of each rewritten edge, position j →
+///                                       # j + 1 after the swap
+///    shifts:     list[(int, int)])      # (track, new shift) of the two swapped tracks
+#[pyfunction]
+#[pyo3(signature = (
+    order, shifts, pos_a, pos_b, bpms, base_key_ids, shift_table, direct_costs,
+    indirect_costs, cost_params_dict, harmonic_mask=None, key_confidence=None,
+    prefer_adjacent=None, intro_bpms=None, outro_bpms=None, outro_blend_secs=None,
+    blend_reference_secs=30.0, compat_costs=None, compat_weight=1.0,
+    compat_replaces_harmonic=false, sparse_costs=None, reoptimize_shifts=true,
+))]
+fn evaluate_swap(
+    order: Vec<usize>,
+    shifts: Vec<i8>,
+    pos_a: usize,
+    pos_b: usize,
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    direct_costs: Array<f64>,
+    indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    harmonic_mask: Option<Vec<u8>>,
+    key_confidence: Option<Vec<f64>>,
+    prefer_adjacent: Option<Vec<(usize, usize, f64)>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    compat_costs: Option<Array<f64>>,
+    compat_weight: f64,
+    compat_replaces_harmonic: bool,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+    reoptimize_shifts: bool,
+) -> PyResult<(f64, Vec<(usize, f64)>, Vec<(usize, i8)>)> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs), ("compat_costs", &compat_costs),
+    ])?;
+    let n = bpms.len();
+    validate_order_and_shifts(&order, &shifts, n)?;
+    if pos_a == pos_b || pos_a >= n || pos_b >= n {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "pos_a ({pos_a}) and pos_b ({pos_b}) must be distinct positions below {n}"
+        )));
+    }
+
+    let mut cp = build_cost_params(&cost_params_dict)?;
+    let data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        prefer_adjacent, intro_bpms, outro_bpms, outro_blend_secs, blend_reference_secs, compat_costs,
+        compat_weight, compat_replaces_harmonic, sparse_costs, ..TableArgs::default()
+    }
+    .build(&cp)?;
+    let tables = data.tables();
+    resolve_objective(&mut cp, n, &tables)?;
+
+    if !reoptimize_shifts {
+        let (a, b) = (order[pos_a], order[pos_b]);
+        let d = cost::change_delta(&order, &shifts, &[(pos_a, b), (pos_b, a)], &[], &tables, &cp)
+            .map_err(pyo3::exceptions::PyValueError::new_err)?;
+        return Ok((d.delta, d.edges, vec![(a, shifts[a]), (b, shifts[b])]));
+    }
+    let p = cost::swap_delta(&order, &shifts, pos_a, pos_b, &tables, &cp);
+    Ok((p.delta, p.edges, p.shifts))
+}
+
+/// evaluate_move(order, shifts, from_pos, to_pos, bpms, base_key_ids, shift_table,
+///               direct_costs, indirect_costs, cost_params)
+///
+/// `evaluate_swap` for dragging one track to a new slot: the track at `from_pos` is taken
+/// out and put back so that it plays at `to_pos`, the tracks in between moving up or down
+/// one place, and its shift is re-chosen there.  Arguments and result as in
+/// `evaluate_swap`; `shifts` holds the one moved track.
+#[pyfunction]
+#[pyo3(signature = (
+    order, shifts, from_pos, to_pos, bpms, base_key_ids, shift_table, direct_costs,
+    indirect_costs, cost_params_dict, harmonic_mask=None, key_confidence=None,
+    prefer_adjacent=None, intro_bpms=None, outro_bpms=None, outro_blend_secs=None,
+    blend_reference_secs=30.0, compat_costs=None, compat_weight=1.0,
+    compat_replaces_harmonic=false, sparse_costs=None,
+))]
+fn evaluate_move(
+    order: Vec<usize>,
+    shifts: Vec<i8>,
+    from_pos: usize,
+    to_pos: usize,
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    direct_costs: Array<f64>,
+    indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    harmonic_mask: Option<Vec<u8>>,
+    key_confidence: Option<Vec<f64>>,
+    prefer_adjacent: Option<Vec<(usize, usize, f64)>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    compat_costs: Option<Array<f64>>,
+    compat_weight: f64,
+    compat_replaces_harmonic: bool,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+) -> PyResult<(f64, Vec<(usize, f64)>, Vec<(usize, i8)>)> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs), ("compat_costs", &compat_costs),
+    ])?;
+    let n = bpms.len();
+    validate_order_and_shifts(&order, &shifts, n)?;
+    if from_pos >= n || to_pos >= n {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "from_pos ({from_pos}) and to_pos ({to_pos}) must be positions below {n}"
+        )));
+    }
+
+    let mut cp = build_cost_params(&cost_params_dict)?;
+    let data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        prefer_adjacent, intro_bpms, outro_bpms, outro_blend_secs, blend_reference_secs, compat_costs,
+        compat_weight, compat_replaces_harmonic, sparse_costs, ..TableArgs::default()
+    }
+    .build(&cp)?;
+    let tables = data.tables();
+    resolve_objective(&mut cp, n, &tables)?;
+
+    let p = cost::move_delta(&order, &shifts, from_pos, to_pos, &tables, &cp);
+    Ok((p.delta, p.edges, p.shifts))
+}
+
+/// evaluate_move_delta(order, shifts, changes, bpms, base_key_ids, shift_table, direct_costs,
+///                     indirect_costs, cost_params, shift_changes=None)
+///
+/// Exact cost change of an arbitrary local move, for search loops written in Python:
+/// `changes` puts track t at position p for each (p, t), and `shift_changes` gives track
+/// t shift s for each (t, s).  The tracks placed must be a rearrangement of the ones they
+/// replace (e.g. [(2, order[5]), (5, order[2])] for a swap), so the order stays a
+/// permutation; nothing is re-chosen.  Only the touched edges are rescored, so a call
+/// costs about the same whatever the length of the mix.  Separation, family-run and
+/// shift-cap rules are not applied.  The optional arguments behave as in `score_orders`.
+///
+/// Returns:
+///   (delta:       float,               # cost after the move - cost before
+///    shift_delta: float,               # the part of delta from the re-shifted tracks'
+///                                      # shift penalties
+///    edge_costs:  list[(int, float)])  # (j, cost) of each touched edge, position j →
+///                                      # j + 1 after the move
+#[pyfunction]
+#[pyo3(signature = (
+    order, shifts, changes, bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+    cost_params_dict, shift_changes=None, harmonic_mask=None, key_confidence=None,
+    prefer_adjacent=None, intro_bpms=None, outro_bpms=None, outro_blend_secs=None,
+    blend_reference_secs=30.0, compat_costs=None, compat_weight=1.0,
+    compat_replaces_harmonic=false, sparse_costs=None,
+))]
+fn evaluate_move_delta(
+    order: Vec<usize>,
+    shifts: Vec<i8>,
+    changes: Vec<(usize, usize)>,
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    direct_costs: Array<f64>,
+    indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    shift_changes: Option<Vec<(usize, i8)>>,
+    harmonic_mask: Option<Vec<u8>>,
+    key_confidence: Option<Vec<f64>>,
+    prefer_adjacent: Option<Vec<(usize, usize, f64)>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    compat_costs: Option<Array<f64>>,
+    compat_weight: f64,
+    compat_replaces_harmonic: bool,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+) -> PyResult<(f64, f64, Vec<(usize, f64)>)> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs), ("compat_costs", &compat_costs),
+    ])?;
+    let n = bpms.len();
+    validate_order_and_shifts(&order, &shifts, n)?;
+
+    let mut cp = build_cost_params(&cost_params_dict)?;
+    let data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        prefer_adjacent, intro_bpms, outro_bpms, outro_blend_secs, blend_reference_secs, compat_costs,
+        compat_weight, compat_replaces_harmonic, sparse_costs, ..TableArgs::default()
+    }
+    .build(&cp)?;
+    let tables = data.tables();
+    resolve_objective(&mut cp, n, &tables)?;
+
+    let d = cost::change_delta(&order, &shifts, &changes, shift_changes.as_deref().unwrap_or(&[]), &tables, &cp)
+        .map_err(pyo3::exceptions::PyValueError::new_err)?;
+    Ok((d.delta, d.shift_delta, d.edges))
+}
+
+/// suggest_insert_position(order, shifts, new_track_bpm, new_track_key_id, bpms,
+///                         base_key_ids, shift_table, direct_costs, indirect_costs, cost_params)
+///
+/// Where a track from outside the mix would fit best: the cost change of inserting it at
+/// every position of `order`, each with the shift that suits that position best.  The edge
+/// the insertion splits is taken out and the two new edges (and any anchor edge it
+/// displaces) put in; nothing else is rescored, so it is cheap enough to call on every
+/// library row.  `harmonic_mask`, `key_confidence`, `intro_bpms`/`outro_bpms` and
+/// `sparse_costs` behave as in `score_orders` and cover the tracks of the mix; the new
+/// track's key confidence is `new_track_key_confidence` and its intro and outro BPM are
+/// `new_track_bpm`.
+///
+///   order  - list[int]  the current mix, a permutation of 0..n-1
+///   shifts - list[int]  indexed by track index, each -1, 0 or +1
+///
+/// Returns list[(position, shift, cost_delta)], one per position 0..=n, cheapest first
+/// (ties keep position order).  `position` is where the track would go in `order`: just
+/// before order[position], or at the end when position = n.
+#[pyfunction]
+#[pyo3(signature = (
+    order, shifts, new_track_bpm, new_track_key_id, bpms, base_key_ids, shift_table,
+    direct_costs, indirect_costs, cost_params_dict, harmonic_mask=None, key_confidence=None,
+    new_track_key_confidence=1.0, intro_bpms=None, outro_bpms=None, sparse_costs=None,
+))]
+fn suggest_insert_position(
+    order: Vec<usize>,
+    mut shifts: Vec<i8>,
+    new_track_bpm: i32,
+    new_track_key_id: u8,
+    mut bpms: Array<i32>,
+    mut base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    direct_costs: Array<f64>,
+    indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    harmonic_mask: Option<Vec<u8>>,
+    mut key_confidence: Option<Vec<f64>>,
+    new_track_key_confidence: f64,
+    mut intro_bpms: Option<Vec<i32>>,
+    mut outro_bpms: Option<Vec<i32>>,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+) -> PyResult<Vec<(usize, i8, f64)>> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs),
+    ])?;
+    let n = bpms.len();
+    validate_order_and_shifts(&order, &shifts, n)?;
+    validate_key_confidence(key_confidence.as_deref(), n)?;
+    validate_track_bpms(&bpms, intro_bpms.as_deref(), outro_bpms.as_deref(), n)?;
+    validate_bpm("new_track_bpm", new_track_bpm)?;
+
+    let mut cp = build_cost_params(&cost_params_dict)?;
+    if usize::from(new_track_key_id) >= cp.num_keys {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "new_track_key_id {new_track_key_id} is out of range (0-{})", cp.num_keys - 1
+        )));
+    }
+    // The new track is node n of a one-larger instance.
+    let track = n;
+    bpms.to_mut().push(new_track_bpm);
+    base_key_ids.to_mut().push(new_track_key_id);
+    shifts.push(0);
+    for b in [&mut intro_bpms, &mut outro_bpms].into_iter().flatten() {
+        b.push(new_track_bpm);
+    }
+    if let Some(c) = &mut key_confidence {
+        c.push(new_track_key_confidence);
+    }
+
+    let data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        intro_bpms, outro_bpms, sparse_costs, ..TableArgs::default()
+    }
+    .build(&cp)?;
+    let tables = data.tables();
+    resolve_objective(&mut cp, n + 1, &tables)?;
+
+    let costs = incremental::insertion_costs(&order, &shifts, track, &tables, &cp);
+    let mut ranked: Vec<(usize, i8, f64)> = costs.into_iter().enumerate().map(|(pos, (s, c))| (pos, s, c)).collect();
+    ranked.sort_by(|a, b| a.2.total_cmp(&b.2));
+    Ok(ranked)
+}
+
+/// suggest_removal(order, shifts, bpms, base_key_ids, shift_table, direct_costs,
+///                 indirect_costs, cost_params)
+///
+/// The tracks of a mix ranked by how much cutting each would save: the counterpart of
+/// `suggest_insert_position`.  Removing a track re-stitches the order as in
+/// `leave_one_out_costs` (its neighbours' shifts re-chosen for their new edge; at either end
+/// of the set only one edge goes), but only the positions around the gap are rescored, so
+/// it stays cheap on long sets.  The optional arguments behave as in `score_orders`.
+///
+///   order  - list[int]  a permutation of 0..n-1
+///   shifts - list[int]  indexed by track index, each -1, 0 or +1
+///
+/// Returns list[(track, cost_delta)], one per track, largest saving (most negative delta)
+/// first; ties keep play order.
+#[pyfunction]
+#[pyo3(signature = (
+    order, shifts, bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+    cost_params_dict, harmonic_mask=None, key_confidence=None, prefer_adjacent=None,
+    intro_bpms=None, outro_bpms=None, outro_blend_secs=None, blend_reference_secs=30.0,
+    compat_costs=None, compat_weight=1.0, compat_replaces_harmonic=false, sparse_costs=None,
+))]
+fn suggest_removal(
+    order: Vec<usize>,
+    shifts: Vec<i8>,
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    direct_costs: Array<f64>,
+    indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    harmonic_mask: Option<Vec<u8>>,
+    key_confidence: Option<Vec<f64>>,
+    prefer_adjacent: Option<Vec<(usize, usize, f64)>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    compat_costs: Option<Array<f64>>,
+    compat_weight: f64,
+    compat_replaces_harmonic: bool,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+) -> PyResult<Vec<(usize, f64)>> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs), ("compat_costs", &compat_costs),
+    ])?;
+    let n = bpms.len();
+    if n == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err("Need at least 1 track"));
+    }
+    validate_order_and_shifts(&order, &shifts, n)?;
+
+    let mut cp = build_cost_params(&cost_params_dict)?;
+    let data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        prefer_adjacent, intro_bpms, outro_bpms, outro_blend_secs, blend_reference_secs, compat_costs,
+        compat_weight, compat_replaces_harmonic, sparse_costs, ..TableArgs::default()
+    }
+    .build(&cp)?;
+    let tables = data.tables();
+    resolve_objective(&mut cp, n, &tables)?;
+
+    let deltas = cost::removal_deltas(&order, &shifts, &tables, &cp);
+    let mut ranked: Vec<(usize, f64)> = order.iter().map(|&t| (t, deltas[t])).collect();
+    ranked.sort_by(|a, b| a.1.total_cmp(&b.1));
+    Ok(ranked)
+}
+
+/// explain_transition(bpms, base_key_ids, shift_table, direct_costs, indirect_costs,
+///                    cost_params, from_track, to_track, from_shift, to_shift)
+///
+/// Explain why the engine scores the transition from `from_track` (at `from_shift`) into
+/// `to_track` (at `to_shift`) the way it does.  Uses the same cost model as the optimizers
+/// but is evaluated on demand only, so it has no effect on optimizer speed.  Pass the same
+/// `harmonic_mask`, `key_confidence`, `prefer_adjacent`, `intro_bpms`/`outro_bpms`,
+/// `outro_blend_secs`, `compat_costs` and `sparse_costs` as the optimizer call, if any.
+///
+/// Returns a dict with:
+///   from_effective_key, to_effective_key - Camelot key IDs after shifting
+///   key_relation                         - classify_transition label of the two effective
+///                                          keys (None unless num_keys is 24)
+///   bpm_diff                             - |outro_bpm[from] - intro_bpm[to]|
+///   tempo_diff                           - the gap compared with the thresholds: bpm_diff,
+///                                          or its log-ratio form with perceptual_tempo
+///   over_threshold, tempo_break          - tempo_diff > tempo_threshold / break threshold
+///   harmonic_assessed                    - False on tempo breaks (keys are not looked up)
+///   direct_cell, indirect_cell           - the direct_costs / indirect_costs entries of the
+///                                          effective-key pair (shown even when not assessed)
+///   surcharge_from_mask                  - True when harmonic_mask decides the surcharge;
+///                                          False for the legacy rule on the two cells
+///   key_confidence                       - harmonic weight from the two tracks' key
+///                                          confidences (1.0 = fully trusted)
+///   blend_scale                          - harmonic factor from the from-track's blend length
+///   cut                                  - True when a hard cut is cheaper than a blend
+///                                          (only possible with cut_penalty)
+///   harmonic_cost, non_harmonic_surcharge - table cost (after both weights, and after the
+///                                           cut discount plus cut_penalty when cut; 0 with
+///                                           compat_replaces_harmonic) and whether the 2×
+///                                           non-harmonic surcharge fired
+///   compat_cost                          - weighted compat_costs entry for the pair
+///   adjacency_bonus                      - bonus credited for a prefer_adjacent pair
+///   tempo_cost                           - weighted tempo contribution
+///   edge_cost                            - harmonic_cost + compat_cost - adjacency_bonus
+///                                          + tempo_cost
+///                                          (what the optimizer sums)
+///   from_shift_cost, to_shift_cost       - weighted shift penalty of each track
+///   perfect                              - edge_cost < perfect_threshold (only with the
+///                                          perfect_transitions objective_mode, which is
+///                                          otherwise explained as "weighted")
+#[pyfunction]
+#[pyo3(signature = (
+    bpms, base_key_ids, shift_table, direct_costs, indirect_costs, cost_params_dict,
+    from_track, to_track, from_shift, to_shift, harmonic_mask=None, key_confidence=None,
+    prefer_adjacent=None, intro_bpms=None, outro_bpms=None,
+    outro_blend_secs=None, blend_reference_secs=30.0, compat_costs=None, compat_weight=1.0,
+    compat_replaces_harmonic=false, sparse_costs=None,
+))]
+fn explain_transition<'py>(
+    py: Python<'py>,
+    bpms: Array<i32>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+    direct_costs: Array<f64>,
+    indirect_costs: Array<f64>,
+    cost_params_dict: CostParamsArg,
+    from_track: usize,
+    to_track: usize,
+    from_shift: i8,
+    to_shift: i8,
+    harmonic_mask: Option<Vec<u8>>,
+    key_confidence: Option<Vec<f64>>,
+    prefer_adjacent: Option<Vec<(usize, usize, f64)>>,
+    intro_bpms: Option<Vec<i32>>,
+    outro_bpms: Option<Vec<i32>>,
+    outro_blend_secs: Option<Vec<f64>>,
+    blend_reference_secs: f64,
+    compat_costs: Option<Array<f64>>,
+    compat_weight: f64,
+    compat_replaces_harmonic: bool,
+    sparse_costs: Option<(f64, f64, Vec<(usize, usize, f64, f64)>)>,
+) -> PyResult<Bound<'py, PyDict>> {
+    check_arrays(&[
+        ("bpms", &bpms), ("base_key_ids", &base_key_ids), ("shift_table", &shift_table),
+        ("direct_costs", &direct_costs), ("indirect_costs", &indirect_costs), ("compat_costs", &compat_costs),
+    ])?;
+    let n = bpms.len();
+    if from_track >= n || to_track >= n {
+        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "track indices must be < {n}"
+        )));
+    }
+    if !(-1..=1).contains(&from_shift) || !(-1..=1).contains(&to_shift) {
+        return Err(pyo3::exceptions::PyValueError::new_err("shifts must be -1, 0 or +1"));
+    }
+    let mut cp = build_cost_params(&cost_params_dict)?;
+    let data = TableArgs {
+        bpms, base_key_ids, shift_table, direct_costs, indirect_costs, harmonic_mask, key_confidence,
+        prefer_adjacent, intro_bpms, outro_bpms, outro_blend_secs, blend_reference_secs, compat_costs,
+        compat_weight, compat_replaces_harmonic, sparse_costs, ..TableArgs::default()
+    }
+    .build(&cp)?;
+    let tables = data.tables();
+
+    // Explain the weighted cost that decides whether the edge is perfect
+    let perfect = cp.objective_mode == ObjectiveMode::PerfectTransitions;
+    if perfect {
+        cp.objective_mode = ObjectiveMode::Weighted;
+    }
+    resolve_objective(&mut cp, n, &tables)?;
+    let e = cost::explain_edge(from_track, to_track, from_shift, to_shift, &tables, &cp);
+    let d = PyDict::new(py);
+    d.set_item("from_effective_key", e.from_effective_key)?;
+    d.set_item("key_relation", e.key_relation.map(cost::KeyRelation::label))?;
+    d.set_item("to_effective_key", e.to_effective_key)?;
+    d.set_item("bpm_diff", e.bpm_diff)?;
+    d.set_item("tempo_diff", e.tempo_diff)?;
+    d.set_item("over_threshold", e.over_threshold)?;
+    d.set_item("tempo_break", e.tempo_break)?;
+    d.set_item("harmonic_assessed", e.harmonic_assessed)?;
+    d.set_item("direct_cell", e.direct_cell)?;
+    d.set_item("indirect_cell", e.indirect_cell)?;
+    d.set_item("surcharge_from_mask", e.surcharge_from_mask)?;
+    d.set_item("key_confidence", e.key_confidence)?;
+    d.set_item("blend_scale", e.blend_scale)?;
+    d.set_item("cut", e.cut)?;
+    d.set_item("harmonic_cost", e.harmonic_cost)?;
+    d.set_item("compat_cost", e.compat_cost)?;
+    d.set_item("non_harmonic_surcharge", e.non_harmonic_surcharge)?;
+    d.set_item("adjacency_bonus", e.adjacency_bonus)?;
+    d.set_item("tempo_cost", e.tempo_cost)?;
+    d.set_item("edge_cost", e.edge_cost)?;
+    d.set_item("from_shift_cost", e.from_shift_cost)?;
+    d.set_item("to_shift_cost", e.to_shift_cost)?;
+    if perfect {
+        d.set_item("perfect", cp.is_perfect(e.edge_cost))?;
+    }
+    Ok(d)
+}
+
+/// random_instance(n, seed)
+///
+/// Deterministic test instance from `testing::random_instance`, so Python tests can run on
+/// the same inputs as the Rust tests.
+///
+/// Returns:
+///   (bpms, base_key_ids, shift_table, direct_costs, indirect_costs, cost_params)
+///   — positional arguments ready for the optimize_* functions (24 Camelot keys)
+#[pyfunction]
+fn random_instance<'py>(
+    py: Python<'py>,
+    n: usize,
+    seed: u64,
+) -> PyResult<(Vec<i32>, Vec<u32>, Vec<u32>, Vec<f64>, Vec<f64>, Bound<'py, PyDict>)> {
+    let inst = testing::random_instance(n, seed);
+    let cp = testing::cost_params();
+    let d = PyDict::new(py);
+    d.set_item("tempo_threshold", cp.tempo_threshold)?;
+    d.set_item("tempo_penalty", cp.tempo_penalty)?;
+    d.set_item("tempo_break_factor", cp.tempo_break_factor)?;
+    d.set_item("tempo_cost_weight", cp.tempo_cost_weight)?;
+    d.set_item("non_harmonic_cost", cp.non_harmonic_cost)?;
+    d.set_item("shift_penalty", cp.shift_penalty)?;
+    d.set_item("shift_weight", cp.shift_weight)?;
+    d.set_item("num_keys", cp.num_keys as f64)?;
+    // Widen the u8 tables so they convert to lists rather than `bytes`
+    let widen = |v: Vec<u8>| v.into_iter().map(u32::from).collect();
+    Ok((inst.bpms, widen(inst.key_ids), widen(inst.shift_table), inst.direct_costs, inst.indirect_costs, d))
+}
+
+/// sparse_key_costs_to_dense(num_keys, sparse_costs)
+///
+/// Expand `sparse_costs` (as accepted by the optimize_* functions) into the dense tables.
+///
+/// Returns:
+///   (direct_costs, indirect_costs)   # num_keys² entries each
+#[pyfunction]
+fn sparse_key_costs_to_dense(
+    num_keys: usize,
+    sparse_costs: (f64, f64, Vec<(usize, usize, f64, f64)>),
+) -> PyResult<(Vec<f64>, Vec<f64>)> {
+    let sparse = build_sparse_costs(Some(sparse_costs), num_keys, false)?;
+    Ok(sparse.map(|s| s.to_dense()).unwrap_or_default())
+}
+
+/// shift_penalty_cost(shifts, cost_params)
+///
+/// Shift component of the cost for a shift vector alone, e.g. for a preview that toggles
+/// single shifts without rescoring the whole order: the shift_penalty of each track's shift
+/// (per direction when it is a dict), exactly as the optimizers count it.  Only `shift_penalty` and `shift_weight` of
+/// `cost_params` matter, but the dict is validated as in `optimize_mix`.
+///
+///   shifts - list[int]  indexed by track index, each -1, 0 or +1
+///
+/// Returns:
+///   (shift_cost:          float,   # the s of cost_breakdown
+///    weighted_shift_cost: float)   # shift_weight × shift_cost, as counted in best_cost
+#[pyfunction]
+fn shift_penalty_cost(
+    shifts: Vec<i8>,
+    cost_params_dict: CostParamsArg,
+) -> PyResult<(f64, f64)> {
+    let cp = build_cost_params(&cost_params_dict)?;
+    cp.shift_penalty_cost(&shifts).map_err(pyo3::exceptions::PyValueError::new_err)
+}
+
+/// classify_transition(ek1, ek2, num_keys=24)
+///
+/// Name the Camelot relationship from effective key `ek1` to `ek2` (key IDs after
+/// shifting, as in explain_transition): "perfect match", "+1 energy", "-1 mood",
+/// "relative major/minor", "+2 energy", "-2 mood", "energy boost" (one semitone up) or
+/// "clash".  The built-in key tables charge 0 for a perfect match, 1 for ±1 and the
+/// relative key, 2 for ±2 and the energy boost, and non_harmonic_cost for a clash.
+/// Raises ValueError unless num_keys is 24.
+#[pyfunction]
+#[pyo3(signature = (ek1, ek2, num_keys=24))]
+fn classify_transition(ek1: usize, ek2: usize, num_keys: usize) -> PyResult<&'static str> {
+    cost::classify_transition(ek1, ek2, num_keys)
+        .map(cost::KeyRelation::label)
+        .map_err(pyo3::exceptions::PyValueError::new_err)
+}
+
+/// build_tables(rules=None)
+///
+/// The Camelot tables `optimize_mix` and friends take, so callers need not encode the
+/// wheel themselves.  `rules` (dict[str, float] | None) prices each kind of transition;
+/// missing names keep the built-in model's cost:
+///   same_key      - 0.0
+///   adjacent      - 1.0  ±1 round the wheel (8A → 7A, 9A)
+///   relative      - 1.0  relative major/minor (8A → 8B)
+///   two_steps     - 2.0  ±2 round the wheel (8A → 6A, 10A)
+///   energy_boost  - 2.0  one semitone up (8A → 3A)
+///   other         - 5.0  everything else; keep it equal to cost_params' non_harmonic_cost
+///                        so clashes pay the non-harmonic surcharge
+/// Each is a finite cost >= 0; an unknown name raises ValueError.  The names follow
+/// classify_transition, and the defaults are the tables empty direct_costs and
+/// indirect_costs select.
+///
+/// Returns:
+///   (shift_table:    list[int],    # 72 entries, shift_table[key_id*3+(shift+1)]: one
+///                                  # semitone is 7 steps round the wheel, same letter
+///    direct_costs:   list[float],  # 24² entries, [ek1*24+ek2]
+///    indirect_costs: list[float])  # a copy of direct_costs
+#[pyfunction]
+#[pyo3(signature = (rules=None))]
+fn build_tables(rules: Option<std::collections::HashMap<String, f64>>) -> PyResult<(Vec<u32>, Vec<f64>, Vec<f64>)> {
+    let mut r = cost::KeyCostRules::builtin(5.0);
+    for (name, c) in rules.unwrap_or_default() {
+        let slot = match name.as_str() {
+            "same_key" => &mut r.same_key,
+            "adjacent" => &mut r.adjacent,
+            "relative" => &mut r.relative,
+            "two_steps" => &mut r.two_steps,
+            "energy_boost" => &mut r.energy_boost,
+            "other" => &mut r.other,
+            _ => {
+                return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "unknown rule {name:?} (expected same_key, adjacent, relative, two_steps, energy_boost or other)"
+                )))
+            }
+        };
+        *slot = c;
+    }
+    let (direct, indirect) = cost::camelot_tables(&r).map_err(pyo3::exceptions::PyValueError::new_err)?;
+    // u32 rather than u8, which pyo3 would return as bytes
+    let shift_table = cost::camelot_shift_table().into_iter().map(u32::from).collect();
+    Ok((shift_table, direct, indirect))
+}
+
+/// A `notation` argument: "camelot", "open_key" or "musical".
+fn key_notation(name: &str) -> PyResult<cost::KeyNotation> {
+    cost::KeyNotation::from_name(name).map_err(pyo3::exceptions::PyValueError::new_err)
+}
+
+/// key_to_id(key, notation="camelot")
+///
+/// Key string to the key ID every function here takes: id = (number - 1) × 2 + letter on
+/// the Camelot wheel, with A (minor) = 0 and B (major) = 1, so "1A" = 0, "1B" = 1,
+/// "8A" = 14 and "12B" = 23.  `notation` is "camelot" ("8A"), "open_key" ("1m" = 8A,
+/// "1d" = 8B) or "musical" ("Am", "F#", "Ebmin", "C major").  Musical names are accepted
+/// whatever the notation; digits are read as the wheel `notation` names.  Case,
+/// surrounding spaces and leading zeros are ignored ("08a" = "8A"); anything else raises
+/// ValueError.
+#[pyfunction]
+#[pyo3(signature = (key, notation="camelot"))]
+fn key_to_id(key: &str, notation: &str) -> PyResult<u8> {
+    cost::parse_key(key, key_notation(notation)?).map_err(pyo3::exceptions::PyValueError::new_err)
+}
+
+/// id_to_key(key_id, notation="camelot")
+///
+/// Inverse of `key_to_id`: the canonical string of a key ID in 0..24 in `notation`, e.g.
+/// 14 is "8A", "1m" or "Am" (musical names use sharps only for F# and F#m).
+/// Raises ValueError for other IDs.
+#[pyfunction]
+#[pyo3(signature = (key_id, notation="camelot"))]
+fn id_to_key(key_id: u8, notation: &str) -> PyResult<String> {
+    cost::format_key(key_id, key_notation(notation)?).map_err(pyo3::exceptions::PyValueError::new_err)
+}
+
+/// keys_to_ids(keys, notation="camelot")
+///
+/// `key_to_id` over a list of key strings, e.g. to build `base_key_ids`.  A malformed key
+/// raises ValueError naming its index.
+#[pyfunction]
+#[pyo3(signature = (keys, notation="camelot"))]
+fn keys_to_ids(keys: Vec<String>, notation: &str) -> PyResult<Vec<u32>> {
+    let notation = key_notation(notation)?;
+    // u32 rather than u8, which pyo3 would return as bytes
+    keys.iter()
+        .enumerate()
+        .map(|(i, key)| {
+            cost::parse_key(key, notation)
+                .map(u32::from)
+                .map_err(|e| pyo3::exceptions::PyValueError::new_err(format!("keys[{i}]: {e}")))
+        })
+        .collect()
+}
+
+/// export_schedule(order, shifts, base_key_ids, shift_table)
+///
+/// The mix in the form DJ software consumes: one `(track, effective_key_id,
+/// shift_semitones)` per entry of `order`, in play order, where the effective key is what
+/// `shift_table` makes of the track's base key at its shift and a shift of ±1 is one
+/// semitone.  `order` may be a subset of the tracks (e.g. with bpm_range) or repeat them;
+/// `shifts` is indexed by track index, as the optimizers return it.  Raises ValueError for
+/// an out-of-range track, key ID or shift, or mismatched lengths.
+///
+/// Returns list[(track: int, effective_key_id: int, shift_semitones: int)]
+#[pyfunction]
+fn export_schedule(
+    order: Vec<usize>,
+    shifts: Vec<i8>,
+    base_key_ids: Array<u8>,
+    shift_table: Array<u8>,
+) -> PyResult<Vec<(usize, usize, i8)>> {
+    check_arrays(&[("base_key_ids", &base_key_ids), ("shift_table", &shift_table)])?;
+    cost::export_schedule(&order, &shifts, &base_key_ids, &shift_table).map_err(pyo3::exceptions::PyValueError::new_err)
+}
+
+/// order_similarity(order_a, order_b)
+///
+/// Compare two orders of the same tracks (any track IDs, each exactly once in both), e.g.
+/// an optimiser result against a hand-made order.  Independent of the cost model.
+///
+/// Returns:
+///   (kendall_tau:        int,   # pairs played in opposite order (0 = identical)
+///    shared_adjacencies: int)   # transitions a → b present in both orders
+#[pyfunction]
+fn order_similarity(order_a: Vec<usize>, order_b: Vec<usize>) -> PyResult<(usize, usize)> {
+    let s = similarity::compare(&order_a, &order_b).map_err(pyo3::exceptions::PyValueError::new_err)?;
+    Ok((s.kendall_tau, s.shared_adjacencies))
+}
+
+#[pymodule]
+fn ydj_mixer_engine(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(optimize_mix, m)?)?;
+    m.add_function(wrap_pyfunction!(optimize_mix_exact, m)?)?;
+    m.add_function(wrap_pyfunction!(optimize_mix_v2, m)?)?;
+    m.add_function(wrap_pyfunction!(optimize_mix_exact_v2, m)?)?;
+    m.add_function(wrap_pyfunction!(optimize_mix_tracks, m)?)?;
+    m.add_function(wrap_pyfunction!(reoptimize_incremental, m)?)?;
+    m.add_class::<MixResult>()?;
+    m.add_class::<PyCostParams>()?;
+    m.add_class::<PyAnnealingParams>()?;
+    m.add_class::<MixOptimizer>()?;
+    m.add_function(wrap_pyfunction!(optimize_mix_fast, m)?)?;
+    m.add_function(wrap_pyfunction!(optimize_mix_segments, m)?)?;
+    m.add_function(wrap_pyfunction!(optimize_mix_shift_sweep, m)?)?;
+    m.add_function(wrap_pyfunction!(optimize_mix_pareto, m)?)?;
+    m.add_function(wrap_pyfunction!(optimize_mix_select, m)?)?;
+    m.add_function(wrap_pyfunction!(optimize_mix_hybrid, m)?)?;
+    m.add_function(wrap_pyfunction!(optimize_mix_repeat, m)?)?;
+    m.add_function(wrap_pyfunction!(estimate_iterations, m)?)?;
+    m.add_function(wrap_pyfunction!(optimize_mix_custom, m)?)?;
+    m.add_function(wrap_pyfunction!(score_orders, m)?)?;
+    m.add_function(wrap_pyfunction!(evaluate_order, m)?)?;
+    m.add_function(wrap_pyfunction!(per_track_costs, m)?)?;
+    m.add_function(wrap_pyfunction!(compare_orders, m)?)?;
+    m.add_function(wrap_pyfunction!(polish_2opt, m)?)?;
+    m.add_function(wrap_pyfunction!(transition_report, m)?)?;
+    m.add_function(wrap_pyfunction!(pairwise_best_costs, m)?)?;
+    m.add_function(wrap_pyfunction!(leave_one_out_costs, m)?)?;
+    m.add_function(wrap_pyfunction!(reverse_segment_delta, m)?)?;
+    m.add_function(wrap_pyfunction!(evaluate_swap, m)?)?;
+    m.add_function(wrap_pyfunction!(evaluate_move, m)?)?;
+    m.add_function(wrap_pyfunction!(evaluate_move_delta, m)?)?;
+    m.add_function(wrap_pyfunction!(suggest_insert_position, m)?)?;
+    m.add_function(wrap_pyfunction!(suggest_removal, m)?)?;
+    m.add_function(wrap_pyfunction!(explain_transition, m)?)?;
+    m.add_function(wrap_pyfunction!(random_instance, m)?)?;
+    m.add_function(wrap_pyfunction!(sparse_key_costs_to_dense, m)?)?;
+    m.add_function(wrap_pyfunction!(order_similarity, m)?)?;
+    m.add_function(wrap_pyfunction!(classify_transition, m)?)?;
+    m.add_function(wrap_pyfunction!(build_tables, m)?)?;
+    m.add_function(wrap_pyfunction!(key_to_id, m)?)?;
+    m.add_function(wrap_pyfunction!(id_to_key, m)?)?;
+    m.add_function(wrap_pyfunction!(keys_to_ids, m)?)?;
+    m.add_function(wrap_pyfunction!(export_schedule, m)?)?;
+    m.add_function(wrap_pyfunction!(shift_penalty_cost, m)?)?;
+    Ok(())
+}
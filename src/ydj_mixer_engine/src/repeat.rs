@@ -0,0 +1,96 @@
+//! Intentional repeats: let a track come back later in the set, e.g. a theme that returns
+//! in a long ambient mix.
+//!
+//! Every extra play becomes a virtual node, a copy of its track with the same BPMs, key and
+//! per-track weights (built like `cost::Subset`).  One separation grouping with a group per
+//! original track keeps the copies of a track at least `min_gap` other tracks apart; it is
+//! always hard, so a gap the plays cannot meet is rejected up front.  The annealer then
+//! orders the nodes like any other tracks, and `Repeats::tracks` maps the result back to
+//! original indices, duplicates included.
+
+use crate::annealing::{self, AnnealingParams, AttemptPanic, SaResult, StatsWeighting};
+use crate::cost::{CostParams, Subset, Tables};
+use crate::separation::{Grouping, Separation};
+
+/// Penalty per pair of copies closer than the gap; only steers a start that broke it.
+const REPEAT_PENALTY: f64 = 10.0;
+
+pub struct Repeats {
+    /// Original track of each node: tracks 0..n first, then one node per extra play.
+    pub track_of: Vec<usize>,
+    subset: Subset,
+    separation: Separation,
+}
+
+impl Repeats {
+    /// Nodes for `max_plays[i]` plays (at least 1) of each track i, copies at least
+    /// `min_gap` other tracks apart.
+    pub fn new(tables: &Tables, max_plays: &[usize], min_gap: usize) -> Result<Self, String> {
+        if let Some(i) = max_plays.iter().position(|&p| p == 0) {
+            return Err(format!("max_plays[{i}] is 0, expected at least 1"));
+        }
+        let n = max_plays.len();
+        let mut track_of: Vec<usize> = (0..n).collect();
+        for (i, &plays) in max_plays.iter().enumerate() {
+            track_of.extend(std::iter::repeat_n(i, plays - 1));
+        }
+        let grouping = Grouping::new(track_of.iter().map(|&i| i as u32).collect(), min_gap, REPEAT_PENALTY);
+        if !grouping.hard {
+            return Err(format!(
+                "{} plays cannot keep repeated tracks {min_gap} tracks apart", track_of.len()
+            ));
+        }
+        let mut separation = Separation::default();
+        separation.push(grouping);
+        Ok(Repeats { subset: Subset::new(tables, &track_of), track_of, separation })
+    }
+
+    /// Number of nodes: every play of every track.
+    pub fn node_count(&self) -> usize {
+        self.track_of.len()
+    }
+
+    /// Tables over the nodes, sharing `base`'s key tables and anchors.
+    pub fn tables<'a>(&'a self, base: &Tables<'a>) -> Tables<'a> {
+        let mut tables = self.subset.tables(base);
+        tables.anchors = base.anchors;
+        tables
+    }
+
+    /// Original track index of each node in `order`.
+    pub fn tracks(&self, order: &[usize]) -> Vec<usize> {
+        order.iter().map(|&v| self.track_of[v]).collect()
+    }
+}
+
+/// Result of `run`.
+pub struct RepeatResult {
+    /// Original track indices in play order, repeated tracks appearing once per play.
+    pub order: Vec<usize>,
+    /// Shift of each play (aligned with `order`): copies of a track may shift differently.
+    pub shifts: Vec<i8>,
+    /// Best result over the nodes (node-indexed shifts).
+    pub result: SaResult,
+    pub n_attempts: usize,
+}
+
+/// `annealing::run_timed` over the nodes of `repeats`.
+pub fn run(
+    repeats: &Repeats,
+    tables: &Tables,
+    cost_params: &CostParams,
+    ann_params: &AnnealingParams,
+    time_limit_secs: f64,
+) -> Result<RepeatResult, AttemptPanic> {
+    let nodes = repeats.tables(tables);
+    let (result, attempt_costs, _, _) = annealing::run_timed(
+        repeats.node_count(), &nodes, cost_params, ann_params, Some(&repeats.separation), None,
+        StatsWeighting::Uniform, time_limit_secs,
+    )?;
+    Ok(RepeatResult {
+        order: repeats.tracks(&result.best_order),
+        shifts: result.best_order.iter().map(|&v| result.best_shifts[v]).collect(),
+        result,
+        n_attempts: attempt_costs.len(),
+    })
+}
@@ -0,0 +1,429 @@
+//! Subset selection: choose which tracks of an `n`-track library to play, and in what order.
+//!
+//! The annealer keeps the selected order and the pool of unused tracks, and mixes five
+//! moves:
+//!   • swap two selected positions, as in `annealing::run_attempt`;
+//!   • replace: an unused track takes the place of a selected one;
+//!   • move in: drop the track at one position and insert an unused track at another;
+//!   • insert an unused track, or remove a selected one (only when the count may vary).
+//!
+//! The count is either fixed (`k`) or left free for a duration target to settle.  The
+//! objective is the usual cost of the selected order (edges, shift penalties, anchor edges)
+//! minus each selected track's inclusion value, a bonus that keeps good tracks from being
+//! dropped just because they are hard to place, plus the duration penalty (see
+//! `DurationTarget`).
+//!
+//! Required tracks (a must-play core of requests or the DJ's own releases) are selected
+//! from the start and never leave: only optional tracks are replaced or dropped, so they
+//! serve as fillers that smooth the transitions between required ones.  Separation
+//! groupings, family-run limits, shifted-track caps and stability penalties are not
+//! applied.  There is no exact solver: choosing k of n multiplies the Held-Karp state space
+//! by C(n, k).
+
+use rand::distr::weighted::WeightedIndex;
+use rand::prelude::*;
+use rand::rng;
+
+use crate::annealing::{compute_per_track_costs, AnnealingParams, MoveStats, SaResult};
+use crate::cost::{affected_edges, optimize_shift_at, sum_edge_costs, total_edge_cost, CostParams, Tables};
+
+/// Target length of the selected set, e.g. 60 ± 3 minutes.
+pub struct DurationTarget<'a> {
+    /// Length of each track in seconds (track-indexed).
+    pub durations: &'a [f64],
+    /// Seconds each track overlaps the next while they blend (track-indexed), subtracted
+    /// once per transition; `None` plays the tracks back to back.
+    pub overlap: Option<&'a [f64]>,
+    pub target: f64,
+    pub tolerance: f64,
+    /// Cost per second the set runs outside target ± tolerance.
+    pub penalty: f64,
+    /// Reject every move that takes the set further outside the window, as with hard
+    /// separation groupings; the penalty then only steers an outlying start into it.
+    pub hard: bool,
+}
+
+impl DurationTarget<'_> {
+    /// Length of track `i` less its overlap into the next track.
+    fn net(&self, i: usize) -> f64 {
+        self.durations[i] - self.overlap.map_or(0.0, |o| o[i])
+    }
+
+    /// Total of `net` over a set plus the overlap of its last track, which has no next one.
+    fn total_from(&self, net_sum: f64, last: usize) -> f64 {
+        net_sum + self.overlap.map_or(0.0, |o| o[last])
+    }
+
+    /// Total length of `order` in seconds: every track's duration less one overlap per
+    /// transition.
+    pub fn total(&self, order: &[usize]) -> f64 {
+        order.last().map_or(0.0, |&last| self.total_from(order.iter().map(|&i| self.net(i)).sum(), last))
+    }
+
+    /// Seconds a set of length `total` lies outside target ± tolerance.
+    pub fn excess(&self, total: f64) -> f64 {
+        ((total - self.target).abs() - self.tolerance).max(0.0)
+    }
+}
+
+/// Best subset found by `run`.
+pub struct Selection {
+    /// `best_order` holds the selected tracks in play order and `best_cost` is net of their
+    /// inclusion values, plus any duration penalty; `best_shifts` is indexed by track index,
+    /// 0 for excluded tracks.
+    pub result: SaResult,
+    /// Tracks left out, in increasing order.
+    pub excluded: Vec<usize>,
+    /// Optional tracks selected, in play order, each with its average adjacent-edge cost
+    /// (as in the per-track stats): what it costs to play there.
+    pub fillers: Vec<(usize, f64)>,
+    /// Total length of the selected order in seconds, with a duration target.
+    pub duration: Option<f64>,
+    pub n_attempts: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Swap,
+    Replace,
+    MoveIn,
+    Insert,
+    Remove,
+}
+
+/// Edge positions among `candidates` that exist in an order of length `len`, written to
+/// `out`; returns how many.  Candidates must be distinct.
+fn valid_edges(candidates: &[Option<usize>], len: usize, out: &mut [usize; 4]) -> usize {
+    let mut count = 0;
+    for j in candidates.iter().flatten().filter(|&&j| j + 1 < len) {
+        out[count] = *j;
+        count += 1;
+    }
+    count
+}
+
+/// Run a single subset-selection attempt over tracks 0..n.
+///
+/// `k` fixes the number of tracks played (1 ≤ k ≤ n); `None` lets it vary between 2 (or
+/// the number of required tracks) and n, for a `duration` target to settle.
+/// `inclusion_value` (track-indexed) is subtracted from the cost for every selected track;
+/// `None` values every track at 0.  `required` (track-indexed) marks tracks that must be
+/// selected, at most `k` of them; `None` makes every track optional.
+pub fn run_attempt(
+    n: usize,
+    k: Option<usize>,
+    tables: &Tables,
+    cost_params: &CostParams,
+    ann_params: &AnnealingParams,
+    inclusion_value: Option<&[f64]>,
+    required: Option<&[bool]>,
+    duration: Option<&DurationTarget>,
+    rng: &mut impl Rng,
+) -> SaResult {
+    let value = |i: usize| inclusion_value.map_or(0.0, |v| v[i]);
+    let is_required = |i: usize| required.is_some_and(|r| r[i]);
+    let duration_cost = |total: f64| duration.map_or(0.0, |d| d.penalty * d.excess(total));
+
+    // Random initial subset (every required track plus random optional ones), order and
+    // shifts; the stable sort keeps the shuffle within each kind.  Without a fixed count,
+    // optional tracks are added until the target length is reached.
+    let mut order: Vec<usize> = (0..n).collect();
+    order.shuffle(rng);
+    order.sort_by_key(|&i| !is_required(i));
+    let min_len = order.iter().take_while(|&&i| is_required(i)).count().max(2).min(n);
+    let len = match (k, duration) {
+        (Some(k), _) => k,
+        (None, Some(d)) => {
+            let mut len = 0;
+            let mut total = 0.0;
+            while len < n && (len < min_len || total < d.target) {
+                total += d.net(order[len]);
+                len += 1;
+            }
+            len
+        }
+        (None, None) => n,
+    };
+    let mut unused = order.split_off(len);
+    order.shuffle(rng);
+    let init = WeightedIndex::new(ann_params.shift_init_weights)
+        .expect("shift_init_weights must be non-negative and not all zero");
+    let mut shifts: Vec<i8> = (0..n).map(|_| init.sample(rng) as i8 - 1).collect();
+    let mut net_sum: f64 = duration.map_or(0.0, |d| order.iter().map(|&i| d.net(i)).sum());
+    let mut optional = order.iter().filter(|&&i| !is_required(i)).count();
+    // With a fixed count and every selected track required, only swaps remain
+    let fixed_subset = k.is_some() && (unused.is_empty() || optional == 0);
+
+    let (h0, t0, s0) = total_edge_cost(&order, &shifts, tables, cost_params);
+    let mut best_cost = h0 + cost_params.tempo_cost_weight * t0 + cost_params.shift_weight * s0
+        + tables.boundary_cost(&order, &shifts, cost_params)
+        - order.iter().map(|&i| value(i)).sum::<f64>()
+        + duration.map_or(0.0, |d| duration_cost(d.total(&order)));
+    let mut best_order = order.clone();
+    let mut best_unused = unused.clone();
+    let mut best_shifts = shifts.clone();
+    let mut best_net_sum = net_sum;
+    let mut best_optional = optional;
+    let (mut h_best, mut t_best, mut s_best) = (h0, t0, s0);
+
+    let mut current_cost = best_cost;
+    let cooling = ann_params.cooling_factor_exp();
+    let mut temp = ann_params.initial_temp;
+    let num_candidates = ann_params.multi_swap_factor * len;
+
+    let mut in_escape_mode = false;
+    let mut escape_counter: usize = 0;
+
+    let mut old_edges = [0usize; 4];
+    let mut new_edges = [0usize; 4];
+
+    for _ in 0..ann_params.total_iterations {
+        if !in_escape_mode {
+            // Reset to best known state
+            order.clone_from(&best_order);
+            unused.clone_from(&best_unused);
+            shifts.copy_from_slice(&best_shifts);
+            net_sum = best_net_sum;
+            optional = best_optional;
+            current_cost = best_cost;
+        }
+
+        // Pick a feasible move: replace, move in and remove drop an optional track, and the
+        // last two kinds only run when the count may vary
+        const MOVES: [Kind; 5] = [Kind::Swap, Kind::Replace, Kind::MoveIn, Kind::Insert, Kind::Remove];
+        let kind = match (fixed_subset, k) {
+            (true, _) => Kind::Swap,
+            (false, Some(_)) => MOVES[rng.random_range(0..3)],
+            (false, None) => MOVES[rng.random_range(0..5)],
+        };
+        let len = order.len();
+        let feasible = match kind {
+            Kind::Swap => len >= 2,
+            Kind::Replace => !unused.is_empty() && optional > 0,
+            Kind::MoveIn => !unused.is_empty() && optional > 0 && len >= 2,
+            Kind::Insert => !unused.is_empty(),
+            Kind::Remove => optional > 0 && len > min_len,
+        };
+        if !feasible {
+            temp *= cooling;
+            continue;
+        }
+        let mut a = rng.random_range(0..len);
+        while kind != Kind::Swap && kind != Kind::Insert && is_required(order[a]) {
+            a = rng.random_range(0..len);
+        }
+        let b = match kind {
+            Kind::Swap | Kind::MoveIn => {
+                let b = rng.random_range(0..len - 1);
+                if b >= a { b + 1 } else { b }
+            }
+            Kind::Insert => rng.random_range(0..=len),
+            _ => a,
+        };
+        let u = match kind {
+            Kind::Replace | Kind::MoveIn | Kind::Insert => rng.random_range(0..unused.len()),
+            _ => 0,
+        };
+
+        // Duration before and after, from the tracks that enter or leave and the new last
+        // track; a hard window vetoes the move before anything changes
+        let duration_delta = match duration {
+            Some(d) => {
+                let p = len - 1;
+                let new_last = match kind {
+                    Kind::Swap if a == p => order[b],
+                    Kind::Swap if b == p => order[a],
+                    Kind::Replace if a == p => unused[u],
+                    Kind::MoveIn if b == p => unused[u],
+                    Kind::MoveIn | Kind::Remove if a == p => order[p - 1],
+                    Kind::Insert if b == len => unused[u],
+                    _ => order[p],
+                };
+                let new_net_sum = match kind {
+                    Kind::Swap => net_sum,
+                    Kind::Replace | Kind::MoveIn => net_sum + d.net(unused[u]) - d.net(order[a]),
+                    Kind::Insert => net_sum + d.net(unused[u]),
+                    Kind::Remove => net_sum - d.net(order[a]),
+                };
+                let old_total = d.total_from(net_sum, order[p]);
+                let new_total = d.total_from(new_net_sum, new_last);
+                if d.hard && d.excess(new_total) > d.excess(old_total) {
+                    temp *= cooling;
+                    continue;
+                }
+                net_sum = new_net_sum;
+                duration_cost(new_total) - duration_cost(old_total)
+            }
+            None => 0.0,
+        };
+
+        // Edges that change, by position before and after the move
+        let (num_old, num_new) = match kind {
+            Kind::MoveIn if a < b => (
+                valid_edges(&[a.checked_sub(1), Some(a), Some(b)], len, &mut old_edges),
+                valid_edges(&[a.checked_sub(1), Some(b - 1), Some(b)], len, &mut new_edges),
+            ),
+            Kind::MoveIn => (
+                valid_edges(&[b.checked_sub(1), Some(a - 1), Some(a)], len, &mut old_edges),
+                valid_edges(&[b.checked_sub(1), Some(b), Some(a)], len, &mut new_edges),
+            ),
+            Kind::Insert => (
+                valid_edges(&[b.checked_sub(1)], len, &mut old_edges),
+                valid_edges(&[b.checked_sub(1), Some(b)], len + 1, &mut new_edges),
+            ),
+            Kind::Remove => (
+                valid_edges(&[a.checked_sub(1), Some(a)], len, &mut old_edges),
+                valid_edges(&[a.checked_sub(1)], len - 1, &mut new_edges),
+            ),
+            Kind::Swap | Kind::Replace => {
+                let num = affected_edges(a, b, len, &mut old_edges);
+                new_edges = old_edges;
+                (num, num)
+            }
+        };
+        // Positions whose track (and so node cost and shift) changes, before and after
+        let (old_pos, new_pos): (&[usize], &[usize]) = match kind {
+            Kind::Swap => (&[a, b], &[a, b]),
+            Kind::Replace => (&[a], &[a]),
+            Kind::MoveIn => (&[a], &[b]),
+            Kind::Insert => (&[], &[b]),
+            Kind::Remove => (&[a], &[]),
+        };
+
+        let node_costs = |order: &[usize], shifts: &[i8], pos: &[usize]| -> f64 {
+            pos.iter().map(|&p| tables.node_cost(order[p], shifts[order[p]], cost_params)).sum()
+        };
+        let old_cost = sum_edge_costs(&old_edges[..num_old], &order, &shifts, tables, cost_params)
+            + tables.boundary_cost(&order, &shifts, cost_params)
+            + node_costs(&order, &shifts, old_pos);
+
+        // Perform the move
+        let value_delta = match kind {
+            Kind::Swap => {
+                order.swap(a, b);
+                0.0
+            }
+            Kind::Replace => {
+                std::mem::swap(&mut order[a], &mut unused[u]);
+                value(unused[u]) - value(order[a])
+            }
+            Kind::MoveIn => {
+                let out = order[a];
+                if a < b {
+                    order[a..=b].rotate_left(1);
+                } else {
+                    order[b..=a].rotate_right(1);
+                }
+                order[b] = unused[u];
+                unused[u] = out;
+                value(out) - value(order[b])
+            }
+            Kind::Insert => {
+                order.insert(b, unused.swap_remove(u));
+                optional += 1;
+                -value(order[b])
+            }
+            Kind::Remove => {
+                let out = order.remove(a);
+                unused.push(out);
+                optional -= 1;
+                value(out)
+            }
+        };
+        for &p in new_pos {
+            optimize_shift_at(&order, &mut shifts, p, tables, cost_params);
+        }
+
+        let new_cost = sum_edge_costs(&new_edges[..num_new], &order, &shifts, tables, cost_params)
+            + tables.boundary_cost(&order, &shifts, cost_params)
+            + node_costs(&order, &shifts, new_pos);
+        let candidate_cost = current_cost + (new_cost - old_cost) + value_delta + duration_delta;
+
+        if candidate_cost < best_cost {
+            best_order.clone_from(&order);
+            best_unused.clone_from(&unused);
+            best_shifts.copy_from_slice(&shifts);
+            best_net_sum = net_sum;
+            best_optional = optional;
+            best_cost = candidate_cost;
+            current_cost = candidate_cost;
+            in_escape_mode = false;
+            let (h, t, s) = total_edge_cost(&best_order, &best_shifts, tables, cost_params);
+            h_best = h;
+            t_best = t;
+            s_best = s;
+        } else if in_escape_mode {
+            current_cost = candidate_cost;
+            escape_counter += 1;
+            if escape_counter > num_candidates {
+                in_escape_mode = false;
+                escape_counter = 0;
+            }
+        } else {
+            let delta = best_cost - candidate_cost; // negative (candidate is worse)
+            if (delta / temp).exp() > rng.random::<f64>() {
+                in_escape_mode = true;
+                escape_counter = 0;
+                current_cost = candidate_cost;
+            }
+        }
+
+        temp *= cooling;
+    }
+
+    for &i in &best_unused {
+        best_shifts[i] = 0;
+    }
+    SaResult {
+        best_order,
+        best_shifts,
+        best_cost,
+        h_cost: h_best,
+        t_cost: t_best,
+        s_cost: s_best,
+        violations: Vec::new(),
+        moves: MoveStats::default(),
+    }
+}
+
+/// Run selection attempts until the time budget (seconds) is exhausted, at least one, and
+/// return the best.
+pub fn run(
+    n: usize,
+    k: Option<usize>,
+    tables: &Tables,
+    cost_params: &CostParams,
+    ann_params: &AnnealingParams,
+    inclusion_value: Option<&[f64]>,
+    required: Option<&[bool]>,
+    duration: Option<&DurationTarget>,
+    time_limit_secs: f64,
+) -> Selection {
+    let start = std::time::Instant::now();
+    let mut rng = rng();
+    let mut best: Option<SaResult> = None;
+    let mut n_attempts = 0;
+    while n_attempts == 0 || start.elapsed().as_secs_f64() < time_limit_secs {
+        let result =
+            run_attempt(n, k, tables, cost_params, ann_params, inclusion_value, required, duration, &mut rng);
+        n_attempts += 1;
+        if best.as_ref().is_none_or(|b| result.best_cost < b.best_cost) {
+            best = Some(result);
+        }
+    }
+    let result = best.unwrap();
+    let mut selected = vec![false; n];
+    for &i in &result.best_order {
+        selected[i] = true;
+    }
+    let excluded = (0..n).filter(|&i| !selected[i]).collect();
+    let track_costs = compute_per_track_costs(n, &result.best_order, &result.best_shifts, tables, cost_params);
+    let fillers = result
+        .best_order
+        .iter()
+        .filter(|&&i| !required.is_some_and(|r| r[i]))
+        .map(|&i| (i, track_costs[i]))
+        .collect();
+    let duration = duration.map(|d| d.total(&result.best_order));
+    Selection { result, excluded, fillers, duration, n_attempts }
+}
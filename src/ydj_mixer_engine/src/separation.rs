@@ -0,0 +1,263 @@
+//! Grouping separation constraints: no two tracks of the same group within `min_gap` positions.
+//!
+//! A grouping assigns every track a group ID (artist, album, label, ...).  Two tracks at
+//! positions p < q violate it when they share a group and `q - p <= min_gap`.
+//! `min_gap = 1` forbids back-to-back repeats, `min_gap = 3` requires at least three other
+//! tracks between repeats, and `min_gap = 0` disables the grouping.
+//!
+//! A grouping is hard when a violation-free ordering exists for it on its own: the SA then
+//! rejects any move that increases its violation count.  Every grouping also charges
+//! `penalty` per violating pair, which is the whole story for infeasible (penalty-mode)
+//! groupings and steers a hard grouping back to zero if the start order was not clean.
+//!
+//! `Separation` also carries the tempo coverage rules (see `coverage.rs`), the other
+//! positional constraint the annealer enforces by rejection, and the owner alternation of
+//! back-to-back sets (see `alternation.rs`), a soft term; Held-Karp ignores both.
+
+use std::collections::HashMap;
+
+use rand::prelude::*;
+
+use crate::alternation::Alternation;
+use crate::coverage::Coverage;
+
+pub struct Grouping {
+    pub group_ids: Vec<u32>,
+    pub min_gap: usize,
+    pub penalty: f64,
+    /// True when a violation-free ordering exists (hard mode); false means penalty mode.
+    pub hard: bool,
+}
+
+impl Grouping {
+    pub fn new(group_ids: Vec<u32>, min_gap: usize, penalty: f64) -> Self {
+        let mut counts: HashMap<u32, usize> = HashMap::new();
+        for &g in &group_ids {
+            *counts.entry(g).or_default() += 1;
+        }
+        // A violation-free ordering exists iff (m - 1) * (min_gap + 1) + c <= n, where m is the
+        // largest group size and c the number of groups of that size.
+        let m = counts.values().copied().max().unwrap_or(0);
+        let c = counts.values().filter(|&&v| v == m).count();
+        let hard = m == 0 || (m - 1) * (min_gap + 1) + c <= group_ids.len();
+        Grouping { group_ids, min_gap, penalty, hard }
+    }
+
+    /// Spacing rule for a few tracks to spread through the set (must-play peaks): `tracks`
+    /// share one group, every other track has its own, and no two of them may be fewer than
+    /// `min_spacing` positions apart (`min_gap = min_spacing - 1`).  An error for tracks out
+    /// of range or repeated, or too many to fit `n` positions at that spacing, so the rule
+    /// is always hard.
+    pub fn spread(n: usize, tracks: &[usize], min_spacing: usize, penalty: f64) -> Result<Self, String> {
+        let mut group_ids: Vec<u32> = (1..=n as u32).collect();
+        for &t in tracks {
+            if t >= n {
+                return Err(format!("track {t} is out of range for {n} tracks"));
+            }
+            if group_ids[t] == 0 {
+                return Err(format!("track {t} is listed more than once"));
+            }
+            group_ids[t] = 0;
+        }
+        let needed = tracks.len().saturating_sub(1) * min_spacing + 1;
+        if tracks.len() > 1 && needed > n {
+            return Err(format!(
+                "{} tracks {min_spacing} positions apart need at least {needed} positions, there are {n}",
+                tracks.len()
+            ));
+        }
+        Ok(Grouping::new(group_ids, min_spacing.saturating_sub(1), penalty))
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.min_gap > 0
+    }
+
+    pub fn mode(&self) -> &'static str {
+        if self.hard { "hard" } else { "penalty" }
+    }
+
+    /// Total number of violating pairs in the given ordering.
+    pub fn violations(&self, order: &[usize]) -> usize {
+        let mut count = 0;
+        for q in 1..order.len() {
+            let lo = q.saturating_sub(self.min_gap);
+            let gq = self.group_ids[order[q]];
+            count += order[lo..q].iter().filter(|&&i| self.group_ids[i] == gq).count();
+        }
+        count
+    }
+
+    /// Fewest positions between two tracks of one group in `order` (1 = back to back),
+    /// `None` when no group has two tracks in it.
+    pub fn closest_repeat(&self, order: &[usize]) -> Option<usize> {
+        let mut last: HashMap<u32, usize> = HashMap::new();
+        let mut closest = None;
+        for (q, &t) in order.iter().enumerate() {
+            if let Some(p) = last.insert(self.group_ids[t], q) {
+                closest = Some(closest.map_or(q - p, |c: usize| c.min(q - p)));
+            }
+        }
+        closest
+    }
+
+    /// Number of violating pairs involving position `pos` (window of `min_gap` on each side).
+    fn violations_at(&self, order: &[usize], pos: usize) -> usize {
+        let lo = pos.saturating_sub(self.min_gap);
+        let hi = (pos + self.min_gap).min(order.len() - 1);
+        let gp = self.group_ids[order[pos]];
+        (lo..=hi)
+            .filter(|&q| q != pos && self.group_ids[order[q]] == gp)
+            .count()
+    }
+
+    /// Number of violating pairs involving position `a` or `b` (each pair counted once).
+    ///
+    /// Comparing this before and after swapping `a` and `b` gives the exact change in
+    /// `violations`, since pairs not touching either position are unaffected by the swap.
+    pub fn local_violations(&self, order: &[usize], a: usize, b: usize) -> usize {
+        let shared = if a.abs_diff(b) <= self.min_gap
+            && self.group_ids[order[a]] == self.group_ids[order[b]]
+        {
+            1
+        } else {
+            0
+        };
+        self.violations_at(order, a) + self.violations_at(order, b) - shared
+    }
+
+    /// Whether appending `track` to `prefix` would violate this grouping.
+    fn clashes(&self, prefix: &[usize], track: usize) -> bool {
+        let lo = prefix.len().saturating_sub(self.min_gap);
+        let g = self.group_ids[track];
+        prefix[lo..].iter().any(|&i| self.group_ids[i] == g)
+    }
+}
+
+/// All active groupings, and any coverage rules and owner alternation, of one
+/// optimisation call.
+#[derive(Default)]
+pub struct Separation {
+    pub groupings: Vec<Grouping>,
+    pub coverage: Option<Coverage>,
+    pub alternation: Option<Alternation>,
+}
+
+impl Separation {
+    pub fn push(&mut self, grouping: Grouping) {
+        if grouping.is_active() {
+            self.groupings.push(grouping);
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.groupings.is_empty() || self.coverage.is_some() || self.alternation.is_some()
+    }
+
+    /// Whether any grouping (not coverage rule) is hard, so the start order must respect it.
+    pub fn any_hard(&self) -> bool {
+        self.groupings.iter().any(|g| g.hard)
+    }
+
+    /// Violation count per grouping for the given ordering.
+    pub fn violations(&self, order: &[usize]) -> Vec<usize> {
+        self.groupings.iter().map(|g| g.violations(order)).collect()
+    }
+
+    /// Weighted penalty of all violations, coverage shortfalls and owner-run excess in the
+    /// given ordering.
+    pub fn penalty(&self, order: &[usize]) -> f64 {
+        self.groupings
+            .iter()
+            .map(|g| g.penalty * g.violations(order) as f64)
+            .sum::<f64>()
+            + self.coverage.as_ref().map_or(0.0, |c| c.penalty(order))
+            + self.alternation.as_ref().map_or(0.0, |alt| alt.penalty(order))
+    }
+
+    /// Penalty delta for swapping positions `a` and `b`, or `None` when the swap would add a
+    /// violation to a hard grouping or a shortfall to a hard coverage rule.  Positions are
+    /// swapped and restored in place.
+    pub fn swap_delta(&self, order: &mut [usize], a: usize, b: usize) -> Option<f64> {
+        let mut delta = 0.0;
+        for g in &self.groupings {
+            let old = g.local_violations(order, a, b);
+            order.swap(a, b);
+            let new = g.local_violations(order, a, b);
+            order.swap(a, b);
+            if g.hard && new > old {
+                return None;
+            }
+            delta += g.penalty * (new as f64 - old as f64);
+        }
+        if let Some(alt) = &self.alternation {
+            delta += alt.swap_delta(order, a, b);
+        }
+        match &self.coverage {
+            Some(c) => c.swap_delta(order, a, b).map(|d| delta + d),
+            None => Some(delta),
+        }
+    }
+
+    /// Penalty for placing `a` directly before `b` (adjacent pairs only, as used by Held-Karp).
+    pub fn adjacent_penalty(&self, a: usize, b: usize) -> f64 {
+        self.groupings
+            .iter()
+            .filter(|g| g.group_ids[a] == g.group_ids[b])
+            .map(|g| g.penalty)
+            .sum()
+    }
+
+    /// Build a random starting order that respects the hard groupings where possible.
+    ///
+    /// Greedy: at each position pick, among tracks that do not clash with a hard grouping,
+    /// one from the most crowded group (ties broken randomly).  For a single grouping this
+    /// is optimal for the feasibility condition in `Grouping::new`; with several groupings
+    /// it can get stuck, in which case a random remaining track is placed and the penalty
+    /// term takes over.
+    pub fn initial_order(&self, n: usize, rng: &mut impl Rng) -> Vec<usize> {
+        let hard: Vec<&Grouping> = self.groupings.iter().filter(|g| g.hard).collect();
+        // Shuffled so that picking the first best-scoring track breaks ties randomly.
+        let mut remaining: Vec<usize> = (0..n).collect();
+        remaining.shuffle(rng);
+        // Remaining tracks per group, per hard grouping.
+        let mut left: Vec<HashMap<u32, usize>> = hard
+            .iter()
+            .map(|g| {
+                let mut m = HashMap::new();
+                for &gid in &g.group_ids {
+                    *m.entry(gid).or_default() += 1;
+                }
+                m
+            })
+            .collect();
+
+        let mut order = Vec::with_capacity(n);
+        while !remaining.is_empty() {
+            let mut best_k = None;
+            let mut best_score = 0usize;
+            for (k, &t) in remaining.iter().enumerate() {
+                if hard.iter().any(|g| g.clashes(&order, t)) {
+                    continue;
+                }
+                let score = hard
+                    .iter()
+                    .zip(&left)
+                    .map(|(g, m)| m[&g.group_ids[t]])
+                    .max()
+                    .unwrap_or(0);
+                if best_k.is_none() || score > best_score {
+                    best_k = Some(k);
+                    best_score = score;
+                }
+            }
+            let k = best_k.unwrap_or_else(|| rng.random_range(0..remaining.len()));
+            let t = remaining.swap_remove(k);
+            for (g, m) in hard.iter().zip(left.iter_mut()) {
+                *m.get_mut(&g.group_ids[t]).unwrap() -= 1;
+            }
+            order.push(t);
+        }
+        order
+    }
+}
@@ -0,0 +1,73 @@
+//! Similarity between two orders of the same tracks, e.g. an optimiser result against a
+//! DJ's hand-made order, or the outputs of two solver configurations.
+//!
+//! Two measures:
+//!   • Kendall-tau distance: the number of track pairs the two orders play in opposite
+//!     order (0 = identical, n(n-1)/2 = reversed), counted in O(n log n);
+//!   • shared adjacencies: the number of transitions a → b (a directly before b) present
+//!     in both orders.  Direction matters, since a → b and b → a are different blends.
+
+use std::collections::HashMap;
+
+/// Kendall-tau distance and shared adjacencies of two orders over the same track IDs.
+pub struct Similarity {
+    pub kendall_tau: usize,
+    pub shared_adjacencies: usize,
+}
+
+/// Compare `a` and `b`, which must be permutations of the same set of track IDs.
+pub fn compare(a: &[usize], b: &[usize]) -> Result<Similarity, String> {
+    if a.len() != b.len() {
+        return Err(format!("orders have different lengths ({} and {})", a.len(), b.len()));
+    }
+    let mut pos_in_b = HashMap::with_capacity(b.len());
+    for (p, &t) in b.iter().enumerate() {
+        if pos_in_b.insert(t, p).is_some() {
+            return Err(format!("track {t} appears twice in the second order"));
+        }
+    }
+    // Positions in `b` of the tracks of `a`, in `a`'s order: discordant pairs are inversions
+    let mut ranks = Vec::with_capacity(a.len());
+    let mut seen = vec![false; b.len()];
+    for &t in a {
+        let p = *pos_in_b
+            .get(&t)
+            .ok_or_else(|| format!("track {t} is in the first order but not the second"))?;
+        if std::mem::replace(&mut seen[p], true) {
+            return Err(format!("track {t} appears twice in the first order"));
+        }
+        ranks.push(p);
+    }
+
+    // a[i] → a[i+1] is shared when b plays them back to back in the same direction
+    let shared_adjacencies = ranks.windows(2).filter(|w| w[1] == w[0] + 1).count();
+    let kendall_tau = count_inversions(&mut ranks);
+    Ok(Similarity { kendall_tau, shared_adjacencies })
+}
+
+/// Number of pairs i < j with v[i] > v[j], by merge sort (sorts `v`).
+fn count_inversions(v: &mut [usize]) -> usize {
+    let n = v.len();
+    if n < 2 {
+        return 0;
+    }
+    let (left, right) = v.split_at_mut(n / 2);
+    let mut count = count_inversions(left) + count_inversions(right);
+    let mut merged = Vec::with_capacity(n);
+    let (mut i, mut j) = (0, 0);
+    while i < left.len() && j < right.len() {
+        if left[i] <= right[j] {
+            merged.push(left[i]);
+            i += 1;
+        } else {
+            // right[j] jumps every remaining element of `left`
+            count += left.len() - i;
+            merged.push(right[j]);
+            j += 1;
+        }
+    }
+    merged.extend_from_slice(&left[i..]);
+    merged.extend_from_slice(&right[j..]);
+    v.copy_from_slice(&merged);
+    count
+}
@@ -0,0 +1,102 @@
+//! Deterministic random instances for tests and benchmarks.
+//!
+//! `random_instance(n, seed)` builds a valid 24-key Camelot instance (key id =
+//! (number - 1) * 2 + letter, with A = 0 and B = 1) from a seeded RNG, so the Rust
+//! integration tests and the Python tests can run on the same inputs.  Instances are
+//! stable for a given seed within one build of the crate.
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+use crate::cost::{self, CostParams, ObjectiveMode, Tables};
+
+pub const NUM_KEYS: usize = 24;
+
+pub struct Instance {
+    pub bpms: Vec<i32>,
+    pub key_ids: Vec<u8>,
+    pub shift_table: Vec<u8>,
+    pub direct_costs: Vec<f64>,
+    pub indirect_costs: Vec<f64>,
+}
+
+impl Instance {
+    pub fn n(&self) -> usize {
+        self.bpms.len()
+    }
+
+    pub fn tables(&self) -> Tables<'_> {
+        Tables::new(
+            &self.bpms, &self.key_ids, &self.shift_table, &self.direct_costs, &self.indirect_costs,
+        )
+    }
+}
+
+/// One semitone moves 7 positions around the wheel; the letter is unchanged.
+pub fn shift_table() -> Vec<u8> {
+    cost::camelot_shift_table()
+}
+
+/// Direct: same key 0, neighbouring number or relative key 1, otherwise `non_harmonic`.
+/// Indirect: two steps round the wheel 2, otherwise `non_harmonic`.
+pub fn cost_tables(non_harmonic: f64) -> (Vec<f64>, Vec<f64>) {
+    let mut direct = vec![non_harmonic; NUM_KEYS * NUM_KEYS];
+    let mut indirect = vec![non_harmonic; NUM_KEYS * NUM_KEYS];
+    for a in 0..NUM_KEYS {
+        for b in 0..NUM_KEYS {
+            let (na, la) = ((a / 2) as i32, a % 2);
+            let (nb, lb) = ((b / 2) as i32, b % 2);
+            let steps = (na - nb).rem_euclid(12).min((nb - na).rem_euclid(12));
+            let idx = a * NUM_KEYS + b;
+            if a == b {
+                direct[idx] = 0.0;
+            } else if (la == lb && steps == 1) || (la != lb && steps == 0) {
+                direct[idx] = 1.0;
+            } else if la == lb && steps == 2 {
+                indirect[idx] = 2.0;
+            }
+        }
+    }
+    (direct, indirect)
+}
+
+/// Random instance of `n` tracks: BPMs in 110..=132 and uniformly random keys.
+pub fn random_instance(n: usize, seed: u64) -> Instance {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let (direct_costs, indirect_costs) = cost_tables(cost_params().non_harmonic_cost);
+    Instance {
+        bpms: (0..n).map(|_| rng.random_range(110..=132)).collect(),
+        key_ids: (0..n).map(|_| rng.random_range(0..NUM_KEYS as u8)).collect(),
+        shift_table: shift_table(),
+        direct_costs,
+        indirect_costs,
+    }
+}
+
+/// Cost parameters matching the instances (the mixer's defaults).
+pub fn cost_params() -> CostParams {
+    CostParams {
+        tempo_threshold: 4.5,
+        tempo_penalty: 5.0,
+        tempo_break_factor: 2.0,
+        tempo_penalty_slope: 0.0,
+        tempo_cost_weight: 1.0,
+        non_harmonic_cost: 5.0,
+        shift_penalty: 1.0,
+        shift_penalty_by_direction: None,
+        shift_weight: 1.0,
+        num_keys: NUM_KEYS,
+        harmonic_across_breaks: false,
+        harmonic_only: false,
+        perceptual_tempo: false,
+        confidence_product: false,
+        cut_penalty: None,
+        cut_harmonic_discount: 0.0,
+        objective_mode: ObjectiveMode::Weighted,
+        perfect_threshold: 1e-9,
+        family_run: None,
+        max_shifted: None,
+        max_shift_cost: None,
+        minmax_blend: 0.0,
+    }
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+use common::{annealing_params, cost_params, instance};
+use ydj_mixer_engine::alternation::Alternation;
+use ydj_mixer_engine::annealing::{run_attempt, AnnealingParams};
+use ydj_mixer_engine::separation::Separation;
+
+#[test]
+fn runs_and_swap_delta_match_recount() {
+    let alt = Alternation::new(vec![0, 0, 0, 1, 1, 2, 0, 1], 2, 3.0);
+    let order = [0, 1, 2, 3, 4, 5, 6, 7];
+    assert_eq!(alt.pattern(&order), [0, 0, 0, 1, 1, 2, 0, 1]);
+    assert_eq!((alt.longest_run(&order), alt.excess(&order)), (3, 1));
+    assert_eq!(alt.penalty(&order), 3.0);
+
+    let mut rng = StdRng::seed_from_u64(4);
+    let owners: Vec<u8> = (0..20).map(|_| rng.random_range(0..3)).collect();
+    let alt = Alternation::new(owners, 1, 2.5);
+    let mut order: Vec<usize> = (0..20).collect();
+    for _ in 0..500 {
+        order.shuffle(&mut rng);
+        let (a, b) = (rng.random_range(0..20), rng.random_range(0..20));
+        let mut swapped = order.clone();
+        swapped.swap(a, b);
+        let delta = alt.penalty(&swapped) - alt.penalty(&order);
+        assert!((alt.swap_delta(&mut order, a, b) - delta).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn alternation_weight_interleaves_two_crates() {
+    let inst = instance(16, 9);
+    let tables = inst.tables();
+    let ap = AnnealingParams { total_iterations: 20000, ..annealing_params() };
+    let owners: Vec<u8> = (0..16).map(|i| (i < 8) as u8).collect();
+    let sep = Separation { alternation: Some(Alternation::new(owners, 1, 10.0)), ..Default::default() };
+    let alt = sep.alternation.as_ref().unwrap();
+    for seed in 0..3 {
+        let free = run_attempt(inst.n(), &tables, &cost_params(), &ap, None, None, &mut StdRng::seed_from_u64(seed));
+        let mut rng = StdRng::seed_from_u64(seed);
+        let r = run_attempt(inst.n(), &tables, &cost_params(), &ap, Some(&sep), None, &mut rng);
+        // Swaps alone cannot always shift a half-alternated order by one place, so allow
+        // a single doubled handover
+        assert!(alt.excess(&r.best_order) <= 1, "{:?}", alt.pattern(&r.best_order));
+        assert!(alt.excess(&r.best_order) < alt.excess(&free.best_order));
+        let plain = common::objective(&r.best_order, &r.best_shifts, &tables, &cost_params());
+        assert!((r.best_cost - plain - alt.penalty(&r.best_order)).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,527 @@
+mod common;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use common::{annealing_params, cost_params, instance, is_permutation, objective};
+use ydj_mixer_engine::annealing::{
+    compute_per_position_costs, compute_per_track_costs, compute_track_costs, distinct_costs, run_attempt, run_attempt_in, run_attempt_recorded, run_attempt_with_moves, run_capped, run_pareto, run_seeded, run_segments, run_shift_sweep,
+    run_timed, run_timed_in, AnnealingParams, CostCap, Scratch, StatsWeighting,
+};
+use ydj_mixer_engine::cost::{edge_cost, edge_costs, Anchors, CostParams, StabilityPenalty, Tables};
+use ydj_mixer_engine::family::{self, FamilyRunLimit};
+
+#[test]
+fn best_cost_matches_full_recompute() {
+    let params = cost_params();
+    for seed in 0..5 {
+        let inst = instance(20, seed);
+        let mut tables = inst.tables();
+        if seed % 2 == 1 {
+            tables.anchors = Anchors { entry: Some((118, 6)), exit: None };
+        }
+        let mut rng = StdRng::seed_from_u64(seed);
+        let r = run_attempt(inst.n(), &tables, &params, &annealing_params(), None, None, &mut rng);
+        assert!(is_permutation(&r.best_order, inst.n()));
+        assert!((r.best_cost - objective(&r.best_order, &r.best_shifts, &tables, &params)).abs() < 1e-9);
+        assert_eq!((r.opener(), r.closer()), (r.best_order[0], r.best_order[inst.n() - 1]));
+    }
+}
+
+#[test]
+fn recorded_history_tracks_the_best_cost() {
+    let params = cost_params();
+    let inst = instance(15, 7);
+    let tables = inst.tables();
+    let ann = annealing_params();
+    let plain = run_attempt(inst.n(), &tables, &params, &ann, None, None, &mut StdRng::seed_from_u64(7));
+    let (r, history) = run_attempt_recorded(inst.n(), &tables, &params, &ann, None, None, 50, &mut StdRng::seed_from_u64(7));
+    assert_eq!((r.best_order, r.best_cost), (plain.best_order, plain.best_cost));
+    assert_eq!(history.len(), ann.total_iterations / 50 + 1);
+    assert_eq!(history[0].0, 0);
+    assert_eq!(*history.last().unwrap(), (ann.total_iterations, r.best_cost));
+    assert!(history.windows(2).all(|w| w[0].0 < w[1].0 && w[1].1 <= w[0].1));
+}
+
+#[test]
+fn scratch_is_resized_for_each_instance() {
+    let params = CostParams { minmax_blend: 0.3, ..cost_params() };
+    let ann = AnnealingParams { auto_initial_temp: Some(0.5), ..annealing_params() };
+    let mut scratch = Scratch::new();
+    let mut reserved = 0;
+    for (n, seed) in [(12, 1), (5, 2), (20, 3), (12, 4)] {
+        let inst = instance(n, seed);
+        let tables = inst.tables();
+        let fresh = run_attempt(n, &tables, &params, &ann, None, None, &mut StdRng::seed_from_u64(seed));
+        let reused = run_attempt_in(&mut scratch, n, &tables, &params, &ann, None, None, &mut StdRng::seed_from_u64(seed));
+        assert!(is_permutation(&reused.best_order, n));
+        assert_eq!(reused.best_shifts.len(), n);
+        assert_eq!((reused.best_order, reused.best_shifts), (fresh.best_order, fresh.best_shifts));
+        assert_eq!(reused.best_cost.to_bits(), fresh.best_cost.to_bits());
+
+        let (best, _, stats, _) =
+            run_timed_in(&mut scratch, n, &tables, &params, &ann, None, None, StatsWeighting::Uniform, 0.0).unwrap();
+        assert!(is_permutation(&best.best_order, n));
+        assert_eq!(stats.avg, compute_per_track_costs(n, &best.best_order, &best.best_shifts, &tables, &params));
+
+        // The buffers grow to the largest instance and are kept through smaller ones
+        assert!(scratch.capacity_bytes() >= reserved);
+        if n == 20 {
+            assert!(scratch.capacity_bytes() > reserved);
+        }
+        reserved = scratch.capacity_bytes();
+    }
+}
+
+#[test]
+fn fixed_shifts_are_kept() {
+    let params = cost_params();
+    let inst = instance(15, 2);
+    let tables = inst.tables();
+    let fixed: Vec<i8> = (0..inst.n()).map(|i| (i % 3) as i8 - 1).collect();
+    let mut rng = StdRng::seed_from_u64(2);
+    let r = run_attempt(inst.n(), &tables, &params, &annealing_params(), None, Some(&fixed), &mut rng);
+    assert_eq!(r.best_shifts, fixed);
+    assert!((r.best_cost - objective(&r.best_order, &r.best_shifts, &tables, &params)).abs() < 1e-9);
+}
+
+#[test]
+fn per_track_stats_are_ordered() {
+    let params = cost_params();
+    let inst = instance(12, 4);
+    let tables = inst.tables();
+    for weighting in [StatsWeighting::Uniform, StatsWeighting::InverseCost, StatsWeighting::WithinPercent(0.0)] {
+        let (_, attempts, stats, secs) =
+            run_timed(inst.n(), &tables, &params, &annealing_params(), None, None, weighting, 0.05).unwrap();
+        assert!(!attempts.is_empty());
+        assert_eq!(secs.len(), attempts.len());
+        assert!(secs.iter().all(|&t| t >= 0.0));
+        for i in 0..inst.n() {
+            assert!(stats.min[i] <= stats.avg[i] + 1e-9 && stats.avg[i] <= stats.max[i] + 1e-9);
+        }
+    }
+}
+
+#[test]
+fn stats_weights_follow_the_gap_to_the_best_even_below_zero() {
+    let inverse = StatsWeighting::InverseCost;
+    // A -10 attempt no longer weighs the same as a 0 one, and neither swamps the rest
+    assert_eq!(inverse.weight(-10.0, -10.0), Some(1.0));
+    assert_eq!(inverse.weight(0.0, -10.0), Some(0.5));
+    assert_eq!(inverse.weight(10.0, -10.0), Some(1.0 / 3.0));
+    assert_eq!(inverse.weight(0.5, 0.0), Some(1.0 / 1.5));
+    // Above a best of 1 the weights are proportional to 1 / cost
+    assert_eq!(inverse.weight(40.0, 20.0), Some(0.5));
+
+    let within = StatsWeighting::WithinPercent(10.0);
+    assert_eq!(within.weight(-10.0, -10.0), Some(1.0));
+    assert_eq!(within.weight(-9.0, -10.0), Some(1.0));
+    assert_eq!(within.weight(-8.5, -10.0), None);
+    assert_eq!(within.weight(0.1, 0.0), Some(1.0));
+    assert_eq!(within.weight(0.2, 0.0), None);
+    assert_eq!(StatsWeighting::Uniform.weight(1e6, -10.0), Some(1.0));
+}
+
+#[test]
+fn segments_cover_their_tracks() {
+    let params = cost_params();
+    let inst = instance(12, 6);
+    let tables = inst.tables();
+    let segments = vec![vec![0, 2, 4, 6, 8], vec![1, 3, 5, 7, 9, 10], vec![11]];
+    let results = run_segments(&segments, &tables, &params, &annealing_params(), 0.1).unwrap();
+    assert_eq!(results.len(), 3);
+    for (seg, r) in segments.iter().zip(&results) {
+        let mut got = r.order.clone();
+        got.sort_unstable();
+        assert_eq!(&got, seg);
+        assert_eq!(r.shifts.len(), seg.len());
+    }
+    assert_eq!(results[2].cost, 0.0);
+}
+
+#[test]
+fn tempo_cap_trades_harmony_for_tempo() {
+    let params = cost_params();
+    let inst = instance(14, 9);
+    let tables = inst.tables();
+    let run = |cap| {
+        let weighting = StatsWeighting::Uniform;
+        run_capped(inst.n(), &tables, &params, &annealing_params(), None, None, weighting, 0.3, cap).unwrap()
+    };
+    let ((free, _, _, _), _) = run(CostCap::Tempo(f64::INFINITY));
+    let ((capped, _, _, _), outcome) = run(CostCap::Tempo(0.0));
+    assert!(is_permutation(&capped.best_order, inst.n()));
+    // best_cost is scored with the original weights whatever the round's factor was
+    assert!((capped.best_cost - objective(&capped.best_order, &capped.best_shifts, &tables, &params)).abs() < 1e-9);
+    assert!(capped.t_cost <= free.t_cost + 1e-9);
+    assert_eq!(outcome.satisfied, capped.t_cost == 0.0);
+}
+
+#[test]
+fn unreachable_cap_is_reported() {
+    // Tempos 60 apart: every edge pays a tempo break, so no order meets t <= 0
+    let params = cost_params();
+    let mut inst = instance(6, 3);
+    inst.bpms = vec![70, 130, 70, 130, 70, 130];
+    let tables = inst.tables();
+    let ((best, _, _, _), outcome) = run_capped(
+        inst.n(), &tables, &params, &annealing_params(), None, None, StatsWeighting::Uniform, 0.1,
+        CostCap::Tempo(0.0),
+    )
+    .unwrap();
+    assert!(!outcome.satisfied);
+    assert!(best.t_cost > 0.0);
+    assert!(is_permutation(&best.best_order, inst.n()));
+}
+
+#[test]
+fn per_position_costs_follow_the_order() {
+    let params = cost_params();
+    let inst = instance(10, 7);
+    let tables = inst.tables();
+    let order: Vec<usize> = (0..inst.n()).rev().collect();
+    let shifts = vec![0i8; inst.n()];
+    let costs = compute_per_position_costs(&order, &shifts, &tables, &params);
+    let edge = |j: usize| edge_cost(order[j], order[j + 1], 0, 0, &tables, &params);
+    assert_eq!(costs.len(), inst.n());
+    assert_eq!(costs[0], edge(0));
+    assert_eq!(costs[9], edge(8));
+    assert_eq!(costs[4], (edge(3) + edge(4)) / 2.0);
+}
+
+#[test]
+fn track_costs_split_each_track_into_its_two_edges() {
+    let params = cost_params();
+    let inst = instance(10, 8);
+    let tables = inst.tables();
+    let mut rng = StdRng::seed_from_u64(8);
+    let order: Vec<usize> = vec![3, 7, 0, 9, 1, 5, 2, 8, 4, 6];
+    let shifts: Vec<i8> = (0..10).map(|_| rng.random_range(-1..=1)).collect();
+    let costs = compute_track_costs(10, &order, &shifts, &tables, &params);
+    let edges = edge_costs(&order, &shifts, &tables, &params);
+    let by_position = compute_per_position_costs(&order, &shifts, &tables, &params);
+    for (p, &t) in order.iter().enumerate() {
+        assert_eq!(costs.incoming[t], p.checked_sub(1).map(|j| edges[j]));
+        assert_eq!(costs.outgoing[t], edges.get(p).copied());
+        assert_eq!(costs.avg[t], by_position[p]);
+    }
+
+    // A subset leaves the other tracks out
+    let subset = compute_track_costs(10, &order[..4], &shifts, &tables, &params);
+    assert_eq!((subset.avg[4], subset.incoming[4], subset.outgoing[4]), (0.0, None, None));
+    assert_eq!(subset.outgoing[9], None);
+}
+
+#[test]
+fn family_runs_are_broken_up() {
+    // Half the tracks share one key: a random start nearly always has long runs
+    let params = CostParams { family_run: Some(FamilyRunLimit { max_run: 1, penalty: 10.0 }), ..cost_params() };
+    for seed in 0..3 {
+        let mut inst = instance(16, seed);
+        for i in (0..16).step_by(2) {
+            inst.key_ids[i] = 16;
+        }
+        let tables = inst.tables();
+        let mut rng = StdRng::seed_from_u64(seed);
+        let r = run_attempt(inst.n(), &tables, &params, &annealing_params(), None, None, &mut rng);
+        assert!(is_permutation(&r.best_order, inst.n()));
+        assert_eq!(family::longest_run(&r.best_order, &r.best_shifts, &tables), 1);
+        assert!((r.best_cost - objective(&r.best_order, &r.best_shifts, &tables, &params)).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn shift_sweep_front_is_non_increasing() {
+    let params = CostParams { shift_penalty: 0.5, ..cost_params() };
+    let inst = instance(14, 4);
+    let tables = inst.tables();
+    let sweep = run_shift_sweep(inst.n(), &tables, &params, &annealing_params(), 4, 0.2).unwrap();
+    assert_eq!(sweep.len(), 5);
+    for (k, r) in sweep.iter().enumerate() {
+        assert!(is_permutation(&r.best_order, inst.n()));
+        assert!(r.best_shifts.iter().filter(|&&s| s != 0).count() <= k);
+        assert!((r.best_cost - objective(&r.best_order, &r.best_shifts, &tables, &params)).abs() < 1e-9);
+        if k > 0 {
+            assert!(r.best_cost <= sweep[k - 1].best_cost);
+        }
+    }
+}
+
+#[test]
+fn pareto_is_sorted_distinct_and_reproducible() {
+    let params = cost_params();
+    let inst = instance(12, 8);
+    let tables = inst.tables();
+    let weights = [4.0, 0.0, 1.0, 1.0, 0.25];
+    let sweep =
+        || run_pareto(inst.n(), &tables, &params, &annealing_params(), &weights, 0.0, Some((42, 3))).unwrap();
+    let points = sweep();
+    assert!(!points.is_empty() && points.len() <= weights.len());
+    for (i, p) in points.iter().enumerate() {
+        let weighted = CostParams { tempo_cost_weight: p.tempo_cost_weight, ..cost_params() };
+        let r = &p.result;
+        assert!((r.best_cost - objective(&r.best_order, &r.best_shifts, &tables, &weighted)).abs() < 1e-9);
+        for q in &points[..i] {
+            assert!(q.tempo_cost_weight < p.tempo_cost_weight);
+            assert!((&q.result.best_order, &q.result.best_shifts) != (&r.best_order, &r.best_shifts));
+        }
+    }
+
+    let again = sweep();
+    assert_eq!(again.len(), points.len());
+    for (p, q) in points.iter().zip(&again) {
+        assert_eq!(p.tempo_cost_weight, q.tempo_cost_weight);
+        assert_eq!(p.result.best_order, q.result.best_order);
+        assert_eq!(p.result.best_shifts, q.result.best_shifts);
+    }
+}
+
+#[test]
+fn shift_init_weights_shape_the_starting_shifts() {
+    let params = cost_params();
+    let inst = instance(40, 9);
+    let tables = inst.tables();
+    // With no iterations an attempt returns its random start
+    let start = |shift_init_weights: [f64; 3], seed: u64| {
+        let ap = AnnealingParams { total_iterations: 0, shift_init_weights, ..annealing_params() };
+        run_attempt(inst.n(), &tables, &params, &ap, None, None, &mut StdRng::seed_from_u64(seed)).best_shifts
+    };
+    assert!(start([0.0, 1.0, 0.0], 1).iter().all(|&s| s == 0));
+    assert!(start([0.0, 0.0, 2.0], 2).iter().all(|&s| s == 1));
+    let mixed = start([1.0, 0.0, 1.0], 3);
+    assert!(mixed.iter().all(|&s| s != 0));
+    assert!(mixed.contains(&-1) && mixed.contains(&1));
+}
+
+#[test]
+fn large_stability_weight_keeps_the_reference_order() {
+    let params = cost_params();
+    let inst = instance(14, 12);
+    let mut tables = inst.tables();
+    let reference: Vec<usize> = (0..14).rev().collect();
+    let st = StabilityPenalty::new(&reference, 1000.0).unwrap();
+    tables.stability = Some(&st);
+    let mut rng = StdRng::seed_from_u64(12);
+    let ap = AnnealingParams { total_iterations: 20000, ..annealing_params() };
+    let r = run_attempt(inst.n(), &tables, &params, &ap, None, None, &mut rng);
+    assert_eq!(r.best_order, reference);
+    assert!((r.best_cost - objective(&r.best_order, &r.best_shifts, &tables, &params)).abs() < 1e-9);
+}
+
+#[test]
+fn panicking_attempt_is_reported_with_its_seed() {
+    let params = cost_params();
+    let inst = instance(10, 5);
+    let tables = inst.tables();
+    // All-zero shift weights make the first attempt panic
+    let ap = AnnealingParams { shift_init_weights: [0.0; 3], ..annealing_params() };
+    let run = || run_seeded(inst.n(), &tables, &params, &ap, None, None, StatsWeighting::Uniform, 3, 7);
+    let err = run().err().unwrap();
+    assert_eq!(err.attempt, 0);
+    assert!(err.message.contains("shift_init_weights"), "{}", err.message);
+    assert_eq!(run().err().unwrap().seed, err.seed);
+
+    // The seed replays the attempt
+    let replay = std::panic::catch_unwind(|| {
+        run_attempt(inst.n(), &tables, &params, &ap, None, None, &mut StdRng::seed_from_u64(err.seed))
+    });
+    assert!(replay.is_err());
+}
+
+#[test]
+fn compatible_playlist_keeps_every_shift_at_zero() {
+    // Every key pair costs 0 at every shift, so a shift never buys anything
+    let params = cost_params();
+    let mut inst = instance(12, 13);
+    inst.direct_costs.fill(0.0);
+    inst.indirect_costs.fill(0.0);
+    inst.bpms = vec![124; 12];
+    let tables = inst.tables();
+    let ap = AnnealingParams { shift_init_weights: [1.0, 0.0, 1.0], ..annealing_params() };
+    let r = run_attempt(inst.n(), &tables, &params, &ap, None, None, &mut StdRng::seed_from_u64(13));
+    assert_eq!(r.best_shifts, vec![0; 12]);
+    assert_eq!(r.best_cost, 0.0);
+}
+
+#[test]
+fn custom_moves_are_scored_and_can_stop_the_attempt() {
+    let params = cost_params();
+    let inst = instance(16, 8);
+    let tables = inst.tables();
+    let ap = annealing_params();
+
+    // A proposer that always declines leaves the native run untouched
+    let mut calls = 0;
+    let mut decline = |_: &[usize], _: &[i8]| -> Result<Option<Vec<usize>>, String> {
+        calls += 1;
+        Ok(None)
+    };
+    let r = run_attempt_with_moves(inst.n(), &tables, &params, &ap, 10, &mut decline, &mut StdRng::seed_from_u64(3))
+        .unwrap();
+    let native = run_attempt(inst.n(), &tables, &params, &ap, None, None, &mut StdRng::seed_from_u64(3));
+    assert_eq!(calls, ap.total_iterations / 10);
+    assert_eq!((r.best_order, r.best_cost), (native.best_order, native.best_cost));
+
+    // Reversing a segment (a 2-opt move) on every other iteration
+    let mut segment_rng = StdRng::seed_from_u64(4);
+    let mut reverse = |order: &[usize], _: &[i8]| -> Result<Option<Vec<usize>>, String> {
+        let (a, b) = (segment_rng.random_range(0..16), segment_rng.random_range(0..16));
+        let mut proposal = order.to_vec();
+        proposal[a.min(b)..=a.max(b)].reverse();
+        Ok(Some(proposal))
+    };
+    let r = run_attempt_with_moves(inst.n(), &tables, &params, &ap, 2, &mut reverse, &mut StdRng::seed_from_u64(3))
+        .unwrap();
+    assert!(is_permutation(&r.best_order, inst.n()));
+    assert!((r.best_cost - objective(&r.best_order, &r.best_shifts, &tables, &params)).abs() < 1e-9);
+
+    let mut calls = 0;
+    let mut stop = |_: &[usize], _: &[i8]| {
+        calls += 1;
+        if calls == 3 { Err("enough") } else { Ok(None) }
+    };
+    let r = run_attempt_with_moves(inst.n(), &tables, &params, &ap, 1, &mut stop, &mut StdRng::seed_from_u64(3));
+    assert_eq!(r.err(), Some("enough"));
+}
+
+#[test]
+fn shift_cost_budget_is_never_overrun() {
+    let inst = instance(16, 21);
+    let tables = inst.tables();
+    let free = run_attempt(inst.n(), &tables, &cost_params(), &annealing_params(), None, None, &mut StdRng::seed_from_u64(2));
+    assert!(free.s_cost > 2.0);
+    for budget in [0.0, 2.0] {
+        let params = CostParams { shift_weight: 0.5, max_shift_cost: Some(budget), ..cost_params() };
+        for seed in 0..3 {
+            let r = run_attempt(inst.n(), &tables, &params, &annealing_params(), None, None, &mut StdRng::seed_from_u64(seed));
+            assert!(params.shift_weight * r.s_cost <= budget + 1e-9);
+            assert!(r.best_shifts.iter().filter(|&&s| s != 0).count() <= 4);
+            assert!((r.best_cost - objective(&r.best_order, &r.best_shifts, &tables, &params)).abs() < 1e-9);
+        }
+    }
+}
+
+#[test]
+fn every_move_is_counted_once_by_outcome() {
+    let inst = instance(16, 21);
+    let tables = inst.tables();
+    let ap = annealing_params();
+    let free = run_attempt(inst.n(), &tables, &cost_params(), &ap, None, None, &mut StdRng::seed_from_u64(3));
+    assert_eq!(free.moves.total(), ap.total_iterations);
+    assert_eq!(free.moves.constraint_rejected(), 0);
+    assert!(free.moves.improved > 0 && free.moves.rejected_worse > 0);
+
+    let params = CostParams { max_shift_cost: Some(0.0), ..cost_params() };
+    let budget = run_attempt(inst.n(), &tables, &params, &ap, None, None, &mut StdRng::seed_from_u64(3));
+    assert_eq!(budget.moves.total(), ap.total_iterations);
+    assert!(budget.moves.rejected_shift_budget > 0);
+    assert_eq!(budget.moves.constraint_rejected(), budget.moves.rejected_shift_budget);
+}
+
+#[test]
+fn validate_rejects_schedules_that_do_not_cool() {
+    assert_eq!(annealing_params().validate(), Ok(()));
+    let rejected = [
+        ("total_iterations", AnnealingParams { total_iterations: 0, ..annealing_params() }),
+        ("final_temp", AnnealingParams { final_temp: 0.0, ..annealing_params() }),
+        ("final_temp", AnnealingParams { final_temp: f64::NAN, ..annealing_params() }),
+        ("initial_temp", AnnealingParams { initial_temp: 0.1, ..annealing_params() }),
+        ("initial_temp", AnnealingParams { initial_temp: f64::INFINITY, ..annealing_params() }),
+        ("auto_initial_temp", AnnealingParams { auto_initial_temp: Some(1.0), ..annealing_params() }),
+        ("auto_initial_temp", AnnealingParams { auto_initial_temp: Some(0.0), ..annealing_params() }),
+    ];
+    for (key, params) in rejected {
+        let err = params.validate().unwrap_err();
+        assert!(err.starts_with(key), "{err}");
+    }
+}
+
+#[test]
+fn minmax_blend_one_minimises_the_worst_edge() {
+    for seed in 0..3 {
+        let inst = instance(6, seed + 60);
+        let tables = inst.tables();
+        let params = CostParams { minmax_blend: 1.0, ..cost_params() };
+
+        // Pure min-max reference over every order and shift assignment
+        let mut reference = f64::INFINITY;
+        let mut order: Vec<usize> = (0..6).collect();
+        for _ in 0..720 {
+            for code in 0..729usize {
+                let shifts: Vec<i8> = (0..6).map(|i| (code / 3usize.pow(i) % 3) as i8 - 1).collect();
+                reference = reference.min(max_edge(&order, &shifts, &tables, &params));
+            }
+            next_permutation(&mut order);
+        }
+
+        let best = (0..3)
+            .map(|attempt| {
+                let mut rng = StdRng::seed_from_u64(seed * 10 + attempt);
+                let r = run_attempt(6, &tables, &params, &annealing_params(), None, None, &mut rng);
+                assert_eq!(r.best_cost, max_edge(&r.best_order, &r.best_shifts, &tables, &params));
+                r.best_cost
+            })
+            .fold(f64::INFINITY, f64::min);
+        assert_eq!(best, reference, "seed {seed}");
+
+        // In between, the reported cost is the blend of the sum and the worst edge
+        let half = CostParams { minmax_blend: 0.5, ..cost_params() };
+        let r = run_attempt(6, &tables, &half, &annealing_params(), None, None, &mut StdRng::seed_from_u64(seed));
+        let sum = objective(&r.best_order, &r.best_shifts, &tables, &half);
+        let expected = 0.5 * sum + 0.5 * max_edge(&r.best_order, &r.best_shifts, &tables, &half);
+        assert!((r.best_cost - expected).abs() < 1e-9);
+    }
+}
+
+fn max_edge(order: &[usize], shifts: &[i8], tables: &Tables, params: &CostParams) -> f64 {
+    edge_costs(order, shifts, tables, params).into_iter().fold(0.0, f64::max)
+}
+
+/// Lexicographic successor, wrapping round to the first permutation after the last.
+fn next_permutation(v: &mut [usize]) {
+    let Some(i) = (1..v.len()).rev().find(|&i| v[i - 1] < v[i]) else {
+        v.reverse();
+        return;
+    };
+    let j = (i..v.len()).rev().find(|&j| v[j] > v[i - 1]).unwrap();
+    v.swap(i - 1, j);
+    v[i..].reverse();
+}
+
+#[test]
+fn auto_initial_temp_is_independent_of_the_cost_scale() {
+    // Scaling every cost and the final temperature by a power of two is exact, so with a
+    // calibrated start both runs make the same moves
+    let inst = instance(16, 80);
+    let tables = inst.tables();
+    let params = cost_params();
+    let scale = 8.0;
+    let direct: Vec<f64> = inst.direct_costs.iter().map(|c| c * scale).collect();
+    let indirect: Vec<f64> = inst.indirect_costs.iter().map(|c| c * scale).collect();
+    let scaled_tables = Tables::new(&inst.bpms, &inst.key_ids, &inst.shift_table, &direct, &indirect);
+    let scaled_params = CostParams {
+        non_harmonic_cost: params.non_harmonic_cost * scale,
+        tempo_cost_weight: params.tempo_cost_weight * scale,
+        shift_weight: params.shift_weight * scale,
+        ..cost_params()
+    };
+    let ap = AnnealingParams { auto_initial_temp: Some(0.8), ..annealing_params() };
+    let scaled_ap = AnnealingParams { final_temp: ap.final_temp * scale, ..ap };
+    for seed in 0..3 {
+        let r = run_attempt(inst.n(), &tables, &params, &ap, None, None, &mut StdRng::seed_from_u64(seed));
+        let scaled = run_attempt(
+            inst.n(), &scaled_tables, &scaled_params, &scaled_ap, None, None, &mut StdRng::seed_from_u64(seed),
+        );
+        assert_eq!((&scaled.best_order, &scaled.best_shifts), (&r.best_order, &r.best_shifts));
+        assert_eq!(scaled.best_cost, r.best_cost * scale);
+        assert!((r.best_cost - objective(&r.best_order, &r.best_shifts, &tables, &params)).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn distinct_costs_bucket_rounding_noise() {
+    assert_eq!(distinct_costs(&[]), 0);
+    assert_eq!(distinct_costs(&[3.0, 3.0 + 1e-12, 3.0 - 1e-12]), 1);
+    assert_eq!(distinct_costs(&[5.0, 3.0, 4.0, 3.0, 5.0]), 3);
+    assert_eq!(distinct_costs(&[1.0, 1.0 + 6e-7, 1.0 + 1.2e-6]), 2);
+}
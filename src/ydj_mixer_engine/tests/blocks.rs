@@ -0,0 +1,107 @@
+mod common;
+
+use common::{cost_params, instance, is_permutation, objective};
+use ydj_mixer_engine::blocks::Contraction;
+use ydj_mixer_engine::cost::{AdjacencyBonus, CompatCosts};
+use ydj_mixer_engine::held_karp;
+
+fn assert_contiguous(order: &[usize], group: &[usize]) {
+    let start = order.iter().position(|&t| t == group[0]).unwrap();
+    assert_eq!(&order[start..start + group.len()], group);
+}
+
+#[test]
+fn blocks_stay_contiguous_and_ordered() {
+    let params = cost_params();
+    let inst = instance(9, 4);
+    let plain = inst.tables();
+    let groups = vec![vec![3, 1], vec![0, 7, 5]];
+    let c = Contraction::new(inst.n(), &groups, &plain, &params).unwrap();
+    assert_eq!(c.len(), 6);
+
+    let tables = c.tables(&plain);
+    let (order, shifts, cost, _, _) = held_karp::run(c.len(), &tables, &params, None, None, false);
+    let (track_order, track_shifts) = c.expand(&order, &shifts);
+    assert!(is_permutation(&track_order, inst.n()));
+    for g in &groups {
+        assert_contiguous(&track_order, g);
+    }
+    // The contracted objective is the plain objective of the expanded order
+    assert!((cost - objective(&track_order, &track_shifts, &plain, &params)).abs() < 1e-9);
+}
+
+#[test]
+fn blocks_keep_boundary_harmonic_weights() {
+    let params = cost_params();
+    let inst = instance(8, 2);
+    let conf = [0.2, 1.0, 0.5, 0.9, 0.0, 0.7, 0.3, 1.0];
+    let mut plain = inst.tables();
+    plain.key_confidence = Some(&conf);
+    plain.exit_key_confidence = Some(&conf);
+    let blend = [1.0, 0.5, 2.0, 1.0, 0.0, 1.0, 3.0, 1.0];
+    plain.blend_scale = Some(&blend);
+    let c = Contraction::new(inst.n(), &[vec![6, 2, 4]], &plain, &params).unwrap();
+    let tables = c.tables(&plain);
+    let (order, shifts, cost, _, _) = held_karp::run(c.len(), &tables, &params, None, None, false);
+    let (track_order, track_shifts) = c.expand(&order, &shifts);
+    assert_contiguous(&track_order, &[6, 2, 4]);
+    assert!((cost - objective(&track_order, &track_shifts, &plain, &params)).abs() < 1e-9);
+}
+
+#[test]
+fn adjacency_bonus_survives_contraction() {
+    let params = cost_params();
+    let inst = instance(8, 3);
+    // 5 → 0 meets the block boundary (last member 5); 3 → 1 is inside the block
+    let adj = AdjacencyBonus::from_pairs(8, &[(5, 0, 40.0), (3, 1, 2.0), (6, 7, 1.0)]).unwrap();
+    let mut plain = inst.tables();
+    plain.adjacency = Some(&adj);
+    let c = Contraction::new(inst.n(), &[vec![3, 1, 5]], &plain, &params).unwrap();
+    let tables = c.tables(&plain);
+    let (order, shifts, cost, _, _) = held_karp::run(c.len(), &tables, &params, None, None, false);
+    let (track_order, track_shifts) = c.expand(&order, &shifts);
+    assert_contiguous(&track_order, &[3, 1, 5, 0]);
+    assert!((cost - objective(&track_order, &track_shifts, &plain, &params)).abs() < 1e-9);
+}
+
+#[test]
+fn compat_costs_survive_contraction() {
+    let params = cost_params();
+    let inst = instance(8, 9);
+    let costs: Vec<f64> = (0..64).map(|k| ((k * 37) % 23) as f64).collect();
+    let compat = CompatCosts::new(8, &costs, 2.0, false).unwrap();
+    let mut plain = inst.tables();
+    plain.compat = Some(&compat);
+    let c = Contraction::new(inst.n(), &[vec![4, 0, 6], vec![7, 2]], &plain, &params).unwrap();
+    let tables = c.tables(&plain);
+    let (order, shifts, cost, _, _) = held_karp::run(c.len(), &tables, &params, None, None, false);
+    let (track_order, track_shifts) = c.expand(&order, &shifts);
+    assert!((cost - objective(&track_order, &track_shifts, &plain, &params)).abs() < 1e-9);
+}
+
+#[test]
+fn block_can_open_the_set() {
+    let params = cost_params();
+    let inst = instance(6, 12);
+    let plain = inst.tables();
+    let groups = vec![vec![2, 0, 4]];
+    let c = Contraction::new(inst.n(), &groups, &plain, &params).unwrap();
+    let tables = c.tables(&plain);
+    // Any node order is valid; put the block first and check it expands in place
+    let order: Vec<usize> = (0..c.len()).collect();
+    let (track_order, _) = c.expand(&order, &vec![0; c.len()]);
+    assert_eq!(&track_order[..3], &[2, 0, 4]);
+    let (_, _, cost, _, _) = held_karp::run(c.len(), &tables, &params, None, None, false);
+    assert!(cost.is_finite());
+}
+
+#[test]
+fn invalid_groups_are_rejected() {
+    let params = cost_params();
+    let inst = instance(5, 0);
+    let plain = inst.tables();
+    let err = |groups: Vec<Vec<usize>>| Contraction::new(5, &groups, &plain, &params).err().unwrap();
+    assert!(err(vec![vec![0, 1], vec![1, 2]]).contains("more than once"));
+    assert!(err(vec![vec![]]).contains("empty"));
+    assert!(err(vec![vec![0, 9]]).contains("only 5 tracks"));
+}
@@ -0,0 +1,35 @@
+mod common;
+
+use common::{annealing_params, cost_params, instance};
+use ydj_mixer_engine::annealing::AnnealingParams;
+use ydj_mixer_engine::calibrate::estimate_budget;
+
+#[test]
+fn tighter_targets_need_at_least_as_many_iterations() {
+    let params = cost_params();
+    let inst = instance(25, 41);
+    let tables = inst.tables();
+    let ann = AnnealingParams { total_iterations: 2000, ..annealing_params() };
+    let mut previous = 0;
+    for target in [0.5, 0.1, 0.01, 0.001] {
+        let est = estimate_budget(inst.n(), &tables, &params, &ann, None, 4, target, 41).unwrap();
+        assert_eq!((est.attempts, est.calibration_iterations), (4, 2000));
+        assert!(est.final_gap >= 0.0);
+        if let Some(i) = est.iterations {
+            assert!(i >= previous, "target {target}: {i} < {previous}");
+            assert!(est.extrapolated == (i > 2000 || est.final_gap > target));
+            assert!(est.secs.unwrap() >= 0.0);
+            previous = i;
+        }
+        assert!(["high", "medium", "low"].contains(&est.confidence()));
+    }
+}
+
+#[test]
+fn target_gap_must_be_positive() {
+    let inst = instance(6, 42);
+    let tables = inst.tables();
+    for target in [0.0, -0.1, f64::NAN] {
+        assert!(estimate_budget(inst.n(), &tables, &cost_params(), &annealing_params(), None, 2, target, 0).is_err());
+    }
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use common::{annealing_params, cost_params, instance};
+use ydj_mixer_engine::annealing::{run_resumed, RunState, StatsWeighting};
+use ydj_mixer_engine::checkpoint;
+
+#[test]
+fn resumed_run_keeps_the_saved_attempts() {
+    let inst = instance(12, 3);
+    let tables = inst.tables();
+    let params = cost_params();
+    let weighting = StatsWeighting::WithinPercent(20.0);
+
+    // Stop after three attempts, as a crash after the third checkpoint would
+    let mut state = RunState::new(inst.n(), weighting);
+    let mut saved = Vec::new();
+    run_resumed(inst.n(), &tables, &params, &annealing_params(), None, None, &mut state, 60.0, &mut |s| {
+        saved = checkpoint::save(s);
+        s.attempts() < 3
+    })
+    .unwrap();
+    assert_eq!(state.attempts(), 3);
+
+    let restored = checkpoint::load(&saved, inst.n(), weighting).unwrap();
+    let (before, costs, stats, _) = state.result();
+    let (after, restored_costs, restored_stats, _) = restored.result();
+    assert_eq!(
+        (&after.best_order, &after.best_shifts, after.best_cost),
+        (&before.best_order, &before.best_shifts, before.best_cost),
+    );
+    assert_eq!(restored_costs, costs);
+    assert_eq!((restored_stats.min, restored_stats.avg), (stats.min, stats.avg));
+
+    let mut resumed = restored;
+    let (best, costs, _, secs) =
+        run_resumed(inst.n(), &tables, &params, &annealing_params(), None, None, &mut resumed, 0.0, &mut |_| true)
+            .unwrap();
+    assert_eq!((costs.len(), secs.len()), (4, 4));
+    assert!(best.best_cost <= before.best_cost);
+}
+
+#[test]
+fn load_rejects_foreign_checkpoints() {
+    let inst = instance(6, 1);
+    let mut state = RunState::new(inst.n(), StatsWeighting::Uniform);
+    let tables = inst.tables();
+    run_resumed(inst.n(), &tables, &cost_params(), &annealing_params(), None, None, &mut state, 0.0, &mut |_| true)
+        .unwrap();
+    let saved = checkpoint::save(&state);
+    assert!(checkpoint::load(&saved, 6, StatsWeighting::Uniform).is_ok());
+    assert!(checkpoint::load(&saved, 7, StatsWeighting::Uniform).err().unwrap().contains("6 tracks"));
+    assert!(checkpoint::load(&saved, 6, StatsWeighting::InverseCost).is_err());
+
+    let text = String::from_utf8(saved).unwrap();
+    let newer = text.replace(&format!("\"version\":{}", checkpoint::VERSION), "\"version\":99");
+    assert!(checkpoint::load(newer.as_bytes(), 6, StatsWeighting::Uniform).err().unwrap().contains("version 99"));
+    assert!(checkpoint::load(b"{\"format\":\"other\",\"version\":1}", 6, StatsWeighting::Uniform).is_err());
+    assert!(checkpoint::load(b"not json", 6, StatsWeighting::Uniform).is_err());
+}
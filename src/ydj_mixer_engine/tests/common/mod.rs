@@ -0,0 +1,32 @@
+//! Shared fixtures for the integration tests; instances come from `ydj_mixer_engine::testing`.
+
+#![allow(dead_code)]
+
+use ydj_mixer_engine::annealing::AnnealingParams;
+use ydj_mixer_engine::cost::{CostParams, Tables};
+
+pub use ydj_mixer_engine::testing::{cost_params, random_instance as instance};
+
+pub fn annealing_params() -> AnnealingParams {
+    AnnealingParams {
+        total_iterations: 5000,
+        initial_temp: 10.0,
+        final_temp: 0.1,
+        multi_swap_factor: 2,
+        shift_init_weights: [1.0; 3],
+        auto_initial_temp: None,
+    }
+}
+
+/// Weighted objective of an order, as reported by the solvers.
+pub fn objective(order: &[usize], shifts: &[i8], tables: &Tables, params: &CostParams) -> f64 {
+    let (h, t, s) = ydj_mixer_engine::cost::total_edge_cost(order, shifts, tables, params);
+    h + params.tempo_cost_weight * t + params.shift_weight * s + tables.boundary_cost(order, shifts, params)
+}
+
+/// Whether `order` is a permutation of 0..n.
+pub fn is_permutation(order: &[usize], n: usize) -> bool {
+    let mut sorted = order.to_vec();
+    sorted.sort_unstable();
+    sorted == (0..n).collect::<Vec<_>>()
+}
@@ -0,0 +1,1054 @@
+mod common;
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+use common::{cost_params, instance, objective};
+use ydj_mixer_engine::cost::{
+    edge_components, Anchors, AdjacencyBonus, CompatCosts, CostParams, edge_cost, effective_key, evaluate_order, explain_edge, optimize_shift_at,
+    camelot_key_costs, camelot_shift_table, camelot_tables, canonicalize_orientation, change_delta, classify_transition, compare_orders, export_schedule, format_camelot_key, format_key, parse_camelot_key,
+    parse_key, KeyCostRules, KeyNotation, KeyRelation, leave_one_out_costs, move_delta, pairwise_best_costs, removal_deltas, restore_f32_value, reverse_segment_delta, sanitize_cost_table,
+    score_orders, swap_delta, transition_report,
+    edge_costs, total_edge_cost, SparseKeyCosts, StabilityPenalty, Tables, FORBIDDEN_COST,
+};
+use ydj_mixer_engine::held_karp;
+
+#[test]
+fn sanitize_reports_first_nan_index() {
+    let mut table = vec![1.0, 2.0, 3.0, f64::NAN, f64::NAN];
+    assert_eq!(sanitize_cost_table(&mut table, false), Err(3));
+    assert_eq!(sanitize_cost_table(&mut table, true), Err(3));
+}
+
+#[test]
+fn sanitize_inf_depends_on_forbidden_flag() {
+    let mut table = vec![0.0, f64::INFINITY, 1.0];
+    assert_eq!(sanitize_cost_table(&mut table, false), Err(1));
+    assert_eq!(sanitize_cost_table(&mut table, true), Ok(()));
+    assert_eq!(table, vec![0.0, FORBIDDEN_COST, 1.0]);
+
+    let mut table = vec![0.0, f64::NEG_INFINITY];
+    assert_eq!(sanitize_cost_table(&mut table, true), Err(1));
+}
+
+#[test]
+fn per_edge_components_sum_to_total() {
+    for harmonic_across_breaks in [false, true] {
+        let params = ydj_mixer_engine::cost::CostParams { harmonic_across_breaks, ..cost_params() };
+        for seed in 0..50 {
+            let inst = instance(12, seed);
+            let tables = inst.tables();
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut order: Vec<usize> = (0..inst.n()).collect();
+            order.shuffle(&mut rng);
+            let shifts: Vec<i8> = (0..inst.n()).map(|_| rng.random_range(-1..=1)).collect();
+
+            let (mut h, mut t, mut s) = (0.0, 0.0, 0.0);
+            for w in order.windows(2) {
+                let (eh, et) = edge_components(w[0], w[1], shifts[w[0]], shifts[w[1]], &tables, &params);
+                assert_eq!(
+                    edge_cost(w[0], w[1], shifts[w[0]], shifts[w[1]], &tables, &params),
+                    eh + params.tempo_cost_weight * et,
+                );
+                h += eh;
+                t += et;
+            }
+            for &i in &order {
+                let (nh, nt, ns) = tables.node_components(i, shifts[i], &params);
+                h += nh;
+                t += nt;
+                s += ns;
+            }
+            assert_eq!(total_edge_cost(&order, &shifts, &tables, &params), (h, t, s));
+        }
+    }
+}
+
+#[test]
+fn explain_edge_matches_edge_cost() {
+    let params = cost_params();
+    let inst = instance(10, 7);
+    let tables = inst.tables();
+    for i in 0..inst.n() {
+        for j in 0..inst.n() {
+            for (s1, s2) in [(-1, 0), (0, 0), (1, -1)] {
+                let e = explain_edge(i, j, s1, s2, &tables, &params);
+                assert_eq!(e.edge_cost, edge_cost(i, j, s1, s2, &tables, &params));
+                assert_eq!(e.harmonic_assessed, !e.tempo_break);
+                // The cells consulted decide the legacy surcharge
+                let idx = e.from_effective_key * params.num_keys + e.to_effective_key;
+                assert_eq!((e.direct_cell, e.indirect_cell), (inst.direct_costs[idx], inst.indirect_costs[idx]));
+                assert!(!e.surcharge_from_mask);
+                if e.harmonic_assessed {
+                    assert_eq!(
+                        e.non_harmonic_surcharge,
+                        e.direct_cell == params.non_harmonic_cost && e.indirect_cell >= params.non_harmonic_cost
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn explain_edge_tempo_cost_matches_edge_cost_bit_for_bit() {
+    // 0.7 × 3.0 × 1.5 rounds differently depending on which product comes first
+    let params = CostParams { tempo_cost_weight: 0.7, tempo_penalty: 3.0, tempo_break_factor: 1.5, ..cost_params() };
+    let mut inst = instance(4, 7);
+    inst.bpms = vec![120, 60, 126, 128];
+    let tables = inst.tables();
+    for (i, j) in [(0, 1), (1, 0), (0, 2), (2, 3)] {
+        let e = explain_edge(i, j, 0, 0, &tables, &params);
+        assert_eq!(e.edge_cost, edge_cost(i, j, 0, 0, &tables, &params));
+        assert_eq!(e.tempo_cost, params.tempo_cost_weight * edge_components(i, j, 0, 0, &tables, &params).1);
+    }
+    assert!(explain_edge(0, 1, 0, 0, &tables, &params).tempo_break);
+}
+
+#[test]
+fn harmonic_mask_matching_legacy_rule_changes_nothing() {
+    let params = cost_params();
+    let inst = instance(10, 3);
+    let mask: Vec<u8> = inst
+        .direct_costs
+        .iter()
+        .zip(&inst.indirect_costs)
+        .map(|(&d, &ind)| u8::from(!(d == params.non_harmonic_cost && ind >= params.non_harmonic_cost)))
+        .collect();
+    let legacy = inst.tables();
+    let mut masked = inst.tables();
+    masked.harmonic_mask = Some(&mask);
+    for i in 0..inst.n() {
+        for j in 0..inst.n() {
+            assert_eq!(
+                edge_cost(i, j, 0, 1, &legacy, &params),
+                edge_cost(i, j, 0, 1, &masked, &params),
+            );
+        }
+    }
+
+    // An all-related mask never applies the surcharge
+    let related = vec![1u8; mask.len()];
+    masked.harmonic_mask = Some(&related);
+    for i in 0..inst.n() {
+        for j in 0..inst.n() {
+            assert!(!explain_edge(i, j, 0, 0, &masked, &params).non_harmonic_surcharge);
+        }
+    }
+}
+
+#[test]
+fn key_confidence_scales_only_the_harmonic_part() {
+    let inst = instance(10, 13);
+    let conf: Vec<f64> = (0..inst.n()).map(|i| i as f64 / 10.0).collect();
+    let plain = inst.tables();
+    let mut weighted = inst.tables();
+    weighted.key_confidence = Some(&conf);
+    weighted.exit_key_confidence = Some(&conf);
+    for confidence_product in [false, true] {
+        let params = ydj_mixer_engine::cost::CostParams { confidence_product, ..cost_params() };
+        for i in 0..inst.n() {
+            for j in 0..inst.n() {
+                let (h, t) = edge_components(i, j, 1, 0, &plain, &params);
+                let (wh, wt) = edge_components(i, j, 1, 0, &weighted, &params);
+                let w = if confidence_product { conf[i] * conf[j] } else { conf[i].min(conf[j]) };
+                assert_eq!((wh, wt), (h * w, t));
+                assert_eq!(explain_edge(i, j, 1, 0, &weighted, &params).key_confidence, w);
+            }
+        }
+    }
+}
+
+#[test]
+fn adjacency_bonus_is_credited_in_either_order() {
+    let params = cost_params();
+    let inst = instance(6, 5);
+    let adj = AdjacencyBonus::from_pairs(6, &[(1, 4, 3.5), (0, 2, 1.0)]).unwrap();
+    let plain = inst.tables();
+    let mut tables = inst.tables();
+    tables.adjacency = Some(&adj);
+    for (i, j, bonus) in [(1, 4, 3.5), (4, 1, 3.5), (2, 0, 1.0), (1, 2, 0.0)] {
+        assert_eq!(
+            edge_cost(i, j, 0, 0, &tables, &params),
+            edge_cost(i, j, 0, 0, &plain, &params) - bonus,
+        );
+        assert_eq!(explain_edge(i, j, 0, 0, &tables, &params).adjacency_bonus, bonus);
+    }
+    assert_eq!(adj.total(&[3, 1, 4, 5, 2, 0]), 4.5);
+}
+
+#[test]
+fn adjacency_pairs_are_validated() {
+    let err = |pairs: &[(usize, usize, f64)]| AdjacencyBonus::from_pairs(4, pairs).err().unwrap();
+    assert!(err(&[(0, 4, 1.0)]).contains(">= 4"));
+    assert!(err(&[(2, 2, 1.0)]).contains("itself"));
+    assert!(err(&[(0, 1, -1.0)]).contains("bonus"));
+    assert!(err(&[(0, 1, f64::NAN)]).contains("bonus"));
+    assert!(err(&[(0, 1, 1.0), (1, 0, 2.0)]).contains("more than once"));
+}
+
+#[test]
+fn half_time_intro_is_not_a_tempo_break() {
+    let params = cost_params();
+    let inst = instance(2, 0);
+    // An 88 BPM track into a 174 BPM DnB track whose intro runs half-time at 87
+    let bpms = [88, 174];
+    let intro = [88, 87];
+    let mut tables = inst.tables();
+    tables.bpms = &bpms;
+    tables.exit_bpms = &bpms;
+    assert!(explain_edge(0, 1, 0, 0, &tables, &params).tempo_break);
+
+    tables.bpms = &intro;
+    let e = explain_edge(0, 1, 0, 0, &tables, &params);
+    assert!(!e.tempo_break && !e.over_threshold);
+    assert_eq!(e.bpm_diff, 1.0);
+    // The reverse edge leaves the DnB track at its full-time outro
+    assert!(explain_edge(1, 0, 0, 0, &tables, &params).tempo_break);
+}
+
+#[test]
+fn extreme_bpms_do_not_overflow() {
+    let params = cost_params();
+    let inst = instance(2, 0);
+    let bpms = [i32::MIN, i32::MAX];
+    let mut tables = inst.tables();
+    tables.bpms = &bpms;
+    tables.exit_bpms = &bpms;
+    let e = explain_edge(0, 1, 0, 0, &tables, &params);
+    assert!(e.tempo_break);
+    assert_eq!(e.bpm_diff, u32::MAX as f64);
+    assert_eq!(e.edge_cost, edge_cost(1, 0, 0, 0, &tables, &params));
+}
+
+#[test]
+fn blend_scale_follows_the_outgoing_track() {
+    let params = cost_params();
+    let inst = instance(8, 17);
+    let scale = [0.25, 2.0, 1.0, 0.0, 0.5, 1.5, 1.0, 0.75];
+    let plain = inst.tables();
+    let mut scaled = inst.tables();
+    scaled.blend_scale = Some(&scale);
+    for (i, &b) in scale.iter().enumerate() {
+        for j in 0..inst.n() {
+            let (h, t) = edge_components(i, j, 0, -1, &plain, &params);
+            assert_eq!(edge_components(i, j, 0, -1, &scaled, &params), (h * b, t));
+            let e = explain_edge(i, j, 0, -1, &scaled, &params);
+            assert_eq!((e.blend_scale, e.harmonic_cost), (b, h * b));
+        }
+    }
+}
+
+#[test]
+fn cut_takes_the_cheaper_of_blend_and_cut() {
+    let inst = instance(10, 23);
+    let tables = inst.tables();
+    let blend = cost_params();
+    let params = ydj_mixer_engine::cost::CostParams {
+        cut_penalty: Some(4.0),
+        cut_harmonic_discount: 0.75,
+        ..cost_params()
+    };
+    let mut cuts = 0;
+    for i in 0..inst.n() {
+        for j in 0..inst.n() {
+            let (h, t) = edge_components(i, j, 0, 0, &tables, &blend);
+            let (ch, ct) = edge_components(i, j, 0, 0, &tables, &params);
+            let cut = h * 0.25 + 4.0;
+            assert_eq!((ch, ct), (h.min(cut), t));
+            let e = explain_edge(i, j, 0, 0, &tables, &params);
+            assert_eq!(e.cut, cut < h);
+            assert!(!explain_edge(i, j, 0, 0, &tables, &blend).cut);
+            cuts += e.cut as usize;
+        }
+    }
+    assert!(cuts > 0);
+}
+
+#[test]
+fn compat_costs_add_to_or_replace_the_harmonic_part() {
+    let params = cost_params();
+    let inst = instance(5, 8);
+    let costs: Vec<f64> = (0..25).map(|k| k as f64).collect();
+    let plain = inst.tables();
+    for replaces in [false, true] {
+        let compat = CompatCosts::new(5, &costs, 0.5, replaces).unwrap();
+        let mut tables = inst.tables();
+        tables.compat = Some(&compat);
+        for i in 0..5 {
+            for j in 0..5 {
+                let (h, t) = edge_components(i, j, 0, 1, &plain, &params);
+                let c = (i * 5 + j) as f64 * 0.5;
+                let expected = if replaces { c } else { h + c };
+                assert_eq!(edge_components(i, j, 0, 1, &tables, &params), (expected, t));
+                let e = explain_edge(i, j, 0, 1, &tables, &params);
+                assert_eq!(e.compat_cost, c);
+                assert_eq!(e.edge_cost, edge_cost(i, j, 0, 1, &tables, &params));
+            }
+        }
+    }
+    assert!(CompatCosts::new(4, &costs, 1.0, false).err().unwrap().contains("expected 4"));
+    let mut bad = costs.clone();
+    bad[7] = f64::NAN;
+    assert!(CompatCosts::new(5, &bad, 1.0, false).err().unwrap().contains("compat_costs[7]"));
+    assert!(CompatCosts::new(5, &costs, -1.0, false).err().unwrap().contains("compat_weight"));
+}
+
+#[test]
+fn sparse_key_costs_match_their_dense_form() {
+    let params = cost_params();
+    let nh = params.non_harmonic_cost;
+    let overrides: Vec<(usize, usize, f64, f64)> =
+        (0..24).flat_map(|k| [(k, k, 0.0, 0.0), (k, (k + 1) % 24, 1.0, 1.0), (k, (k + 12) % 24, 2.0, 1.5)]).collect();
+    let sparse = SparseKeyCosts::new(24, (nh, nh), &overrides).unwrap();
+    let (direct, indirect) = sparse.to_dense();
+    let inst = instance(12, 31);
+    let dense = Tables::new(&inst.bpms, &inst.key_ids, &inst.shift_table, &direct, &indirect);
+    let mut via_sparse = Tables::new(&inst.bpms, &inst.key_ids, &inst.shift_table, &[], &[]);
+    via_sparse.sparse_costs = Some(&sparse);
+    for i in 0..inst.n() {
+        for j in 0..inst.n() {
+            for (s1, s2) in [(-1, 0), (0, 0), (1, -1)] {
+                assert_eq!(
+                    edge_cost(i, j, s1, s2, &via_sparse, &params),
+                    edge_cost(i, j, s1, s2, &dense, &params),
+                );
+            }
+        }
+    }
+
+    let err = |o: &[(usize, usize, f64, f64)]| SparseKeyCosts::new(24, (nh, nh), o).err().unwrap();
+    assert!(err(&[(24, 0, 1.0, 1.0)]).contains(">= 24"));
+    assert!(err(&[(3, 4, 1.0, 1.0), (3, 4, 2.0, 2.0)]).contains("(3, 4)"));
+    assert!(err(&[(3, 4, f64::NAN, 1.0)]).contains("finite"));
+}
+
+#[test]
+fn score_orders_matches_single_scoring_on_any_thread_count() {
+    let params = cost_params();
+    let inst = instance(10, 17);
+    let tables = inst.tables();
+    let mut rng = StdRng::seed_from_u64(17);
+    let mut orders = Vec::new();
+    let mut shifts = Vec::new();
+    for _ in 0..25 {
+        let mut order: Vec<usize> = (0..inst.n()).collect();
+        order.shuffle(&mut rng);
+        orders.push(order);
+        shifts.push((0..inst.n()).map(|_| rng.random_range(-1i8..=1)).collect::<Vec<i8>>());
+    }
+    let expected: Vec<f64> =
+        orders.iter().zip(&shifts).map(|(o, s)| objective(o, s, &tables, &params)).collect();
+    for threads in [1, 3, 8, 40] {
+        assert_eq!(score_orders(&orders, &shifts, &tables, &params, threads), expected);
+    }
+    assert!(score_orders(&[], &[], &tables, &params, 4).is_empty());
+}
+
+#[test]
+fn evaluate_order_scores_like_score_orders_and_checks_its_input() {
+    let params = cost_params();
+    let inst = instance(9, 18);
+    let mut tables = inst.tables();
+    tables.anchors = Anchors { entry: Some((119, 7)), exit: None };
+    let mut rng = StdRng::seed_from_u64(18);
+    let mut order: Vec<usize> = (0..9).collect();
+    order.shuffle(&mut rng);
+    let shifts: Vec<i8> = (0..9).map(|_| rng.random_range(-1..=1)).collect();
+
+    let (cost, breakdown) = evaluate_order(&order, &shifts, &tables, &params).unwrap();
+    assert_eq!(cost, score_orders(std::slice::from_ref(&order), std::slice::from_ref(&shifts), &tables, &params, 1)[0]);
+    assert_eq!(breakdown, total_edge_cost(&order, &shifts, &tables, &params));
+
+    let mut repeated = order.clone();
+    repeated[0] = repeated[1];
+    assert!(evaluate_order(&repeated, &shifts, &tables, &params).unwrap_err().contains("permutation"));
+    assert!(evaluate_order(&order[1..], &shifts, &tables, &params).is_err());
+    let mut too_far = shifts.clone();
+    too_far[4] = 2;
+    assert_eq!(
+        evaluate_order(&order, &too_far, &tables, &params),
+        Err("shifts[4] is 2, shifts must be -1, 0 or +1".to_string())
+    );
+}
+
+#[test]
+fn compare_orders_lists_the_transitions_each_order_alone_plays() {
+    let params = cost_params();
+    let inst = instance(6, 19);
+    let tables = inst.tables();
+    let (a, b) = ([0, 1, 2, 3, 4, 5], [0, 1, 3, 2, 4, 5]);
+    let (shifts_a, shifts_b) = ([0i8, 1, 0, -1, 0, 0], [0i8; 6]);
+    let c = compare_orders(&a, &shifts_a, &b, &shifts_b, &tables, &params).unwrap();
+    assert_eq!((c.cost_a, c.breakdown_a), evaluate_order(&a, &shifts_a, &tables, &params).unwrap());
+    assert_eq!((c.cost_b, c.breakdown_b), evaluate_order(&b, &shifts_b, &tables, &params).unwrap());
+    assert_eq!(c.edge_costs_a, edge_costs(&a, &shifts_a, &tables, &params));
+    assert_eq!(c.edge_costs_b, edge_costs(&b, &shifts_b, &tables, &params));
+    assert_eq!(c.only_in_a, vec![(1, 2), (2, 3), (3, 4)]);
+    assert_eq!(c.only_in_b, vec![(1, 3), (3, 2), (2, 4)]);
+
+    let same = compare_orders(&a, &shifts_a, &a, &shifts_a, &tables, &params).unwrap();
+    assert!(same.only_in_a.is_empty() && same.only_in_b.is_empty());
+    assert!(compare_orders(&a, &shifts_a, &b[..5], &shifts_b, &tables, &params).unwrap_err().starts_with("order_b"));
+}
+
+#[test]
+fn transition_report_columns_sum_to_the_breakdown() {
+    let mut params = cost_params();
+    params.tempo_cost_weight = 0.7;
+    let inst = instance(12, 20);
+    let confidence: Vec<f64> = (0..12).map(|i| 0.5 + i as f64 / 24.0).collect();
+    let blend: Vec<f64> = (0..12).map(|i| 0.75 + i as f64 / 48.0).collect();
+    let mut tables = inst.tables();
+    tables.anchors = Anchors { entry: Some((120, 3)), exit: Some((128, 5)) };
+    tables.key_confidence = Some(&confidence);
+    tables.exit_key_confidence = Some(&confidence);
+    tables.blend_scale = Some(&blend);
+    let mut rng = StdRng::seed_from_u64(20);
+    let mut order: Vec<usize> = (0..12).collect();
+    order.shuffle(&mut rng);
+    let shifts: Vec<i8> = (0..12).map(|_| rng.random_range(-1..=1)).collect();
+
+    let report = transition_report(&order, &shifts, &tables, &params);
+    assert_eq!(report.len(), 11);
+    let (h, t, _) = total_edge_cost(&order, &shifts, &tables, &params);
+    assert_eq!(report.iter().map(|r| r.harmonic).sum::<f64>(), h);
+    assert_eq!(report.iter().map(|r| r.tempo).sum::<f64>(), t);
+    let (cost, _) = evaluate_order(&order, &shifts, &tables, &params).unwrap();
+    let total: f64 = report.iter().map(|r| r.cost + r.shift_cost + r.anchor_cost).sum();
+    assert!((total - cost).abs() < 1e-9);
+    assert_eq!(report[0].anchor_cost + report[10].anchor_cost, tables.boundary_cost(&order, &shifts, &params));
+    for (r, w) in report.iter().zip(order.windows(2)) {
+        assert_eq!(r.key_confidence, params.confidence_weight(confidence[w[0]], confidence[w[1]]));
+        assert_eq!(r.blend_scale, blend[w[0]]);
+        assert_eq!((r.from_track, r.to_track), (w[0], w[1]));
+        assert_eq!(r.bpm_diff, (inst.bpms[w[0]] - inst.bpms[w[1]]).abs() as f64);
+        assert_eq!(r.from_key, effective_key(&inst.shift_table, inst.key_ids[w[0]], shifts[w[0]]));
+    }
+}
+
+#[test]
+fn stability_charges_each_broken_reference_pair() {
+    let st = StabilityPenalty::new(&[2, 0, 3, 1], 1.5).unwrap();
+    assert_eq!(st.broken(&[2, 0, 3, 1]), 0);
+    // 2→0 and 3→1 survive; 0→3 is broken
+    assert_eq!(st.broken(&[3, 1, 2, 0]), 1);
+    // Reversing breaks every pair
+    assert_eq!(st.broken(&[1, 3, 0, 2]), 3);
+    assert_eq!((st.get(0, 3), st.get(3, 0)), (0.0, 1.5));
+
+    // Nodes {2, 0} and {3} and {1}: only boundary successors survive the contraction
+    let nodes = st.select(&[0, 3, 1], &[2, 3, 1]);
+    assert_eq!(nodes.broken(&[0, 1, 2]), 0);
+    assert_eq!(nodes.broken(&[1, 2, 0]), 1);
+
+    let inst = instance(4, 3);
+    let mut tables = inst.tables();
+    let params = cost_params();
+    let plain = edge_cost(1, 2, 0, 0, &tables, &params);
+    tables.stability = Some(&st);
+    assert_eq!(edge_cost(1, 2, 0, 0, &tables, &params), plain + 1.5);
+    assert_eq!(explain_edge(1, 2, 0, 0, &tables, &params).stability_cost, 1.5);
+
+    assert!(StabilityPenalty::new(&[0, 0, 1], 1.0).is_err());
+    assert!(StabilityPenalty::new(&[0, 1], -1.0).is_err());
+}
+
+#[test]
+fn harmonic_only_drops_the_tempo_component() {
+    let params = CostParams { harmonic_only: true, ..cost_params() };
+    let across = CostParams { harmonic_across_breaks: true, ..cost_params() };
+    let mut inst = instance(8, 21);
+    // Every edge is a tempo break, and so are both anchor edges
+    inst.bpms = vec![70, 130, 70, 130, 70, 130, 70, 130];
+    let mut tables = inst.tables();
+    tables.anchors = Anchors { entry: Some((200, 3)), exit: Some((10, 9)) };
+    let order: Vec<usize> = (0..8).collect();
+    let shifts = vec![1i8, 0, -1, 0, 1, 0, -1, 0];
+    let (h, t, s) = total_edge_cost(&order, &shifts, &tables, &params);
+    let (across_h, across_t, across_s) = total_edge_cost(&order, &shifts, &tables, &across);
+    assert_eq!(t, 0.0);
+    assert!(across_t > 0.0);
+    assert_eq!((h, s), (across_h, across_s));
+    for w in order.windows(2) {
+        let e = explain_edge(w[0], w[1], shifts[w[0]], shifts[w[1]], &tables, &params);
+        assert!(!e.tempo_break && e.harmonic_assessed);
+        assert_eq!(e.tempo_cost, 0.0);
+        assert_eq!(edge_components(w[0], w[1], shifts[w[0]], shifts[w[1]], &tables, &params).1, 0.0);
+    }
+}
+
+#[test]
+fn tempo_slope_grows_with_the_overshoot() {
+    // Threshold 4.5, break at 9: a step of 5, plus 2 per BPM over the threshold
+    let params = CostParams { tempo_penalty_slope: 2.0, ..cost_params() };
+    let mut inst = instance(6, 22);
+    inst.bpms = vec![120, 124, 130, 138, 128, 128];
+    let tables = inst.tables();
+    let t = |i: usize, j: usize| edge_components(i, j, 0, 0, &tables, &params).1;
+    assert_eq!(t(0, 1), 0.0);
+    assert_eq!(t(1, 2), 5.0 + 2.0 * 1.5);
+    assert_eq!(t(2, 3), 5.0 + 2.0 * 3.5);
+    assert_eq!(t(0, 3), 10.0);
+    assert_eq!(edge_components(1, 2, 0, 0, &tables, &cost_params()).1, 5.0);
+    let e = explain_edge(2, 3, 0, 0, &tables, &params);
+    assert!(e.over_threshold && !e.tempo_break);
+    assert_eq!(e.tempo_cost, 12.0);
+    let order: Vec<usize> = (0..6).collect();
+    let shifts = vec![0i8; 6];
+    let (_, total_t, _) = total_edge_cost(&order, &shifts, &tables, &params);
+    // 124 → 130 and 130 → 138 are sloped, 138 → 128 is a break
+    assert_eq!(total_t, 8.0 + 12.0 + 10.0);
+}
+
+#[test]
+fn perceptual_tempo_weighs_a_jump_by_its_ratio() {
+    let params = CostParams { perceptual_tempo: true, ..cost_params() };
+    let mut inst = instance(6, 23);
+    // The same 4 BPM jump at 90, 170 and 120
+    inst.bpms = vec![90, 94, 170, 174, 120, 124];
+    let tables = inst.tables();
+    let t = |i: usize, j: usize, p: &CostParams| edge_components(i, j, 0, 0, &tables, p).1;
+    assert_eq!((t(0, 1, &params), t(2, 3, &params), t(4, 5, &params)), (5.0, 0.0, 0.0));
+    assert_eq!((t(0, 1, &cost_params()), t(2, 3, &cost_params())), (0.0, 0.0));
+    // 90 → 120 is a break either way
+    assert_eq!(t(0, 4, &params), 10.0);
+
+    let e = explain_edge(0, 1, 0, 0, &tables, &params);
+    assert_eq!(e.bpm_diff, 4.0);
+    assert!((e.tempo_diff - 120.0 * (94.0f64 / 90.0).ln()).abs() < 1e-12);
+    assert!(e.over_threshold && !e.tempo_break);
+    assert!((params.tempo_diff(170, 174) - params.tempo_diff(174, 170)).abs() < 1e-12);
+
+    let order = [0, 1, 2, 3, 4, 5];
+    let (_, total_t, _) = total_edge_cost(&order, &[0; 6], &tables, &params);
+    // 94 → 170 is a break; 174 → 120 is one too
+    assert_eq!(total_t, 5.0 + 10.0 + 0.0 + 10.0 + 0.0);
+}
+
+#[test]
+fn shift_ties_go_to_zero() {
+    // Tempo breaks on both sides: no shift changes the edges, and none is penalised
+    let params = CostParams { shift_penalty: 0.0, ..cost_params() };
+    let mut inst = instance(3, 23);
+    inst.bpms = vec![70, 130, 70];
+    let tables = inst.tables();
+    let order = [0, 1, 2];
+    for start in [-1, 1] {
+        let mut shifts = vec![start; 3];
+        optimize_shift_at(&order, &mut shifts, 1, &tables, &params);
+        assert_eq!(shifts, [start, 0, start]);
+    }
+}
+
+#[test]
+fn pairwise_best_costs_take_the_cheapest_shift_pair() {
+    let params = cost_params();
+    let inst = instance(6, 29);
+    let tables = inst.tables();
+    let costs = pairwise_best_costs(6, &tables, &params);
+    assert_eq!(costs.len(), 36);
+    for i in 0..6 {
+        assert_eq!(costs[i * 6 + i], 0.0);
+        for j in (0..6).filter(|&j| j != i) {
+            let all: Vec<f64> = [-1, 0, 1]
+                .iter()
+                .flat_map(|&s1| [-1, 0, 1].map(|s2| edge_cost(i, j, s1, s2, &tables, &params)))
+                .collect();
+            assert!(all.iter().all(|&c| c >= costs[i * 6 + j]));
+            assert!(all.contains(&costs[i * 6 + j]));
+        }
+    }
+}
+
+#[test]
+fn shift_search_pays_for_the_shift_penalty() {
+    // No edge saves more than the penalty, so every shift goes back to 0
+    let params = CostParams { shift_penalty: 100.0, ..cost_params() };
+    let inst = instance(8, 31);
+    let tables = inst.tables();
+    let order: Vec<usize> = (0..8).collect();
+    let mut shifts = vec![1; 8];
+    for pos in 0..8 {
+        optimize_shift_at(&order, &mut shifts, pos, &tables, &params);
+    }
+    assert_eq!(shifts, vec![0; 8]);
+}
+
+#[test]
+fn validate_rejects_incoherent_params() {
+    assert_eq!(cost_params().validate(), Ok(()));
+    assert_eq!(CostParams { tempo_break_factor: 1.0, ..cost_params() }.validate(), Ok(()));
+    let rejected = [
+        ("tempo_break_factor", CostParams { tempo_break_factor: 0.5, ..cost_params() }),
+        ("tempo_break_factor", CostParams { tempo_break_factor: f64::INFINITY, ..cost_params() }),
+        ("tempo_threshold", CostParams { tempo_threshold: -1.0, ..cost_params() }),
+        ("tempo_penalty", CostParams { tempo_penalty: -5.0, ..cost_params() }),
+        ("tempo_cost_weight", CostParams { tempo_cost_weight: -1.0, ..cost_params() }),
+        ("non_harmonic_cost", CostParams { non_harmonic_cost: f64::NAN, ..cost_params() }),
+        ("shift_penalty", CostParams { shift_penalty: -1.0, ..cost_params() }),
+        ("shift_penalty", CostParams { shift_penalty_by_direction: Some((0.5, -1.0)), ..cost_params() }),
+        ("shift_penalty", CostParams { shift_penalty_by_direction: Some((f64::NAN, 1.0)), ..cost_params() }),
+        ("shift_weight", CostParams { shift_weight: -0.5, ..cost_params() }),
+        ("minmax_blend", CostParams { minmax_blend: 1.5, ..cost_params() }),
+        ("minmax_blend", CostParams { minmax_blend: f64::NAN, ..cost_params() }),
+    ];
+    for (key, params) in rejected {
+        let err = params.validate().unwrap_err();
+        assert!(err.starts_with(key), "{err}");
+    }
+}
+
+#[test]
+fn canonical_orientation_only_flips_symmetric_orders() {
+    let params = cost_params();
+    let inst = instance(8, 37);
+    let tables = inst.tables();
+    let shifts = vec![0; 8];
+    let reversible = |order: &[usize]| {
+        let reversed: Vec<usize> = order.iter().rev().copied().collect();
+        objective(order, &shifts, &tables, &params) == objective(&reversed, &shifts, &tables, &params)
+    };
+
+    let mut order = vec![7, 2, 5, 0, 3, 6, 1, 4];
+    assert!(reversible(&order));
+    assert!(canonicalize_orientation(&mut order, &shifts, &tables, &params, |_| 0.0));
+    assert_eq!(order, [4, 1, 6, 3, 0, 5, 2, 7]);
+    assert!(!canonicalize_orientation(&mut order, &shifts, &tables, &params, |_| 0.0));
+
+    // An order-dependent extra term makes the reversal cost more, so it is kept
+    let mut order = vec![7, 2, 5, 0, 3, 6, 1, 4];
+    assert!(!canonicalize_orientation(&mut order, &shifts, &tables, &params, |o| o[0] as f64));
+    assert_eq!(order[0], 7);
+}
+
+#[test]
+fn shift_dependent_compat_costs_follow_both_shifts() {
+    let params = cost_params();
+    let n = 4;
+    let inst = instance(n, 41);
+    let mut tables = inst.tables();
+    let costs: Vec<f64> = (0..n * n * 9).map(|k| k as f64).collect();
+    let compat = CompatCosts::with_shifts(n, &costs, 0.5, true).unwrap();
+    tables.compat = Some(&compat);
+    let plain = CostParams { harmonic_only: true, ..cost_params() };
+    for (s1, s2) in [(-1, -1), (0, 1), (1, 0)] {
+        let entry = (2 * n + 3) * 9 + (s1 + 1) as usize * 3 + (s2 + 1) as usize;
+        assert_eq!(compat.get(2, 3, s1, s2), 0.5 * entry as f64);
+        assert_eq!(edge_cost(2, 3, s1, s2, &tables, &plain), 0.5 * entry as f64);
+        assert_eq!(explain_edge(2, 3, s1, s2, &tables, &params).compat_cost, 0.5 * entry as f64);
+    }
+    assert_eq!(compat.total(&[2, 3, 0], &[0, 1, 0, -1]), compat.get(2, 3, 0, -1) + compat.get(3, 0, -1, 0));
+    assert!(CompatCosts::with_shifts(n, &costs[..n * n], 1.0, true).is_err());
+}
+
+#[test]
+fn shift_penalty_cost_counts_nonzero_shifts() {
+    let params = CostParams { shift_penalty: 1.5, shift_weight: 2.0, ..cost_params() };
+    assert_eq!(params.shift_penalty_cost(&[0, 1, -1, 0, 1]), Ok((4.5, 9.0)));
+    assert_eq!(params.shift_penalty_cost(&[]), Ok((0.0, 0.0)));
+    let err = params.shift_penalty_cost(&[0, 2]).unwrap_err();
+    assert!(err.contains("shifts[1] is 2"), "{err}");
+
+    // Same as the optimizers' s component
+    let inst = instance(6, 43);
+    let shifts = [1, 0, -1, -1, 0, 1];
+    let (_, _, s) = total_edge_cost(&[0, 1, 2, 3, 4, 5], &shifts, &inst.tables(), &params);
+    assert_eq!(params.shift_penalty_cost(&shifts).unwrap().0, s);
+}
+
+#[test]
+fn shift_penalties_can_differ_by_direction() {
+    let params = CostParams { shift_penalty_by_direction: Some((0.25, 2.0)), ..cost_params() };
+    assert_eq!((params.shift_penalty_of(-1), params.shift_penalty_of(0), params.shift_penalty_of(1)), (0.25, 0.0, 2.0));
+    assert_eq!(params.shift_penalty_cost(&[0, 1, -1, -1]), Ok((2.5, 2.5)));
+    let inst = instance(6, 43);
+    let tables = inst.tables();
+    let shifts = [1, 0, -1, -1, 0, 1];
+    let (_, _, s) = total_edge_cost(&[0, 1, 2, 3, 4, 5], &shifts, &tables, &params);
+    assert_eq!(s, 4.5);
+
+    // Either shift of track 1 turns its clash with track 0 into a perfect match, so the
+    // search takes the cheaper direction
+    let mut inst = instance(2, 47);
+    inst.bpms = vec![120, 120];
+    inst.key_ids = vec![0, 12];
+    inst.shift_table = (0..24u8).flat_map(|k| [k; 3]).collect();
+    inst.shift_table[12 * 3..12 * 3 + 3].copy_from_slice(&[0, 12, 0]);
+    let tables = inst.tables();
+    for (penalties, expected) in [((0.25, 2.0), -1), ((2.0, 0.25), 1)] {
+        let params = CostParams { shift_penalty_by_direction: Some(penalties), ..cost_params() };
+        let mut shifts = vec![0; 2];
+        optimize_shift_at(&[0, 1], &mut shifts, 1, &tables, &params);
+        assert_eq!(shifts, [0, expected]);
+    }
+}
+
+#[test]
+fn builtin_camelot_costs_follow_the_wheel() {
+    // Camelot key id = (number - 1) * 2 + letter, A = 0, B = 1
+    let key = |number: usize, letter: char| (number - 1) * 2 + (letter == 'B') as usize;
+    let params = cost_params();
+    let (direct, indirect) = camelot_key_costs(params.non_harmonic_cost);
+    assert_eq!(direct.len(), 24 * 24);
+    let inst = instance(2, 1);
+    let tables = Tables::new(&inst.bpms, &inst.key_ids, &inst.shift_table, &direct, &indirect);
+    let cost = |a: usize, b: usize| tables.harmonic_cost(a * 24 + b, &params);
+
+    assert_eq!(cost(key(8, 'A'), key(8, 'A')), (0.0, false));
+    for b in [key(8, 'B'), key(9, 'A'), key(7, 'A')] {
+        assert_eq!(cost(key(8, 'A'), b), (1.0, false));
+        assert_eq!(cost(b, key(8, 'A')), (1.0, false));
+    }
+    assert_eq!(cost(key(12, 'B'), key(1, 'B')), (1.0, false));
+    assert_eq!(cost(key(8, 'A'), key(10, 'A')), (2.0, false));
+    assert_eq!(cost(key(8, 'A'), key(3, 'A')), (2.0, false));
+    assert_eq!(cost(key(8, 'A'), key(3, 'B')), (15.0, true));
+    assert_eq!(cost(key(8, 'A'), key(9, 'B')), (15.0, true));
+}
+
+#[test]
+fn leave_one_out_restitches_around_each_track() {
+    let params = cost_params();
+    let mut inst = instance(10, 44);
+    let tables = inst.tables();
+    let mut rng = StdRng::seed_from_u64(44);
+    let mut order: Vec<usize> = (0..10).collect();
+    order.shuffle(&mut rng);
+    let shifts: Vec<i8> = (0..10).map(|_| rng.random_range(-1..=1)).collect();
+    let costs = leave_one_out_costs(&order, &shifts, &tables, &params);
+    for (pos, &track) in order.iter().enumerate() {
+        let mut rest = order.clone();
+        rest.remove(pos);
+        // Re-choosing the neighbours' shifts can only improve on keeping them
+        assert!(costs[track] <= objective(&rest, &shifts, &tables, &params) + 1e-9);
+    }
+
+    // One clashing track in an otherwise uniform set: dropping it leaves a free set
+    inst.bpms = vec![120; 10];
+    inst.key_ids = vec![0; 10];
+    inst.bpms[6] = 126;
+    inst.key_ids[6] = 13;
+    let tables = inst.tables();
+    let shifts = vec![0; 10];
+    let base = objective(&order, &shifts, &tables, &params);
+    let costs = leave_one_out_costs(&order, &shifts, &tables, &params);
+    assert_eq!(costs[6], 0.0);
+    assert!(base > 0.0 && (0..10).filter(|&t| t != 6).all(|t| costs[t] > 0.0));
+}
+
+#[test]
+fn removal_deltas_match_leave_one_out() {
+    let params = cost_params();
+    let mut rng = StdRng::seed_from_u64(45);
+    for n in [1, 2, 3, 5, 12] {
+        let inst = instance(n, 45 + n as u64);
+        let mut tables = inst.tables();
+        tables.anchors = Anchors { entry: Some((121, 3)), exit: Some((127, 20)) };
+        let mut order: Vec<usize> = (0..n).collect();
+        order.shuffle(&mut rng);
+        let shifts: Vec<i8> = (0..n).map(|_| rng.random_range(-1..=1)).collect();
+        let base = objective(&order, &shifts, &tables, &params);
+        let costs = leave_one_out_costs(&order, &shifts, &tables, &params);
+        let deltas = removal_deltas(&order, &shifts, &tables, &params);
+        for t in 0..n {
+            assert!((deltas[t] - (costs[t] - base)).abs() < 1e-9, "n = {n}, track {t}");
+        }
+    }
+}
+
+#[test]
+fn swap_and_move_previews_match_a_full_rescore() {
+    let params = cost_params();
+    let inst = instance(8, 48);
+    let intro: Vec<i32> = inst.bpms.iter().map(|b| b - 2).collect();
+    let mut tables = inst.tables();
+    tables.bpms = &intro;
+    tables.anchors = Anchors { entry: Some((119, 7)), exit: Some((130, 2)) };
+    let mut rng = StdRng::seed_from_u64(48);
+    let mut order: Vec<usize> = (0..8).collect();
+    order.shuffle(&mut rng);
+    let shifts: Vec<i8> = (0..8).map(|_| rng.random_range(-1..=1)).collect();
+    let before = objective(&order, &shifts, &tables, &params);
+    let adjacent = |o: &[usize], x: usize, y: usize| o.windows(2).any(|w| w == [x, y]);
+
+    for a in 0..8 {
+        for b in (0..8).filter(|&b| b != a) {
+            let preview = swap_delta(&order, &shifts, a, b, &tables, &params);
+            let mut edited = order.clone();
+            edited.swap(a, b);
+            let mut edited_shifts = shifts.clone();
+            for &(t, s) in &preview.shifts {
+                edited_shifts[t] = s;
+            }
+            let after = objective(&edited, &edited_shifts, &tables, &params);
+            assert!((preview.delta - (after - before)).abs() < 1e-9, "swap {a} {b}");
+            let costs = edge_costs(&edited, &edited_shifts, &tables, &params);
+            for &(j, c) in &preview.edges {
+                assert!((c - costs[j]).abs() < 1e-12);
+            }
+        }
+    }
+
+    for from in 0..8 {
+        for to in 0..8 {
+            let preview = move_delta(&order, &shifts, from, to, &tables, &params);
+            let mut moved = order.clone();
+            let track = moved.remove(from);
+            moved.insert(to, track);
+            let mut moved_shifts = shifts.clone();
+            assert_eq!(preview.shifts.len(), 1);
+            moved_shifts[track] = preview.shifts[0].1;
+            let after = objective(&moved, &moved_shifts, &tables, &params);
+            assert!((preview.delta - (after - before)).abs() < 1e-9, "move {from} to {to}");
+            let costs = edge_costs(&moved, &moved_shifts, &tables, &params);
+            for j in 0..7 {
+                match preview.edges.iter().find(|&&(e, _)| e == j) {
+                    Some(&(_, c)) => assert!((c - costs[j]).abs() < 1e-12),
+                    // Every edge left out was already there, between unmoved tracks
+                    None => assert!(
+                        moved[j] != track && moved[j + 1] != track && adjacent(&order, moved[j], moved[j + 1]),
+                        "move {from} to {to}: edge {j}"
+                    ),
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn change_delta_matches_a_full_rescore_on_random_moves() {
+    let params = cost_params();
+    let inst = instance(12, 49);
+    let mut tables = inst.tables();
+    tables.anchors = Anchors { entry: Some((119, 7)), exit: Some((130, 2)) };
+    let mut rng = StdRng::seed_from_u64(49);
+    let mut order: Vec<usize> = (0..12).collect();
+    order.shuffle(&mut rng);
+    let shifts: Vec<i8> = (0..12).map(|_| rng.random_range(-1..=1)).collect();
+    let before = objective(&order, &shifts, &tables, &params);
+
+    for round in 0..500 {
+        // Rearrange a random handful of positions and re-shift a random handful of tracks
+        let k = rng.random_range(0..=5);
+        let positions: Vec<usize> = (0..12).choose_multiple(&mut rng, k);
+        let mut tracks: Vec<usize> = positions.iter().map(|&p| order[p]).collect();
+        tracks.shuffle(&mut rng);
+        let changes: Vec<(usize, usize)> = positions.into_iter().zip(tracks).collect();
+        let reshifted = rng.random_range(0..=3);
+        let shift_changes: Vec<(usize, i8)> = (0..12)
+            .choose_multiple(&mut rng, reshifted)
+            .into_iter()
+            .map(|t| (t, rng.random_range(-1..=1)))
+            .collect();
+
+        let result = change_delta(&order, &shifts, &changes, &shift_changes, &tables, &params).unwrap();
+        let mut edited = order.clone();
+        for &(p, t) in &changes {
+            edited[p] = t;
+        }
+        let mut edited_shifts = shifts.clone();
+        for &(t, s) in &shift_changes {
+            edited_shifts[t] = s;
+        }
+        let after = objective(&edited, &edited_shifts, &tables, &params);
+        assert!((result.delta - (after - before)).abs() < 1e-9, "round {round}");
+        let costs = edge_costs(&edited, &edited_shifts, &tables, &params);
+        for j in 0..11 {
+            match result.edges.iter().find(|&&(e, _)| e == j) {
+                Some(&(_, c)) => assert!((c - costs[j]).abs() < 1e-12),
+                None => assert!(
+                    (edited[j], edited[j + 1]) == (order[j], order[j + 1])
+                        && !shift_changes.iter().any(|&(t, _)| t == edited[j] || t == edited[j + 1]),
+                    "round {round}: edge {j}"
+                ),
+            }
+        }
+    }
+
+    assert!(change_delta(&order, &shifts, &[(0, order[1])], &[], &tables, &params).is_err());
+    assert!(change_delta(&order, &shifts, &[(0, order[0]), (0, order[0])], &[], &tables, &params).is_err());
+    assert!(change_delta(&order, &shifts, &[], &[(0, 2)], &tables, &params).is_err());
+    let err = change_delta(&order, &shifts, &[], &[(3, 1), (5, 0), (3, -1)], &tables, &params).unwrap_err();
+    assert!(err.contains("track 3 is re-shifted more than once"), "{err}");
+}
+
+#[test]
+fn reverse_segment_delta_matches_a_full_rescore() {
+    let params = cost_params();
+    let inst = instance(9, 47);
+    // Distinct intro and outro BPMs make every edge direction-dependent
+    let intro: Vec<i32> = inst.bpms.iter().map(|b| b - 3).collect();
+    let outro: Vec<i32> = inst.bpms.iter().map(|b| b + 2).collect();
+    let mut tables = inst.tables();
+    tables.bpms = &intro;
+    tables.exit_bpms = &outro;
+    tables.anchors = Anchors { entry: Some((118, 4)), exit: Some((132, 17)) };
+    let mut rng = StdRng::seed_from_u64(47);
+    let mut order: Vec<usize> = (0..9).collect();
+    order.shuffle(&mut rng);
+    let shifts: Vec<i8> = (0..9).map(|_| rng.random_range(-1..=1)).collect();
+    let base = objective(&order, &shifts, &tables, &params);
+    for i in 0..9 {
+        for j in i..9 {
+            let mut flipped = order.clone();
+            flipped[i..=j].reverse();
+            let (delta, edges) = reverse_segment_delta(&order, &shifts, i, j, &tables, &params);
+            let expected = objective(&flipped, &shifts, &tables, &params) - base;
+            assert!((delta - expected).abs() < 1e-9, "{i}..={j}: {delta} vs {expected}");
+            assert_eq!(edges, edge_costs(&flipped, &shifts, &tables, &params)[i.saturating_sub(1)..j.min(7) + 1]);
+        }
+    }
+}
+
+#[test]
+fn float32_tables_keep_the_non_harmonic_surcharge() {
+    // 5.3 is not representable in f32; the other Camelot costs (0, 1, 2) are
+    let params = CostParams { non_harmonic_cost: 5.3, ..cost_params() };
+    let mut inst = instance(8, 46);
+    (inst.direct_costs, inst.indirect_costs) = camelot_key_costs(params.non_harmonic_cost);
+    let exact = held_karp::run(8, &inst.tables(), &params, None, None, false);
+
+    let mut rounded = instance(8, 46);
+    let pairs = [(&mut rounded.direct_costs, &inst.direct_costs), (&mut rounded.indirect_costs, &inst.indirect_costs)];
+    for (table, from) in pairs {
+        *table = from.iter().map(|&c| c as f32 as f64).collect();
+        restore_f32_value(table, params.non_harmonic_cost);
+        assert_eq!(table, from);
+    }
+    let restored = held_karp::run(8, &rounded.tables(), &params, None, None, false);
+    assert_eq!((restored.0, restored.1, restored.2), (exact.0, exact.1, exact.2));
+}
+
+#[test]
+fn key_relations_match_the_builtin_ranking() {
+    let camelot = |number: usize, letter: usize| (number - 1) * 2 + letter;
+    let cases = [
+        ((8, 0), (8, 0), KeyRelation::PerfectMatch),
+        ((8, 0), (9, 0), KeyRelation::EnergyUp),
+        ((12, 1), (1, 1), KeyRelation::EnergyUp),
+        ((8, 0), (7, 0), KeyRelation::MoodDown),
+        ((8, 0), (8, 1), KeyRelation::Relative),
+        ((8, 0), (10, 0), KeyRelation::EnergyUpTwo),
+        ((1, 0), (11, 0), KeyRelation::MoodDownTwo),
+        ((8, 0), (3, 0), KeyRelation::EnergyBoost),
+        ((8, 0), (9, 1), KeyRelation::Clash),
+        ((8, 0), (4, 0), KeyRelation::Clash),
+    ];
+    for ((n1, l1), (n2, l2), relation) in cases {
+        assert_eq!(classify_transition(camelot(n1, l1), camelot(n2, l2), 24), Ok(relation));
+    }
+    assert_eq!(KeyRelation::EnergyBoost.label(), "energy boost");
+    assert!(classify_transition(0, 1, 12).is_err());
+    assert!(classify_transition(0, 24, 24).is_err());
+
+    let (direct, _) = camelot_key_costs(5.0);
+    for a in 0..24 {
+        for b in 0..24 {
+            let expected = match KeyRelation::classify(a, b) {
+                KeyRelation::PerfectMatch => 0.0,
+                KeyRelation::Clash => 5.0,
+                KeyRelation::EnergyUp | KeyRelation::MoodDown | KeyRelation::Relative => 1.0,
+                _ => 2.0,
+            };
+            assert_eq!(direct[a * 24 + b], expected, "{a} -> {b}");
+        }
+    }
+}
+
+#[test]
+fn camelot_key_strings_parse_to_ids() {
+    assert_eq!(parse_camelot_key("1A"), Ok(0));
+    assert_eq!(parse_camelot_key("8A"), Ok(14));
+    assert_eq!(parse_camelot_key("08b"), Ok(15));
+    assert_eq!(parse_camelot_key(" 12B "), Ok(23));
+    for bad in ["", "A", "0A", "13A", "8C", "8", "+8A", "8AB", "8Ä"] {
+        assert!(parse_camelot_key(bad).is_err(), "{bad:?}");
+    }
+    for id in 0..24 {
+        assert_eq!(parse_camelot_key(&format_camelot_key(id).unwrap()), Ok(id));
+    }
+    assert_eq!(format_camelot_key(14).as_deref(), Ok("8A"));
+    assert!(format_camelot_key(24).is_err());
+}
+
+#[test]
+fn export_schedule_lists_effective_keys_in_play_order() {
+    let table = camelot_shift_table();
+    let key = |k: &str| parse_camelot_key(k).unwrap();
+    let keys = [key("8A"), key("12B"), key("1A")];
+    let shifts = [1, 0, -1];
+    let schedule = export_schedule(&[2, 0, 1], &shifts, &keys, &table).unwrap();
+    // 1A down a semitone is 6A; 8A up a semitone is 3A
+    assert_eq!(schedule, vec![(2, key("6A") as usize, -1), (0, key("3A") as usize, 1), (1, key("12B") as usize, 0)]);
+    // A subset of the tracks, or a repeated one, is fine
+    assert_eq!(export_schedule(&[1, 1], &shifts, &keys, &table).unwrap().len(), 2);
+
+    assert!(export_schedule(&[3], &shifts, &keys, &table).is_err());
+    assert!(export_schedule(&[0], &shifts[..2], &keys, &table).is_err());
+    assert!(export_schedule(&[0], &[2, 0, 0], &keys, &table).is_err());
+    assert!(export_schedule(&[0], &shifts, &keys, &table[..30]).is_err());
+    assert!(export_schedule(&[0], &shifts, &keys, &table[..40]).is_err());
+}
+
+#[test]
+fn camelot_tables_match_the_hand_checked_wheel() {
+    let shifts = camelot_shift_table();
+    assert_eq!(shifts.len(), 72);
+    // 8A (A minor): G# minor = 1A, Bb minor = 3A.  12B (E major): Eb major = 5B, F major = 7B
+    assert_eq!(shifts[14 * 3..14 * 3 + 3], [0, 14, 4]);
+    assert_eq!(shifts[23 * 3..23 * 3 + 3], [9, 23, 13]);
+    assert_eq!(shifts[0..3], [10, 0, 14]);
+
+    let rules = KeyCostRules {
+        same_key: 0.5, adjacent: 1.0, relative: 1.5, two_steps: 2.0, energy_boost: 2.5, other: 9.0,
+    };
+    let (direct, indirect) = camelot_tables(&rules).unwrap();
+    assert_eq!(direct, indirect);
+    // Rows of 8A (id 14) and 1B (id 1), written out by hand
+    let row_8a = [
+        9.0, 9.0, 9.0, 9.0, 2.5, 9.0, 9.0, 9.0, 9.0, 9.0, 2.0, 9.0,
+        1.0, 9.0, 0.5, 1.5, 1.0, 9.0, 2.0, 9.0, 9.0, 9.0, 9.0, 9.0,
+    ];
+    let row_1b = [
+        1.5, 0.5, 9.0, 1.0, 9.0, 2.0, 9.0, 9.0, 9.0, 9.0, 9.0, 9.0,
+        9.0, 9.0, 9.0, 2.5, 9.0, 9.0, 9.0, 9.0, 9.0, 2.0, 9.0, 1.0,
+    ];
+    assert_eq!(direct[14 * 24..15 * 24], row_8a);
+    assert_eq!(direct[24..48], row_1b);
+
+    assert_eq!(camelot_tables(&KeyCostRules::builtin(5.0)).unwrap(), camelot_key_costs(5.0));
+    let bad = KeyCostRules { two_steps: -1.0, ..KeyCostRules::builtin(5.0) };
+    assert!(camelot_tables(&bad).unwrap_err().starts_with("two_steps"));
+}
+
+#[test]
+fn key_notations_round_trip_through_ids() {
+    use KeyNotation::{Camelot, Musical, OpenKey};
+    for id in 0..24 {
+        for notation in [Camelot, OpenKey, Musical] {
+            let written = format_key(id, notation).unwrap();
+            assert_eq!(parse_key(&written, notation), Ok(id), "{written}");
+            // Musical names read the same whatever the wheel notation
+            let musical = format_key(id, Musical).unwrap();
+            assert_eq!(parse_key(&musical, notation), Ok(id), "{musical}");
+        }
+    }
+    // The wheels are seven places apart: A minor is 8A and 1m, C major 8B and 1d
+    for (camelot, open_key, musical) in [("8A", "1m", "Am"), ("8B", "1d", "C"), ("1B", "6d", "B"), ("12A", "5m", "Dbm")] {
+        let id = parse_key(camelot, Camelot).unwrap();
+        assert_eq!(parse_key(open_key, OpenKey), Ok(id));
+        assert_eq!(format_key(id, OpenKey).as_deref(), Ok(open_key));
+        assert_eq!(format_key(id, Musical).as_deref(), Ok(musical));
+    }
+    for (name, camelot) in [("F#maj", "2B"), ("f# minor", "11A"), ("C#m", "12A"), ("Bb Major", "6B"), ("E♭m", "2A")] {
+        assert_eq!(parse_key(name, OpenKey), parse_key(camelot, Camelot), "{name}");
+    }
+    assert!(parse_key("8A", OpenKey).is_err());
+    assert!(parse_key("1m", Camelot).is_err());
+    for bad in ["H", "Amaj7", "C##", "12", "Musical"] {
+        assert!(parse_key(bad, Musical).is_err(), "{bad:?}");
+    }
+    assert_eq!(KeyNotation::from_name("open_key"), Ok(OpenKey));
+    assert!(KeyNotation::from_name("traktor").is_err());
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+use common::{annealing_params, cost_params, instance, objective};
+use ydj_mixer_engine::annealing::run_attempt;
+use ydj_mixer_engine::coverage::{Coverage, CoverageRule};
+use ydj_mixer_engine::separation::Separation;
+
+fn rule(positions: (f64, f64), bpms: (i32, i32), min_count: usize) -> CoverageRule {
+    CoverageRule { positions, bpms, min_count, penalty: 10.0 }
+}
+
+#[test]
+fn swap_delta_matches_recount() {
+    let inst = instance(20, 2);
+    let rules = [rule((0.0, 0.25), (110, 115), 2), rule((0.6, 1.0), (125, 132), 3)];
+    let penalty_only = [rule((0.0, 0.1), (110, 120), 3), rule((0.5, 0.55), (110, 132), 5)];
+    let coverage = Coverage::new(&rules, &inst.bpms).unwrap();
+    let soft = Coverage::new(&penalty_only, &inst.bpms).unwrap();
+    assert_eq!(coverage.modes(), ["hard", "hard"]);
+    assert_eq!(soft.modes(), ["penalty", "penalty"]);
+
+    let mut rng = StdRng::seed_from_u64(2);
+    let mut order: Vec<usize> = (0..20).collect();
+    for _ in 0..500 {
+        order.shuffle(&mut rng);
+        let (a, b) = (rng.random_range(0..20), rng.random_range(0..20));
+        let mut swapped = order.clone();
+        swapped.swap(a, b);
+        let delta = soft.penalty(&swapped) - soft.penalty(&order);
+        assert!((soft.swap_delta(&order, a, b).unwrap() - delta).abs() < 1e-9);
+        let (old, new) = (coverage.shortfalls(&order), coverage.shortfalls(&swapped));
+        match coverage.swap_delta(&order, a, b) {
+            Some(d) => assert!((d - (coverage.penalty(&swapped) - coverage.penalty(&order))).abs() < 1e-9),
+            None => assert!(new.iter().zip(&old).any(|(n, o)| n > o)),
+        }
+    }
+    assert!(Coverage::new(&[rule((0.5, 0.5), (100, 120), 1)], &inst.bpms).is_err());
+}
+
+#[test]
+fn annealing_meets_hard_rules() {
+    let params = cost_params();
+    let mut inst = instance(16, 5);
+    // Two slow and two fast outliers that the cost alone would push together at one end
+    for (i, bpm) in [(0, 90), (1, 92), (2, 150), (3, 152)] {
+        inst.bpms[i] = bpm;
+    }
+    let tables = inst.tables();
+    let rules = [rule((0.0, 0.25), (0, 99), 2), rule((0.75, 1.0), (140, 999), 2)];
+    let sep = Separation { coverage: Some(Coverage::new(&rules, &inst.bpms).unwrap()), ..Default::default() };
+    for seed in 0..3 {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let r = run_attempt(inst.n(), &tables, &params, &annealing_params(), Some(&sep), None, &mut rng);
+        let coverage = sep.coverage.as_ref().unwrap();
+        assert_eq!(coverage.shortfalls(&r.best_order), [0, 0]);
+        let order = &r.best_order;
+        assert!(order[..4].contains(&0) && order[..4].contains(&1));
+        assert!(order[12..].contains(&2) && order[12..].contains(&3));
+        assert!((r.best_cost - objective(order, &r.best_shifts, &tables, &params)).abs() < 1e-9);
+    }
+}
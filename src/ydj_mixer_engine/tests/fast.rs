@@ -0,0 +1,37 @@
+mod common;
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use common::{annealing_params, cost_params, instance, is_permutation, objective};
+use ydj_mixer_engine::annealing::{run_attempt, AnnealingParams};
+use ydj_mixer_engine::fast;
+
+#[test]
+fn fast_is_a_reasonable_seed_compared_to_annealing() {
+    let params = cost_params();
+    // A long schedule, so that SA sets the reference quality on 60 tracks
+    let ann = AnnealingParams { total_iterations: 50_000, ..annealing_params() };
+    let mut ratios = Vec::new();
+    for seed in 0..10 {
+        let inst = instance(60, seed);
+        let tables = inst.tables();
+
+        let (order, shifts, cost, _) = fast::run(inst.n(), &tables, &params);
+        assert!(is_permutation(&order, inst.n()));
+        assert!((cost - objective(&order, &shifts, &tables, &params)).abs() < 1e-9);
+
+        let sa = run_attempt(inst.n(), &tables, &params, &ann, None, None, &mut StdRng::seed_from_u64(seed));
+        ratios.push(cost / sa.best_cost);
+
+        // Far better than the identity order it replaces (measured: at most 0.29 of it)
+        let identity: Vec<usize> = (0..inst.n()).collect();
+        assert!(cost < 0.3 * objective(&identity, &vec![0; inst.n()], &tables, &params));
+    }
+    // Approximate by design.  Measured over these seeds: fast / SA from 0.96 to 1.33, 1.16
+    // on average
+    let worst = ratios.iter().copied().fold(0.0, f64::max);
+    let mean = ratios.iter().sum::<f64>() / ratios.len() as f64;
+    assert!(worst <= 1.4, "fast / SA ratios {ratios:?}");
+    assert!(mean <= 1.25, "fast / SA ratios {ratios:?}");
+}
@@ -0,0 +1,318 @@
+mod common;
+
+use common::{annealing_params, cost_params, instance, is_permutation, objective};
+use ydj_mixer_engine::annealing;
+use ydj_mixer_engine::cost::{
+    edge_cost, edge_costs, Anchors, CostParams, ObjectiveMode, StabilityPenalty, Tables,
+};
+use ydj_mixer_engine::family::{self, FamilyRunLimit};
+use ydj_mixer_engine::held_karp;
+
+/// Exhaustive minimum over every order and every shift assignment.
+fn brute_force(n: usize, tables: &Tables) -> f64 {
+    brute_force_with(n, tables, None, false, &cost_params())
+}
+
+/// `brute_force`, optionally pinning the first track and/or adding the wrap edge.
+fn brute_force_with(
+    n: usize,
+    tables: &Tables,
+    start: Option<usize>,
+    cyclic: bool,
+    params: &CostParams,
+) -> f64 {
+    let mut best = f64::INFINITY;
+    let mut order: Vec<usize> = (0..n).collect();
+    permute(&mut order, 0, &mut |order| {
+        if start.is_some_and(|s| order[0] != s) {
+            return;
+        }
+        for code in 0..3usize.pow(n as u32) {
+            let shifts: Vec<i8> = (0..n).map(|i| (code / 3usize.pow(i as u32) % 3) as i8 - 1).collect();
+            let mut c = objective(order, &shifts, tables, params);
+            if cyclic {
+                let (first, last) = (order[0], order[n - 1]);
+                c += edge_cost(last, first, shifts[last], shifts[first], tables, params);
+            }
+            best = best.min(c);
+        }
+    });
+    best
+}
+
+fn permute(v: &mut [usize], k: usize, f: &mut impl FnMut(&[usize])) {
+    if k == v.len() {
+        f(v);
+        return;
+    }
+    for i in k..v.len() {
+        v.swap(k, i);
+        permute(v, k + 1, f);
+        v.swap(k, i);
+    }
+}
+
+#[test]
+fn matches_brute_force() {
+    let params = cost_params();
+    for seed in 0..3 {
+        let inst = instance(6, seed);
+        let tables = inst.tables();
+        let (order, shifts, cost, _, _) = held_karp::run(inst.n(), &tables, &params, None, None, false);
+        assert!(is_permutation(&order, inst.n()));
+        assert_eq!(cost, brute_force(inst.n(), &tables));
+        assert_eq!(cost, objective(&order, &shifts, &tables, &params));
+    }
+}
+
+#[test]
+fn anchors_match_brute_force() {
+    let params = cost_params();
+    let inst = instance(6, 11);
+    let mut tables = inst.tables();
+    tables.anchors = Anchors { entry: Some((120, 4)), exit: Some((128, 17)) };
+    let (order, shifts, cost, _, _) = held_karp::run(inst.n(), &tables, &params, None, None, false);
+    assert_eq!(cost, brute_force(inst.n(), &tables));
+    assert_eq!(cost, objective(&order, &shifts, &tables, &params));
+}
+
+#[test]
+fn never_worse_than_annealing() {
+    let params = cost_params();
+    let inst = instance(10, 5);
+    let tables = inst.tables();
+    let (_, _, exact, _, _) = held_karp::run(inst.n(), &tables, &params, None, None, false);
+    let (sa, _, _, _) = annealing::run_timed(
+        inst.n(), &tables, &params, &annealing_params(), None, None,
+        annealing::StatsWeighting::Uniform, 0.2,
+    )
+    .unwrap();
+    assert!(exact <= sa.best_cost);
+}
+
+#[test]
+fn annealing_mostly_matches_exact_on_small_sets() {
+    // The shift search charges the shift penalty like the DP does, so the two usually agree
+    // on sets this small.  Optima that shift several neighbours together stay out of reach
+    // of a search that re-shifts one position at a time, hence "mostly".
+    let params = cost_params();
+    let mut agree = 0;
+    for seed in 0..12 {
+        let inst = instance(7, 40 + seed);
+        let tables = inst.tables();
+        let (_, _, exact, _, _) = held_karp::run(inst.n(), &tables, &params, None, None, false);
+        let (sa, _, _, _) = annealing::run_seeded(
+            inst.n(), &tables, &params, &annealing_params(), None, None,
+            annealing::StatsWeighting::Uniform, 4, seed,
+        )
+        .unwrap();
+        assert!(exact <= sa.best_cost + 1e-9);
+        agree += usize::from((sa.best_cost - exact).abs() < 1e-9);
+    }
+    assert!(agree >= 8, "annealing matched the exact optimum on {agree} of 12 sets");
+}
+
+#[test]
+fn shift_breakdown_recombines() {
+    let params = cost_params();
+    let inst = instance(9, 8);
+    let tables = inst.tables();
+    let (_, _, cost, (h, t, s), _) = held_karp::run(inst.n(), &tables, &params, None, None, false);
+    assert_eq!(cost, h + params.tempo_cost_weight * t + params.shift_weight * s);
+}
+
+#[test]
+fn pinned_start_matches_brute_force() {
+    let params = cost_params();
+    let inst = instance(6, 14);
+    let tables = inst.tables();
+    for start in [0, 3] {
+        let (order, shifts, cost, _, _) = held_karp::run(inst.n(), &tables, &params, None, Some(start), false);
+        assert_eq!(order[0], start);
+        assert_eq!(cost, brute_force_with(inst.n(), &tables, Some(start), false, &params));
+        assert_eq!(cost, objective(&order, &shifts, &tables, &params));
+    }
+}
+
+#[test]
+fn cycle_matches_brute_force() {
+    let params = cost_params();
+    for seed in 0..3 {
+        let inst = instance(6, seed + 20);
+        let tables = inst.tables();
+        let (order, shifts, cost, (h, t, s), _) =
+            held_karp::run(inst.n(), &tables, &params, None, Some(2), true);
+        assert_eq!(order[0], 2);
+        assert!(is_permutation(&order, inst.n()));
+        // Any rotation of a cycle costs the same, so pinning the start loses nothing
+        assert_eq!(cost, brute_force_with(inst.n(), &tables, None, true, &params));
+        assert_eq!(cost, h + params.tempo_cost_weight * t + params.shift_weight * s);
+        let wrap = edge_cost(order[5], order[0], shifts[order[5]], shifts[order[0]], &tables, &params);
+        assert_eq!(cost, objective(&order, &shifts, &tables, &params) + wrap);
+    }
+}
+
+#[test]
+fn reused_workspace_matches_fresh_runs() {
+    let params = cost_params();
+    let mut workspace = held_karp::Workspace::new();
+    // Larger, smaller and cyclic runs through one table must not see each other's state
+    for (n, seed, cyclic) in [(9, 30, false), (5, 31, false), (7, 32, true), (9, 33, false)] {
+        let inst = instance(n, seed);
+        let tables = inst.tables();
+        let fresh = held_karp::run(n, &tables, &params, None, None, cyclic);
+        let reused = held_karp::run_in(&mut workspace, n, &tables, &params, None, None, cyclic);
+        assert_eq!((reused.0, reused.1, reused.2), (fresh.0, fresh.1, fresh.2));
+    }
+    assert_eq!(workspace.capacity_bytes(), held_karp::memory_bytes(9, &params).unwrap());
+}
+
+#[test]
+fn memory_estimate_matches_the_dp_table() {
+    let params = cost_params();
+    assert_eq!(held_karp::memory_bytes(20, &params), Some((1 << 20) * 20 * 3 * 8));
+    assert!(held_karp::memory_bytes(21, &params).unwrap() > 512 << 20);
+    assert_eq!(held_karp::memory_bytes(64, &params), None);
+    assert_eq!(held_karp::memory_bytes(1000, &params), None);
+    let limited = CostParams { family_run: Some(FamilyRunLimit { max_run: 3, penalty: 10.0 }), ..cost_params() };
+    assert_eq!(held_karp::memory_bytes(10, &limited), Some((1 << 10) * 10 * 3 * 3 * 8));
+}
+
+#[test]
+fn lexicographic_objective_puts_harmony_first() {
+    // 2A, 1A, 3A at one tempo: the weighted optimum keeps every shift at 0 and pays two
+    // neighbour steps (h = 2); three shifts (cost 3) can save one of them
+    let mut inst = instance(3, 0);
+    inst.bpms = vec![120; 3];
+    inst.key_ids = vec![2, 0, 4];
+    let tables = inst.tables();
+    let weighted = cost_params();
+    let (_, _, _, (h, _, s), _) = held_karp::run(3, &tables, &weighted, None, None, false);
+    assert_eq!((h, s), (2.0, 0.0));
+
+    let mut lexicographic = CostParams { objective_mode: ObjectiveMode::LexicographicHThenT, ..cost_params() };
+    let scale = lexicographic.resolve_objective(3, &tables).unwrap();
+    // 4 edges at the worst tempo charge (5 × 2) plus 3 shifts: 43 < 64
+    assert_eq!(scale, 64.0);
+    assert_eq!(lexicographic.objective_mode, ObjectiveMode::Weighted);
+    let (order, shifts, cost, (h, t, s), _) = held_karp::run(3, &tables, &lexicographic, None, None, false);
+    assert_eq!((h, t, s), (1.0, 0.0, 3.0));
+    assert_eq!(cost, h + (t + s) / scale);
+    assert_eq!(cost, brute_force_with(3, &tables, None, false, &lexicographic));
+    assert!(objective(&order, &shifts, &tables, &weighted) > 2.0);
+}
+
+#[test]
+fn perfect_transitions_objective_counts_imperfect_edges() {
+    let inst = instance(6, 31);
+    let tables = inst.tables();
+    let normal = cost_params();
+    let imperfect = |order: &[usize], shifts: &[i8]| {
+        edge_costs(order, shifts, &tables, &normal).iter().filter(|&&c| !normal.is_perfect(c)).count() as f64
+    };
+
+    let mut perfect = CostParams { objective_mode: ObjectiveMode::PerfectTransitions, ..cost_params() };
+    // Six tracks at one shift each: the shift tie-break stays below one edge
+    assert_eq!(perfect.resolve_objective(6, &tables).unwrap(), 8.0);
+    assert_eq!(perfect.objective_mode, ObjectiveMode::PerfectTransitions);
+    let (order, shifts, cost, (h, t, s), _) = held_karp::run(6, &tables, &perfect, None, None, false);
+    assert_eq!(cost, brute_force_with(6, &tables, None, false, &perfect));
+    assert_eq!((h, t), (imperfect(&order, &shifts), 0.0));
+    assert_eq!(cost, h + s / 8.0);
+
+    // The weighted optimum never has fewer imperfect edges
+    let (w_order, w_shifts, ..) = held_karp::run(6, &tables, &normal, None, None, false);
+    assert!(h <= imperfect(&w_order, &w_shifts));
+}
+
+#[test]
+fn family_run_limit_matches_brute_force() {
+    // Four tracks of family 1 (2A/2B at shift 0) and two of family 6: runs of at most two
+    let mut inst = instance(6, 2);
+    inst.bpms = vec![120; 6];
+    inst.key_ids = vec![2, 3, 2, 3, 12, 13];
+    let tables = inst.tables();
+    let params = CostParams { family_run: Some(FamilyRunLimit { max_run: 2, penalty: 10.0 }), ..cost_params() };
+    let (order, shifts, cost, _, _) = held_karp::run(6, &tables, &params, None, None, false);
+    assert!(family::longest_run(&order, &shifts, &tables) <= 2);
+    assert_eq!(cost, objective(&order, &shifts, &tables, &params));
+
+    // Brute force over the orders and shifts that respect the limit
+    let mut best = f64::INFINITY;
+    let mut all: Vec<usize> = (0..6).collect();
+    permute(&mut all, 0, &mut |order| {
+        for code in 0..3usize.pow(6) {
+            let shifts: Vec<i8> = (0..6).map(|i| (code / 3usize.pow(i as u32) % 3) as i8 - 1).collect();
+            if family::longest_run(order, &shifts, &tables) <= 2 {
+                best = best.min(objective(order, &shifts, &tables, &params));
+            }
+        }
+    });
+    assert_eq!(cost, best);
+    let (_, _, unlimited, _, _) = held_karp::run(6, &tables, &cost_params(), None, None, false);
+    assert!(unlimited < cost);
+
+    // A single family cannot be split at all
+    inst.key_ids = vec![2; 6];
+    let mut one_family = inst.tables();
+    let same_number: Vec<u8> = (0..24u8).flat_map(|k| [k, k, k]).collect();
+    one_family.shift_table = &same_number;
+    let (_, _, cost, _, _) = held_karp::run(6, &one_family, &params, None, None, false);
+    assert_eq!(cost, f64::INFINITY);
+}
+
+#[test]
+fn shift_sweep_matches_brute_force() {
+    // Cheap shifts, so the optimum keeps improving as more tracks may shift
+    let params = CostParams { shift_penalty: 0.5, ..cost_params() };
+    let shifted = |shifts: &[i8]| shifts.iter().filter(|&&s| s != 0).count();
+    for seed in 0..3 {
+        let inst = instance(5, seed);
+        let tables = inst.tables();
+        let sweep = held_karp::shift_sweep(5, &tables, &params, None, 3);
+        assert_eq!(sweep.len(), 4);
+
+        // Brute-force optimum per number of shifted tracks
+        let mut best = [f64::INFINITY; 6];
+        let mut all: Vec<usize> = (0..5).collect();
+        permute(&mut all, 0, &mut |order| {
+            for code in 0..3usize.pow(5) {
+                let shifts: Vec<i8> = (0..5).map(|i| (code / 3usize.pow(i as u32) % 3) as i8 - 1).collect();
+                let c = &mut best[shifted(&shifts)];
+                *c = c.min(objective(order, &shifts, &tables, &params));
+            }
+        });
+
+        for (k, (order, shifts, cost, _, _)) in sweep.iter().enumerate() {
+            assert!(is_permutation(order, 5));
+            assert!(shifted(shifts) <= k);
+            assert_eq!(*cost, objective(order, shifts, &tables, &params));
+            assert_eq!(*cost, best[..=k].iter().copied().fold(f64::INFINITY, f64::min));
+            if k > 0 {
+                assert!(*cost <= sweep[k - 1].2);
+            }
+            // A single run under the same cap agrees
+            let capped = CostParams { max_shifted: Some(k), ..params };
+            assert_eq!(held_karp::run(5, &tables, &capped, None, None, false).2, *cost);
+        }
+    }
+}
+
+#[test]
+fn large_stability_weight_returns_the_reference() {
+    let params = cost_params();
+    let inst = instance(7, 21);
+    let mut tables = inst.tables();
+    let reference = [4, 1, 6, 0, 2, 5, 3];
+    let st = StabilityPenalty::new(&reference, 1000.0).unwrap();
+    tables.stability = Some(&st);
+    let (order, shifts, cost, _, _) = held_karp::run(7, &tables, &params, None, None, false);
+    assert_eq!(order, reference);
+    assert_eq!(cost, objective(&order, &shifts, &tables, &params));
+
+    // A weight of zero changes nothing
+    let free = StabilityPenalty::new(&reference, 0.0).unwrap();
+    tables.stability = Some(&free);
+    let plain = held_karp::run(7, &inst.tables(), &params, None, None, false).2;
+    assert_eq!(held_karp::run(7, &tables, &params, None, None, false).2, plain);
+}
@@ -0,0 +1,40 @@
+mod common;
+
+use common::{annealing_params, cost_params, instance, is_permutation, objective};
+use ydj_mixer_engine::annealing::{run_seeded, StatsWeighting};
+use ydj_mixer_engine::hybrid::{self, clusters};
+
+#[test]
+fn clusters_split_by_tempo() {
+    let inst = instance(22, 1);
+    let tables = inst.tables();
+    let cs = clusters(inst.n(), &tables, 8);
+    assert_eq!(cs.iter().map(Vec::len).collect::<Vec<_>>(), [7, 7, 8]);
+    assert!(is_permutation(&cs.concat(), inst.n()));
+    for w in cs.windows(2) {
+        let top = w[0].iter().map(|&i| inst.bpms[i]).max().unwrap();
+        assert!(w[1].iter().all(|&i| inst.bpms[i] >= top));
+    }
+}
+
+#[test]
+fn hybrid_matches_or_beats_plain_annealing() {
+    let params = cost_params();
+    for seed in 0..3 {
+        let inst = instance(22, seed);
+        let tables = inst.tables();
+        let h = hybrid::run_seeded(inst.n(), &tables, &params, &annealing_params(), 12, 4, seed);
+        let r = &h.result;
+        assert!(is_permutation(&r.best_order, inst.n()));
+        assert!(is_permutation(&h.clusters.concat(), inst.n()));
+        assert!((r.best_cost - objective(&r.best_order, &r.best_shifts, &tables, &params)).abs() < 1e-9);
+        assert!(r.best_cost <= h.stitched_cost);
+        assert_eq!(h.n_attempts, 4);
+
+        // Plain annealing with the same attempts and seed
+        let ap = annealing_params();
+        let (plain, _, _, _) =
+            run_seeded(inst.n(), &tables, &params, &ap, None, None, StatsWeighting::Uniform, 4, seed).unwrap();
+        assert!(r.best_cost <= plain.best_cost);
+    }
+}
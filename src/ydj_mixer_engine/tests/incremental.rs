@@ -0,0 +1,86 @@
+mod common;
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+use common::{annealing_params, cost_params, instance, is_permutation, objective};
+use ydj_mixer_engine::cost::Anchors;
+use ydj_mixer_engine::incremental::{insert_cheapest, insertion_costs, reoptimize, splice};
+
+#[test]
+fn cheapest_insertion_matches_every_alternative() {
+    let params = cost_params();
+    let inst = instance(10, 31);
+    let mut tables = inst.tables();
+    tables.anchors = Anchors { entry: Some((122, 5)), exit: Some((128, 18)) };
+    let mut rng = StdRng::seed_from_u64(31);
+    for track in 0..10 {
+        let mut order: Vec<usize> = (0..10).filter(|&t| t != track).collect();
+        order.shuffle(&mut rng);
+        let mut shifts: Vec<i8> = (0..10).map(|_| rng.random_range(-1..=1)).collect();
+        let mut cheapest = f64::INFINITY;
+        for pos in 0..=order.len() {
+            for s in [-1, 0, 1] {
+                let mut o = order.clone();
+                o.insert(pos, track);
+                shifts[track] = s;
+                cheapest = cheapest.min(objective(&o, &shifts, &tables, &params));
+            }
+        }
+        insert_cheapest(&mut order, &mut shifts, track, &tables, &params);
+        assert!(is_permutation(&order, 10));
+        assert!((objective(&order, &shifts, &tables, &params) - cheapest).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn insertion_costs_match_a_full_rescore_at_every_position() {
+    let params = cost_params();
+    let inst = instance(12, 33);
+    let mut tables = inst.tables();
+    tables.anchors = Anchors { entry: Some((118, 2)), exit: None };
+    let mut rng = StdRng::seed_from_u64(33);
+    let mut order: Vec<usize> = (0..11).collect();
+    order.shuffle(&mut rng);
+    let mut shifts: Vec<i8> = (0..12).map(|_| rng.random_range(-1..=1)).collect();
+    let before = objective(&order, &shifts, &tables, &params);
+    let costs = insertion_costs(&order, &shifts, 11, &tables, &params);
+    assert_eq!(costs.len(), 12);
+    for (pos, &(shift, delta)) in costs.iter().enumerate() {
+        let mut o = order.clone();
+        o.insert(pos, 11);
+        let mut cheapest = f64::INFINITY;
+        for s in [-1, 0, 1] {
+            shifts[11] = s;
+            let after = objective(&o, &shifts, &tables, &params) - before;
+            if s == shift {
+                assert!((after - delta).abs() < 1e-9, "position {pos}, shift {s}");
+            }
+            cheapest = cheapest.min(after);
+        }
+        assert!((delta - cheapest).abs() < 1e-9, "position {pos}");
+    }
+}
+
+#[test]
+fn reoptimize_never_loses_to_the_splice() {
+    let params = cost_params();
+    let inst = instance(14, 32);
+    let tables = inst.tables();
+    let mut rng = StdRng::seed_from_u64(32);
+    let mut kept: Vec<usize> = (0..11).collect();
+    kept.shuffle(&mut rng);
+    let shifts = vec![0i8; 14];
+    let added = [11, 12, 13];
+    let mut spliced_shifts = shifts.clone();
+    let spliced = splice(&kept, &mut spliced_shifts, &added, &tables, &params);
+    assert!(is_permutation(&spliced, 14));
+    let floor = objective(&spliced, &spliced_shifts, &tables, &params);
+
+    let (best, attempt_costs, _, _) =
+        reoptimize(14, &tables, &params, &annealing_params(), &kept, &shifts, &added, 0.05).unwrap();
+    assert!(is_permutation(&best.best_order, 14));
+    assert!(!attempt_costs.is_empty());
+    assert!(best.best_cost <= floor + 1e-9);
+    assert!((best.best_cost - objective(&best.best_order, &best.best_shifts, &tables, &params)).abs() < 1e-9);
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+use common::{cost_params, instance, is_permutation, objective};
+use ydj_mixer_engine::cost::{reverse_segment_delta, Anchors};
+use ydj_mixer_engine::polish::two_opt;
+
+#[test]
+fn two_opt_reaches_a_local_optimum_under_directed_costs() {
+    let params = cost_params();
+    let inst = instance(14, 31);
+    let intro: Vec<i32> = inst.bpms.iter().map(|b| b - 3).collect();
+    let mut tables = inst.tables();
+    tables.bpms = &intro;
+    tables.anchors = Anchors { entry: Some((118, 4)), exit: Some((131, 9)) };
+    let mut rng = StdRng::seed_from_u64(31);
+    let mut start: Vec<usize> = (0..14).collect();
+    start.shuffle(&mut rng);
+    let shifts: Vec<i8> = (0..14).map(|_| rng.random_range(-1..=1)).collect();
+
+    let p = two_opt(&start, &shifts, &tables, &params, usize::MAX);
+    assert!(p.converged && p.moves > 0);
+    assert!(is_permutation(&p.order, 14));
+    assert!((p.cost - objective(&p.order, &shifts, &tables, &params)).abs() < 1e-9);
+    assert!(p.cost < objective(&start, &shifts, &tables, &params));
+    for i in 0..14 {
+        for j in i..14 {
+            assert!(reverse_segment_delta(&p.order, &shifts, i, j, &tables, &params).0 > -1e-9, "{i}..={j}");
+        }
+    }
+    assert_eq!(two_opt(&start, &shifts, &tables, &params, usize::MAX), p);
+}
+
+#[test]
+fn two_opt_stops_at_its_move_budget() {
+    let params = cost_params();
+    let inst = instance(14, 32);
+    let tables = inst.tables();
+    let start: Vec<usize> = (0..14).rev().collect();
+    let shifts = vec![0i8; 14];
+    let full = two_opt(&start, &shifts, &tables, &params, usize::MAX);
+    assert!(full.moves > 1);
+
+    let none = two_opt(&start, &shifts, &tables, &params, 0);
+    assert_eq!((none.order, none.moves, none.converged), (start.clone(), 0, false));
+    let one = two_opt(&start, &shifts, &tables, &params, 1);
+    assert_eq!((one.moves, one.converged), (1, false));
+    // The budget is spent exactly when the last improving move is applied
+    let exact = two_opt(&start, &shifts, &tables, &params, full.moves);
+    assert_eq!(exact, full);
+}
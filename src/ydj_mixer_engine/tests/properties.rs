@@ -0,0 +1,182 @@
+//! Randomised invariants over `testing::random_instance`.
+
+mod common;
+
+use proptest::prelude::*;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use common::{annealing_params, cost_params, instance, objective};
+use ydj_mixer_engine::annealing::{run_attempt, AnnealingParams};
+use ydj_mixer_engine::cost::{
+    affected_edges, optimize_shift_at, AdjacencyBonus, CompatCosts, sum_edge_costs, Anchors, CostParams,
+    Tables,
+};
+use ydj_mixer_engine::family::FamilyRunLimit;
+use ydj_mixer_engine::held_karp;
+
+/// The annealing move's incremental cost: affected edges, boundary and the two node costs.
+fn local_cost(
+    order: &[usize],
+    shifts: &[i8],
+    edges: &[usize],
+    a: usize,
+    b: usize,
+    tables: &Tables,
+    params: &CostParams,
+) -> f64 {
+    sum_edge_costs(edges, order, shifts, tables, params)
+        + tables.boundary_cost(order, shifts, params)
+        + tables.node_cost(order[a], shifts[order[a]], params)
+        + tables.node_cost(order[b], shifts[order[b]], params)
+}
+
+fn anchors(entry: Option<(i32, u8)>, exit: Option<(i32, u8)>) -> Anchors {
+    Anchors { entry, exit }
+}
+
+fn anchor() -> impl Strategy<Value = Option<(i32, u8)>> {
+    proptest::option::of((100i32..140, 0u8..24))
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn swap_delta_matches_full_recompute(
+        n in 2usize..30,
+        seed in any::<u64>(),
+        perm_seed in any::<u64>(),
+        moves in prop::collection::vec((any::<usize>(), any::<usize>()), 1..20),
+        across_breaks in any::<bool>(),
+        confidence in proptest::option::of(prop::collection::vec(0.0f64..=1.0, 30)),
+        confidence_product in any::<bool>(),
+        blend in proptest::option::of(prop::collection::vec(0.0f64..3.0, 30)),
+        pairs in prop::collection::vec((0usize..30, 0usize..30, 0.0f64..20.0), 0..10),
+        entry in anchor(),
+        exit in anchor(),
+        cut_penalty in proptest::option::of(0.0f64..20.0),
+        cut_harmonic_discount in 0.0f64..=1.0,
+        compat in proptest::option::of((prop::collection::vec(0.0f64..50.0, 900), any::<bool>())),
+    ) {
+        let params = CostParams {
+            harmonic_across_breaks: across_breaks,
+            confidence_product,
+            cut_penalty,
+            cut_harmonic_discount,
+            ..cost_params()
+        };
+        let inst = instance(n, seed);
+        let mut tables = inst.tables();
+        tables.anchors = anchors(entry, exit);
+        tables.key_confidence = confidence.as_deref();
+        tables.exit_key_confidence = confidence.as_deref();
+        tables.blend_scale = blend.as_deref();
+        let adjacency = AdjacencyBonus::from_edges(
+            n,
+            pairs.iter().map(|&(a, b, bonus)| (a % n, b % n, bonus)).filter(|&(a, b, _)| a != b),
+        );
+        tables.adjacency = Some(&adjacency);
+        let compat = compat.map(|(costs, replaces)| CompatCosts::new(n, &costs[..n * n], 0.5, replaces).unwrap());
+        tables.compat = compat.as_ref();
+
+        let mut order: Vec<usize> = (0..n).collect();
+        rand::seq::SliceRandom::shuffle(&mut order[..], &mut StdRng::seed_from_u64(perm_seed));
+        let mut shifts = vec![0i8; n];
+        let mut current = objective(&order, &shifts, &tables, &params);
+        let mut edges = [0usize; 4];
+
+        for (a, b) in moves {
+            let a = a % n;
+            let b = b % n;
+            if a == b {
+                continue;
+            }
+            let count = affected_edges(a, b, n, &mut edges);
+            let before = local_cost(&order, &shifts, &edges[..count], a, b, &tables, &params);
+            order.swap(a, b);
+            optimize_shift_at(&order, &mut shifts, a, &tables, &params);
+            optimize_shift_at(&order, &mut shifts, b, &tables, &params);
+            let after = local_cost(&order, &shifts, &edges[..count], a, b, &tables, &params);
+
+            current += after - before;
+            let full = objective(&order, &shifts, &tables, &params);
+            prop_assert!((current - full).abs() < 1e-6, "delta {current} vs full {full}");
+        }
+    }
+
+    #[test]
+    fn held_karp_never_worse_than_annealing(
+        n in 2usize..9,
+        seed in any::<u64>(),
+        entry in anchor(),
+        exit in anchor(),
+    ) {
+        let params = cost_params();
+        let inst = instance(n, seed);
+        let mut tables = inst.tables();
+        tables.anchors = anchors(entry, exit);
+        let ap = AnnealingParams { total_iterations: 500, ..annealing_params() };
+
+        let (order, shifts, exact, _, _) = held_karp::run(n, &tables, &params, None, None, false);
+        prop_assert!((exact - objective(&order, &shifts, &tables, &params)).abs() < 1e-9);
+
+        let r = run_attempt(n, &tables, &params, &ap, None, None, &mut StdRng::seed_from_u64(seed));
+        prop_assert!(exact <= r.best_cost + 1e-9, "exact {exact} > SA {}", r.best_cost);
+    }
+
+    #[test]
+    fn breakdown_recombines_to_best_cost(
+        n in 2usize..40,
+        seed in any::<u64>(),
+        across_breaks in any::<bool>(),
+        entry in anchor(),
+        exit in anchor(),
+    ) {
+        let params = CostParams { harmonic_across_breaks: across_breaks, ..cost_params() };
+        let inst = instance(n, seed);
+        let mut tables = inst.tables();
+        tables.anchors = anchors(entry, exit);
+        let ap = AnnealingParams { total_iterations: 1000, ..annealing_params() };
+
+        let r = run_attempt(n, &tables, &params, &ap, None, None, &mut StdRng::seed_from_u64(seed));
+        let recombined = r.h_cost
+            + params.tempo_cost_weight * r.t_cost
+            + params.shift_weight * r.s_cost
+            + tables.boundary_cost(&r.best_order, &r.best_shifts, &params);
+        prop_assert!((r.best_cost - recombined).abs() < 1e-6, "best {} vs {recombined}", r.best_cost);
+    }
+
+    #[test]
+    fn family_excess_delta_matches_full_recompute(
+        n in 2usize..30,
+        seed in any::<u64>(),
+        max_run in 1usize..4,
+        moves in prop::collection::vec((any::<usize>(), any::<usize>(), -1i8..=1, -1i8..=1), 1..30),
+    ) {
+        let limit = FamilyRunLimit { max_run, penalty: 1.0 };
+        let mut inst = instance(n, seed);
+        // Few keys, so long runs are common
+        for k in inst.key_ids.iter_mut() {
+            *k %= 4;
+        }
+        let tables = inst.tables();
+        let mut order: Vec<usize> = (0..n).collect();
+        let mut shifts = vec![0i8; n];
+        let mut current = limit.excess(&order, &shifts, &tables);
+
+        for (a, b, sa, sb) in moves {
+            let (a, b) = (a % n, b % n);
+            if a == b {
+                continue;
+            }
+            let before = limit.local_excess(&order, &shifts, &tables, a, b);
+            order.swap(a, b);
+            shifts[order[a]] = sa;
+            shifts[order[b]] = sb;
+            let after = limit.local_excess(&order, &shifts, &tables, a, b);
+            current = current + after - before;
+            prop_assert_eq!(current, limit.excess(&order, &shifts, &tables));
+        }
+    }
+}
@@ -0,0 +1,88 @@
+"""Buffer-protocol inputs of the Python bindings against the list path.
+
+Cargo only builds tests/*.rs, so these run separately against the built module:
+
+    maturin develop && python -m unittest discover tests/python
+
+`array.array` exposes the same buffer protocol as a NumPy array, so no NumPy is needed.
+"""
+import random
+import unittest
+from array import array
+
+import ydj_mixer_engine as engine
+
+
+def instance(n=10, seed=3):
+    bpms, keys, shift_table, direct, indirect, cost_params = engine.random_instance(n, seed)
+    rng = random.Random(seed)
+    orders = [rng.sample(range(n), n) for _ in range(20)]
+    shifts = [[rng.choice((-1, 0, 1)) for _ in range(n)] for _ in orders]
+    return bpms, keys, shift_table, direct, indirect, cost_params, orders, shifts
+
+
+class BufferInputs(unittest.TestCase):
+    def test_buffers_score_like_lists(self):
+        bpms, keys, shift_table, direct, indirect, cp, orders, shifts = instance()
+        expected = engine.score_orders(bpms, keys, shift_table, direct, indirect, cp, orders, shifts)
+        buffers = (array("i", bpms), array("B", keys), array("B", shift_table), array("d", direct), array("d", indirect))
+        self.assertEqual(engine.score_orders(*buffers, cp, orders, shifts), expected)
+
+    def test_strided_views_are_rejected_by_name(self):
+        bpms, keys, shift_table, direct, indirect, cp, orders, shifts = instance()
+        # Every other entry of a table twice as long: a non-contiguous view
+        doubled = array("d", [c for c in direct for _ in range(2)])
+        strided = memoryview(doubled)[::2]
+        self.assertFalse(strided.c_contiguous)
+        with self.assertRaisesRegex(ValueError, r"^direct_costs: expected a C-contiguous 1-D float64 .*strides \(16,\)$"):
+            engine.score_orders(bpms, keys, shift_table, strided, indirect, cp, orders, shifts)
+
+    def test_other_dtypes_are_rejected_by_name(self):
+        bpms, keys, shift_table, direct, indirect, cp, orders, shifts = instance()
+        expected = engine.score_orders(bpms, keys, shift_table, direct, indirect, cp, orders, shifts)
+        # float32 tables are widened; int64 BPMs (NumPy's default integer) are not converted
+        got = engine.score_orders(bpms, keys, shift_table, array("f", direct), indirect, cp, orders, shifts)
+        self.assertEqual(got, expected)
+        with self.assertRaisesRegex(ValueError, r"^bpms: expected a C-contiguous 1-D int32 array, got dtype int64$"):
+            engine.score_orders(array("q", bpms), keys, shift_table, direct, indirect, cp, orders, shifts)
+        with self.assertRaisesRegex(ValueError, r"^base_key_ids: expected .* uint8 array, got dtype int32$"):
+            engine.score_orders(bpms, array("i", keys), shift_table, direct, indirect, cp, orders, shifts)
+        with self.assertRaisesRegex(ValueError, r"^indirect_costs: expected .* float64 \(or float32\) array, got dtype int64$"):
+            engine.score_orders(bpms, keys, shift_table, direct, array("q", [1] * len(indirect)), cp, orders, shifts)
+
+    def test_multidimensional_buffers_are_rejected(self):
+        bpms, keys, shift_table, direct, indirect, cp, orders, shifts = instance()
+        square = memoryview(array("d", direct)).cast("B").cast("d", (24, 24))
+        with self.assertRaisesRegex(ValueError, r"^direct_costs: .* got shape \(24, 24\)$"):
+            engine.score_orders(bpms, keys, shift_table, square, indirect, cp, orders, shifts)
+
+    def test_sanitizing_never_writes_to_the_callers_buffer(self):
+        bpms, keys, shift_table, direct, indirect, cp, _, _ = instance()
+        forbidden = array("d", direct)
+        forbidden[1] = float("inf")
+        before = forbidden.tolist()
+        ap = {"total_iterations": 200, "initial_temp": 10.0, "final_temp": 0.1, "multi_swap_factor": 2}
+        engine.optimize_mix(bpms, keys, shift_table, forbidden, indirect, cp, ap, 0.01, inf_forbidden=True)
+        self.assertEqual(forbidden.tolist(), before)
+        with self.assertRaisesRegex(ValueError, r"direct_costs\[1\] is inf"):
+            engine.optimize_mix(bpms, keys, shift_table, forbidden, indirect, cp, ap, 0.01)
+
+    def test_callbacks_cannot_rewrite_the_tables_mid_run(self):
+        bpms, keys, shift_table, direct, indirect, cp, _, _ = instance()
+        ap = {"total_iterations": 2000, "initial_temp": 10.0, "final_temp": 0.1, "multi_swap_factor": 2}
+        expected = engine.optimize_mix_custom(bpms, keys, shift_table, direct, indirect, cp, ap, lambda o, s: None,
+                                              move_interval=100, seed=5)
+        buffer = array("d", direct)
+
+        def propose(order, shifts):
+            # The run copied the table before calling back: this write must not reach it
+            buffer[:] = array("d", [50.0] * len(buffer))
+
+        got = engine.optimize_mix_custom(bpms, keys, shift_table, buffer, indirect, cp, ap, propose,
+                                         move_interval=100, seed=5)
+        self.assertEqual(got, expected)
+        self.assertEqual(buffer[0], 50.0)
+
+
+if __name__ == "__main__":
+    unittest.main()
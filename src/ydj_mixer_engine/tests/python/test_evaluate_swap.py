@@ -0,0 +1,64 @@
+"""evaluate_swap with reoptimize_shifts=False against full rescoring with evaluate_order.
+
+Run like test_arrays.py:
+
+    maturin develop && python -m unittest discover tests/python
+"""
+import random
+import unittest
+
+import ydj_mixer_engine as engine
+
+
+def instance(n=10, seed=3):
+    bpms, keys, shift_table, direct, indirect, cost_params = engine.random_instance(n, seed)
+    rng = random.Random(seed)
+    order = rng.sample(range(n), n)
+    shifts = [rng.choice((-1, 0, 1)) for _ in range(n)]
+    return order, shifts, (bpms, keys, shift_table, direct, indirect, cost_params)
+
+
+def swapped(order, pos_a, pos_b):
+    after = list(order)
+    after[pos_a], after[pos_b] = after[pos_b], after[pos_a]
+    return after
+
+
+class FixedShiftSwaps(unittest.TestCase):
+    def test_delta_is_the_rescored_difference_with_shifts_held(self):
+        order, shifts, tables = instance()
+        before = engine.evaluate_order(order, shifts, *tables)[0]
+        for pos_a in range(len(order)):
+            for pos_b in range(pos_a + 1, len(order)):
+                delta, _, new_shifts = engine.evaluate_swap(order, shifts, pos_a, pos_b, *tables,
+                                                            reoptimize_shifts=False)
+                after = engine.evaluate_order(swapped(order, pos_a, pos_b), shifts, *tables)[0]
+                self.assertAlmostEqual(delta, after - before, places=9)
+                a, b = order[pos_a], order[pos_b]
+                self.assertEqual(sorted(new_shifts), sorted([(a, shifts[a]), (b, shifts[b])]))
+
+    def test_reoptimizing_finds_the_shift_a_held_swap_misses(self):
+        order, shifts, tables = instance()
+        before = engine.evaluate_order(order, shifts, *tables)[0]
+        helped = 0
+        for pos_a in range(len(order)):
+            for pos_b in range(pos_a + 1, len(order)):
+                held = engine.evaluate_swap(order, shifts, pos_a, pos_b, *tables, reoptimize_shifts=False)
+                moved = engine.evaluate_swap(order, shifts, pos_a, pos_b, *tables)
+                # The held shifts are among those re-chosen from, so never cheaper
+                self.assertLessEqual(moved[0], held[0] + 1e-9)
+                if moved[0] >= held[0] - 1e-9:
+                    continue
+                # A shift change that helps: new shifts, scored under them
+                helped += 1
+                self.assertNotEqual(dict(moved[2]), dict(held[2]))
+                new_shifts = list(shifts)
+                for track, shift in moved[2]:
+                    new_shifts[track] = shift
+                after = engine.evaluate_order(swapped(order, pos_a, pos_b), new_shifts, *tables)[0]
+                self.assertAlmostEqual(moved[0], after - before, places=9)
+        self.assertGreater(helped, 0)
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,34 @@
+mod common;
+
+use common::{annealing_params, cost_params, instance, objective};
+use ydj_mixer_engine::repeat::{run, Repeats};
+
+#[test]
+fn repeated_track_keeps_its_gap() {
+    let params = cost_params();
+    let inst = instance(8, 4);
+    let tables = inst.tables();
+    let mut max_plays = vec![1; inst.n()];
+    max_plays[2] = 2;
+    // 9 plays with 6 tracks between the copies: only positions 0 and 7, 0 and 8, or 1 and 8
+    let repeats = Repeats::new(&tables, &max_plays, 6).unwrap();
+    assert_eq!(repeats.node_count(), 9);
+    for _ in 0..3 {
+        let r = run(&repeats, &tables, &params, &annealing_params(), 0.02).unwrap();
+        assert_eq!(r.order.len(), 9);
+        let plays: Vec<usize> = (0..9).filter(|&p| r.order[p] == 2).collect();
+        assert_eq!(plays.len(), 2);
+        assert!(plays[1] - plays[0] > 6, "{plays:?}");
+        let mut others: Vec<usize> = r.order.iter().copied().filter(|&i| i != 2).collect();
+        others.sort_unstable();
+        assert_eq!(others, [0, 1, 3, 4, 5, 6, 7]);
+        assert_eq!(r.order, repeats.tracks(&r.result.best_order));
+        let nodes = repeats.tables(&tables);
+        let expected = objective(&r.result.best_order, &r.result.best_shifts, &nodes, &params);
+        assert!((r.result.best_cost - expected).abs() < 1e-9);
+    }
+
+    // Eight tracks between the copies need 10 plays
+    assert!(Repeats::new(&tables, &max_plays, 8).is_err());
+    assert!(Repeats::new(&tables, &[1, 0, 1], 1).is_err());
+}
@@ -0,0 +1,144 @@
+mod common;
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use common::{annealing_params, cost_params, instance, is_permutation, objective};
+use ydj_mixer_engine::annealing::AnnealingParams;
+use ydj_mixer_engine::cost::{edge_cost, Anchors};
+use ydj_mixer_engine::select::{run, run_attempt, DurationTarget};
+
+#[test]
+fn best_cost_matches_full_recompute() {
+    let params = cost_params();
+    for seed in 0..6 {
+        let inst = instance(20, seed);
+        let mut tables = inst.tables();
+        if seed % 2 == 1 {
+            tables.anchors = Anchors { entry: Some((118, 6)), exit: Some((126, 11)) };
+        }
+        let value: Vec<f64> = (0..inst.n()).map(|i| (i % 4) as f64).collect();
+        let k = 5 + seed as usize;
+        let mut rng = StdRng::seed_from_u64(seed);
+        let r = run_attempt(inst.n(), Some(k), &tables, &params, &annealing_params(), Some(&value), None, None, &mut rng);
+        assert_eq!(r.best_order.len(), k);
+        let mut distinct = r.best_order.clone();
+        distinct.sort_unstable();
+        distinct.dedup();
+        assert_eq!(distinct.len(), k);
+        let selected_value: f64 = r.best_order.iter().map(|&i| value[i]).sum();
+        let expected = objective(&r.best_order, &r.best_shifts, &tables, &params) - selected_value;
+        assert!((r.best_cost - expected).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn excluded_tracks_complete_the_library() {
+    let params = cost_params();
+    let inst = instance(15, 3);
+    let tables = inst.tables();
+    let sel = run(inst.n(), Some(6), &tables, &params, &annealing_params(), None, None, None, 0.05);
+    assert!(sel.n_attempts >= 1);
+    assert_eq!(sel.excluded.len(), 9);
+    assert!(sel.excluded.windows(2).all(|w| w[0] < w[1]));
+    assert!(sel.excluded.iter().all(|&i| sel.result.best_shifts[i] == 0));
+    let all: Vec<usize> = sel.result.best_order.iter().chain(&sel.excluded).copied().collect();
+    assert!(is_permutation(&all, inst.n()));
+
+    // Keeping the whole library is plain ordering
+    let sel = run(inst.n(), Some(inst.n()), &tables, &params, &annealing_params(), None, None, None, 0.0);
+    assert!(is_permutation(&sel.result.best_order, inst.n()));
+    assert!(sel.excluded.is_empty());
+}
+
+#[test]
+fn inclusion_value_keeps_a_hard_track() {
+    let params = cost_params();
+    let mut inst = instance(12, 5);
+    // Track 0 is a tempo outlier: without a bonus it is never worth playing
+    inst.bpms[0] = 60;
+    let tables = inst.tables();
+    let mut value = vec![0.0; inst.n()];
+    let picks = |value: Option<&[f64]>| {
+        let mut rng = StdRng::seed_from_u64(5);
+        run_attempt(inst.n(), Some(6), &tables, &params, &annealing_params(), value, None, None, &mut rng).best_order
+    };
+    assert!(!picks(None).contains(&0));
+    value[0] = 100.0;
+    assert!(picks(Some(&value)).contains(&0));
+}
+
+#[test]
+fn required_tracks_are_always_selected() {
+    let params = cost_params();
+    let mut inst = instance(16, 7);
+    // Required tempo outliers, which the plain selection would drop
+    inst.bpms[3] = 60;
+    inst.bpms[11] = 180;
+    let tables = inst.tables();
+    let mut required = vec![false; inst.n()];
+    for i in [3, 11, 14] {
+        required[i] = true;
+    }
+    for seed in 0..4 {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let ap = annealing_params();
+        let r = run_attempt(inst.n(), Some(5), &tables, &params, &ap, None, Some(&required), None, &mut rng);
+        assert!([3, 11, 14].iter().all(|i| r.best_order.contains(i)));
+        assert!((r.best_cost - objective(&r.best_order, &r.best_shifts, &tables, &params)).abs() < 1e-9);
+    }
+
+    let sel = run(inst.n(), Some(5), &tables, &params, &annealing_params(), None, Some(&required), None, 0.02);
+    let order = &sel.result.best_order;
+    let shifts = &sel.result.best_shifts;
+    let fillers: Vec<usize> = sel.fillers.iter().map(|&(i, _)| i).collect();
+    assert_eq!(fillers, order.iter().copied().filter(|&i| !required[i]).collect::<Vec<_>>());
+    for &(i, cost) in &sel.fillers {
+        let p = order.iter().position(|&t| t == i).unwrap();
+        let edges: Vec<f64> = [p.checked_sub(1), (p + 1 < order.len()).then_some(p)]
+            .into_iter()
+            .flatten()
+            .map(|j| edge_cost(order[j], order[j + 1], shifts[order[j]], shifts[order[j + 1]], &tables, &params))
+            .collect();
+        assert_eq!(cost, edges.iter().sum::<f64>() / edges.len() as f64);
+    }
+
+    // Exactly k required tracks: only their order is annealed
+    let sel = run(inst.n(), Some(3), &tables, &params, &annealing_params(), None, Some(&required), None, 0.0);
+    let mut selected = sel.result.best_order.clone();
+    selected.sort_unstable();
+    assert_eq!(selected, [3, 11, 14]);
+    assert!(sel.fillers.is_empty());
+}
+
+#[test]
+fn duration_target_sets_the_length() {
+    let params = cost_params();
+    let inst = instance(30, 11);
+    let tables = inst.tables();
+    // Tracks of 3 to 7 minutes; a 40-minute set takes about 8 of them
+    let durations: Vec<f64> = (0..inst.n()).map(|i| 180.0 + 60.0 * (i % 5) as f64).collect();
+    let overlap = vec![20.0; inst.n()];
+    let ap = AnnealingParams { total_iterations: 20000, ..annealing_params() };
+    for (hard, overlap) in [(false, None), (true, None), (false, Some(&overlap[..]))] {
+        let target =
+            DurationTarget { durations: &durations, overlap, target: 2400.0, tolerance: 60.0, penalty: 1.0, hard };
+        for seed in 0..3 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let r = run_attempt(inst.n(), None, &tables, &params, &ap, None, None, Some(&target), &mut rng);
+            let total = target.total(&r.best_order);
+            let expected = objective(&r.best_order, &r.best_shifts, &tables, &params) + target.excess(total);
+            assert!((r.best_cost - expected).abs() < 1e-9);
+            assert!((2340.0..=2460.0).contains(&total), "{total}");
+            let played: f64 = r.best_order.iter().map(|&i| durations[i]).sum();
+            let overlaps = if overlap.is_some() { 20.0 * (r.best_order.len() - 1) as f64 } else { 0.0 };
+            assert!((total - (played - overlaps)).abs() < 1e-9);
+        }
+    }
+
+    let target =
+        DurationTarget { durations: &durations, overlap: None, target: 2400.0, tolerance: 60.0, penalty: 1.0, hard: false };
+    let sel = run(inst.n(), None, &tables, &params, &ap, None, None, Some(&target), 0.0);
+    assert_eq!(sel.duration, Some(target.total(&sel.result.best_order)));
+    assert_eq!(sel.excluded.len() + sel.result.best_order.len(), inst.n());
+}
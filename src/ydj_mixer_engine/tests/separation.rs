@@ -0,0 +1,89 @@
+mod common;
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use common::{annealing_params, cost_params, instance};
+use ydj_mixer_engine::annealing::run_attempt;
+use ydj_mixer_engine::separation::{Grouping, Separation};
+
+#[test]
+fn feasibility_decides_mode() {
+    // Largest group of 3 with gap 2 needs (3 - 1) * 3 + 1 = 7 tracks
+    assert!(Grouping::new(vec![0, 0, 0, 1, 2, 3, 4], 2, 1.0).hard);
+    assert!(!Grouping::new(vec![0, 0, 0, 1, 2, 3], 2, 1.0).hard);
+    assert!(!Grouping::new(vec![0, 0, 1], 0, 1.0).is_active());
+}
+
+#[test]
+fn violations_count_pairs_within_gap() {
+    let g = Grouping::new(vec![0, 0, 1, 1], 2, 1.0);
+    assert_eq!(g.violations(&[0, 1, 2, 3]), 2);
+    assert_eq!(g.violations(&[0, 2, 1, 3]), 2);
+    assert_eq!(g.violations(&[0, 2, 3, 1]), 1);
+}
+
+#[test]
+fn two_hard_groupings_are_respected() {
+    let params = cost_params();
+    let inst = instance(16, 9);
+    let tables = inst.tables();
+    let artists: Vec<u32> = (0..16).map(|i| i % 4).collect();
+    let labels: Vec<u32> = (0..16).map(|i| i / 8).collect();
+    let mut sep = Separation::default();
+    sep.push(Grouping::new(artists, 2, 10.0));
+    sep.push(Grouping::new(labels, 0, 10.0));
+    sep.push(Grouping::new((0..16).map(|i| i % 8).collect(), 3, 10.0));
+    assert_eq!(sep.groupings.len(), 2);
+    assert!(sep.groupings.iter().all(|g| g.hard));
+
+    for seed in 0..3 {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let r = run_attempt(inst.n(), &tables, &params, &annealing_params(), Some(&sep), None, &mut rng);
+        assert_eq!(r.violations, vec![0, 0]);
+    }
+}
+
+#[test]
+fn penalty_mode_is_charged_in_best_cost() {
+    let params = cost_params();
+    let inst = instance(8, 1);
+    let tables = inst.tables();
+    let mut sep = Separation::default();
+    // Five of eight tracks share an artist: infeasible with gap 1
+    sep.push(Grouping::new(vec![0, 0, 0, 0, 0, 1, 2, 3], 1, 100.0));
+    assert!(!sep.any_hard());
+    let mut rng = StdRng::seed_from_u64(1);
+    let r = run_attempt(inst.n(), &tables, &params, &annealing_params(), Some(&sep), None, &mut rng);
+    let base = common::objective(&r.best_order, &r.best_shifts, &tables, &params);
+    assert_eq!(r.violations, vec![1]);
+    assert!((r.best_cost - base - 100.0).abs() < 1e-9);
+}
+
+#[test]
+fn spread_tracks_keep_their_spacing() {
+    assert!(Grouping::spread(10, &[1, 4, 7, 11], 3, 10.0).is_err());
+    assert!(Grouping::spread(10, &[1, 4, 1], 3, 10.0).is_err());
+    // Four tracks three apart need (4 - 1) * 3 + 1 = 10 positions
+    assert!(Grouping::spread(9, &[0, 1, 2, 3], 3, 10.0).is_err());
+    assert!(!Grouping::spread(10, &[0, 1, 2], 1, 10.0).unwrap().is_active());
+
+    let params = cost_params();
+    let inst = instance(16, 12);
+    let tables = inst.tables();
+    let spread = [2, 5, 6, 11, 15];
+    let mut sep = Separation::default();
+    sep.push(Grouping::spread(16, &spread, 3, 10.0).unwrap());
+    assert!(sep.any_hard());
+    for seed in 0..3 {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let r = run_attempt(inst.n(), &tables, &params, &annealing_params(), Some(&sep), None, &mut rng);
+        assert_eq!(r.violations, vec![0]);
+        let closest = sep.groupings[0].closest_repeat(&r.best_order).unwrap();
+        let mut positions: Vec<usize> =
+            spread.iter().map(|&t| r.best_order.iter().position(|&u| u == t).unwrap()).collect();
+        positions.sort_unstable();
+        assert_eq!(closest, positions.windows(2).map(|w| w[1] - w[0]).min().unwrap());
+        assert!(closest >= 3);
+    }
+}
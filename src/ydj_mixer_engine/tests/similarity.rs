@@ -0,0 +1,51 @@
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+use ydj_mixer_engine::similarity::compare;
+
+/// Quadratic reference count of discordant pairs.
+fn discordant_pairs(a: &[usize], b: &[usize]) -> usize {
+    let pos = |t: usize| b.iter().position(|&x| x == t).unwrap();
+    let mut count = 0;
+    for i in 0..a.len() {
+        for j in i + 1..a.len() {
+            if pos(a[i]) > pos(a[j]) {
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+#[test]
+fn identical_and_reversed_orders() {
+    let a = [7, 3, 9, 1, 4];
+    let same = compare(&a, &a).unwrap();
+    assert_eq!((same.kendall_tau, same.shared_adjacencies), (0, 4));
+    let reversed: Vec<usize> = a.iter().rev().copied().collect();
+    let rev = compare(&a, &reversed).unwrap();
+    assert_eq!((rev.kendall_tau, rev.shared_adjacencies), (10, 0));
+}
+
+#[test]
+fn matches_quadratic_count() {
+    let mut rng = StdRng::seed_from_u64(3);
+    for n in [1, 2, 17, 64] {
+        let mut a: Vec<usize> = (0..n).map(|i| i * 5 + 2).collect();
+        let mut b = a.clone();
+        a.shuffle(&mut rng);
+        b.shuffle(&mut rng);
+        let s = compare(&a, &b).unwrap();
+        assert_eq!(s.kendall_tau, discordant_pairs(&a, &b));
+        let shared = a.windows(2).filter(|w| b.windows(2).any(|v| v == *w)).count();
+        assert_eq!(s.shared_adjacencies, shared);
+    }
+}
+
+#[test]
+fn rejects_different_sets() {
+    assert!(compare(&[0, 1, 2], &[0, 1]).is_err());
+    assert!(compare(&[0, 1, 2], &[0, 1, 3]).is_err());
+    assert!(compare(&[0, 1, 1], &[0, 1, 2]).is_err());
+    assert!(compare(&[0, 1, 2], &[2, 2, 0]).is_err());
+}